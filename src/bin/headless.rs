@@ -0,0 +1,8 @@
+use moded::startup;
+
+
+// moded --headless without the window, see rpc
+fn main() {
+    let args = startup::init(std::env::args().skip(1));
+    startup::run_headless(&args.path, args.socket.as_deref());
+}
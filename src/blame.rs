@@ -0,0 +1,100 @@
+use std::{ffi::OsStr, path::Path};
+
+use crate::{gap_buffer::TextBuffer, shell};
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BlameLine {
+    pub author: String,
+    // seconds since the unix epoch
+    pub time: i64,
+    pub summary: String,
+    pub committed: bool,
+}
+
+impl BlameLine {
+    pub fn describe(&self) -> String {
+        if !self.committed {
+            return String::from("Not committed yet")
+        }
+
+        format!("{}, {}, {}", self.author, format_date(self.time), self.summary)
+    }
+}
+
+// blames the current contents of the buffer, so unsaved edits show as not committed
+pub fn blame_line(buffer: &TextBuffer, line: usize) -> Result<BlameLine, String> {
+    let Some(path) = &buffer.file_path else { return Err(String::from("Buffer has no file")) };
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Some(name) = path.file_name() else { return Err(String::from("Buffer has no file")) };
+
+    // straight to git, a quote in a file name can't end up as shell code
+    let range = format!("{},{}", line + 1, line + 1);
+    let args = [OsStr::new("-C"), dir.as_os_str(), OsStr::new("blame"), OsStr::new("--porcelain"), OsStr::new("-L"), OsStr::new(&range), OsStr::new("--contents"), OsStr::new("-"), OsStr::new("--"), name];
+    let text = (0..buffer.total_lines()).map(|line| buffer.raw_line(line)).collect::<String>();
+    let output = shell::filter_program("git", &args, &text)?;
+
+    parse_porcelain(&output).ok_or_else(|| String::from("Can't read git blame output"))
+}
+
+fn parse_porcelain(output: &str) -> Option<BlameLine> {
+    let mut lines = output.lines();
+    let hash = lines.next()?.split(' ').next()?;
+    let mut blame = BlameLine {
+        author: String::new(),
+        time: 0,
+        summary: String::new(),
+        committed: hash.bytes().any(|b| b != b'0'),
+    };
+
+    for line in lines {
+        // the line's content comes last, after a tab
+        if line.starts_with('\t') {
+            break
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "author" => blame.author = value.to_owned(),
+            "author-time" => blame.time = value.parse().ok()?,
+            "summary" => blame.summary = value.to_owned(),
+            _ => {},
+        }
+    }
+
+    Some(blame)
+}
+
+// yyyy-mm-dd in utc
+fn format_date(time: i64) -> String {
+    // from howard hinnant's days_from_civil inverse
+    let days = time.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_porcelain() {
+        let output = "3f2a9c1e 12 12 1\nauthor Jane Doe\nauthor-mail <jane@example.com>\nauthor-time 1700000000\n\
+            author-tz +0100\nsummary Fix cursor clamping\nfilename src/main.rs\n\tlet x = 1;\n";
+        let blame = parse_porcelain(output).unwrap();
+        assert_eq!(blame.describe(), "Jane Doe, 2023-11-14, Fix cursor clamping");
+
+        let output = "0000000000000000000000000000000000000000 3 3 1\nauthor Not Committed Yet\nauthor-time 1700000000\n\tfoo\n";
+        assert_eq!(parse_porcelain(output).unwrap().describe(), "Not committed yet");
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+    }
+}
@@ -0,0 +1,69 @@
+use std::{any::Any, collections::HashMap};
+
+
+// per buffer storage like vim's b: variables, lets features keep their own
+// state on a buffer without adding fields to TextBuffer
+#[derive(Default)]
+pub struct BufferVars {
+    map: HashMap<String, Box<dyn Any>>,
+}
+
+impl BufferVars {
+    pub fn new() -> Self {
+        Self { map: HashMap::new() }
+    }
+
+    pub fn set<T: Any>(&mut self, name: &str, value: T) {
+        self.map.insert(name.to_owned(), Box::new(value));
+    }
+
+    // returns None also when the stored value is of a different type
+    pub fn get<T: Any>(&self, name: &str) -> Option<&T> {
+        self.map.get(name)?.downcast_ref::<T>()
+    }
+
+    pub fn get_mut<T: Any>(&mut self, name: &str) -> Option<&mut T> {
+        self.map.get_mut(name)?.downcast_mut::<T>()
+    }
+
+    // replaces the value if it exists with a different type
+    pub fn get_or_insert_with<T: Any>(&mut self, name: &str, f: impl FnOnce() -> T) -> &mut T {
+        if self.get::<T>(name).is_none() {
+            self.set(name, f());
+        }
+
+        self.get_mut::<T>(name).unwrap()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.map.remove(name).is_some()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_access() {
+        let mut vars = BufferVars::new();
+        vars.set("changedtick", 3usize);
+
+        assert_eq!(vars.get::<usize>("changedtick"), Some(&3));
+        assert_eq!(vars.get::<String>("changedtick"), None);
+
+        *vars.get_mut::<usize>("changedtick").unwrap() += 1;
+        assert_eq!(vars.get::<usize>("changedtick"), Some(&4));
+
+        let name = vars.get_or_insert_with("changedtick", || String::from("replaced"));
+        assert_eq!(name, "replaced");
+
+        assert!(vars.remove("changedtick"));
+        assert!(!vars.contains("changedtick"));
+    }
+}
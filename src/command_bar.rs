@@ -1,94 +1,1000 @@
-use std::{io::Write, path::Path, sync::atomic::Ordering};
+use std::{fs, path::{Path, PathBuf}, sync::atomic::Ordering};
 
-use crate::{editor::{next_buffer_id, Editor}, gap_buffer::{LineView, TextBuffer}, State, SHOULD_QUIT};
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptFn;
+use crate::{blame, editor::{next_buffer_id, Editor}, format, gap_buffer::{LinePos, TextBuffer}, ignore, indent::{indent_text, indent_width}, keys::{self, Key, KeyMap, MapMode}, layout::{Axis, WindowCommand}, log, shell, options::{LocalOptions, Options}, substitute::Substitute, width, State, SHOULD_QUIT};
 
 pub enum CommandBarAction {
     None,
     Quit,
     NewBuffer(TextBuffer),
     SwitchToBuffer(usize),
+    MoveCursor(LinePos),
+    ReplaceLines(LineRange, String),
+    Message(String),
+    QuickfixMove(isize),
+    Window(WindowCommand),
+    // new text for the whole buffer, applied as a line diff
+    ReplaceBuffer(String),
+    // :retab's new text for the range or the whole buffer, and the tabstop
+    // that's set if the text can be changed
+    Retab(Option<LineRange>, String, usize),
+    // splits the window to show the buffer the current one is diffed with
+    DiffSplit(DiffWith),
+    DiffOff,
+    DefineCommand(UserCommand),
+    // keys typed in normal mode, on each line of the range if there's one
+    Normal(Option<LineRange>, String),
+    #[cfg(feature = "scripting")]
+    CallScript(ScriptFn, String),
+    Save,
+    // :d, the removed text goes to the deleted text list
+    DeleteLines(LineRange),
+    // :w {path}
+    SaveAs(PathBuf),
+    // one map for each mode, replacing maps of the same keys
+    Map(Vec<KeyMap>),
+    Unmap(Vec<MapMode>, Vec<Key>),
+    // the current buffer's new :setlocal options
+    SetLocal(LocalOptions),
+    // :cd, or :lcd with true for only the current buffer
+    ChangeDir(PathBuf, bool),
 }
 
-type Result = std::result::Result<CommandBarAction, ()>;
-type BarFn = fn (&mut State, &Editor, &str) -> Result;
+// a command defined by the user, the body is a command line where <args> is
+// replaced by what was typed after the name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserCommand {
+    pub name: String,
+    pub body: String,
+}
+
+// what :diffsplit compares the current buffer with
+pub enum DiffWith {
+    Buffer(usize),
+    File(TextBuffer),
+}
+
+// zero indexed, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+pub type CommandResult = std::result::Result<CommandBarAction, ()>;
+type Result = CommandResult;
+type BarFn = fn (&mut State, &Editor, Option<LineRange>, &str) -> Result;
+// candidates for the word being typed as the argument
+type CompleteFn = fn (&State, &Editor, &str) -> Vec<String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    None,
+    Text,
+    File,
+    Buffer,
+    Option,
+}
 
-macro_rules! lookup_table {
-    ($($name:expr => $func:expr),* $(,)?) => {
-        const NAMES: &[&str] = &[
-            $($name),*
-        ];
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    // accepts a line range like :10,20
+    pub range: bool,
+    pub arg: ArgType,
+    pub complete: Option<CompleteFn>,
+    // runs shell commands, refused when the editor's no_shell is set
+    pub shell: bool,
+    pub handler: BarFn,
+}
+
+impl Command {
+    const fn new(name: &'static str, handler: BarFn) -> Self {
+        Self { name, aliases: &[], range: false, arg: ArgType::None, complete: None, shell: false, handler }
+    }
+
+    const fn aliases(mut self, aliases: &'static [&'static str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    const fn range(mut self) -> Self {
+        self.range = true;
+        self
+    }
+
+    const fn shell(mut self) -> Self {
+        self.shell = true;
+        self
+    }
 
-        const FUNCTIONS: &[BarFn] = &[
-            $($func),*
-        ];
+    const fn arg(mut self, arg: ArgType) -> Self {
+        self.arg = arg;
+        self.complete = match arg {
+            ArgType::None | ArgType::Text => None,
+            ArgType::File => Some(complete_files),
+            ArgType::Buffer => Some(complete_buffers),
+            ArgType::Option => Some(complete_options),
+        };
+        self
+    }
+}
+
+
+// the message when no_shell refuses a command
+pub const SHELL_REFUSED: &str = "Shell commands aren't allowed here";
+
+// keep this sorted by name
+const COMMANDS: &[Command] = &[
+    Command::new("!", shell).range().arg(ArgType::Text).shell(),
+    Command::new("DeletedLines", deleted_lines).arg(ArgType::Text),
+    Command::new("S", substitute_preserve_case).range().arg(ArgType::Text),
+    Command::new("blame", blame),
+    Command::new("buffer", buffer).aliases(&["b"]).arg(ArgType::Buffer),
+    Command::new("cd", cd).arg(ArgType::File),
+    Command::new("close", close).aliases(&["clo"]),
+    Command::new("cnext", quickfix_next),
+    Command::new("command", command).aliases(&["com"]).arg(ArgType::Text),
+    Command::new("cprevious", quickfix_previous),
+    Command::new("delete", delete).aliases(&["d"]).range(),
+    Command::new("diffoff", diff_off),
+    Command::new("diffsplit", diff_split).arg(ArgType::File),
+    Command::new("edit", edit).aliases(&["e"]).arg(ArgType::File),
+    Command::new("enew", enew).aliases(&["ene"]),
+    Command::new("format", format).arg(ArgType::Text),
+    Command::new("goto", goto).arg(ArgType::Text),
+    Command::new("imap", imap).arg(ArgType::Text),
+    Command::new("inoremap", inoremap).aliases(&["ino"]).arg(ArgType::Text),
+    Command::new("iunmap", iunmap).aliases(&["iu"]).arg(ArgType::Text),
+    Command::new("lcd", lcd).arg(ArgType::File),
+    Command::new("map", map).arg(ArgType::Text),
+    Command::new("messages", messages).aliases(&["mes"]),
+    Command::new("nmap", nmap).aliases(&["nm"]).arg(ArgType::Text),
+    Command::new("nnoremap", nnoremap).aliases(&["nn"]).arg(ArgType::Text),
+    Command::new("noremap", noremap).aliases(&["no"]).arg(ArgType::Text),
+    Command::new("normal", normal).aliases(&["norm"]).range().arg(ArgType::Text),
+    Command::new("nunmap", nunmap).aliases(&["nun"]).arg(ArgType::Text),
+    Command::new("oldfiles", oldfiles).aliases(&["ol"]).arg(ArgType::Text),
+    Command::new("only", only).aliases(&["on"]),
+    Command::new("pwd", pwd),
+    Command::new("quit", quit).aliases(&["q"]),
+    Command::new("retab", retab).aliases(&["ret"]).range().arg(ArgType::Text),
+    Command::new("set", set).arg(ArgType::Option),
+    Command::new("setlocal", setlocal).aliases(&["setl"]).arg(ArgType::Option),
+    Command::new("split", split).aliases(&["sp"]),
+    Command::new("substitute", substitute).aliases(&["s"]).range().arg(ArgType::Text),
+    Command::new("unmap", unmap).aliases(&["unm"]).arg(ArgType::Text),
+    Command::new("view", view).aliases(&["vie"]).arg(ArgType::File),
+    Command::new("vmap", vmap).aliases(&["vm"]).arg(ArgType::Text),
+    Command::new("vnoremap", vnoremap).aliases(&["vn"]).arg(ArgType::Text),
+    Command::new("vsplit", vsplit).aliases(&["vs"]),
+    Command::new("vunmap", vunmap).aliases(&["vu"]).arg(ArgType::Text),
+    Command::new("write", write).aliases(&["w"]).arg(ArgType::File),
+];
+
+
+// exact name or alias, otherwise the first command the input is a prefix of
+pub fn match_cmd(input: &str) -> Option<&'static Command> {
+    if let Some(command) = COMMANDS.iter().find(|command| command.name == input || command.aliases.contains(&input)) {
+        return Some(command)
+    }
+
+    COMMANDS.iter().find(|command| command.name.starts_with(input))
+}
+
+// leaves out the files and folders wildignore matches
+fn complete_files(state: &State, editor: &Editor, word: &str) -> Vec<String> {
+    let (dir, file) = match word.rfind('/') {
+        Some(i) => (&word[..(i + 1)], &word[(i + 1)..]),
+        None => ("", word),
     };
+    let Ok(entries) = fs::read_dir(editor.dir().join(dir)) else { return Vec::new() };
+
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.path().is_dir();
+            let slash = if is_dir { "/" } else { "" };
+            let path = format!("{dir}{name}{slash}");
+            (name.starts_with(file) && !ignore::is_ignored(&state.options.wildignore, &path, is_dir)).then_some(path)
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+
+    files
 }
 
+fn complete_buffers(_: &State, editor: &Editor, word: &str) -> Vec<String> {
+    editor.buffers.iter()
+        .filter_map(|buffer| buffer.file_path.as_ref())
+        .map(|path| path.display().to_string())
+        .filter(|path| path.contains(word))
+        .collect()
+}
+
+fn complete_options(_: &State, _: &Editor, word: &str) -> Vec<String> {
+    Options::NAMES.iter()
+        .filter(|option| option.starts_with(word))
+        .map(|option| option.to_string())
+        .collect()
+}
 
-// keep this sorted
-lookup_table! {
-    "e" => edit,
-    "edit" => edit,
-    "q" => quit,
-    "quit" => quit,
-    "w" => write,
-    "write" => write,
+// the candidates of a tab completion, shown above the command bar like vim's
+// wildmenu. nothing is selected until tab is pressed again
+pub struct Wildmenu {
+    // the input before the word being completed
+    pub prefix: String,
+    pub candidates: Vec<String>,
+    pub selected: Option<usize>,
 }
 
+impl Wildmenu {
+    // moves the selection by step, wrapping around, returns the input with the selected candidate
+    pub fn step(&mut self, step: isize) -> String {
+        let len = self.candidates.len() as isize;
+        let next = match self.selected {
+            Some(i) => (i as isize + step).rem_euclid(len),
+            None if step > 0 => 0,
+            None => len - 1,
+        } as usize;
+        self.selected = Some(next);
 
-pub fn match_cmd(input: &str) -> Option<BarFn> {
-    let n = NAMES.binary_search(&input);
-    let n = match n {
-        Ok(n) => return Some(FUNCTIONS[n]),
-        Err(n) => n,
+        format!("{}{}", self.prefix, self.candidates[next])
+    }
+
+    // the candidates that fit on a row of cols cells with the selected one, and
+    // the col each starts at. the row scrolls a page at a time
+    pub fn visible(&self, cols: usize) -> Vec<(usize, usize)> {
+        let mut pages = vec![Vec::new()];
+        let mut col = 0;
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            let cells = width::str_width(candidate);
+            if col > 0 && col + cells > cols {
+                pages.push(Vec::new());
+                col = 0;
+            }
+            pages.last_mut().unwrap().push((i, col));
+            col += cells + 2;
+        }
+
+        let selected = self.selected.unwrap_or(0);
+        pages.into_iter().find(|page| page.iter().any(|&(i, _)| i == selected)).unwrap_or_default()
+    }
+}
+
+// the completed input, and a wildmenu when there's more than one candidate
+pub fn complete(input: &str, state: &State, editor: &Editor) -> Option<(String, Option<Wildmenu>)> {
+    let (name, args) = split_command(input);
+    let (prefix, word, candidates) = if name.len() == input.len() {
+        let names = COMMANDS.iter().map(|command| command.name)
+            .chain(editor.user_commands.iter().map(|command| command.name.as_str()))
+            .filter(|command| command.starts_with(name))
+            .map(|command| command.to_owned());
+        ("", name, names.collect::<Vec<_>>())
+    } else {
+        // completes the last word of the arguments
+        let word_start = input.len() - args.len() + args.rfind(' ').map_or(0, |i| i + 1);
+        let word = &input[word_start..];
+        let candidates = match_cmd(name)?.complete?(state, editor, word);
+        (&input[..word_start], word, candidates)
+    };
+
+    let completed = match candidates.as_slice() {
+        [] => return None,
+        [only] => only.clone(),
+        [first, rest @ ..] => {
+            let mut len = first.len();
+            for other in rest {
+                len = len.min(first.bytes().zip(other.bytes()).take_while(|(a, b)| a == b).count());
+            }
+            while !first.is_char_boundary(len) {
+                len -= 1;
+            }
+            // buffer names match anywhere so the common prefix can be shorter than what's typed
+            if len < word.len() { word.to_owned() } else { first[..len].to_owned() }
+        },
+    };
+
+    let wildmenu = (candidates.len() > 1).then(|| Wildmenu { prefix: prefix.to_owned(), candidates, selected: None });
+    Some((format!("{prefix}{completed}"), wildmenu))
+}
+
+
+// parses "%", "N", "N,M" where N and M can also be ".", "$" or the visual
+// selection's "'<" and "'>", each followed by any "+N" and "-N". returns rest of the input
+pub fn parse_range(input: &str, cursor_line: usize, total_lines: usize, marks: Option<(usize, usize)>) -> (Option<LineRange>, &str) {
+    if let Some(rest) = input.strip_prefix('%') {
+        return (Some(LineRange { start: 0, end: total_lines - 1 }), rest)
+    }
+
+    let context = (cursor_line, total_lines, marks);
+    let Some((start, rest)) = parse_address(input, context) else { return (None, input) };
+    let (end, rest) = if let Some(after_comma) = rest.strip_prefix(',') {
+        parse_address(after_comma, context).unwrap_or((start, after_comma))
+    } else {
+        (start, rest)
     };
 
-    if NAMES[n].starts_with(input) {
-        return Some(FUNCTIONS[n]);
+    (Some(LineRange { start: start.min(end), end: start.max(end) }), rest)
+}
+
+// a lone offset like "+2" is from the cursor line
+fn parse_address(input: &str, (cursor_line, total_lines, marks): (usize, usize, Option<(usize, usize)>)) -> Option<(usize, &str)> {
+    let (line, mut rest) = if let Some(rest) = input.strip_prefix('.') {
+        (cursor_line as isize, rest)
+    } else if let Some(rest) = input.strip_prefix('$') {
+        (total_lines as isize - 1, rest)
+    } else if let Some(rest) = input.strip_prefix("'<") {
+        (marks?.0 as isize, rest)
+    } else if let Some(rest) = input.strip_prefix("'>") {
+        (marks?.1 as isize, rest)
+    } else if input.starts_with(['+', '-']) {
+        (cursor_line as isize, input)
+    } else {
+        let digits = input.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 {
+            return None
+        }
+        (input[..digits].parse::<isize>().ok()?.max(1) - 1, &input[digits..])
+    };
+
+    let mut line = line;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let digits = rest[1..].chars().take_while(|c| c.is_ascii_digit()).count();
+        let by = if digits == 0 { 1 } else { rest[1..(1 + digits)].parse::<isize>().ok()? };
+        line += if sign == '+' { by } else { -by };
+        rest = &rest[(1 + digits)..];
     }
 
-    None
+    Some((line.clamp(0, total_lines as isize - 1) as usize, rest))
 }
 
+// splits "name args" into its parts, "!" is a command name on its own
+pub fn split_command(input: &str) -> (&str, &str) {
+    if let Some(rest) = input.strip_prefix('!') {
+        return ("!", rest.trim())
+    }
 
+    let name_len = input.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+    (&input[..name_len], input[name_len..].trim())
+}
 
-fn write(_: &mut State, editor: &Editor, args: &str) -> Result {
+
+
+fn write(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
     let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
-    let view = buffer.full_view();
-    let Some(file_path) = &buffer.file_path else { return Err(()) };
-    let mut file = std::fs::File::create(file_path).unwrap();
-    match view {
-        LineView::Contiguous(s) => {
-            file.write_all(s.as_bytes()).unwrap();
-        },
-        LineView::Parts(s1, s2) => {
-            file.write_all(s1.as_bytes()).unwrap();
-            file.write_all(s2.as_bytes()).unwrap();
+    if !args.trim().is_empty() {
+        return Ok(CommandBarAction::SaveAs(editor.dir().join(args.trim())))
+    }
+    if buffer.file_path.is_none() {
+        return Ok(CommandBarAction::Message("No file name, use :w {path}".to_owned()))
+    }
+
+    Ok(CommandBarAction::Save)
+}
+
+fn delete(_: &mut State, editor: &Editor, range: Option<LineRange>, _: &str) -> Result {
+    let Some(cursor) = editor.cursors.get(editor.current_buffer) else { return Err(()) };
+    let line = cursor.y - 1;
+    Ok(CommandBarAction::DeleteLines(range.unwrap_or(LineRange { start: line, end: line })))
+}
+
+// an empty buffer without a file
+fn enew(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::NewBuffer(TextBuffer::from_data(next_buffer_id(), Vec::new())))
+}
+
+// pipes the buffer through the formatter given as args, formatprg or the file type's default
+fn format(state: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let formatter = if args.is_empty() {
+        format::formatter_for(&state.options.formatprg, buffer.file_path.as_deref())
+    } else {
+        Some(format::Formatter::Shell(args.to_owned()))
+    };
+    let Some(formatter) = formatter else {
+        return Ok(CommandBarAction::Message("No formatter for this file".to_owned()))
+    };
+    if editor.no_shell && matches!(formatter, format::Formatter::Shell(_)) {
+        return Ok(CommandBarAction::Message(SHELL_REFUSED.to_owned()))
+    }
+
+    match format::run(&formatter, buffer) {
+        Ok(text) => Ok(CommandBarAction::ReplaceBuffer(text)),
+        Err(e) => Ok(CommandBarAction::Message(e)),
+    }
+}
+
+// :cd {folder} changes the folder relative paths are from, back to the
+// project root without one. :lcd does it only while the current buffer is
+// shown
+fn cd(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    change_dir(editor, args, false)
+}
+
+fn lcd(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    change_dir(editor, args, true)
+}
+
+fn change_dir(editor: &Editor, args: &str, local: bool) -> Result {
+    if args.trim().is_empty() {
+        return Ok(CommandBarAction::ChangeDir(editor.root_folder.clone(), local))
+    }
+    let path = editor.buffers.get(editor.current_buffer).and_then(|buffer| buffer.file_path.as_deref());
+    Ok(CommandBarAction::ChangeDir(PathBuf::from(expand_filename(args.trim(), path)?), local))
+}
+
+fn pwd(_: &mut State, editor: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::Message(editor.dir().display().to_string()))
+}
+
+// % in an argument is the current file, followed by vim's modifiers: :p for
+// the full path, :h its folder, :t its name, :r without the extension and :e
+// only the extension. %% is a plain %
+fn expand_filename(arg: &str, path: Option<&Path>) -> std::result::Result<String, ()> {
+    let mut expanded = String::new();
+    let mut rest = arg;
+    while let Some(i) = rest.find('%') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            expanded.push('%');
+            rest = after;
+            continue
+        }
+
+        let mut path = path.ok_or(())?.to_path_buf();
+        while let Some(modifier) = rest.strip_prefix(':').and_then(|after| after.chars().next()) {
+            path = match modifier {
+                'p' => std::path::absolute(&path).map_err(|_| ())?,
+                'h' => path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf(),
+                't' => PathBuf::from(path.file_name().unwrap_or_default()),
+                'r' => path.with_extension(""),
+                'e' => PathBuf::from(path.extension().unwrap_or_default()),
+                _ => break,
+            };
+            rest = &rest[2..];
+        }
+        expanded.push_str(&path.display().to_string());
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+// the recent files numbered for :e #N, the ones with text in their path if
+// there's any
+fn oldfiles(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let list = editor.recent.files.iter().enumerate()
+        .map(|(i, file)| (i + 1, file.path.display().to_string()))
+        .filter(|(_, path)| path.contains(args))
+        .map(|(n, path)| format!("{n}: {path}\n"))
+        .collect::<String>();
+    if list.is_empty() {
+        return Ok(CommandBarAction::Message("No recent files".to_owned()))
+    }
+
+    Ok(CommandBarAction::NewBuffer(TextBuffer::from_data(next_buffer_id(), list.into_bytes())))
+}
+
+// the log's recent entries, oldest first
+fn messages(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    let entries = log::entries();
+    if entries.is_empty() {
+        return Ok(CommandBarAction::Message("No messages".to_owned()))
+    }
+
+    let list = entries.iter().map(|entry| format!("{entry}\n")).collect::<String>();
+    Ok(CommandBarAction::NewBuffer(TextBuffer::from_data(next_buffer_id(), list.into_bytes())))
+}
+
+// lists recent deletes, with a number puts that one below the cursor line
+fn deleted_lines(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    if editor.deleted.is_empty() {
+        return Ok(CommandBarAction::Message("No deleted text".to_owned()))
+    }
+
+    if args.is_empty() {
+        let mut list = String::new();
+        for (i, text) in editor.deleted.iter().enumerate() {
+            let lines = text.trim_end_matches('\n').lines().count().max(1);
+            let first = text.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+            list.push_str(&format!("{}: ({} lines) {}\n", i + 1, lines, first.trim()));
+        }
+        let buffer = TextBuffer::from_data(next_buffer_id(), list.into_bytes());
+        return Ok(CommandBarAction::NewBuffer(buffer))
+    }
+
+    let n = args.parse::<usize>().map_err(|_| ())?;
+    let Some(text) = n.checked_sub(1).and_then(|i| editor.deleted.get(i)) else { return Err(()) };
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let Some(cursor) = editor.cursors.get(editor.current_buffer) else { return Err(()) };
+    let line = cursor.y - 1;
+
+    let mut new_text = buffer.line(line);
+    new_text.push('\n');
+    new_text.push_str(text.trim_end_matches('\n'));
+    new_text.push('\n');
+    Ok(CommandBarAction::ReplaceLines(LineRange { start: line, end: line }, new_text))
+}
+
+// author, date and summary of the commit that last changed the cursor line
+fn blame(_: &mut State, editor: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let Some(cursor) = editor.cursors.get(editor.current_buffer) else { return Err(()) };
+
+    match blame::blame_line(buffer, cursor.y - 1) {
+        Ok(blame) => Ok(CommandBarAction::Message(blame.describe())),
+        Err(e) => Ok(CommandBarAction::Message(e)),
+    }
+}
+
+// the file goes in a window to the left and both windows mark what changed.
+// without a file, or with the current buffer's own, the current buffer is
+// compared with what was last saved, read into a new readonly buffer
+fn diff_split(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let path = if args.is_empty() {
+        buffer.file_path.clone().ok_or(())?
+    } else {
+        editor.dir().join(args)
+    };
+    if !path.is_file() {
+        return Ok(CommandBarAction::Message(format!("Can't read {}", path.display())))
+    }
+
+    let wanted = path.canonicalize().ok();
+    let open = editor.buffers.iter().position(|buffer| buffer.file_path.as_ref().and_then(|path| path.canonicalize().ok()) == wanted);
+    match open {
+        Some(i) if i != editor.current_buffer => Ok(CommandBarAction::DiffSplit(DiffWith::Buffer(i))),
+        open => match TextBuffer::from_path(next_buffer_id(), &path) {
+            Ok(mut saved) => {
+                saved.readonly |= open.is_some();
+                Ok(CommandBarAction::DiffSplit(DiffWith::File(saved)))
+            },
+            Err(e) => Ok(CommandBarAction::Message(format!("Can't read {}: {e}", path.display()))),
         },
     }
+}
 
-    Ok(CommandBarAction::None)
+fn diff_off(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::DiffOff)
+}
+
+// like :edit but a new buffer is read-only, an open one is only switched to
+fn view(state: &mut State, editor: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    match edit(state, editor, range, args)? {
+        CommandBarAction::NewBuffer(mut buffer) => {
+            buffer.readonly = true;
+            Ok(CommandBarAction::NewBuffer(buffer))
+        },
+        action => Ok(action),
+    }
 }
 
-fn edit(_: &mut State, editor: &Editor, args: &str) -> Result {
+fn edit(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let expanded = expand_filename(args, editor.buffers.get(editor.current_buffer).and_then(|buffer| buffer.file_path.as_deref()))?;
+    let args = expanded.as_str();
+    // #N is the Nth file :oldfiles lists
+    let recent = match args.strip_prefix('#') {
+        Some(n) => {
+            let n = n.parse::<usize>().map_err(|_| ())?;
+            let Some(file) = n.checked_sub(1).and_then(|i| editor.recent.files.get(i)) else { return Err(()) };
+            Some(file.path.display().to_string())
+        },
+        None => None,
+    };
+    let args = recent.as_deref().unwrap_or(args);
+    let wanted = editor.dir().join(args).canonicalize().ok();
+
     for (i, buffer) in editor.buffers.iter().enumerate() {
         let Some(path) = &buffer.file_path else { continue };
-        if let Some(path) = path.as_os_str().to_str() {
-            if path == args {
-                return Ok(CommandBarAction::SwitchToBuffer(i))
-            }
+        if path.as_os_str().to_str() == Some(args) || (wanted.is_some() && path.canonicalize().ok() == wanted) {
+            return Ok(CommandBarAction::SwitchToBuffer(i))
         }
     }
 
     if args.len() > 0 {
-        let buffer = TextBuffer::from_path(next_buffer_id(), Path::new(args));
-        return Ok(CommandBarAction::NewBuffer(buffer))
+        // kept absolute so :cd doesn't change which file it is
+        let path = editor.dir().join(args);
+        return match TextBuffer::from_path(next_buffer_id(), &path) {
+            Ok(buffer) => Ok(CommandBarAction::NewBuffer(buffer)),
+            Err(e) => Ok(CommandBarAction::Message(format!("Can't open {args}: {e}"))),
+        }
     }
 
     Ok(CommandBarAction::None)
 }
 
-fn quit(_: &mut State, _: &Editor, _: &str) -> Result {
+// switches by buffer number or by a part of its path
+fn buffer(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    if let Ok(n) = args.parse::<usize>() {
+        if n == 0 || n > editor.buffers.len() {
+            return Err(())
+        }
+        return Ok(CommandBarAction::SwitchToBuffer(n - 1))
+    }
+
+    let matches = editor.buffers.iter().enumerate()
+        .filter(|(_, buffer)| buffer.file_path.as_ref().is_some_and(|path| path.display().to_string().contains(args)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    match matches.as_slice() {
+        [i] => Ok(CommandBarAction::SwitchToBuffer(*i)),
+        [] => Ok(CommandBarAction::Message(format!("No matching buffer for {args}"))),
+        _ => Ok(CommandBarAction::Message(format!("More than one match for {args}"))),
+    }
+}
+
+// ":command Name body" defines a command, without args lists them
+fn command(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    if args.is_empty() {
+        if editor.user_commands.is_empty() {
+            return Ok(CommandBarAction::Message("No user commands".to_owned()))
+        }
+        let list = editor.user_commands.iter().map(|command| format!("{}  {}\n", command.name, command.body)).collect::<String>();
+        let buffer = TextBuffer::from_data(next_buffer_id(), list.into_bytes());
+        return Ok(CommandBarAction::NewBuffer(buffer))
+    }
+
+    let (name, body) = args.split_once(' ').unwrap_or((args, ""));
+    let body = body.trim();
+    if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Ok(CommandBarAction::Message("User command names are letters and start with an uppercase letter".to_owned()))
+    }
+    if COMMANDS.iter().any(|command| command.name == name || command.aliases.contains(&name)) {
+        return Ok(CommandBarAction::Message(format!("Can't redefine :{name}")))
+    }
+    if body.is_empty() {
+        return Err(())
+    }
+
+    Ok(CommandBarAction::DefineCommand(UserCommand { name: name.to_owned(), body: body.to_owned() }))
+}
+
+const NORMAL_VISUAL: &[MapMode] = &[MapMode::Normal, MapMode::Visual];
+
+fn map(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, NORMAL_VISUAL, false)
+}
+
+fn nmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Normal], false)
+}
+
+fn vmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Visual], false)
+}
+
+fn imap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Insert], false)
+}
+
+fn noremap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, NORMAL_VISUAL, true)
+}
+
+fn nnoremap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Normal], true)
+}
+
+fn vnoremap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Visual], true)
+}
+
+fn inoremap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    define_map(editor, args, &[MapMode::Insert], true)
+}
+
+fn unmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    remove_map(editor, args, NORMAL_VISUAL)
+}
+
+fn nunmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    remove_map(editor, args, &[MapMode::Normal])
+}
+
+fn vunmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    remove_map(editor, args, &[MapMode::Visual])
+}
+
+fn iunmap(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    remove_map(editor, args, &[MapMode::Insert])
+}
+
+// "{lhs} {rhs}" in vim's key notation maps lhs in modes, "{lhs}" alone lists
+// the maps starting with it and nothing lists them all. a * marks noremap
+fn define_map(editor: &Editor, args: &str, modes: &[MapMode], noremap: bool) -> Result {
+    let (lhs, rhs) = args.split_once(' ').unwrap_or((args, ""));
+    let (lhs, rhs) = (keys::parse(lhs), rhs.trim());
+    if rhs.is_empty() {
+        let list = editor.key_maps.iter()
+            .filter(|map| modes.contains(&map.mode) && map.lhs.starts_with(&lhs))
+            .map(|map| format!("{}  {}  {}{}\n", map.mode.letter(), keys::to_notation(&map.lhs), if map.noremap { "*" } else { "" }, map.rhs))
+            .collect::<String>();
+        if list.is_empty() {
+            return Ok(CommandBarAction::Message("No mapping found".to_owned()))
+        }
+        let buffer = TextBuffer::from_data(next_buffer_id(), list.into_bytes());
+        return Ok(CommandBarAction::NewBuffer(buffer))
+    }
+
+    Ok(CommandBarAction::Map(modes.iter().map(|&mode| KeyMap { mode, lhs: lhs.clone(), rhs: rhs.to_owned(), noremap }).collect()))
+}
+
+fn remove_map(editor: &Editor, args: &str, modes: &[MapMode]) -> Result {
+    let lhs = keys::parse(args);
+    if lhs.is_empty() {
+        return Err(())
+    }
+    if !editor.key_maps.iter().any(|map| modes.contains(&map.mode) && map.lhs == lhs) {
+        return Ok(CommandBarAction::Message(format!("No such mapping: {args}")))
+    }
+
+    Ok(CommandBarAction::Unmap(modes.to_vec(), lhs))
+}
+
+fn normal(_: &mut State, _: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    if args.is_empty() {
+        return Err(())
+    }
+
+    Ok(CommandBarAction::Normal(range, args.to_owned()))
+}
+
+// closes the window when there are a few
+fn quit(_: &mut State, editor: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    if editor.layout.is_split() {
+        return Ok(CommandBarAction::Window(WindowCommand::Close))
+    }
     SHOULD_QUIT.store(true, Ordering::Relaxed);
     Ok(CommandBarAction::None)
 }
+
+fn split(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::Window(WindowCommand::Split(Axis::Rows)))
+}
+
+fn vsplit(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::Window(WindowCommand::Split(Axis::Cols)))
+}
+
+fn close(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::Window(WindowCommand::Close))
+}
+
+fn only(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::Window(WindowCommand::Only))
+}
+
+fn quickfix_next(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::QuickfixMove(1))
+}
+
+fn quickfix_previous(_: &mut State, _: &Editor, _: Option<LineRange>, _: &str) -> Result {
+    Ok(CommandBarAction::QuickfixMove(-1))
+}
+
+// byte count is 1-indexed like in vim
+fn goto(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let byte = if args.is_empty() {
+        1
+    } else {
+        args.trim().parse::<usize>().map_err(|_| ())?
+    };
+
+    // line separators aren't valid cursor positions
+    let pos = buffer.clamp_to_char(buffer.byte_to_linepos(byte.max(1) - 1));
+
+    Ok(CommandBarAction::MoveCursor(pos))
+}
+
+// like in vim the current buffer gets the new value too, also over a :setlocal
+// or detected one
+fn set(state: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let mut local = editor.buffers.get(editor.current_buffer).map(|buffer| buffer.options.clone());
+    for arg in split_set_args(args) {
+        state.options.set(&arg)?;
+        if let Some(local) = &mut local {
+            local.clear(&arg);
+        }
+    }
+
+    Ok(local.map_or(CommandBarAction::None, CommandBarAction::SetLocal))
+}
+
+fn setlocal(_: &mut State, editor: &Editor, _: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let mut options = buffer.options.clone();
+    for arg in split_set_args(args) {
+        options.set(&arg)?;
+    }
+
+    Ok(CommandBarAction::SetLocal(options))
+}
+
+// splits at whitespace, "\ " is a space inside a value like in vim
+fn split_set_args(args: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut chars = args.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some(next) if next.is_whitespace() => current.push(next),
+                Some(next) => { current.push('\\'); current.push(next) },
+                None => current.push('\\'),
+            },
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    out.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        out.push(current);
+    }
+
+    out
+}
+
+// without a range runs the command, with a range filters those lines through it
+fn shell(_: &mut State, editor: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    if args.is_empty() {
+        return Err(())
+    }
+
+    if let Some(range) = range {
+        let mut input = String::new();
+        for line in range.start..(range.end + 1) {
+            input.push_str(&buffer.raw_line(line));
+        }
+
+        return match shell::filter(args, &input) {
+            Ok(output) => Ok(CommandBarAction::ReplaceLines(range, output)),
+            Err(e) => Ok(CommandBarAction::Message(e)),
+        }
+    }
+
+    match shell::run(args) {
+        Ok(output) if output.trim_end().contains('\n') => {
+            let buffer = TextBuffer::from_data(next_buffer_id(), output.into_bytes());
+            Ok(CommandBarAction::NewBuffer(buffer))
+        },
+        Ok(output) => Ok(CommandBarAction::Message(output.trim_end().to_owned())),
+        Err(e) => Ok(CommandBarAction::Message(e)),
+    }
+}
+
+// :retab [tabstop] redoes the leading blanks of the range or the whole buffer
+// with tabs or spaces as expandtab says. a new tabstop is set after the old one
+// is used to measure the indents
+fn retab(state: &mut State, editor: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let new_tabstop = match args.trim() {
+        "" => state.options.tabstop,
+        n => n.parse::<usize>().ok().filter(|&n| n > 0).ok_or(())?,
+    };
+    let expandtab = state.options.expandtab(&buffer.options);
+    let lines = range.map_or(0..buffer.total_lines(), |range| range.start..range.end + 1);
+
+    let mut text = String::new();
+    let mut changed = false;
+    for line in lines {
+        let content = buffer.raw_line(line);
+        let blanks = content.len() - content.trim_start_matches([' ', '\t']).len();
+        let indent = indent_text(indent_width(&content, state.options.tabstop), expandtab, new_tabstop);
+        changed |= content[..blanks] != indent;
+        text.push_str(&indent);
+        text.push_str(&content[blanks..]);
+    }
+
+    if !changed {
+        state.options.tabstop = new_tabstop;
+        return Ok(CommandBarAction::None)
+    }
+    Ok(CommandBarAction::Retab(range, text, new_tabstop))
+}
+
+fn substitute(_: &mut State, editor: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    substitute_lines(editor, range, args, false)
+}
+
+// like :s but the replacement follows the case of each match
+fn substitute_preserve_case(_: &mut State, editor: &Editor, range: Option<LineRange>, args: &str) -> Result {
+    substitute_lines(editor, range, args, true)
+}
+
+fn substitute_lines(editor: &Editor, range: Option<LineRange>, args: &str, preserve_case: bool) -> Result {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(()) };
+    let Some(cursor) = editor.cursors.get(editor.current_buffer) else { return Err(()) };
+    let range = range.unwrap_or(LineRange { start: cursor.y - 1, end: cursor.y - 1 });
+
+    let sub = Substitute::parse(args, preserve_case)?;
+
+    let mut text = String::new();
+    let mut found = false;
+    for line in range.start..(range.end + 1) {
+        let content = buffer.line(line);
+        if let Some(replaced) = sub.apply(&content) {
+            text.push_str(&replaced);
+            found = true;
+        } else {
+            text.push_str(&content);
+        }
+        text.push('\n');
+    }
+
+    if !found {
+        return Ok(CommandBarAction::Message(format!("Pattern not found: {}", sub.pattern)))
+    }
+
+    Ok(CommandBarAction::ReplaceLines(range, text))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_cmd() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].name < pair[1].name));
+
+        assert_eq!(match_cmd("s").unwrap().name, "substitute");
+        assert_eq!(match_cmd("se").unwrap().name, "set");
+        assert_eq!(match_cmd("b").unwrap().name, "buffer");
+        assert_eq!(match_cmd("bl").unwrap().name, "blame");
+        assert_eq!(match_cmd("cp").unwrap().name, "cprevious");
+        assert!(match_cmd("x").is_none());
+    }
+
+    #[test]
+    fn test_wildmenu() {
+        let candidates = ["edit", "enew", "e漢字"].map(str::to_owned).to_vec();
+        let mut wildmenu = Wildmenu { prefix: "set ".to_owned(), candidates, selected: None };
+        assert_eq!(wildmenu.visible(11), [(0, 0), (1, 6)]);
+        assert_eq!(wildmenu.step(-1), "set e漢字");
+        assert_eq!(wildmenu.visible(11), [(2, 0)]);
+        assert_eq!(wildmenu.step(1), "set edit");
+        assert_eq!(wildmenu.step(1), "set enew");
+        assert_eq!(wildmenu.visible(100), [(0, 0), (1, 6), (2, 12)]);
+    }
+
+    #[test]
+    fn test_expand_filename() {
+        let path = Some(Path::new("src/editor.rs"));
+        assert_eq!(expand_filename("%", path), Ok("src/editor.rs".to_owned()));
+        assert_eq!(expand_filename("%:h/lib.rs", path), Ok("src/lib.rs".to_owned()));
+        assert_eq!(expand_filename("%:t:r.txt", path), Ok("editor.txt".to_owned()));
+        assert_eq!(expand_filename("%:e 100%%", path), Ok("rs 100%".to_owned()));
+        assert_eq!(expand_filename("%:h:h", path), Ok(".".to_owned()));
+        assert_eq!(expand_filename("%:x", path), Ok("src/editor.rs:x".to_owned()));
+        assert_eq!(expand_filename("%", None), Err(()));
+        assert_eq!(expand_filename("plain", None), Ok("plain".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let range = |start, end| Some(LineRange { start, end });
+        assert_eq!(parse_range("10,20d", 4, 100, None), (range(9, 19), "d"));
+        assert_eq!(parse_range(".,$y", 4, 100, None), (range(4, 99), "y"));
+        assert_eq!(parse_range("%s/a/b/", 4, 100, None), (range(0, 99), "s/a/b/"));
+        assert_eq!(parse_range("'<,'>s/a/b/", 4, 100, Some((2, 7))), (range(2, 7), "s/a/b/"));
+        assert_eq!(parse_range("'<,'>d", 4, 100, None), (None, "'<,'>d"));
+
+        // offsets, alone they're from the cursor
+        assert_eq!(parse_range(".+2,$-1d", 4, 100, None), (range(6, 98), "d"));
+        assert_eq!(parse_range("-,+d", 4, 100, None), (range(3, 5), "d"));
+        assert_eq!(parse_range("'>+1", 4, 100, Some((2, 7))), (range(8, 8), ""));
+        assert_eq!(parse_range("1-5,500", 4, 100, None), (range(0, 99), ""));
+        assert_eq!(parse_range("s/a/b/", 4, 100, None), (None, "s/a/b/"));
+    }
+}
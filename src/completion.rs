@@ -0,0 +1,130 @@
+use crate::{gap_buffer::{LinePos, TextBuffer}, lsp::TextEdit, vim_commands::is_letter};
+
+
+pub struct CompletionItem {
+    pub label: String,
+    pub text: String,
+    // what the item is matched against while typing
+    pub filter: String,
+    // start of the text the item replaces, the start of the word when None
+    pub replace_start: Option<LinePos>,
+    // chars after the cursor the item replaces too
+    pub replace_after: usize,
+    // edits away from the cursor that come with the item, like adding an import
+    pub additional_edits: Vec<TextEdit>,
+}
+
+impl CompletionItem {
+    pub fn new(label: &str) -> Self {
+        Self { label: label.to_owned(), text: label.to_owned(), filter: label.to_owned(), replace_start: None, replace_after: 0, additional_edits: Vec::new() }
+    }
+}
+
+pub struct CompletionMenu {
+    pub items: Vec<CompletionItem>,
+    // indexes into items that match what has been typed so far
+    pub shown: Vec<usize>,
+    pub selected: usize,
+    // start of the word being completed
+    pub start: LinePos,
+}
+
+pub const MAX_SHOWN_ITEMS: usize = 10;
+
+impl CompletionMenu {
+    pub fn new(items: Vec<CompletionItem>, start: LinePos, typed: &str) -> Option<Self> {
+        let mut menu = Self { items, shown: Vec::new(), selected: 0, start };
+        menu.filter(typed);
+        if menu.shown.is_empty() {
+            return None
+        }
+
+        Some(menu)
+    }
+
+    pub fn filter(&mut self, typed: &str) {
+        let typed = typed.to_lowercase();
+        self.shown = (0..self.items.len())
+            .filter(|i| self.items[*i].filter.to_lowercase().starts_with(&typed))
+            .collect();
+        self.selected = self.selected.min(self.shown.len().saturating_sub(1));
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.shown.is_empty() {
+            self.selected = (self.selected + 1) % self.shown.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.shown.is_empty() {
+            self.selected = (self.selected + self.shown.len() - 1) % self.shown.len();
+        }
+    }
+
+    pub fn selected_item(&self) -> Option<&CompletionItem> {
+        self.shown.get(self.selected).map(|i| &self.items[*i])
+    }
+
+    // the window of shown items around the selection, with the index of the first one
+    pub fn visible_items(&self) -> (usize, &[usize]) {
+        let first = self.selected.saturating_sub(MAX_SHOWN_ITEMS - 1);
+        let end = (first + MAX_SHOWN_ITEMS).min(self.shown.len());
+        (first, &self.shown[first..end])
+    }
+}
+
+// start of the word that ends at pos
+pub fn word_start(buffer: &TextBuffer, pos: LinePos) -> LinePos {
+    let line = buffer.line(pos.line).chars().collect::<Vec<_>>();
+    let mut col = pos.col.min(line.len());
+    while col > 0 && is_letter(line[col - 1]) {
+        col -= 1;
+    }
+
+    LinePos { line: pos.line, col }
+}
+
+// words in the buffer starting with prefix, closest to the cursor line first
+pub fn buffer_words(buffer: &TextBuffer, prefix: &str, cursor: LinePos) -> Vec<CompletionItem> {
+    let total = buffer.total_lines();
+    let mut lines = (0..total).collect::<Vec<_>>();
+    lines.sort_by_key(|line| line.abs_diff(cursor.line));
+
+    let mut words: Vec<String> = Vec::new();
+    for line in lines {
+        let text = buffer.line(line);
+        for word in text.split(|c: char| !is_letter(c)) {
+            if word.len() > prefix.len() && word.starts_with(prefix) && !words.iter().any(|w| w == word) {
+                words.push(word.to_owned());
+            }
+        }
+    }
+
+    words.iter().map(|word| CompletionItem::new(word)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_words() {
+        let buffer = TextBuffer::from_data(0, b"fn foo_bar() {}\nlet food = foo_bar;\nfoo".to_vec());
+        let cursor = LinePos::new(2, 3);
+        let start = word_start(&buffer, cursor);
+        assert_eq!(start, LinePos::new(2, 0));
+
+        let words = buffer_words(&buffer, "foo", cursor);
+        let labels = words.iter().map(|item| item.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["food", "foo_bar"]);
+
+        let mut menu = CompletionMenu::new(words, start, "foo").unwrap();
+        menu.select_next();
+        assert_eq!(menu.selected_item().unwrap().label, "foo_bar");
+        menu.filter("foo_");
+        assert_eq!(menu.shown, [1]);
+        assert!(CompletionMenu::new(Vec::new(), start, "").is_none());
+    }
+}
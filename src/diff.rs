@@ -0,0 +1,279 @@
+use std::{ops::Range, time::Instant};
+
+use crate::gap_buffer::{LinePos, TextBuffer};
+
+
+// old lines that were replaced by new lines, either range can be empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl Hunk {
+    // the lines on side, 0 for old and 1 for new, and the ones on the other side
+    pub fn sides(&self, side: usize) -> (&Range<usize>, &Range<usize>) {
+        if side == 0 { (&self.old, &self.new) } else { (&self.new, &self.old) }
+    }
+}
+
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    // most changes are small, only diff what's between the common start and end
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let a_mid = &a[prefix..(a.len() - suffix)];
+    let b_mid = &b[prefix..(b.len() - suffix)];
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut matches = common_lines(a_mid, b_mid);
+    matches.push((a_mid.len(), b_mid.len()));
+    for (x, y) in matches {
+        if x > i || y > j {
+            hunks.push(Hunk { old: (prefix + i)..(prefix + x), new: (prefix + j)..(prefix + y) });
+        }
+        i = x + 1;
+        j = y + 1;
+    }
+
+    hunks
+}
+
+// myers' algorithm in linear space, returns the index pairs of lines that stay the same
+fn common_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    find_common(a, b, 0, 0, &mut matches);
+    matches
+}
+
+// splits the diff at its middle snake and does both halves the same way,
+// a_start and b_start are where a and b begin in the whole sequences
+fn find_common<T: PartialEq>(a: &[T], b: &[T], a_start: usize, b_start: usize, matches: &mut Vec<(usize, usize)>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    matches.extend((0..prefix).map(|i| (a_start + i, b_start + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let (a_start, b_start) = (a_start + prefix, b_start + prefix);
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[..(a.len() - suffix)], &b[..(b.len() - suffix)]);
+
+    if !a.is_empty() && !b.is_empty() {
+        let (x, y, u, v) = middle_snake(a, b);
+        find_common(&a[..x], &b[..y], a_start, b_start, matches);
+        matches.extend((0..(u - x)).map(|i| (a_start + x + i, b_start + y + i)));
+        find_common(&a[u..], &b[v..], a_start + u, b_start + v, matches);
+    }
+    matches.extend((0..suffix).map(|i| (a_start + a.len() + i, b_start + b.len() + i)));
+}
+
+// the lines both ways of searching meet on in the middle of a shortest edit,
+// from (x, y) to (u, v). the backward search keeps how far it got from the ends
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + offset) as usize;
+
+    for d in 0..(max + 1) {
+        for k in (-d..(d + 1)).step_by(2) {
+            let start = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let mut x = start;
+            while x < n && x - k < m && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[at(k)] = x;
+            if odd && (delta - k).abs() < d && x + backward[at(delta - k)] >= n {
+                return (start as usize, (start - k) as usize, x as usize, (x - k) as usize)
+            }
+        }
+
+        for k in (-d..(d + 1)).step_by(2) {
+            let start = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let mut x = start;
+            while x < n && x - k < m && a[(n - x - 1) as usize] == b[(m - x + k - 1) as usize] {
+                x += 1;
+            }
+            backward[at(k)] = x;
+            if !odd && (delta - k).abs() <= d && x + forward[at(delta - k)] >= n {
+                return ((n - x) as usize, (m - x + k) as usize, (n - start) as usize, (m - start + k) as usize)
+            }
+        }
+    }
+
+    unreachable!("the searches always meet")
+}
+
+// where a line ends up after the hunks are applied, lines inside a changed
+// hunk go to the matching line of its replacement
+pub fn map_line(hunks: &[Hunk], line: usize) -> usize {
+    let mut shift = 0isize;
+    for hunk in hunks {
+        if line < hunk.old.start {
+            break
+        }
+        if line < hunk.old.end {
+            return hunk.new.start + (line - hunk.old.start).min(hunk.new.len().saturating_sub(1))
+        }
+        shift = hunk.new.end as isize - hunk.old.end as isize;
+    }
+
+    (line as isize + shift) as usize
+}
+
+// changes the buffer to new_text by only touching the lines that differ
+pub fn apply_to_buffer(buffer: &mut TextBuffer, new_text: &str) -> Vec<Hunk> {
+    let old_lines = (0..buffer.total_lines()).map(|line| buffer.line(line)).collect::<Vec<_>>();
+    let new_lines = new_text.lines().map(|line| line.to_owned()).collect::<Vec<_>>();
+    let hunks = diff(&old_lines, &new_lines);
+
+    // from the end so earlier line numbers stay valid
+    for hunk in hunks.iter().rev() {
+        let text = new_lines[hunk.new.clone()].iter().map(|line| format!("{line}\n")).collect::<String>();
+        if !hunk.old.is_empty() {
+            buffer.replace_lines(hunk.old.start, hunk.old.end - 1, &text);
+        } else if hunk.old.start < buffer.total_lines() {
+            buffer.insert_text(LinePos::new(hunk.old.start, 0), &text);
+        } else {
+            let last = buffer.total_lines() - 1;
+            let text = format!("\n{}", text.trim_end_matches('\n'));
+            buffer.insert_text(LinePos::new(last, buffer.line_len(last)), &text);
+        }
+    }
+
+    hunks
+}
+
+// two buffers compared line by line, the hunks follow the edits to both. a
+// hunk's old lines are in the first buffer, the one :diffsplit was run in
+pub struct DiffState {
+    pub buffers: [usize; 2],
+    pub hunks: Vec<Hunk>,
+    // the buffers' changes the hunks were found at
+    pub computed: (u64, u64),
+    // the buffers' changes when the editor last saw an edit, and when to diff them
+    pub due: Option<((u64, u64), Instant)>,
+}
+
+impl DiffState {
+    pub fn new(a: &TextBuffer, b: &TextBuffer) -> Self {
+        let mut state = Self { buffers: [a.id, b.id], hunks: Vec::new(), computed: (0, 0), due: None };
+        state.update(a, b);
+
+        state
+    }
+
+    // the two buffers out of all of them, None once one is closed
+    pub fn find<'a>(&self, buffers: &'a [TextBuffer]) -> Option<(&'a TextBuffer, &'a TextBuffer)> {
+        let find = |id| buffers.iter().find(|buffer| buffer.id == id);
+        Some((find(self.buffers[0])?, find(self.buffers[1])?))
+    }
+
+    pub fn update(&mut self, a: &TextBuffer, b: &TextBuffer) {
+        let lines = |buffer: &TextBuffer| (0..buffer.total_lines()).map(|line| buffer.line(line)).collect::<Vec<_>>();
+        self.hunks = diff(&lines(a), &lines(b));
+        self.computed = (a.changes, b.changes);
+        self.due = None;
+    }
+
+    // which of the two buffers the buffer id is, see Hunk::sides
+    pub fn side(&self, id: usize) -> Option<usize> {
+        self.buffers.iter().position(|&buffer| buffer == id)
+    }
+
+    // first line of the next hunk after line in the buffer on side
+    pub fn next_hunk(&self, side: usize, line: usize) -> Option<usize> {
+        self.hunks.iter().map(|hunk| hunk.sides(side).0.start).find(|&start| start > line)
+    }
+
+    pub fn previous_hunk(&self, side: usize, line: usize) -> Option<usize> {
+        self.hunks.iter().rev().map(|hunk| hunk.sides(side).0.start).find(|&start| start < line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "x", "c", "d", "e"];
+        let hunks = diff(&a, &b);
+        assert_eq!(hunks, [Hunk { old: 1..2, new: 1..2 }, Hunk { old: 4..4, new: 4..5 }]);
+        assert_eq!(map_line(&hunks, 1), 1);
+        assert_eq!(map_line(&hunks, 3), 3);
+
+        let hunks = diff(&["x", "a", "b"], &["a", "y", "b"]);
+        assert_eq!(hunks, [Hunk { old: 0..1, new: 0..0 }, Hunk { old: 2..2, new: 1..2 }]);
+        assert_eq!(map_line(&hunks, 2), 2);
+        assert!(diff(&a, &a).is_empty());
+    }
+
+    // as many lines are kept as the longest common subsequence has
+    #[test]
+    fn test_common_lines() {
+        let lcs = |a: &[u8], b: &[u8]| {
+            let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in (0..a.len()).rev() {
+                for j in (0..b.len()).rev() {
+                    table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+                }
+            }
+            table[0][0]
+        };
+        let cases: [(&[u8], &[u8]); 6] = [
+            (b"abcabba", b"cbabac"),
+            (b"xyz", b"abc"),
+            (b"", b"abc"),
+            (b"abcdefg", b"gfedcba"),
+            (b"aaaabbbb", b"bbbbaaaa"),
+            (b"the quick brown fox", b"a quick brown dog jumps"),
+        ];
+        for (a, b) in cases {
+            let matches = common_lines(a, b);
+            assert_eq!(matches.len(), lcs(a, b));
+            assert!(matches.iter().all(|&(x, y)| a[x] == b[y]));
+            assert!(matches.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1));
+        }
+    }
+
+    #[test]
+    fn test_apply_to_buffer() {
+        let mut buf = TextBuffer::from_data(0, b"fn main(){\nlet x=1;\n}\n".to_vec());
+        let new = "fn main() {\n    let x = 1;\n}\n\nfn other() {}\n";
+        apply_to_buffer(&mut buf, new);
+
+        let text = (0..buf.total_lines()).map(|line| buf.raw_line(line)).collect::<String>();
+        assert_eq!(text, new);
+    }
+
+    #[test]
+    fn test_hunk_jumps() {
+        let old = TextBuffer::from_data(0, b"a\nb\nc\nd\n".to_vec());
+        let new = TextBuffer::from_data(1, b"a\nx\nc\nd\ne\n".to_vec());
+        let diff = DiffState::new(&old, &new);
+        assert_eq!(diff.side(1), Some(1));
+        assert_eq!(diff.side(2), None);
+
+        assert_eq!(diff.next_hunk(1, 0), Some(1));
+        assert_eq!(diff.next_hunk(1, 1), Some(4));
+        assert_eq!(diff.next_hunk(1, 4), None);
+        assert_eq!(diff.previous_hunk(1, 4), Some(1));
+        assert_eq!(diff.previous_hunk(1, 1), None);
+
+        // the added line is a place where the old buffer has nothing
+        assert_eq!(diff.next_hunk(0, 1), Some(4));
+        assert_eq!(diff.previous_hunk(0, 4), Some(1));
+    }
+}
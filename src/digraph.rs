@@ -0,0 +1,164 @@
+// chars typed by name in insert mode. ctrl-k takes two chars like vim's
+// RFC1345 digraphs, a: is ä and Eu is €. ctrl-v takes a codepoint, u and
+// four hex digits, U and eight, x and two, o and three octal ones or up to
+// three decimal ones. anything else after ctrl-v is typed as it is
+
+
+// an accent or mark after a letter, then pairs of the letter and the letter with it
+const MARKS: &[(char, &str)] = &[
+    (':', "aäeëiïoöuüyÿAÄEËIÏOÖUÜ"),
+    ('\'', "aáeéiíoóuúyýcćnńsśzźAÁEÉIÍOÓUÚYÝCĆNŃSŚZŹ"),
+    ('!', "aàeèiìoòuùAÀEÈIÌOÒUÙ"),
+    ('>', "aâeêiîoôuûAÂEÊIÎOÔUÛ"),
+    ('?', "aãnñoõAÃNÑOÕ"),
+    (',', "cçsşCÇSŞ"),
+    ('<', "cčeěnňrřsšzžCČEĚNŇRŘSŠZŽ"),
+    ('/', "oøOØ"),
+    ('*', "aαbβgγdδeεzζyηhθiιkκlλmμnνcξoοpπrρsσtτuυfφxχqψwωAΑBΒGΓDΔEΕZΖYΗHΘIΙKΚLΛMΜNΝCΞOΟPΠRΡSΣTΤUΥFΦXΧQΨWΩ"),
+];
+
+const DIGRAPHS: &[(&str, char)] = &[
+    ("aa", 'å'), ("AA", 'Å'), ("ae", 'æ'), ("AE", 'Æ'), ("ss", 'ß'),
+    ("Eu", '€'), ("Pd", '£'), ("Ye", '¥'), ("Ct", '¢'), ("Co", '©'), ("Rg", '®'), ("TM", '™'),
+    ("DG", '°'), ("+-", '±'), ("*X", '×'), ("-:", '÷'), ("My", 'µ'), ("00", '∞'), ("RT", '√'),
+    ("!=", '≠'), ("=<", '≤'), (">=", '≥'), ("FA", '∀'), ("TE", '∃'), ("(-", '∈'),
+    ("<<", '«'), (">>", '»'), ("\"6", '“'), ("\"9", '”'), ("'6", '‘'), ("'9", '’'),
+    ("-N", '–'), ("-M", '—'), ("!I", '¡'), ("?I", '¿'), ("SE", '§'), ("PI", '¶'), ("NS", '\u{a0}'),
+    ("->", '→'), ("<-", '←'), ("-!", '↑'), ("-v", '↓'), ("OK", '✓'), ("XX", '✗'),
+    ("1S", '¹'), ("2S", '²'), ("3S", '³'), ("12", '½'), ("14", '¼'), ("34", '¾'),
+];
+
+// the digraph of first and second, the other way around works too
+pub fn digraph(first: char, second: char) -> Option<char> {
+    let find = |a: char, b: char| {
+        let name = format!("{a}{b}");
+        if let Some((_, c)) = DIGRAPHS.iter().find(|(digraph, _)| *digraph == name) {
+            return Some(*c)
+        }
+        let (_, letters) = MARKS.iter().find(|(mark, _)| *mark == b)?;
+        let letters = letters.chars().collect::<Vec<_>>();
+        letters.chunks(2).find(|pair| pair[0] == a).map(|pair| pair[1])
+    };
+
+    find(first, second).or_else(|| find(second, first))
+}
+
+// the two chars ctrl-k takes for c
+pub fn digraph_of(c: char) -> Option<String> {
+    if let Some((name, _)) = DIGRAPHS.iter().find(|(_, digraph)| *digraph == c) {
+        return Some(name.to_string())
+    }
+    MARKS.iter().find_map(|(mark, letters)| {
+        let letters = letters.chars().collect::<Vec<_>>();
+        letters.chunks(2).find(|pair| pair[1] == c).map(|pair| format!("{}{mark}", pair[0]))
+    })
+}
+
+// what ga shows for c, its codepoint in decimal, hex and octal, the digraph
+// for it if there is one and its bytes in utf-8
+pub fn char_info(c: char) -> String {
+    let shown = if c.is_control() { format!("^{}", char::from_u32(c as u32 ^ 0x40).unwrap_or('?')) } else { c.to_string() };
+    let code = c as u32;
+    let mut info = format!("<{shown}> {code}, Hex {code:0w$x}, Oct {code:o}", w = if code > 0xff { 4 } else { 2 });
+    if let Some(digraph) = digraph_of(c) {
+        info.push_str(&format!(", Digr {digraph}"));
+    }
+    let bytes = c.to_string().bytes().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>();
+    info.push_str(&format!(", UTF-8 {}", bytes.join(" ")));
+    info
+}
+
+// what's been typed after ctrl-k or ctrl-v
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Literal {
+    Digraph(Vec<char>),
+    Codepoint(String),
+}
+
+impl Literal {
+    // the next typed char, Some with what to type once it's done
+    pub fn push(&mut self, c: char) -> Option<String> {
+        match self {
+            Literal::Digraph(chars) => {
+                chars.push(c);
+                // an unknown digraph types its second char like vim
+                let &[first, second] = chars.as_slice() else { return None };
+                Some(digraph(first, second).unwrap_or(second).to_string())
+            },
+            Literal::Codepoint(typed) if typed.is_empty() => {
+                if !matches!(c, 'u' | 'U' | 'x' | 'o') && !c.is_ascii_digit() {
+                    return Some(c.to_string())
+                }
+                typed.push(c);
+                None
+            },
+            Literal::Codepoint(typed) => {
+                let (radix, max) = match typed.chars().next() {
+                    Some('u') => (16, 4),
+                    Some('U') => (16, 8),
+                    Some('x') => (16, 2),
+                    Some('o') => (8, 3),
+                    _ => (10, 3),
+                };
+                if !c.is_digit(radix) {
+                    return Some(format!("{}{c}", self.finish()))
+                }
+                typed.push(c);
+                let digits = typed.trim_start_matches(['u', 'U', 'x', 'o']).len();
+                (digits == max).then(|| self.finish())
+            },
+        }
+    }
+
+    // the char of the digits typed so far, for when a key that isn't one ends it
+    pub fn finish(&self) -> String {
+        let Literal::Codepoint(typed) = self else { return String::new() };
+        let digits = typed.trim_start_matches(['u', 'U', 'x', 'o']);
+        let radix = match typed.chars().next() {
+            Some('u' | 'U' | 'x') => 16,
+            Some('o') => 8,
+            _ => 10,
+        };
+        let c = u32::from_str_radix(digits, radix).ok().and_then(char::from_u32);
+        c.map(String::from).unwrap_or_default()
+    }
+
+    // shown in the message line while it waits
+    pub fn keys(&self) -> String {
+        match self {
+            Literal::Digraph(chars) => format!("^K{}", chars.iter().collect::<String>()),
+            Literal::Codepoint(typed) => format!("^V{typed}"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(digraph('a', ':'), Some('ä'));
+        assert_eq!(digraph(':', 'a'), Some('ä'));
+        assert_eq!(digraph('E', 'u'), Some('€'));
+        assert_eq!(digraph('l', '*'), Some('λ'));
+        assert_eq!(digraph('q', 'q'), None);
+
+        let typed = |literal: Literal, keys: &str| {
+            let mut literal = literal;
+            keys.chars().map(|c| literal.push(c)).collect::<Vec<_>>()
+        };
+        assert_eq!(typed(Literal::Digraph(Vec::new()), "o/"), [None, Some("ø".into())]);
+        assert_eq!(typed(Literal::Digraph(Vec::new()), "qq"), [None, Some("q".into())]);
+        assert_eq!(typed(Literal::Codepoint(String::new()), "u20ac"), [None, None, None, None, Some("€".into())]);
+        assert_eq!(typed(Literal::Codepoint(String::new()), "x41"), [None, None, Some("A".into())]);
+        assert_eq!(typed(Literal::Codepoint(String::new()), "65z"), [None, None, Some("Az".into())]);
+        assert_eq!(typed(Literal::Codepoint(String::new()), "("), [Some("(".into())]);
+
+        assert_eq!(char_info('a'), "<a> 97, Hex 61, Oct 141, UTF-8 61");
+        assert_eq!(char_info('ä'), "<ä> 228, Hex e4, Oct 344, Digr a:, UTF-8 c3 a4");
+        assert_eq!(char_info('€'), "<€> 8364, Hex 20ac, Oct 20254, Digr Eu, UTF-8 e2 82 ac");
+        assert_eq!(char_info('\t'), "<^I> 9, Hex 09, Oct 11, UTF-8 09");
+    }
+}
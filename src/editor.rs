@@ -1,8 +1,19 @@
-use std::{env, fs, io::Write, path::{Path, PathBuf}, sync::atomic};
+use std::{collections::{HashMap, VecDeque}, env, fs, io::{self, IsTerminal, Write}, path::{Path, PathBuf}, sync::atomic, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use crate::{command_bar::{match_cmd, CommandBarAction}, gap_buffer::{LinePos, LineView, TextBuffer}, indent::indent_wanted, search::search, vim_commands::*, CursorPos, SpecialKey, State};
+#[cfg(feature = "scripting")]
+use crate::{options::config_dir, scripting::{ScriptFn, ScriptOp, Scripting}, virtual_text::{Annotation, AnnotationKind}};
+use crate::{command_bar::{complete, match_cmd, parse_range, split_command, CommandBarAction, CommandResult, DiffWith, UserCommand, Wildmenu, SHELL_REFUSED}, completion::{buffer_words, word_start, CompletionItem, CompletionMenu}, diff::{self, DiffState}, digraph::{self, Literal}, encoding::Encoding, fold, format, gap_buffer::{backup_file, LinePos, TextBuffer}, indent::{align_closing, indent_text, indent_wanted, reindent}, options::{config_path, FoldMethod, Options, UndoChunk}, lsp::{self, Location, LspClient, LspEvent}, notification::{Level, Notifications}, project, recent::RecentFiles, search::{search, SearchJob, ASYNC_SEARCH_THRESHOLD}, tags::{Address, TagFile}, tasks::Tasks, vim_commands::*, which_key, keys::{self, Key, KeyMap, MapMode}, layout::{Axis, Layout, View, WindowCommand, RESIZE_STEP}, log, width, wrap, CursorPos, Modifiers, MouseEvent, SpecialKey, State, SHOULD_QUIT};
 
 static LAST_BUFFER_ID: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+const MAX_DELETED: usize = 9;
+// how deep user commands can call other user commands
+const MAX_COMMAND_DEPTH: usize = 10;
+// stops macros that replay themselves
+const MAX_FEED_DEPTH: usize = 20;
+// clicks closer together than this count as a double or triple click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+// the diff is found again once edits stop for this long
+const DIFF_DELAY: Duration = Duration::from_millis(300);
 pub fn next_buffer_id() -> usize {
     LAST_BUFFER_ID.fetch_add(1, atomic::Ordering::Relaxed)
 }
@@ -12,6 +23,7 @@ pub fn next_buffer_id() -> usize {
 pub enum EditorMode {
     Insert,
     Normal,
+    Replace,
     Visual,
     VisualLine,
     CommandBar,
@@ -22,61 +34,765 @@ pub struct Editor {
     pub buffers: Vec<TextBuffer>,
     pub cursors: Vec<CursorPos>,
     pub current_buffer: usize,
+    // the split windows, the focused one shows the current buffer
+    pub layout: Layout,
     pub root_folder: PathBuf,
     pub search_results: Vec<LinePos>,
+    // the search for a big buffer while it runs, see search.rs
+    pub search_job: Option<SearchJob>,
+    // threads for work that shouldn't hold up a frame
+    pub tasks: Tasks,
+    // set for --headless, whose clients can't be trusted to run shell commands
+    pub no_shell: bool,
     pub command_bar_input: String,
+    // the candidates of the last tab completion in the command bar
+    pub wildmenu: Option<Wildmenu>,
+    pub message: String,
+    pub completion: Option<CompletionMenu>,
+    // lines of the hover popup shown at the cursor
+    pub hover: Option<Vec<String>>,
+    pub notifications: Notifications,
+    // the screen flashes until then when visualbell is set
+    pub flash_until: Option<Instant>,
+    // after ctrl-w r, h/j/k/l size the focused window until escape
+    resizing: bool,
+    pub lsp_clients: Vec<LspClient>,
+    // buffer id and position to go back to with ctrl-o
+    pub jump_list: Vec<(usize, LinePos)>,
+    pub quickfix: Vec<Location>,
+    pub quickfix_index: usize,
+    // text removed by recent deletes, newest first, like vim's numbered registers
+    pub deleted: VecDeque<String>,
+    pub diff: Option<DiffState>,
+    // loaded from root_folder/tags on the first ctrl-]
+    pub tags: Option<TagFile>,
+    // where to go back to with ctrl-t
+    pub tag_stack: Vec<(usize, LinePos)>,
+    // the folder from :cd, and the ones from :lcd by buffer id
+    global_dir: PathBuf,
+    local_dirs: HashMap<usize, PathBuf>,
+    // files opened before and where they were left, loaded with load_recent
+    pub recent: RecentFiles,
+    // defined with :command or in the config
+    pub user_commands: Vec<UserCommand>,
+    // defined with :map and friends
+    pub key_maps: Vec<KeyMap>,
+    // keys typed so far that could still become a map
+    map_pending: Vec<Key>,
+    // when the keys pending now were typed, for the which-key hints
+    pending_since: Option<Instant>,
+    // a char being typed by name after ctrl-k or ctrl-v in insert mode
+    literal: Option<Literal>,
+    // false while the keys of a noremap run
+    remap: bool,
+    // register and keys typed so far while recording a macro with q
+    pub recording: Option<(char, String)>,
+    pub macros: HashMap<char, String>,
+    pub last_macro: Option<char>,
+    // how many feed_keys calls are running, keys fed deeper than where the
+    // recording started come from replaying and aren't recorded
+    feed_depth: usize,
+    recording_depth: usize,
+    // when and where the last click was and how many came quickly one after another
+    last_click: Option<(Instant, LinePos, u32)>,
+    drag_anchor: Option<LinePos>,
+    #[cfg(feature = "scripting")]
+    pub scripting: Scripting,
     pub visual_range_anchor: LinePos,
+    // the last visual selection, for the '< and '> ranges
+    pub visual_marks: Option<(LinePos, LinePos)>,
+    // a d or = waiting for the search typed after it, with where it started
+    search_motion: Option<(Motion, LinePos)>,
+    // times the text of the current insert is typed again on escape and where
+    // it started, for 3itext
+    insert_repeat: Option<(usize, LinePos)>,
     pub motion: Motion,
     pub mode: EditorMode,
 }
 
 
 impl Editor {
-    pub fn from_path(path: &Path) -> Self {
-        println!("{path:?}");
-        let buf = TextBuffer::from_path(next_buffer_id(), path);
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        log::debug(&format!("Opening {}", path.display()));
+        Ok(Self::from_buffer(TextBuffer::from_path(next_buffer_id(), path)?))
+    }
+
+    pub fn from_buffer(buf: TextBuffer) -> Self {
         let cursor = CursorPos::new(buf.id);
-        let root = env::current_dir().expect("Didn't find current dir");
+        // the project the file is in, otherwise the one moded was started in
+        let cwd = env::current_dir().expect("Didn't find current dir");
+        let dir = buf.file_path.as_deref().and_then(Path::parent).map_or(cwd.clone(), |dir| cwd.join(dir));
+        let root = project::find_root(&dir).or_else(|| project::find_root(&cwd)).unwrap_or_else(|| cwd.clone());
 
         Self { 
             buffers: vec![buf],
             cursors: vec![cursor],
             current_buffer: 0,
+            layout: Layout::new(),
             root_folder: root,
             mode: EditorMode::Normal,
             motion: Motion::new(),
             visual_range_anchor: LinePos { line: 0, col: 0 },
+            visual_marks: None,
+            insert_repeat: None,
+            search_motion: None,
             command_bar_input: String::new(),
+            wildmenu: None,
+            message: String::new(),
+            completion: None,
+            hover: None,
+            notifications: Notifications::default(),
+            flash_until: None,
+            resizing: false,
+            lsp_clients: Vec::new(),
+            jump_list: Vec::new(),
+            quickfix: Vec::new(),
+            quickfix_index: 0,
+            deleted: VecDeque::new(),
+            diff: None,
+            tags: None,
+            tag_stack: Vec::new(),
+            global_dir: cwd.clone(),
+            local_dirs: HashMap::new(),
+            recent: RecentFiles::default(),
+            user_commands: Vec::new(),
+            key_maps: Vec::new(),
+            map_pending: Vec::new(),
+            pending_since: None,
+            literal: None,
+            remap: true,
+            recording: None,
+            macros: HashMap::new(),
+            last_macro: None,
+            feed_depth: 0,
+            recording_depth: 0,
+            last_click: None,
+            drag_anchor: None,
+            #[cfg(feature = "scripting")]
+            scripting: Scripting::new(),
             search_results: Vec::new(),
+            search_job: None,
+            tasks: Tasks::default(),
+            no_shell: false,
+        }
+    }
+
+    pub fn save_to_file(&mut self, state: &State) {
+        if self.refuse_readonly() {
+            return
+        }
+        // writing a half read file would cut it short, and formatting or trimming
+        // it would change the text for nothing
+        if self.buffers.get(self.current_buffer).is_some_and(|buffer| buffer.loading.is_some()) {
+            self.message = "Still loading the file, not saved".to_owned();
+            return
+        }
+        if state.options.format_on_save {
+            self.format_before_save(state);
+        }
+        if state.options.trim_on_save {
+            self.trim_trailing_whitespace();
+        }
+
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        let Some(file_path) = buffer.file_path.clone() else {
+            self.message = "No file name, use :w {path}".to_owned();
+            return
+        };
+        if let Err(e) = write_file(buffer, &file_path, &state.options) {
+            self.message = format!("{}: {e}", file_path.display());
+            return
+        }
+        buffer.saved_changes = buffer.changes;
+        let lines = buffer.total_lines();
+        self.notify(format!("{} written, {lines} lines", file_path.display()), Level::Info);
+    }
+
+    // a buffer without a file is saved to path and keeps it, other buffers are copied there
+    pub fn save_as(&mut self, state: &State, path: PathBuf) {
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        if buffer.file_path.is_none() {
+            buffer.file_path = Some(path);
+            self.save_to_file(state);
+            return
+        }
+
+        if buffer.loading.is_some() {
+            self.message = "Still loading the file, not saved".to_owned();
+            return
+        }
+        match write_file(buffer, &path, &state.options) {
+            Ok(()) => self.notify(format!("Written to {}", path.display()), Level::Info),
+            Err(e) => self.message = format!("{}: {e}", path.display()),
+        }
+    }
+
+    // what can follow the pending keys once they've waited for whichkey ms
+    pub fn which_key(&self, state: &State) -> Option<Vec<String>> {
+        let delay = Duration::from_millis(state.options.which_key as u64);
+        if state.options.which_key == 0 || self.pending_since?.elapsed() < delay {
+            return None
+        }
+        let maps = self.key_maps.iter().filter(|map| Some(map.mode) == self.map_mode());
+        let hints = which_key::hints(&self.motion.keys, maps, &self.map_pending);
+
+        (!hints.is_empty()).then_some(hints)
+    }
+
+    pub fn notify(&mut self, text: String, level: Level) {
+        match level {
+            Level::Info => log::info(&text),
+            Level::Error => log::error(&text),
+        }
+        self.notifications.push(text, level, Instant::now());
+    }
+
+    // true and a message if the current buffer is read-only
+    fn refuse_readonly(&mut self) -> bool {
+        let readonly = self.buffers.get(self.current_buffer).is_some_and(|buffer| buffer.readonly);
+        if readonly {
+            self.message = "Buffer is read-only".to_owned();
+        }
+
+        readonly
+    }
+
+    // titlestring with %f the file name, %F its path, %m [+] if it's modified,
+    // %r [RO] if it's read-only and %e the encoding if it isn't utf-8
+    pub fn title(&self, titlestring: &str) -> String {
+        let buffer = self.buffers.get(self.current_buffer);
+        let path = buffer.and_then(|buffer| buffer.file_path.as_deref());
+        let mut title = String::new();
+        let mut chars = titlestring.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                title.push(c);
+                continue
+            }
+            match chars.next() {
+                Some('f') => title.push_str(&path.and_then(Path::file_name).map_or(String::from("[No Name]"), |name| name.to_string_lossy().into_owned())),
+                Some('F') => title.push_str(&path.map_or(String::from("[No Name]"), |path| path.display().to_string())),
+                Some('m') if buffer.is_some_and(TextBuffer::is_modified) => title.push_str(" [+]"),
+                Some('m') => (),
+                Some('r') if buffer.is_some_and(|buffer| buffer.readonly) => title.push_str(" [RO]"),
+                Some('r') => (),
+                Some('e') => match buffer.map(|buffer| buffer.encoding) {
+                    Some(encoding) if encoding != Encoding::Utf8 => title.push_str(&format!(" [{}]", encoding.name())),
+                    _ => (),
+                },
+                Some(c) => title.push(c),
+                None => title.push('%'),
+            }
         }
+
+        title
     }
 
-    pub fn save_to_file(&mut self) {
+    // keeps what was removed since the buffer's history.collect_removed
+    fn save_deleted_text(&mut self) {
         let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
-        let view = buffer.full_view();
-        let Some(file_path) = &buffer.file_path else { return };
-        let mut file = std::fs::File::create(file_path).unwrap();
-        match view {
-            LineView::Contiguous(s) => {
-                file.write_all(s.as_bytes()).unwrap();
+        let removed = buffer.history.take_removed();
+        if removed.is_empty() {
+            return
+        }
+
+        self.deleted.push_front(String::from_utf8_lossy(&removed).replace("\r\n", "\n"));
+        self.deleted.truncate(MAX_DELETED);
+    }
+
+    // the screen flashes with visualbell set, or when there's no terminal to ring
+    pub fn bell(&mut self, state: &mut State) {
+        if state.options.visual_bell || !ring_terminal_bell() {
+            let until = Instant::now() + Duration::from_millis(100);
+            self.flash_until = Some(until);
+            // draws the normal background again
+            state.scheduler.redraw_at(until);
+        }
+    }
+
+    // resolves and runs one command line without the ':', None if there's no such command
+    fn eval_command(&self, state: &mut State, line: &str, depth: usize) -> Option<CommandResult> {
+        let buffer = self.buffers.get(self.current_buffer)?;
+        let cursor = self.cursors.get(self.current_buffer)?;
+        let marks = self.visual_marks.map(|(start, end)| (start.line, end.line));
+        let (range, rest) = parse_range(line, cursor.y - 1, buffer.total_lines(), marks);
+        let (name, args) = split_command(rest);
+        if name.is_empty() {
+            return Some(Ok(CommandBarAction::None))
+        }
+
+        if let Some(user) = self.user_commands.iter().find(|command| command.name == name) {
+            if depth >= MAX_COMMAND_DEPTH {
+                return Some(Ok(CommandBarAction::Message(format!("Too many nested commands in :{name}"))))
+            }
+            // the range is passed on to the body
+            let range_text = &line[..(line.len() - rest.len())];
+            let body = format!("{range_text}{}", user.body.replace("<args>", args));
+            return self.eval_command(state, &body, depth + 1)
+        }
+
+        #[cfg(feature = "scripting")]
+        if let Some(func) = self.scripting.command(name) {
+            return Some(Ok(CommandBarAction::CallScript(func.clone(), args.to_owned())))
+        }
+
+        let command = match_cmd(name)?;
+        if range.is_some() && !command.range {
+            return Some(Ok(CommandBarAction::Message(format!("No range allowed for :{}", command.name))))
+        }
+        if command.shell && self.no_shell {
+            return Some(Ok(CommandBarAction::Message(SHELL_REFUSED.to_owned())))
+        }
+
+        Some((command.handler)(state, self, range, args))
+    }
+
+    fn apply_command_action(&mut self, state: &mut State, action: CommandBarAction) {
+        match action {
+            CommandBarAction::NewBuffer(buf) => {
+                self.cursors.push(CursorPos::new(buf.id));
+                self.buffers.push(buf);
+                self.restore_position(self.buffers.len() - 1);
+                self.remember_position(self.buffers.len() - 1);
+                self.switch_to(state, self.buffers.len() - 1);
+            },
+            CommandBarAction::SwitchToBuffer(buf) => {
+                self.switch_to(state, buf);
+            },
+            CommandBarAction::MoveCursor(pos) => {
+                if let Some(cursor) = self.cursors.get_mut(self.current_buffer) {
+                    cursor.from_linepos(pos);
+                }
+            },
+            CommandBarAction::ReplaceLines(..) | CommandBarAction::ReplaceBuffer(_) | CommandBarAction::DeleteLines(_) | CommandBarAction::Retab(..) if self.refuse_readonly() => {},
+            CommandBarAction::Retab(range, text, tabstop) => {
+                state.options.tabstop = tabstop;
+                let action = match range {
+                    Some(range) => CommandBarAction::ReplaceLines(range, text),
+                    None => CommandBarAction::ReplaceBuffer(text),
+                };
+                self.apply_command_action(state, action);
+            },
+            CommandBarAction::DeleteLines(range) => {
+                let buffer = &mut self.buffers[self.current_buffer];
+                buffer.history.collect_removed();
+                buffer.replace_lines(range.start, range.end, "");
+                let line = range.start.min(buffer.total_lines() - 1);
+                self.cursors[self.current_buffer].from_linepos(LinePos { line, col: 0 });
+                self.save_deleted_text();
+            },
+            CommandBarAction::ReplaceLines(range, text) => {
+                let buffer = &mut self.buffers[self.current_buffer];
+                buffer.replace_lines(range.start, range.end, &text);
+                let line = range.start.min(buffer.total_lines() - 1);
+                self.cursors[self.current_buffer].from_linepos(LinePos { line, col: 0 });
+            },
+            CommandBarAction::Message(message) => {
+                self.message = message;
+            },
+            CommandBarAction::QuickfixMove(by) => {
+                self.quickfix_move(by);
+            },
+            CommandBarAction::Window(command) => {
+                self.window_command(state, command);
+            },
+            CommandBarAction::ReplaceBuffer(text) => {
+                self.replace_buffer_text(&text);
+            },
+            CommandBarAction::DiffSplit(with) => {
+                let this = self.current_buffer;
+                self.window_command(state, WindowCommand::Split(Axis::Cols));
+                match with {
+                    DiffWith::Buffer(i) => self.switch_to(state, i),
+                    DiffWith::File(buffer) => self.apply_command_action(state, CommandBarAction::NewBuffer(buffer)),
+                }
+                let diff = DiffState::new(&self.buffers[this], &self.buffers[self.current_buffer]);
+                self.message = format!("{} changes", diff.hunks.len());
+                self.diff = Some(diff);
+            },
+            CommandBarAction::DiffOff => {
+                self.diff = None;
+            },
+            CommandBarAction::DefineCommand(command) => {
+                self.user_commands.retain(|existing| existing.name != command.name);
+                self.user_commands.push(command);
+            },
+            CommandBarAction::Normal(range, keys) => {
+                let Some(range) = range else {
+                    self.feed_normal_keys(state, &keys);
+                    return
+                };
+                for line in range.start..(range.end + 1) {
+                    let Some(cursor) = self.cursors.get_mut(self.current_buffer) else { return };
+                    cursor.from_linepos(LinePos::new(line, 0));
+                    self.feed_normal_keys(state, &keys);
+                }
+            },
+            #[cfg(feature = "scripting")]
+            CommandBarAction::CallScript(func, args) => {
+                self.call_script(state, &func, &args);
+            },
+            CommandBarAction::Save => {
+                self.save_to_file(state);
+            },
+            CommandBarAction::SaveAs(path) => {
+                self.save_as(state, path);
+            },
+            CommandBarAction::ChangeDir(dir, local) => {
+                self.change_dir(&dir, local);
+            },
+            CommandBarAction::SetLocal(options) => {
+                if let Some(buffer) = self.buffers.get_mut(self.current_buffer) {
+                    buffer.options = options;
+                }
+            },
+            CommandBarAction::Map(maps) => {
+                for map in maps {
+                    self.key_maps.retain(|existing| existing.mode != map.mode || existing.lhs != map.lhs);
+                    self.key_maps.push(map);
+                }
+            },
+            CommandBarAction::Unmap(modes, lhs) => {
+                self.key_maps.retain(|map| !modes.contains(&map.mode) || map.lhs != lhs);
+            },
+            CommandBarAction::Quit => {
+                SHOULD_QUIT.store(true, atomic::Ordering::Relaxed);
+            },
+            CommandBarAction::None => {},
+        }
+    }
+
+    // runs a command line without the ':' like it was typed in the command bar
+    pub fn run_command(&mut self, state: &mut State, line: &str) {
+        match self.eval_command(state, line, 0) {
+            Some(Ok(action)) => self.apply_command_action(state, action),
+            Some(Err(_)) => self.message = format!("Invalid command: {line}"),
+            None => self.message = format!("Not an editor command: {line}"),
+        }
+    }
+
+    // runs the ex commands in the config file, lines starting with " are comments,
+    // then the plugins
+    pub fn load_config(&mut self, state: &mut State) {
+        #[cfg(feature = "scripting")]
+        self.load_plugins(state);
+
+        let Some(path) = config_path() else { return };
+        let Ok(text) = fs::read_to_string(&path) else { return };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let line = line.strip_prefix(':').unwrap_or(line);
+            if line.is_empty() || line.starts_with('"') {
+                continue
+            }
+
+            match self.eval_command(state, line, 0) {
+                Some(Ok(action)) => self.apply_command_action(state, action),
+                Some(Err(_)) => self.message = format!("{}:{}: invalid command: {line}", path.display(), i + 1),
+                None => self.message = format!("{}:{}: not an editor command: {line}", path.display(), i + 1),
+            }
+        }
+    }
+
+    // the .rhai files in the plugins folder of the config dir, in name order
+    #[cfg(feature = "scripting")]
+    fn load_plugins(&mut self, state: &mut State) {
+        let Some(dir) = config_dir().map(|dir| dir.join("plugins")) else { return };
+        let Ok(entries) = fs::read_dir(dir) else { return };
+        let mut paths = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect::<Vec<_>>();
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "rhai"));
+        paths.sort();
+
+        for path in paths {
+            let buffer = &self.buffers[self.current_buffer];
+            let cursor = self.cursors[self.current_buffer].to_linepos();
+            match self.scripting.load(&path, buffer, cursor) {
+                Ok(ops) => self.apply_script_ops(state, ops),
+                Err(e) => self.message = format!("{}: {e}", path.display()),
+            }
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    fn call_script(&mut self, state: &mut State, func: &ScriptFn, args: &str) {
+        let buffer = &self.buffers[self.current_buffer];
+        let cursor = self.cursors[self.current_buffer].to_linepos();
+        match self.scripting.call(func, args, buffer, cursor) {
+            Ok(ops) => self.apply_script_ops(state, ops),
+            Err(e) => self.message = e,
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    fn apply_script_ops(&mut self, state: &mut State, ops: Vec<ScriptOp>) {
+        for op in ops {
+            if matches!(op, ScriptOp::SetLine(..) | ScriptOp::InsertLine(..) | ScriptOp::RemoveLine(_)) && self.refuse_readonly() {
+                continue
+            }
+            let buffer = &mut self.buffers[self.current_buffer];
+            match op {
+                ScriptOp::SetLine(line, text) if line < buffer.total_lines() => {
+                    buffer.replace_lines(line, line, &format!("{text}\n"));
+                },
+                ScriptOp::InsertLine(line, text) if line < buffer.total_lines() => {
+                    buffer.insert_text(LinePos::new(line, 0), &format!("{text}\n"));
+                },
+                ScriptOp::InsertLine(_, text) => {
+                    let last = buffer.total_lines() - 1;
+                    buffer.insert_text(LinePos::new(last, buffer.line_len(last)), &format!("\n{text}"));
+                },
+                ScriptOp::RemoveLine(line) if line < buffer.total_lines() => buffer.remove_line(line),
+                ScriptOp::SetCursor(pos) => self.cursors[self.current_buffer].from_linepos(pos),
+                ScriptOp::Command(line) => self.run_command(state, &line),
+                ScriptOp::Keys(keys) => self.feed_keys(state, &keys),
+                ScriptOp::Message(message) => self.message = message,
+                ScriptOp::Annotate(line, text) if line < buffer.total_lines() => {
+                    buffer.virtual_text.add(Annotation { line, text, kind: AnnotationKind::Hint, source: "plugin" });
+                },
+                ScriptOp::ClearAnnotations => buffer.virtual_text.replace("plugin", Vec::new()),
+                ScriptOp::SetLine(..) | ScriptOp::RemoveLine(..) | ScriptOp::Annotate(..) => {},
+            }
+        }
+
+        // the script may have moved the cursor off the text
+        let buffer = &self.buffers[self.current_buffer];
+        let cursor = &mut self.cursors[self.current_buffer];
+        cursor.from_linepos(buffer.clamp_to_char(cursor.to_linepos()));
+    }
+
+    // runs keys in vim notation like "dd", "ifoo<Esc>" or "<C-r>" as if they
+    // were typed, used by macros, :normal and tests
+    pub fn feed_keys(&mut self, state: &mut State, keys: &str) {
+        if self.feed_depth >= MAX_FEED_DEPTH {
+            self.message = "Keys nest too deep".to_owned();
+            return
+        }
+
+        let typed = std::mem::take(&mut state.io);
+        self.feed_depth += 1;
+        for key in keys::parse(keys) {
+            state.io = key.to_io();
+            self.handle_input(state);
+        }
+        self.feed_depth -= 1;
+        state.io = typed;
+    }
+
+    fn feed_keys_noremap(&mut self, state: &mut State, keys: &str) {
+        let remap = std::mem::replace(&mut self.remap, false);
+        self.feed_keys(state, keys);
+        self.remap = remap;
+    }
+
+    // maps apply at the start of a command, not after an operator or count
+    fn map_mode(&self) -> Option<MapMode> {
+        match self.mode {
+            EditorMode::Normal if self.motion.is_empty() => Some(MapMode::Normal),
+            EditorMode::Visual | EditorMode::VisualLine if self.motion.is_empty() => Some(MapMode::Visual),
+            EditorMode::Insert => Some(MapMode::Insert),
+            _ => None,
+        }
+    }
+
+    // with maps for the mode every typed key goes through map_key, true if
+    // the frame's keys were handled there
+    fn map_keys(&mut self, state: &mut State) -> bool {
+        if !self.remap {
+            return false
+        }
+        let Some(mode) = self.map_mode() else { return false };
+        if self.map_pending.is_empty() && !self.key_maps.iter().any(|map| map.mode == mode) {
+            return false
+        }
+        let typed = keys::from_io(&state.io);
+        if typed.is_empty() {
+            return false
+        }
+
+        for key in typed {
+            self.map_key(state, key);
+        }
+        true
+    }
+
+    // keys are held while they could still become a longer map. once they
+    // can't the longest map they start with runs, or else the first key as
+    // typed, and the rest are looked at again
+    fn map_key(&mut self, state: &mut State, key: Key) {
+        let mut typed = std::mem::take(&mut self.map_pending);
+        typed.push(key);
+        let Some(mode) = self.map_mode() else {
+            self.feed_keys_noremap(state, &keys::to_notation(&typed));
+            return
+        };
+        let maps = self.key_maps.iter().filter(|map| map.mode == mode);
+        if maps.clone().any(|map| map.lhs.len() > typed.len() && map.lhs.starts_with(&typed)) {
+            self.map_pending = typed;
+            return
+        }
+
+        let used = match maps.filter(|map| typed.starts_with(&map.lhs)).max_by_key(|map| map.lhs.len()).cloned() {
+            Some(map) if map.noremap => {
+                self.feed_keys_noremap(state, &map.rhs);
+                map.lhs.len()
             },
-            LineView::Parts(s1, s2) => {
-                file.write_all(s1.as_bytes()).unwrap();
-                file.write_all(s2.as_bytes()).unwrap();
+            Some(map) => {
+                self.feed_keys(state, &map.rhs);
+                map.lhs.len()
             },
+            None => {
+                self.feed_keys_noremap(state, &keys::to_notation(&typed[..1]));
+                1
+            },
+        };
+        for &key in &typed[used..] {
+            self.map_key(state, key);
+        }
+    }
+
+    // like vim's :normal an unfinished command is cancelled
+    fn feed_normal_keys(&mut self, state: &mut State, keys: &str) {
+        self.feed_keys(state, keys);
+        if self.mode != EditorMode::Normal || !self.motion.is_empty() {
+            self.feed_keys(state, "<Esc>");
+        }
+    }
+
+    // a failing formatter doesn't stop the save, it only leaves a message
+    fn format_before_save(&mut self, state: &State) {
+        let Some(buffer) = self.buffers.get(self.current_buffer) else { return };
+        let Some(formatter) = format::formatter_for(&state.options.formatprg, buffer.file_path.as_deref()) else { return };
+        if self.no_shell && matches!(formatter, format::Formatter::Shell(_)) {
+            self.message = SHELL_REFUSED.to_owned();
+            return
+        }
+        match format::run(&formatter, buffer) {
+            Ok(text) => self.replace_buffer_text(&text),
+            Err(e) => self.message = format!("Formatting failed: {e}"),
+        }
+    }
+
+    // removes spaces and tabs at the ends of lines as one undo step, the cursor stays in its column
+    fn trim_trailing_whitespace(&mut self) {
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get_mut(self.current_buffer) else { return };
+        if buffer.hex {
+            return
+        }
+
+        let trailing = buffer.lines_in(0..buffer.total_lines()).enumerate().filter_map(|(line, view)| {
+            let text = view.to_string();
+            let kept = text.trim_end_matches([' ', '\t']).chars().count();
+            let len = text.chars().count();
+            (kept < len).then_some((line, kept, len - kept))
+        }).collect::<Vec<_>>();
+        if trailing.is_empty() {
+            return
+        }
+
+        buffer.history.break_point();
+        for (line, kept, len) in trailing {
+            buffer.remove_from_line(line, kept, len);
         }
+        buffer.history.break_point();
+        cursor.from_linepos(buffer.clamp_to_char(cursor.to_linepos()));
+    }
+
+    // only touches the lines that changed so the cursor stays on the same text
+    pub fn replace_buffer_text(&mut self, text: &str) {
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get_mut(self.current_buffer) else { return };
+
+        let hunks = diff::apply_to_buffer(buffer, text);
+        let line = diff::map_line(&hunks, cursor.y - 1).min(buffer.total_lines() - 1);
+        let col = (cursor.x - 1).min(buffer.line_len(line).max(1) - 1);
+        cursor.from_linepos(LinePos { line, col });
     }
 
     pub fn handle_input(&mut self, state: &mut State) {
+        // before recording, so a macro replays the same commands on any layout
+        let commands = matches!(self.mode, EditorMode::Normal | EditorMode::Visual | EditorMode::VisualLine);
+        if state.options.physical_keys && commands && !self.motion.wants_char() && !state.io.physical_chars.is_empty() {
+            state.io.chars = std::mem::take(&mut state.io.physical_chars);
+        }
+        let had_input = state.io.has_input();
+        if let Some((_, recorded)) = &mut self.recording {
+            if self.feed_depth <= self.recording_depth {
+                recorded.push_str(&keys::to_notation(&keys::from_io(&state.io)));
+            }
+        }
+        if had_input {
+            self.message.clear();
+            self.hover = None;
+        }
+        for event in std::mem::take(&mut state.io.mouse) {
+            self.handle_mouse(state, event);
+        }
+        if self.resizing && self.resize_keys(state) {
+            return
+        }
+        let inserting = self.mode == EditorMode::Insert || self.mode == EditorMode::Replace;
+        if inserting && self.literal_keys(state) {
+            return
+        }
+        if self.map_keys(state) {
+            return
+        }
+        if self.mode == EditorMode::Insert && self.handle_completion_keys(state) {
+            self.update_diff(state);
+            return
+        }
+
         let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
         let Some(cursor) = self.cursors.get_mut(self.current_buffer) else { return };
-        if self.mode ==  EditorMode::Insert {
+
+        if self.mode == EditorMode::Insert || self.mode == EditorMode::Replace {
             let line = cursor.y - 1;
-            if !state.io.chars.is_empty() {
-                buffer.insert_into_line(line, cursor.x - 1, state.io.chars.as_bytes());
-                cursor.x += state.io.chars.chars().count();
+            // typing past the end of the line with virtualedit fills the gap with spaces
+            let line_len = buffer.line_len(line);
+            if cursor.x - 1 > line_len && state.io.has_input() && !state.io.pressed_special(SpecialKey::Escape) {
+                buffer.insert_into_line(line, line_len, " ".repeat(cursor.x - 1 - line_len).as_bytes());
+            }
+            // ctrl + letter comes in as the plain letter, don't type it
+            if !state.io.chars.is_empty() && !state.io.pressed_special(SpecialKey::Control) {
+                let typed = state.io.chars.chars().next().unwrap_or(' ');
+                if breaks_undo(state.options.undo_chunk, char_before(buffer, cursor), typed) {
+                    buffer.history.break_point();
+                }
+                let count = state.io.chars.chars().count();
+                // replace mode types over the chars under the cursor
+                if self.mode == EditorMode::Replace {
+                    let over = count.min(buffer.line_len(line).saturating_sub(cursor.x - 1));
+                    if over > 0 {
+                        buffer.remove_from_line(line, cursor.x - 1, over);
+                    }
+                }
+                let pairs = state.options.autopairs && self.mode == EditorMode::Insert && count == 1;
+                let (before, after) = (char_before(buffer, cursor), buffer.line(line).chars().nth(cursor.x - 1));
+                if pairs && after == Some(typed) && matches!(typed, ')' | ']' | '}' | '"' | '\'') {
+                    // types over the closing half put in before
+                    cursor.x += 1;
+                } else {
+                    buffer.insert_into_line(line, cursor.x - 1, state.io.chars.as_bytes());
+                    cursor.x += count;
+                    if let Some(close) = auto_pair(typed, before, after).filter(|_| pairs) {
+                        buffer.insert_into_line(line, cursor.x - 1, close.to_string().as_bytes());
+                    }
+                }
+                cursor.wanted_x = cursor.x;
+                // a closing bracket starting a line goes back to its opening line's indent
+                if self.mode == EditorMode::Insert && count == 1 && matches!(typed, '}' | ']' | ')') {
+                    let pos = LinePos { line, col: cursor.x - 2 };
+                    let (tabstop, shiftwidth, expandtab) = (state.options.tabstop, state.options.shiftwidth(&buffer.options), state.options.expandtab(&buffer.options));
+                    cursor.x = align_closing(buffer, pos, tabstop, shiftwidth, expandtab) + 2;
+                    cursor.wanted_x = cursor.x;
+                }
             }
             if state.io.pressed_special(SpecialKey::Enter) {
+                if breaks_undo(state.options.undo_chunk, char_before(buffer, cursor), '\n') {
+                    buffer.history.break_point();
+                }
                 let line_len = buffer.line_len(line);
                 if line_len - (cursor.x - 1) > 0 {
                     buffer.split_line_at_index(line, cursor.x - 1);
@@ -86,30 +802,55 @@ impl Editor {
                 cursor.y += 1;
                 cursor.x = 1;
 
-                let indent = indent_wanted(line + 1, &buffer);
+                let tabstop = state.options.tabstop;
+                let indent = indent_wanted(line + 1, buffer, tabstop, state.options.shiftwidth(&buffer.options));
                 if let Some(indent) = indent {
                     if indent > 0 {
-                        buffer.insert_into_line(line + 1, 0, " ".repeat(indent).as_bytes());
-                        cursor.x = indent + 1;
+                        let text = indent_text(indent, state.options.expandtab(&buffer.options), tabstop);
+                        buffer.insert_into_line(line + 1, 0, text.as_bytes());
+                        cursor.x = text.len() + 1;
                         cursor.wanted_x = cursor.x;
                     }
                 }
             }
             if state.io.pressed_special(SpecialKey::Tab) {
-                buffer.insert_into_line(line, cursor.x - 1, " ".repeat(4).as_bytes());
-                cursor.x += 4;
+                // with expandtab spaces up to the next shiftwidth
+                let text = if state.options.expandtab(&buffer.options) {
+                    let shiftwidth = state.options.shiftwidth(&buffer.options);
+                    let col = width::display_col(&buffer.line(line), cursor.x - 1, state.options.tabstop);
+                    " ".repeat(shiftwidth - col % shiftwidth)
+                } else {
+                    "\t".to_owned()
+                };
+                buffer.insert_into_line(line, cursor.x - 1, text.as_bytes());
+                cursor.x += text.len();
                 cursor.wanted_x = cursor.x;
             }
             if state.io.pressed_special(SpecialKey::Escape) {
+                // backing up over where the insert started drops the repeat
+                if let Some((times, start)) = self.insert_repeat.take().filter(|&(_, start)| start < cursor.to_linepos()) {
+                    let end = cursor.to_linepos();
+                    let typed = buffer.chars.get_by_range(buffer.linepos_to_byte(start)..buffer.linepos_to_byte(end));
+                    let typed = String::from_utf8_lossy(&typed).into_owned();
+                    let end = (0..times).fold(end, |end, _| buffer.insert_text(end, &typed));
+                    cursor.from_linepos(end);
+                }
                 self.mode = EditorMode::Normal;
                 cursor.x -= 1;
                 cursor.x = cursor.x.max(1);
                 cursor.wanted_x = cursor.x;
             }
-            if state.io.pressed_special(SpecialKey::Backspace) {
+            if state.io.pressed_special(SpecialKey::Backspace) && self.mode == EditorMode::Replace {
+                cursor.x = (cursor.x - 1).max(1);
+                cursor.wanted_x = cursor.x;
+            } else if state.io.pressed_special(SpecialKey::Backspace) {
                 let row_len = buffer.line_len(line);
                 if row_len > 0 && cursor.x > 1 {
-                    buffer.remove_from_line(line, cursor.x as usize - 2, 1);
+                    // an empty pair goes together
+                    let after = buffer.line(line).chars().nth(cursor.x - 1);
+                    let pair = char_before(buffer, cursor).and_then(|before| auto_pair(before, None, None));
+                    let len = if state.options.autopairs && pair.is_some() && pair == after { 2 } else { 1 };
+                    buffer.remove_from_line(line, cursor.x as usize - 2, len);
                     cursor.x -= 1;
                     cursor.wanted_x = cursor.x;
                 } else if cursor.x == 1 && cursor.y > 1 {
@@ -121,42 +862,30 @@ impl Editor {
                 }
             }
         } else if self.mode == EditorMode::CommandBar {
-            if !state.io.chars.is_empty() {
-                self.command_bar_input.push_str(&state.io.chars);
-                state.cmd_bar_cursor_x += state.io.chars.chars().count();
+            // tab and the arrows move through an open wildmenu, other keys close it
+            let step = if state.io.pressed_special(SpecialKey::Tab) || state.io.pressed_special(SpecialKey::Right) {
+                1
+            } else if state.io.pressed_special(SpecialKey::Left) {
+                -1
+            } else {
+                0
+            };
+            if let Some(wildmenu) = self.wildmenu.as_mut().filter(|_| step != 0) {
+                self.command_bar_input = format!(":{}", wildmenu.step(step));
+                state.cmd_bar_cursor_x = self.command_bar_input.chars().count();
+                return
+            }
+            if had_input {
+                self.wildmenu = None;
             }
-            if state.io.pressed_special(SpecialKey::Enter) {
-                let parts = self.command_bar_input.splitn(2, " ").collect::<Vec<_>>();
-                let func = match_cmd(&parts[0][1..]);
-                let Some(func) = func else { return };
-                let res = if parts.len() > 1 {
-                    func(state, &self, parts[1])
-                } else {
-                    func(state, &self, &"")
-                };
-
-                match res {
-                    Ok(CommandBarAction::NewBuffer(buf)) => {
-                        self.cursors.push(CursorPos::new(buf.id));
-                        self.buffers.push(buf);
-                        self.current_buffer = self.buffers.len() - 1;
-                    },
-                    Ok(CommandBarAction::SwitchToBuffer(buf)) => {
-                        self.current_buffer = buf;
-                    },
-                    Ok(CommandBarAction::None) => {}, 
-                    Err(_) => todo!(),
-                    _ => todo!(),
-                }
 
-                //println!("executing cmd: {}", self.command_bar_input);
+            edit_prompt_input(&mut self.command_bar_input, state);
+            if state.io.pressed_special(SpecialKey::Enter) {
+                let input = self.command_bar_input[1..].to_owned();
                 state.cmd_bar_cursor_x = 1;
                 self.command_bar_input.clear();
                 self.mode = EditorMode::Normal;
-            }
-            if state.io.pressed_special(SpecialKey::Backspace) {
-                self.command_bar_input.pop();
-                state.cmd_bar_cursor_x -= 1;
+                self.run_command(state, &input);
             }
             if state.io.pressed_special(SpecialKey::Escape) {
                 self.command_bar_input.clear();
@@ -165,23 +894,53 @@ impl Editor {
             if self.command_bar_input.is_empty() {
                 self.mode = EditorMode::Normal;
             }
-        } else if self.mode == EditorMode::Search {
-            if !state.io.chars.is_empty() {
-                self.command_bar_input.push_str(&state.io.chars);
-                state.cmd_bar_cursor_x += 1;
-                let positions = search(&self.command_bar_input.as_bytes()[1..], &buffer);
-                self.search_results = positions;
+
+            // only completes at the end of the input
+            let at_end = state.cmd_bar_cursor_x == self.command_bar_input.chars().count();
+            if self.mode == EditorMode::CommandBar && at_end && state.io.pressed_special(SpecialKey::Tab) {
+                if let Some((completed, wildmenu)) = complete(&self.command_bar_input[1..], state, self) {
+                    self.command_bar_input = format!(":{completed}");
+                    state.cmd_bar_cursor_x = self.command_bar_input.chars().count();
+                    self.wildmenu = wildmenu;
+                }
             }
-            if state.io.pressed_special(SpecialKey::Backspace) {
-                self.command_bar_input.pop();
-                state.cmd_bar_cursor_x -= 1;
+        } else if self.mode == EditorMode::Search {
+            if edit_prompt_input(&mut self.command_bar_input, state) && self.command_bar_input.len() > 1 {
+                let needle = &self.command_bar_input.as_bytes()[1..];
+                if buffer.chars.len() > ASYNC_SEARCH_THRESHOLD {
+                    self.search_job = Some(SearchJob::spawn(needle, buffer, &self.tasks));
+                } else {
+                    self.search_job = None;
+                    self.search_results = search(needle, buffer);
+                }
             }
             if state.io.pressed_special(SpecialKey::Enter) {
-                if let Some(pos) = closest_position(cursor.to_linepos(), &self.search_results) {
-                    cursor.from_linepos(pos);
+                // the jump needs the results
+                if let Some(job) = self.search_job.take() {
+                    self.search_results = job.wait(buffer);
                 }
+                // an operator goes up to the next match, not one under the cursor
+                let search_motion = self.search_motion.take();
+                let found = if search_motion.is_some() {
+                    next_position(cursor.to_linepos(), &self.search_results)
+                } else {
+                    closest_position(cursor.to_linepos(), &self.search_results)
+                };
                 self.command_bar_input.clear();
                 self.mode = EditorMode::Normal;
+                match found {
+                    Some((_, true)) if !state.options.wrap_scan => self.message = wrap_message(true, false),
+                    Some((pos, wrapped)) => {
+                        if wrapped {
+                            self.message = wrap_message(true, true);
+                        }
+                        match search_motion {
+                            Some((motion, from)) => self.operate_to(state, motion, from, pos),
+                            None => cursor.from_linepos(pos),
+                        }
+                    },
+                    None => {},
+                }
             }
             if state.io.pressed_special(SpecialKey::Escape) {
                 self.command_bar_input.clear();
@@ -189,52 +948,964 @@ impl Editor {
             }
             if self.command_bar_input.is_empty() {
                 self.mode = EditorMode::Normal;
+                self.search_motion = None;
             }
         } else {
-            let chars = state.io.chars.chars().collect::<Vec<_>>();
+            let mut chars = state.io.chars.chars().collect::<Vec<_>>();
+            // home is 0, a count typed before it doesn't take it as a digit
+            if state.io.pressed_special(SpecialKey::Home) {
+                if let Some(Modifier::Count(_)) = self.motion.modifier {
+                    self.motion.modifier = None;
+                }
+                chars.push('0');
+            }
             for char in chars {
-                self.motion.parse(&state, char, self.mode);
+                if char == 'q' && self.motion.is_empty() && !state.io.pressed_special(SpecialKey::Control) {
+                    if let Some((register, recorded)) = self.recording.take() {
+                        // the q that stopped the recording was recorded too
+                        let recorded = recorded.strip_suffix('q').unwrap_or(&recorded);
+                        self.macros.insert(register, recorded.to_owned());
+                        continue
+                    }
+                }
+                // plugin maps are checked first, written like the motion keys with ^ for ctrl
+                #[cfg(feature = "scripting")]
+                {
+                    let ctrl = if state.io.pressed_special(SpecialKey::Control) { "^" } else { "" };
+                    let keys = format!("{}{ctrl}{char}", self.motion.keys);
+                    if let Some(func) = self.scripting.map(&keys).cloned() {
+                        self.motion.clear();
+                        self.call_script(state, &func, "");
+                        continue
+                    }
+                }
+                if !self.motion.parse(state, char, self.mode) {
+                    // the keys so far are kept until they make a whole map
+                    #[cfg(feature = "scripting")]
+                    if self.scripting.is_map_prefix(&self.motion.keys) {
+                        continue
+                    }
+                    self.message = format!("Unknown key sequence: {}", self.motion.keys);
+                    self.motion.clear();
+                    self.bell(state);
+                    continue
+                }
+                let deleting = self.motion.action == Some(Action::Delete);
+                if deleting {
+                    if let Some(buffer) = self.buffers.get_mut(self.current_buffer) {
+                        buffer.history.collect_removed();
+                    }
+                }
                 if self.execute_cmd(state) {
+                    if deleting {
+                        self.save_deleted_text();
+                    }
                     self.motion.clear();
                 }
             }
             //self.execute_commands(state);
             if state.io.pressed_special(SpecialKey::Escape) {
                 self.motion.clear();
+                if matches!(self.mode, EditorMode::Visual | EditorMode::VisualLine) {
+                    let anchor = self.visual_range_anchor;
+                    let cursor = self.cursors[self.current_buffer].to_linepos();
+                    self.visual_marks = Some((anchor.min(cursor), anchor.max(cursor)));
+                }
                 self.mode = EditorMode::Normal;
             }
         } 
+
+        // the cursor isn't left inside a closed fold, typing in one opens it
+        if let (Some(buffer), Some(cursor)) = (self.buffers.get_mut(self.current_buffer), self.cursors.get_mut(self.current_buffer)) {
+            let pos = cursor.to_linepos();
+            if matches!(self.mode, EditorMode::Insert | EditorMode::Replace) {
+                buffer.folds.reveal(pos.line);
+            } else if buffer.folds.is_hidden(pos.line) {
+                cursor.from_linepos(buffer.clamp_to_char(LinePos::new(buffer.folds.shown_line(pos.line), pos.col)));
+            }
+        }
+
+        // everything done in one insert session is undone together
+        if self.mode != EditorMode::Insert && self.mode != EditorMode::Replace {
+            if let Some(buffer) = self.buffers.get_mut(self.current_buffer) {
+                buffer.history.break_point();
+            }
+        }
+        if self.motion.keys.is_empty() && self.map_pending.is_empty() {
+            self.pending_since = None;
+        } else if had_input && state.options.which_key > 0 {
+            self.pending_since = Some(Instant::now());
+            state.scheduler.redraw_after(Duration::from_millis(state.options.which_key as u64));
+        }
+        self.update_completion();
+        self.update_diff(state);
+        if had_input {
+            self.update_folds(state);
+        }
     }
 
-    fn execute_cmd(&mut self, state: &mut State) -> bool {
-        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return true };
-        let Some(current_cursor) = self.cursors.get_mut(self.current_buffer) else { return true };
-        let Some(obj) = self.motion.object else { return false };
-        let cursor = current_cursor.to_linepos();
+    // h and l make the focused window narrower and wider, k and j shorter and
+    // taller, until escape or enter. returns true if the input was used up
+    fn resize_keys(&mut self, state: &mut State) -> bool {
+        if state.io.pressed_special(SpecialKey::Escape) || state.io.pressed_special(SpecialKey::Enter) {
+            self.resizing = false;
+            return true
+        }
+        let step = RESIZE_STEP as isize;
+        for c in std::mem::take(&mut state.io.chars).chars() {
+            let resized = match c {
+                'h' => self.layout.resize(Axis::Cols, -step),
+                'l' => self.layout.resize(Axis::Cols, step),
+                'k' => self.layout.resize(Axis::Rows, -step),
+                'j' => self.layout.resize(Axis::Rows, step),
+                _ => true,
+            };
+            if !resized {
+                self.bell(state);
+            }
+        }
+        self.message = "-- RESIZE --".to_owned();
+        true
+    }
 
-        match obj {
-            Object::BackWord => 'b: {
-                let Some(pos) = find_previous_word_start(cursor, &buffer) else { break 'b };
-                if self.motion.action == Some(Action::Delete) {
-                    buffer.remove_by_range(pos, cursor);
-                }
-                current_cursor.from_linepos(pos);
-            },
-            Object::BackWORD => {
-                todo!();
-                //let Some(pos) = find_previous_WORD_start(cursor, &buffer) else { break 'b };
-            },
-            Object::Word => 'b: {
-                if self.motion.modifier == Some(Modifier::Inside) {
-                    let Some(start) = find_current_word_start(cursor, &buffer) else { break 'b };
-                    let Some(end) = find_current_word_end(cursor, &buffer) else { break 'b };
-                    if self.motion.action == Some(Action::Delete) {
-                        buffer.remove_from_line(cursor.line, start.col, end.col - start.col + 1);
-                        current_cursor.x = ((start.col + 1).min(buffer.line_len(cursor.line))).max(1);
-                        current_cursor.wanted_x = current_cursor.x;
-                    } else if self.mode == EditorMode::Visual {
-                        self.visual_range_anchor = start;
-                        current_cursor.from_linepos(end);
+    // ctrl-k and ctrl-v start a char typed by name, the keys after them are
+    // used up until it's done and then the char goes on as if it was typed.
+    // returns true if the input was used up
+    fn literal_keys(&mut self, state: &mut State) -> bool {
+        let ctrl = state.io.pressed_special(SpecialKey::Control);
+        let Some(literal) = &mut self.literal else {
+            if ctrl && (state.io.pressed_char('k') || state.io.pressed_char('v')) {
+                let literal = if state.io.pressed_char('k') { Literal::Digraph(Vec::new()) } else { Literal::Codepoint(String::new()) };
+                self.message = literal.keys();
+                self.literal = Some(literal);
+                return true
+            }
+            return false
+        };
+
+        // a tab right after ctrl-v is a real one even with expandtab
+        if *literal == Literal::Codepoint(String::new()) && state.io.pressed_special(SpecialKey::Tab) {
+            self.literal = None;
+            state.io.special_keys.retain(|key| *key != SpecialKey::Tab);
+            state.io.chars.insert(0, '\t');
+            return false
+        }
+        let other_keys = state.io.special_keys.iter().any(|key| *key != SpecialKey::Control);
+        if state.io.chars.is_empty() && !other_keys {
+            return true
+        }
+
+        let mut typed = String::new();
+        for c in std::mem::take(&mut state.io.chars).chars() {
+            match &mut self.literal {
+                Some(literal) => if let Some(text) = literal.push(c) {
+                    typed.push_str(&text);
+                    self.literal = None;
+                },
+                None => typed.push(c),
+            }
+        }
+        // enter, escape and the like end it with what was typed and then do their thing
+        if let Some(literal) = self.literal.take_if(|_| other_keys) {
+            typed.insert_str(0, &literal.finish());
+        }
+        match &self.literal {
+            Some(literal) => self.message = literal.keys(),
+            None => self.message.clear(),
+        }
+        state.io.chars = typed;
+        self.literal.is_some() && !other_keys
+    }
+
+    // ctrl-n / ctrl-p open the completion menu and move in it, ctrl-y, enter or tab
+    // accept and ctrl-e closes it. returns true if the input was used up
+    fn handle_completion_keys(&mut self, state: &State) -> bool {
+        let ctrl = state.io.pressed_special(SpecialKey::Control);
+        if ctrl && (state.io.pressed_char('n') || state.io.pressed_char('p')) {
+            match &mut self.completion {
+                Some(menu) if state.io.pressed_char('n') => menu.select_next(),
+                Some(menu) => menu.select_previous(),
+                None => self.start_completion(),
+            }
+            return true
+        }
+
+        if self.completion.is_none() {
+            return false
+        }
+        if (ctrl && state.io.pressed_char('y')) || state.io.pressed_special(SpecialKey::Enter) || state.io.pressed_special(SpecialKey::Tab) {
+            self.accept_completion();
+            return true
+        }
+        if ctrl && state.io.pressed_char('e') {
+            self.completion = None;
+            return true
+        }
+
+        false
+    }
+
+    // asks the buffer's language server for completions, the menu opens when the
+    // answer comes in. without a server words from the buffer are used
+    fn start_completion(&mut self) {
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get(self.current_buffer) else { return };
+        let pos = cursor.to_linepos();
+
+        if let Some(client) = lsp_client(&mut self.lsp_clients, buffer, &self.root_folder) {
+            client.request_completion(buffer, pos);
+            return
+        }
+
+        self.open_completion_menu(None, pos);
+    }
+
+    fn open_completion_menu(&mut self, items: Option<Vec<CompletionItem>>, pos: LinePos) {
+        let Some(buffer) = self.buffers.get(self.current_buffer) else { return };
+        let start = word_start(buffer, pos);
+        let typed = buffer.line(pos.line).chars().skip(start.col).take(pos.col - start.col).collect::<String>();
+
+        let items = match items {
+            Some(items) if !items.is_empty() => items,
+            _ => buffer_words(buffer, &typed, pos),
+        };
+        self.completion = CompletionMenu::new(items, start, &typed);
+    }
+
+    fn accept_completion(&mut self) {
+        let Some(menu) = self.completion.take() else { return };
+        let Some(item) = menu.selected_item() else { return };
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get_mut(self.current_buffer) else { return };
+        let pos = cursor.to_linepos();
+
+        let start = item.replace_start.unwrap_or(menu.start);
+        if start.line != pos.line || start.col > pos.col {
+            return
+        }
+        let end = LinePos::new(pos.line, (pos.col + item.replace_after).min(buffer.line_len(pos.line)));
+
+        // the other edits can't overlap the item's, from the bottom up so each
+        // one's place is still where the server said
+        let mut edits = item.additional_edits.iter().filter(|edit| edit.end <= start || edit.start >= end).collect::<Vec<_>>();
+        edits.sort_by_key(|edit| edit.start);
+        for edit in edits.iter().rev().filter(|edit| edit.start >= end) {
+            buffer.replace_range(edit.start, edit.end, &edit.text);
+        }
+        let mut after = buffer.replace_range(start, end, &item.text);
+        for edit in edits.iter().rev().filter(|edit| edit.end <= start) {
+            let edit_end = buffer.replace_range(edit.start, edit.end, &edit.text);
+            after = if after.line == edit.end.line {
+                LinePos::new(edit_end.line, edit_end.col + after.col - edit.end.col)
+            } else {
+                LinePos::new(after.line - edit.end.line + edit_end.line, after.col)
+            };
+        }
+        cursor.from_linepos(after);
+    }
+
+    // narrows the menu down to what has been typed since it opened
+    fn update_completion(&mut self) {
+        let Some(menu) = &mut self.completion else { return };
+        let Some(cursor) = self.cursors.get(self.current_buffer) else { return };
+        let pos = cursor.to_linepos();
+        if self.mode != EditorMode::Insert || pos.line != menu.start.line || pos.col < menu.start.col {
+            self.completion = None;
+            return
+        }
+
+        let line = self.buffers[self.current_buffer].line(pos.line);
+        let typed = line.chars().skip(menu.start.col).take(pos.col - menu.start.col).collect::<String>();
+        menu.filter(&typed);
+        if menu.shown.is_empty() {
+            self.completion = None;
+        }
+    }
+
+    // adds what's been read of big files, called every frame. true if any buffer grew
+    pub fn poll_loading(&mut self) -> bool {
+        let mut loaded = false;
+        for i in 0..self.buffers.len() {
+            let (was_loading, was_lossy) = (self.buffers[i].loading.is_some(), self.buffers[i].lossy);
+            let name = self.buffers[i].file_path.as_ref().map_or(String::new(), |path| path.display().to_string());
+            match self.buffers[i].poll_loading() {
+                Ok(grew) => {
+                    loaded |= grew;
+                    if self.buffers[i].lossy && !was_lossy {
+                        self.notify(format!("{name} isn't all utf-8, opened readonly so it can't be saved over"), Level::Error);
+                    }
+                },
+                Err(err) => {
+                    self.notify(format!("Couldn't read all of {name} ({err}), opened readonly so it can't be saved over"), Level::Error);
+                    loaded = true;
+                },
+            }
+            // the remembered place may not have been loaded yet when the file was opened
+            if was_loading && self.buffers[i].loading.is_none() && self.cursors[i].to_linepos() == LinePos::new(0, 0) {
+                self.restore_position(i);
+                loaded = true;
+            }
+        }
+
+        loaded
+    }
+
+    // shows buffer i where its view was left, keeping the one being left for later
+    pub fn switch_to(&mut self, state: &mut State, i: usize) {
+        if let Some(cursor) = self.cursors.get_mut(self.current_buffer) {
+            cursor.start_line = state.start_line;
+        }
+        self.remember_position(self.current_buffer);
+        self.current_buffer = i;
+        if let Some(cursor) = self.cursors.get(i) {
+            state.start_line = cursor.start_line;
+        }
+    }
+
+    // moves into dir, relative to the folder now. only while the current buffer
+    // is shown if local
+    pub fn change_dir(&mut self, dir: &Path, local: bool) {
+        let dir = match self.dir().join(dir).canonicalize() {
+            Ok(dir) if dir.is_dir() => dir,
+            _ => {
+                self.message = format!("No such folder: {}", dir.display());
+                return
+            },
+        };
+        let Some(id) = self.buffers.get(self.current_buffer).map(|buffer| buffer.id) else { return };
+        if local {
+            self.local_dirs.insert(id, dir);
+        } else {
+            self.local_dirs.remove(&id);
+            self.global_dir = dir;
+        }
+    }
+
+    // the current buffer's folder from :lcd, otherwise the one from :cd.
+    // relative paths in commands are from here
+    pub fn dir(&self) -> &Path {
+        let local = self.buffers.get(self.current_buffer).and_then(|buffer| self.local_dirs.get(&buffer.id));
+        local.unwrap_or(&self.global_dir)
+    }
+
+    // moves the process into dir, for shell commands, formatters and language
+    // servers. called by the frontends after the input is handled
+    pub fn enter_dir(&mut self) {
+        let dir = self.dir().to_owned();
+        if env::current_dir().is_ok_and(|cwd| cwd == dir) {
+            return
+        }
+        if let Err(e) = env::set_current_dir(&dir) {
+            self.message = format!("{}: {e}", dir.display());
+        }
+    }
+
+    // the recent files list and where the first buffer was left last time
+    pub fn load_recent(&mut self, state: &mut State) {
+        self.recent = RecentFiles::load();
+        self.restore_position(self.current_buffer);
+        state.start_line = self.cursors[self.current_buffer].start_line;
+    }
+
+    // after a panic, every buffer with unsaved changes is written to dir as
+    // "{time}-{id}-{name}". returns the files written
+    pub fn save_recovery(&self, dir: &Path) -> Vec<PathBuf> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let mut saved = Vec::new();
+        for buffer in self.buffers.iter().filter(|buffer| buffer.is_modified()) {
+            let name = buffer.file_path.as_ref().and_then(|path| path.file_name()).map_or("unnamed".into(), |name| name.to_string_lossy());
+            let path = dir.join(format!("{time}-{}-{name}", buffer.id));
+            if fs::create_dir_all(dir).is_ok() && fs::write(&path, buffer.recovery_data()).is_ok() {
+                saved.push(path);
+            }
+        }
+
+        saved
+    }
+
+    // keeps where every file's cursor and view are, for the next time they're opened
+    pub fn save_recent(&mut self, state: &State) {
+        if let Some(cursor) = self.cursors.get_mut(self.current_buffer) {
+            cursor.start_line = state.start_line;
+        }
+        // the open files go first for the next time, the current one at the top
+        let current = self.current_buffer;
+        for i in (0..self.buffers.len()).filter(|&i| i != current).chain([current]) {
+            let (buffer, cursor) = (&self.buffers[i], &self.cursors[i]);
+            if let Some(path) = &buffer.file_path {
+                self.recent.remember(path, cursor.to_linepos(), cursor.start_line);
+            }
+        }
+        self.recent.save();
+    }
+
+    fn remember_position(&mut self, i: usize) {
+        let (Some(buffer), Some(cursor)) = (self.buffers.get(i), self.cursors.get(i)) else { return };
+        let Some(path) = &buffer.file_path else { return };
+        self.recent.update(path, cursor.to_linepos(), cursor.start_line);
+    }
+
+    // puts the cursor of a file opened again where it was left
+    fn restore_position(&mut self, i: usize) {
+        let (Some(buffer), Some(cursor)) = (self.buffers.get(i), self.cursors.get_mut(i)) else { return };
+        let Some(file) = buffer.file_path.as_ref().and_then(|path| self.recent.get(path)) else { return };
+        let pos = buffer.clamp_to_char(file.pos);
+        cursor.from_linepos(pos);
+        cursor.start_line = file.start_line.min(pos.line);
+    }
+
+    pub fn loading(&self) -> bool {
+        self.buffers.iter().any(|buffer| buffer.loading.is_some())
+    }
+
+    // true when a running search finished and the highlights changed
+    pub fn poll_search(&mut self) -> bool {
+        let Some(job) = &mut self.search_job else { return false };
+        let Some(buffer) = self.buffers.iter().find(|buffer| buffer.id == job.buffer_id) else {
+            self.search_job = None;
+            return false
+        };
+        let Some(results) = job.poll(buffer, &self.tasks) else { return false };
+        self.notify(format!("Search done, {} matches", results.len()), Level::Info);
+        self.search_results = results;
+        self.search_job = None;
+        true
+    }
+
+    // handles messages from language servers, called every frame
+    // true if a server answered, what's on screen may have changed
+    pub fn poll_lsp(&mut self) -> bool {
+        let mut events = Vec::new();
+        for client in &mut self.lsp_clients {
+            events.extend(client.poll());
+        }
+        let answered = !events.is_empty();
+
+        for event in events {
+            match event {
+                LspEvent::Completion { buffer, pos, result } => {
+                    let Some(current) = self.buffers.get(self.current_buffer) else { continue };
+                    let cursor = self.cursors[self.current_buffer].to_linepos();
+                    // the answer is stale if the user moved on
+                    if current.id != buffer || self.mode != EditorMode::Insert || cursor.line != pos.line {
+                        continue
+                    }
+                    let items = lsp::completion_items(&result, current, pos);
+                    self.open_completion_menu(Some(items), cursor);
+                },
+                LspEvent::Hover { buffer, result } => {
+                    if self.buffers.get(self.current_buffer).map(|b| b.id) != Some(buffer) {
+                        continue
+                    }
+
+                    let lines = lsp::hover_lines(&result);
+                    if lines.is_empty() {
+                        self.message = "No hover information".to_owned();
+                    } else {
+                        self.hover = Some(lines);
+                    }
+                },
+                LspEvent::Started { command } => self.notify(format!("{command} started"), Level::Info),
+                LspEvent::Diagnostics { path, annotations } => {
+                    // only open files get them, the server sends them again when one's opened
+                    let wanted = path.canonicalize().unwrap_or(path);
+                    let buffer = self.buffers.iter_mut().find(|buffer| {
+                        let Some(path) = &buffer.file_path else { return false };
+                        path.canonicalize().unwrap_or_else(|_| path.clone()) == wanted
+                    });
+                    if let Some(buffer) = buffer {
+                        buffer.virtual_text.replace("lsp", annotations);
+                    }
+                },
+                LspEvent::Locations { buffer, what, result } => {
+                    if self.buffers.get(self.current_buffer).map(|b| b.id) != Some(buffer) {
+                        continue
+                    }
+
+                    let locations = lsp::locations(&result);
+                    match locations.len() {
+                        0 => self.message = format!("No {what} found"),
+                        1 => self.jump_to_location(&locations[0]),
+                        total => {
+                            let first = locations[0].clone();
+                            self.quickfix = locations;
+                            self.quickfix_index = 0;
+                            self.jump_to_location(&first);
+                            self.message = format!("(1 of {total}) {what}");
+                        },
+                    }
+                },
+            }
+        }
+
+        answered
+    }
+
+    // opens path as a new buffer unless it's open already, returns the buffer index
+    pub fn open_file(&mut self, path: &Path) -> io::Result<usize> {
+        let wanted = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let open = self.buffers.iter().position(|buffer| {
+            let Some(path) = &buffer.file_path else { return false };
+            path.canonicalize().unwrap_or_else(|_| path.clone()) == wanted
+        });
+        if let Some(i) = open {
+            return Ok(i)
+        }
+
+        let buffer = TextBuffer::from_path(next_buffer_id(), path)?;
+        self.cursors.push(CursorPos::new(buffer.id));
+        self.buffers.push(buffer);
+        Ok(self.buffers.len() - 1)
+    }
+
+    // clicks move the cursor, double clicks select a word and triple clicks the line.
+    // dragging selects from where the button was pressed. the command bar keeps its focus
+    fn handle_mouse(&mut self, state: &mut State, event: MouseEvent) {
+        if matches!(self.mode, EditorMode::CommandBar | EditorMode::Search) {
+            return
+        }
+
+        match event {
+            MouseEvent::Press(x, y) if x >= state.width as f32 - state.scrollbar_width() => self.scrollbar_click(state, y),
+            MouseEvent::Press(x, y) => {
+                let Some(pos) = self.pos_at_pixel(state, x, y) else { return };
+                let clicks = match self.last_click {
+                    Some((at, last, clicks)) if last == pos && at.elapsed() < DOUBLE_CLICK => clicks % 3 + 1,
+                    _ => 1,
+                };
+                self.last_click = Some((Instant::now(), pos, clicks));
+                self.completion = None;
+                self.hover = None;
+                self.motion.clear();
+                self.drag_anchor = None;
+
+                let buffer = &self.buffers[self.current_buffer];
+                let cursor = &mut self.cursors[self.current_buffer];
+                match clicks {
+                    1 => {
+                        if matches!(self.mode, EditorMode::Visual | EditorMode::VisualLine) {
+                            self.mode = EditorMode::Normal;
+                        }
+                        self.drag_anchor = Some(pos);
+                        cursor.from_linepos(pos);
+                    },
+                    2 => {
+                        let (start, end) = word_bounds(&buffer.line(pos.line), pos.col).unwrap_or((pos.col, pos.col));
+                        self.mode = EditorMode::Visual;
+                        self.visual_range_anchor = LinePos { line: pos.line, col: start };
+                        cursor.from_linepos(LinePos { line: pos.line, col: end });
+                    },
+                    _ => {
+                        self.mode = EditorMode::VisualLine;
+                        self.visual_range_anchor = pos;
+                        cursor.from_linepos(pos);
+                    },
+                }
+            },
+            MouseEvent::Drag(x, y) => {
+                let Some(anchor) = self.drag_anchor else { return };
+                let Some(pos) = self.pos_at_pixel(state, x, y) else { return };
+                if pos == anchor && self.mode != EditorMode::Visual {
+                    return
+                }
+
+                // insert mode lets the anchor be after the last char
+                let buffer = &self.buffers[self.current_buffer];
+                let anchor_col = anchor.col.min(buffer.line_len(anchor.line).max(1) - 1);
+                self.mode = EditorMode::Visual;
+                self.visual_range_anchor = LinePos { line: anchor.line, col: anchor_col };
+                let col = pos.col.min(buffer.line_len(pos.line).max(1) - 1);
+                self.cursors[self.current_buffer].from_linepos(LinePos { line: pos.line, col });
+            },
+            MouseEvent::Release => self.drag_anchor = None,
+        }
+    }
+
+    // clicking the scrollbar jumps to that far into the file, the line goes in the middle
+    fn scrollbar_click(&mut self, state: &mut State, y: f32) {
+        let Some(buffer) = self.buffers.get(self.current_buffer) else { return };
+        let fraction = (y / state.height as f32).clamp(0.0, 1.0);
+        let line = ((fraction * buffer.total_lines() as f32) as usize).min(buffer.total_lines() - 1);
+
+        self.completion = None;
+        self.hover = None;
+        self.motion.clear();
+        if matches!(self.mode, EditorMode::Visual | EditorMode::VisualLine) {
+            self.mode = EditorMode::Normal;
+        }
+        self.cursors[self.current_buffer].from_linepos(LinePos { line, col: 0 });
+        state.start_line = line.saturating_sub(state.max_rows() / 2);
+    }
+
+    // the text position under a pixel, clamped to the text like cursor movement is
+    fn pos_at_pixel(&self, state: &State, x: f32, y: f32) -> Option<LinePos> {
+        let buffer = self.buffers.get(self.current_buffer)?;
+        let row = (y.max(0.0) / state.char_height) as usize;
+        let cell = (x.max(0.0) / state.char_width) as usize;
+
+        let rows = wrap::screen_rows(buffer, state);
+        let row = *rows.get(row).or(rows.last())?;
+        let line = row.line;
+        let col = row.start + width::col_at_display(&row.text(&buffer.line(line)), cell, state.options.tabstop);
+        // insert mode can be after the last char, clicks past a wrapped row stay on it
+        let last_col = match self.mode {
+            _ if row.end < buffer.line_len(line) => row.end - 1,
+            EditorMode::Insert | EditorMode::Replace => buffer.line_len(line),
+            _ => buffer.line_len(line).max(1) - 1,
+        };
+
+        Some(LinePos { line, col: col.min(last_col) })
+    }
+
+    fn push_jump(&mut self) {
+        let Some(buffer) = self.buffers.get(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get(self.current_buffer) else { return };
+        self.jump_list.push((buffer.id, cursor.to_linepos()));
+    }
+
+    pub fn jump_to_location(&mut self, location: &Location) {
+        let i = match self.open_file(&location.path) {
+            Ok(i) => i,
+            Err(e) => {
+                self.message = format!("Can't open {}: {e}", location.path.display());
+                return
+            },
+        };
+        self.push_jump();
+        self.current_buffer = i;
+
+        let buffer = &self.buffers[i];
+        let line = location.line.min(buffer.total_lines() - 1);
+        let col = lsp::col_from_utf16(&buffer.line(line), location.character);
+        self.cursors[i].from_linepos(LinePos { line, col });
+    }
+
+    fn jump_back(&mut self) {
+        let Some(jump) = self.jump_list.pop() else { return };
+        self.return_to(jump);
+    }
+
+    fn return_to(&mut self, (id, pos): (usize, LinePos)) {
+        let Some(i) = self.buffers.iter().position(|buffer| buffer.id == id) else { return };
+        self.current_buffer = i;
+
+        // the buffer may have changed since
+        let buffer = &self.buffers[i];
+        self.cursors[i].from_linepos(buffer.clamp_to_char(pos));
+    }
+
+    fn goto_tag(&mut self) {
+        let Some(buffer) = self.buffers.get(self.current_buffer) else { return };
+        let Some(cursor) = self.cursors.get(self.current_buffer) else { return };
+        let Some(name) = identifier_at(&buffer.line(cursor.y - 1), cursor.x - 1) else { return };
+        let from = (buffer.id, cursor.to_linepos());
+
+        if self.tags.as_ref().is_none_or(TagFile::is_stale) {
+            self.tags = TagFile::load(&self.root_folder.join("tags")).ok();
+        }
+        let Some(tags) = &self.tags else {
+            self.message = "No tags file".to_owned();
+            return
+        };
+        let found = tags.find(&name);
+        let Some(tag) = found.first().map(|&tag| tag.clone()) else {
+            self.message = format!("Tag not found: {name}");
+            return
+        };
+        if found.len() > 1 {
+            self.message = format!("Tag 1 of {}", found.len());
+        }
+
+        let i = match self.open_file(&tag.path) {
+            Ok(i) => i,
+            Err(e) => {
+                self.message = format!("Can't open {}: {e}", tag.path.display());
+                return
+            },
+        };
+        let buffer = &self.buffers[i];
+        let line = match &tag.address {
+            Address::Line(line) => *line,
+            address => (0..buffer.total_lines()).find(|&line| address.matches(&buffer.line(line))).unwrap_or(0),
+        };
+
+        self.tag_stack.push(from);
+        self.jump_to_location(&Location { path: tag.path, line, character: 0 });
+    }
+
+    fn quickfix_move(&mut self, by: isize) {
+        if self.quickfix.is_empty() {
+            self.message = "Quickfix list is empty".to_owned();
+            return
+        }
+
+        let last = self.quickfix.len() - 1;
+        self.quickfix_index = self.quickfix_index.saturating_add_signed(by).min(last);
+        let location = self.quickfix[self.quickfix_index].clone();
+        self.jump_to_location(&location);
+        self.message = format!("({} of {}) {}", self.quickfix_index + 1, self.quickfix.len(), location.path.display());
+    }
+
+    // what the focused window shows, kept in the layout while another one is focused
+    fn current_view(&self, state: &State) -> View {
+        let cursor = self.cursors.get(self.current_buffer).map_or(LinePos::new(0, 0), CursorPos::to_linepos);
+        View { buffer: self.current_buffer, cursor, start_line: state.start_line }
+    }
+
+    // the focused window shows view again, its buffer may have changed since
+    fn show_view(&mut self, state: &mut State, view: View) {
+        let Some(buffer) = self.buffers.get(view.buffer) else { return };
+        let cursor = buffer.clamp_to_char(view.cursor);
+        let start_line = view.start_line.min(buffer.total_lines() - 1);
+        if view.buffer != self.current_buffer {
+            self.switch_to(state, view.buffer);
+        }
+        self.cursors[view.buffer].from_linepos(cursor);
+        state.start_line = start_line;
+    }
+
+    // ctrl-w and :split, :vsplit, :close and :only
+    pub fn window_command(&mut self, state: &mut State, command: WindowCommand) {
+        if matches!(self.mode, EditorMode::Visual | EditorMode::VisualLine) {
+            self.mode = EditorMode::Normal;
+        }
+        match command {
+            WindowCommand::Split(axis) => self.layout.split(axis, self.current_view(state)),
+            WindowCommand::Close => match self.layout.close() {
+                Some(view) => self.show_view(state, view),
+                None => self.message = "Can't close the last window".to_owned(),
+            },
+            WindowCommand::Only => self.layout.only(),
+            WindowCommand::Zoom => self.layout.toggle_zoom(),
+            WindowCommand::Resize if self.layout.is_zoomed() || !self.layout.is_split() => self.message = "No split to resize".to_owned(),
+            WindowCommand::Resize => {
+                self.resizing = true;
+                self.message = "-- RESIZE --".to_owned();
+            },
+            WindowCommand::Next => self.focus_window(state, self.layout.next()),
+            WindowCommand::Focus(direction) => {
+                if let Some(id) = self.layout.neighbor(direction) {
+                    self.focus_window(state, id);
+                }
+            },
+        }
+    }
+
+    pub fn focus_window(&mut self, state: &mut State, id: usize) {
+        if id == self.layout.focused {
+            return
+        }
+        if let Some(view) = self.layout.focus(id, self.current_view(state)) {
+            self.show_view(state, view);
+        }
+    }
+
+    // the operator goes over the lines from the cursor to where the motion
+    // moves it. == takes a count of lines and in visual mode the selection's
+    fn execute_operator(&mut self, state: &mut State, operator: Operator) -> bool {
+        let cursor = self.cursors[self.current_buffer].to_linepos();
+        let (start, end) = match self.motion.object {
+            Some(Object::VisualSelection) => {
+                self.mode = EditorMode::Normal;
+                let anchor = self.visual_range_anchor.line;
+                (anchor.min(cursor.line), anchor.max(cursor.line))
+            },
+            Some(Object::Line) if self.motion.action.is_none() => {
+                let count = match self.motion.modifier {
+                    Some(Modifier::Count(n)) => n as usize,
+                    _ => 1,
+                };
+                (cursor.line, cursor.line + count - 1)
+            },
+            _ => {
+                // run as a move, keys that edit or switch modes aren't motions
+                self.motion.operator = None;
+                let mode = self.mode;
+                if self.motion.edits() || !self.execute_cmd(state) || self.mode != mode {
+                    return true
+                }
+                let moved = self.cursors[self.current_buffer].to_linepos().line;
+                (cursor.line.min(moved), cursor.line.max(moved))
+            },
+        };
+
+        let buffer = &mut self.buffers[self.current_buffer];
+        let end = end.min(buffer.total_lines() - 1);
+        match operator {
+            Operator::Reindent => {
+                let options = &state.options;
+                reindent(buffer, start, end, options.tabstop, options.shiftwidth(&buffer.options), options.expandtab(&buffer.options));
+                if end > start + 1 {
+                    self.message = format!("{} lines indented", end - start + 1);
+                }
+            },
+            Operator::Surround => unreachable!("ys goes through surround"),
+            Operator::Fold if state.options.fold_method != FoldMethod::Manual => {
+                self.message = "Cannot create fold with current foldmethod".to_string();
+            },
+            Operator::Fold => buffer.folds.add(start, end),
+            Operator::Format => {
+                let width = if state.options.textwidth > 0 { state.options.textwidth } else { 79 };
+                let lines = (start..=end).map(|line| buffer.line(line)).collect::<Vec<_>>();
+                let formatted = format::reflow(&lines, width, state.options.tabstop);
+                if formatted != lines {
+                    buffer.replace_lines(start, end, &(formatted.join("\n") + "\n"));
+                }
+            },
+        }
+
+        // to the first non-blank of the first line
+        let col = buffer.line(start).chars().take_while(|c| c.is_whitespace()).count();
+        self.cursors[self.current_buffer].from_linepos(buffer.clamp_to_char(LinePos::new(start, col)));
+        true
+    }
+
+    // ys puts the pair around the chars the motion goes over, yss around the
+    // line without its indent and S in visual mode around the selection
+    fn surround(&mut self, state: &mut State) -> bool {
+        let Some(pair) = self.motion.new_pair else { return false };
+        let cursor = self.cursors[self.current_buffer].to_linepos();
+        let buffer = &self.buffers[self.current_buffer];
+        let line_range = |line: usize| {
+            let indent = buffer.line(line).chars().take_while(|c| c.is_whitespace()).count();
+            (LinePos::new(line, indent), LinePos::new(line, buffer.line_len(line)))
+        };
+        // the end is one past the last char
+        let (start, mut end) = match self.motion.object {
+            Some(Object::VisualSelection) => {
+                let (min, max) = (self.visual_range_anchor.min(cursor), self.visual_range_anchor.max(cursor));
+                let visual_line = self.mode == EditorMode::VisualLine;
+                self.mode = EditorMode::Normal;
+                if visual_line {
+                    (line_range(min.line).0, line_range(max.line).1)
+                } else {
+                    (min, LinePos::new(max.line, max.col + 1))
+                }
+            },
+            Some(Object::Line) => line_range(cursor.line),
+            Some(object @ (Object::Word | Object::WORD)) if self.motion.modifier == Some(Modifier::Inside) => {
+                let word = if matches!(object, Object::Word) {
+                    find_current_word_start(cursor, buffer).zip(find_current_word_end(cursor, buffer))
+                } else {
+                    find_current_WORD_start(cursor, buffer).zip(find_current_WORD_end(cursor, buffer))
+                };
+                let Some((start, end)) = word else { return true };
+                (start, LinePos::new(end.line, end.col + 1))
+            },
+            object => {
+                // run as a move, these land on the last char they go over
+                let inclusive = matches!(object, Some(Object::WordEnd | Object::WORDEnd | Object::LineEnd | Object::MatchingBracket));
+                self.motion.operator = None;
+                let mode = self.mode;
+                if self.motion.edits() || !self.execute_cmd(state) || self.mode != mode {
+                    return true
+                }
+                let moved = self.cursors[self.current_buffer].to_linepos();
+                let (start, end) = (cursor.min(moved), cursor.max(moved));
+                (start, if inclusive { LinePos::new(end.line, end.col + 1) } else { end })
+            },
+        };
+
+        let buffer = &mut self.buffers[self.current_buffer];
+        // a motion to the start of a line stops at the end of the one before
+        if end.col == 0 && end.line > start.line {
+            end = LinePos::new(end.line - 1, buffer.line_len(end.line - 1));
+        }
+        let Some((open, close)) = surround_text(pair) else {
+            self.message = format!("Can't surround with {pair}");
+            return true
+        };
+        buffer.insert_text(end, &close);
+        buffer.insert_text(start, &open);
+        self.cursors[self.current_buffer].from_linepos(start);
+        true
+    }
+
+    // d/ and =/ wait in search mode for where they go up to
+    fn operate_to(&mut self, state: &mut State, motion: Motion, from: LinePos, to: LinePos) {
+        let buffer = &mut self.buffers[self.current_buffer];
+        let (start, end) = (from.min(to), from.max(to));
+        if motion.action == Some(Action::Delete) {
+            buffer.history.collect_removed();
+            // up to the match, not including it
+            let last = if end.col > 0 {
+                LinePos { line: end.line, col: end.col - 1 }
+            } else if end.line > start.line {
+                LinePos { line: end.line - 1, col: buffer.line_len(end.line - 1) }
+            } else {
+                return
+            };
+            if start <= last {
+                buffer.remove_by_range(start, last);
+            }
+            self.cursors[self.current_buffer].from_linepos(self.buffers[self.current_buffer].clamp_to_char(start));
+            self.save_deleted_text();
+        } else if let Some(operator) = motion.operator {
+            let mut motion = motion;
+            motion.object = Some(Object::Line);
+            motion.modifier = Some(Modifier::Count((end.line - start.line + 1) as u32));
+            self.cursors[self.current_buffer].from_linepos(start);
+            self.motion = motion;
+            self.execute_operator(state, operator);
+            self.motion.clear();
+        }
+    }
+
+    fn execute_cmd(&mut self, state: &mut State) -> bool {
+        if self.motion.edits() && self.refuse_readonly() {
+            return true
+        }
+        if matches!(self.motion.object, Some(Object::SearchMode)) && self.motion.edits() {
+            let cursor = self.cursors[self.current_buffer].to_linepos();
+            let mut motion = std::mem::replace(&mut self.motion, Motion::new());
+            motion.object = None;
+            self.search_motion = Some((motion, cursor));
+            self.mode = EditorMode::Search;
+            self.command_bar_input.push('/');
+            state.cmd_bar_cursor_x = 1;
+            return true
+        }
+        if self.motion.operator == Some(Operator::Surround) && self.motion.object.is_some() {
+            return self.surround(state)
+        }
+        if let (Some(operator), Some(_)) = (self.motion.operator, self.motion.object) {
+            return self.execute_operator(state, operator)
+        }
+        // edits still waiting for the delay would move the hunks
+        if let (Some(Object::NextHunk | Object::PreviousHunk), Some(diff)) = (self.motion.object, &mut self.diff) {
+            if let Some((a, b)) = diff.find(&self.buffers).filter(|(a, b)| diff.computed != (a.changes, b.changes)) {
+                diff.update(a, b);
+            }
+        }
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return true };
+        let Some(current_cursor) = self.cursors.get_mut(self.current_buffer) else { return true };
+        let Some(obj) = self.motion.object else { return false };
+        // past the end of the line with virtualedit, commands work from the end
+        let cursor = buffer.clamp_pos(current_cursor.to_linepos());
+
+        match obj {
+            Object::BackWord => 'b: {
+                let Some(pos) = find_previous_word_start(cursor, &buffer) else { break 'b };
+                if self.motion.action == Some(Action::Delete) {
+                    buffer.remove_by_range(pos, cursor);
+                }
+                current_cursor.from_linepos(pos);
+            },
+            Object::BackWORD => {
+                todo!();
+                //let Some(pos) = find_previous_WORD_start(cursor, &buffer) else { break 'b };
+            },
+            Object::Word => 'b: {
+                if self.motion.modifier == Some(Modifier::Inside) {
+                    let Some(start) = find_current_word_start(cursor, &buffer) else { break 'b };
+                    let Some(end) = find_current_word_end(cursor, &buffer) else { break 'b };
+                    if self.motion.action == Some(Action::Delete) {
+                        buffer.remove_from_line(cursor.line, start.col, end.col - start.col + 1);
+                        current_cursor.x = ((start.col + 1).min(buffer.line_len(cursor.line))).max(1);
+                        current_cursor.wanted_x = current_cursor.x;
+                    } else if self.mode == EditorMode::Visual {
+                        self.visual_range_anchor = start;
+                        current_cursor.from_linepos(end);
                     }
                 } else {
                     let pos = if let Some(Modifier::Count(n)) = self.motion.modifier {
@@ -311,8 +1982,26 @@ impl Editor {
                 if line_len > 0 {
                     current_cursor.x += 1;
                 }
+                self.insert_repeat = insert_repeat(self.motion.modifier, current_cursor.to_linepos());
+            },
+            Object::Insert => {
+                self.mode = EditorMode::Insert;
+                self.insert_repeat = insert_repeat(self.motion.modifier, cursor);
+            },
+            Object::AppendLineEnd => {
+                self.mode = EditorMode::Insert;
+                current_cursor.x = buffer.line_len(cursor.line) + 1;
+                current_cursor.wanted_x = current_cursor.x;
+                self.insert_repeat = insert_repeat(self.motion.modifier, current_cursor.to_linepos());
+            },
+            // before the first non-blank, at the end of a line of blanks
+            Object::InsertLineStart => {
+                self.mode = EditorMode::Insert;
+                current_cursor.x = buffer.line(cursor.line).chars().take_while(|c| c.is_whitespace()).count() + 1;
+                current_cursor.wanted_x = current_cursor.x;
+                self.insert_repeat = insert_repeat(self.motion.modifier, current_cursor.to_linepos());
             },
-            Object::Insert => self.mode = EditorMode::Insert,
+            Object::ReplaceMode => self.mode = EditorMode::Replace,
             Object::NormalMode => self.mode = EditorMode::Normal,
             Object::VisualMode => {
                 self.mode = EditorMode::Visual;
@@ -348,41 +2037,58 @@ impl Editor {
                 }
             },
             Object::CommandBarMode => {
-                self.mode = EditorMode::CommandBar;
                 self.command_bar_input.push(':');
-                state.cmd_bar_cursor_x = 1;
+                // from visual mode the command gets the selected lines
+                if matches!(self.mode, EditorMode::Visual | EditorMode::VisualLine) {
+                    let anchor = self.visual_range_anchor;
+                    self.visual_marks = Some((anchor.min(cursor), anchor.max(cursor)));
+                    self.command_bar_input.push_str("'<,'>");
+                }
+                self.mode = EditorMode::CommandBar;
+                state.cmd_bar_cursor_x = self.command_bar_input.chars().count();
             },
+            // a closed fold is gone over as one line
             Object::Up => {
                 if cursor.line > 0 {
-                    current_cursor.y -= 1;
-                    let max_x = (buffer.line_len(cursor.line - 1)).max(1);
-                    if current_cursor.wanted_x > max_x {
+                    let line = buffer.folds.shown_line(cursor.line - 1);
+                    current_cursor.y = line + 1;
+                    let max_x = (buffer.line_len(line)).max(1);
+                    let wanted_x = same_screen_col(buffer, cursor.line, line, current_cursor.wanted_x, state.options.tabstop);
+                    if wanted_x > max_x && !state.options.virtualedit.all {
                         current_cursor.x = max_x;
                     } else {
-                        current_cursor.x = current_cursor.wanted_x;
+                        current_cursor.x = wanted_x;
                     }
                 }
             },
             Object::Down => {
-                if cursor.line < buffer.total_lines() - 1 {
-                    current_cursor.y += 1;
-                    let max_x = buffer.line_len(cursor.line + 1).max(1);
-                    if current_cursor.wanted_x > max_x {
+                let line = buffer.folds.closed_at(cursor.line).map_or(cursor.line, |fold| fold.end) + 1;
+                if line < buffer.total_lines() {
+                    current_cursor.y = line + 1;
+                    let max_x = buffer.line_len(line).max(1);
+                    let wanted_x = same_screen_col(buffer, cursor.line, line, current_cursor.wanted_x, state.options.tabstop);
+                    if wanted_x > max_x && !state.options.virtualedit.all {
                         current_cursor.x = max_x;
                     } else {
-                        current_cursor.x = current_cursor.wanted_x;
+                        current_cursor.x = wanted_x;
                     }
                 }
             },
+            Object::RowUp | Object::RowDown => {
+                let down = matches!(obj, Object::RowDown);
+                if let Some(pos) = wrap::row_move(buffer, state, cursor, down) {
+                    current_cursor.from_linepos(pos);
+                }
+            },
             Object::Left => {
-                if cursor.col > 0 {
+                if current_cursor.x > 1 {
                     current_cursor.x -= 1;
                     current_cursor.wanted_x = current_cursor.x;
                 }
             },
             Object::Right => {
                 let line_len = buffer.line_len(cursor.line);
-                if cursor.col + 1 < line_len {
+                if cursor.col + 1 < line_len || state.options.virtualedit.all {
                     current_cursor.x += 1;
                     current_cursor.wanted_x += 1;
                 } else if self.mode == EditorMode::Visual && current_cursor.x == line_len {
@@ -421,21 +2127,44 @@ impl Editor {
                         let line = line.min(total_lines);
                         let line_len = buffer.line_len(line - 1);
                         current_cursor.y = line;
-                        current_cursor.x = current_cursor.x.min(line_len);
+                        current_cursor.x = current_cursor.x.min(line_len).max(1);
                     } else {
                         let last_line = buffer.total_lines() - 1;
                         let line_len = buffer.line_len(last_line);
                         current_cursor.y = last_line + 1;
-                        current_cursor.x = current_cursor.wanted_x.min(line_len);
+                        current_cursor.x = current_cursor.wanted_x.min(line_len).max(1);
                     }
                 }
             },
             Object::LineStart => {
                 if self.motion.action == Some(Action::Delete) {
                     buffer.remove_from_line(cursor.line, 0, cursor.col);
+                    current_cursor.x = 1;
+                } else {
+                    let first = first_nonblank(buffer, cursor.line);
+                    let smart = state.options.smart_home && cursor.col != first;
+                    current_cursor.x = if smart { first + 1 } else { 1 };
+                }
+                current_cursor.wanted_x = current_cursor.x;
+            },
+            Object::FirstNonBlank => {
+                let first = first_nonblank(buffer, cursor.line);
+                if self.motion.action == Some(Action::Delete) {
+                    let start = first.min(cursor.col);
+                    buffer.remove_from_line(cursor.line, start, first.max(cursor.col) - start);
+                    current_cursor.x = (start + 1).min(buffer.line_len(cursor.line)).max(1);
+                } else {
+                    current_cursor.x = first + 1;
                 }
-                current_cursor.x = 1;
-                current_cursor.wanted_x = 1;
+                current_cursor.wanted_x = current_cursor.x;
+            },
+            Object::FirstNonBlankDown => {
+                let down = match self.motion.modifier {
+                    Some(Modifier::Count(n)) => n as usize - 1,
+                    _ => 0,
+                };
+                let line = (cursor.line + down).min(buffer.total_lines() - 1);
+                current_cursor.from_linepos(LinePos { line, col: first_nonblank(buffer, line) });
             },
             Object::LineEnd => 'b: {
                 if self.motion.action == Some(Action::Delete) {
@@ -456,10 +2185,21 @@ impl Editor {
                 }
                 current_cursor.wanted_x = current_cursor.x;
             },
+            Object::MatchingBracket => 'b: {
+                let Some(pos) = find_matching_bracket(cursor, buffer) else { break 'b };
+                if self.motion.action == Some(Action::Delete) {
+                    let start = cursor.min(pos);
+                    buffer.remove_by_range(start, cursor.max(pos));
+                    current_cursor.from_linepos(start);
+                } else {
+                    current_cursor.from_linepos(pos);
+                }
+            },
             Object::CharUnderCursor => {
                 let n = if let Some(Modifier::Count(n)) = self.motion.modifier { n } else { 1 };
                 let line_len = buffer.line_len(cursor.line);
-                if line_len > 0 {
+                // nothing is under a cursor past the end
+                if cursor.col < line_len {
                     buffer.remove_from_line(cursor.line, cursor.col, (n as usize).min(line_len - cursor.col));
                     if (current_cursor.x - 1) as usize >= (line_len - 1) && current_cursor.x > 1 {
                         current_cursor.x -= 1;
@@ -467,19 +2207,103 @@ impl Editor {
                     }
                 }
             },
+            // ds takes the pair away, cs puts another one in its place
+            Object::Surrounding => 'b: {
+                let Some(found) = self.motion.pair.and_then(|pair| surrounding(cursor, buffer, pair)) else { break 'b };
+                let (open, close) = match self.motion.new_pair {
+                    Some(pair) => {
+                        let Some(text) = surround_text(pair) else {
+                            self.message = format!("Can't surround with {pair}");
+                            break 'b
+                        };
+                        text
+                    },
+                    None => (String::new(), String::new()),
+                };
+                // the close first so the open doesn't move
+                buffer.remove_by_range(found.close.0, found.close.1);
+                buffer.insert_text(found.close.0, &close);
+                buffer.remove_by_range(found.open.0, found.open.1);
+                buffer.insert_text(found.open.0, &open);
+                current_cursor.from_linepos(buffer.clamp_to_char(found.open.0));
+            },
+            Object::ChangeLineEnd => {
+                buffer.remove_from_line(cursor.line, cursor.col, buffer.line_len(cursor.line).saturating_sub(cursor.col));
+                self.mode = EditorMode::Insert;
+            },
+            // a count takes the lines below too, the indent stays
+            Object::ChangeLine => {
+                let n = if let Some(Modifier::Count(n)) = self.motion.modifier { n as usize } else { 1 };
+                for _ in 1..n.min(buffer.total_lines() - cursor.line) {
+                    buffer.remove_line(cursor.line + 1);
+                }
+                let indent = buffer.line(cursor.line).chars().take_while(|c| c.is_whitespace()).count();
+                buffer.remove_from_line(cursor.line, indent, buffer.line_len(cursor.line) - indent);
+                current_cursor.x = indent + 1;
+                current_cursor.wanted_x = current_cursor.x;
+                self.mode = EditorMode::Insert;
+            },
+            Object::ChangeChar => {
+                let n = if let Some(Modifier::Count(n)) = self.motion.modifier { n as usize } else { 1 };
+                buffer.remove_from_line(cursor.line, cursor.col, n.min(buffer.line_len(cursor.line).saturating_sub(cursor.col)));
+                self.mode = EditorMode::Insert;
+            },
             Object::SearchMode => {
                 self.mode = EditorMode::Search;
                 self.command_bar_input.push('/');
                 state.cmd_bar_cursor_x = 1;
             },
-            Object::NextSearchResult => 'b: {
-                let Some(pos) = next_position(cursor, &self.search_results) else { break 'b };
+            Object::NextSearchResult | Object::PreviousSearchResult => 'b: {
+                let forward = matches!(obj, Object::NextSearchResult);
+                let found = if forward { next_position(cursor, &self.search_results) } else { previous_position(cursor, &self.search_results) };
+                let Some((pos, wrapped)) = found else { break 'b };
+                // with nowrapscan n and N stop at the last match
+                if wrapped {
+                    self.message = wrap_message(forward, state.options.wrap_scan);
+                    if !state.options.wrap_scan {
+                        break 'b
+                    }
+                }
                 current_cursor.from_linepos(pos);
             },
-            Object::PreviousSearchResult => 'b: {
-                let Some(pos) = previous_position(cursor, &self.search_results) else { break 'b };
-                current_cursor.from_linepos(pos);
+            Object::FoldOpen | Object::FoldClose | Object::FoldToggle => {
+                let found = match obj {
+                    Object::FoldOpen => buffer.folds.open(cursor.line),
+                    Object::FoldClose => buffer.folds.close(cursor.line),
+                    _ => buffer.folds.toggle(cursor.line),
+                };
+                if !found {
+                    self.message = "No fold found".to_string();
+                }
             },
+            Object::CharInfo => {
+                self.message = match buffer.line(cursor.line).chars().nth(cursor.col) {
+                    Some(c) if c != '\n' => digraph::char_info(c),
+                    _ => "NUL".to_owned(),
+                };
+            },
+            Object::Counts => {
+                let (min, max) = (self.visual_range_anchor.min(cursor), self.visual_range_anchor.max(cursor));
+                self.message = match self.mode {
+                    EditorMode::Visual => {
+                        let mut text = String::new();
+                        for line in min.line..=max.line {
+                            let chars = buffer.line(line).chars().collect::<Vec<_>>();
+                            let start = if line == min.line { min.col.min(chars.len()) } else { 0 };
+                            let end = if line == max.line { (max.col + 1).min(chars.len()) } else { chars.len() };
+                            text.extend(&chars[start..end.max(start)]);
+                            if line < max.line {
+                                text.push('\n');
+                            }
+                        }
+                        format!("Selected {}", counts(&text))
+                    },
+                    EditorMode::VisualLine => format!("Selected {}", counts(&(min.line..=max.line).map(|line| buffer.raw_line(line)).collect::<String>())),
+                    _ => counts(&(0..buffer.total_lines()).map(|line| buffer.raw_line(line)).collect::<String>()),
+                };
+            },
+            Object::FoldOpenAll => buffer.folds.open_all(),
+            Object::FoldCloseAll => buffer.folds.close_all(),
             Object::PageTop => 'b: {
                 if self.motion.action == Some(Action::Scroll) {
                     state.start_line = cursor.line;
@@ -528,10 +2352,11 @@ impl Editor {
             },
             Object::InsertLineUp => {
                 buffer.insert_empty_line(cursor.line);
-                let indent = indent_wanted(cursor.line, &buffer);
+                let indent = indent_wanted(cursor.line, buffer, state.options.tabstop, state.options.shiftwidth(&buffer.options));
                 if let Some(indent) = indent {
-                    buffer.insert_into_line(cursor.line, 0, " ".repeat(indent).as_bytes());
-                    current_cursor.x = indent + 1;
+                    let text = indent_text(indent, state.options.expandtab(&buffer.options), state.options.tabstop);
+                    buffer.insert_into_line(cursor.line, 0, text.as_bytes());
+                    current_cursor.x = text.len() + 1;
                     current_cursor.wanted_x = current_cursor.x;
                 } else {
                     current_cursor.x = 1;
@@ -541,10 +2366,11 @@ impl Editor {
             },
             Object::InsertLineDown => {
                 buffer.insert_empty_line(cursor.line + 1);
-                let indent = indent_wanted(cursor.line + 1, &buffer);
+                let indent = indent_wanted(cursor.line + 1, buffer, state.options.tabstop, state.options.shiftwidth(&buffer.options));
                 if let Some(indent) = indent {
-                    buffer.insert_into_line(cursor.line + 1, 0, " ".repeat(indent).as_bytes());
-                    current_cursor.x = indent + 1;
+                    let text = indent_text(indent, state.options.expandtab(&buffer.options), state.options.tabstop);
+                    buffer.insert_into_line(cursor.line + 1, 0, text.as_bytes());
+                    current_cursor.x = text.len() + 1;
                     current_cursor.wanted_x = current_cursor.x;
                 } else {
                     current_cursor.x = 0;
@@ -553,24 +2379,279 @@ impl Editor {
                 current_cursor.y += 1;
                 self.mode = EditorMode::Insert;
             },
+            Object::Definition | Object::References | Object::Hover => 'b: {
+                let Some(client) = lsp_client(&mut self.lsp_clients, buffer, &self.root_folder) else {
+                    self.message = "No language server for this file".to_owned();
+                    break 'b
+                };
+                match obj {
+                    Object::Definition => client.request_definition(buffer, cursor),
+                    Object::References => client.request_references(buffer, cursor),
+                    _ => client.request_hover(buffer, cursor),
+                }
+            },
+            Object::JumpBack => self.jump_back(),
+            Object::GotoTag => self.goto_tag(),
+            Object::Window(command) => self.window_command(state, command),
+            Object::RecordMacro => {
+                let Some(register) = self.motion.register else { return true };
+                self.recording = Some((register, String::new()));
+                self.recording_depth = self.feed_depth;
+            },
+            Object::ReplayMacro => 'b: {
+                let Some(register) = self.motion.register.filter(|&r| r != '@').or(self.last_macro) else { break 'b };
+                let Some(keys) = self.macros.get(&register).cloned() else {
+                    self.message = format!("Register {register} is empty");
+                    break 'b
+                };
+                let times = if let Some(Modifier::Count(n)) = self.motion.modifier { n } else { 1 };
+                self.last_macro = Some(register);
+                self.motion.clear();
+                for _ in 0..times {
+                    self.feed_keys(state, &keys);
+                }
+            },
+            Object::PopTag => {
+                match self.tag_stack.pop() {
+                    Some(jump) => self.return_to(jump),
+                    None => self.message = "Tag stack is empty".to_owned(),
+                }
+            },
+            Object::Undo | Object::Redo => {
+                let pos = if matches!(obj, Object::Undo) { buffer.undo() } else { buffer.redo() };
+                let Some(mut pos) = pos else {
+                    self.message = if matches!(obj, Object::Undo) { "Already at oldest change" } else { "Already at newest change" }.to_owned();
+                    return true
+                };
+                pos.col = pos.col.min(buffer.line_len(pos.line).max(1) - 1);
+                current_cursor.from_linepos(pos);
+            },
+            Object::NextHunk | Object::PreviousHunk => 'b: {
+                let Some((diff, side)) = self.diff.as_ref().and_then(|diff| Some((diff, diff.side(buffer.id)?))) else {
+                    self.message = "Not in diff mode".to_owned();
+                    break 'b
+                };
+                let line = if matches!(obj, Object::NextHunk) { diff.next_hunk(side, cursor.line) } else { diff.previous_hunk(side, cursor.line) };
+                let Some(line) = line else {
+                    self.bell(state);
+                    break 'b
+                };
+                current_cursor.from_linepos(LinePos::new(line.min(buffer.total_lines() - 1), 0));
+            },
         }
 
         true
     }
+
+    // with foldmethod=indent the folds are worked out again after the text
+    // changes, not while typing in insert mode
+    fn update_folds(&mut self, state: &State) {
+        let Some(buffer) = self.buffers.get_mut(self.current_buffer) else { return };
+        if state.options.fold_method == FoldMethod::Manual {
+            buffer.folds.computed = None;
+            return
+        }
+        if matches!(self.mode, EditorMode::Insert | EditorMode::Replace) || buffer.folds.computed == Some(buffer.changes) {
+            return
+        }
+        buffer.folds.replace(fold::indent_folds(buffer, state.options.tabstop));
+        buffer.folds.computed = Some(buffer.changes);
+    }
+
+    // waits for a pause in the edits, running the diff on every key is slow on big files
+    fn update_diff(&mut self, state: &mut State) {
+        let Some(diff) = &mut self.diff else { return };
+        let Some((a, b)) = diff.find(&self.buffers) else {
+            self.diff = None;
+            return
+        };
+        let changes = (a.changes, b.changes);
+        if diff.computed == changes {
+            return
+        }
+        let now = Instant::now();
+        match diff.due {
+            Some((due, at)) if due == changes && at <= now => diff.update(a, b),
+            Some((due, _)) if due == changes => (),
+            _ => {
+                diff.due = Some((changes, now + DIFF_DELAY));
+                state.scheduler.redraw_at(now + DIFF_DELAY);
+            },
+        }
+    }
+}
+
+// BEL to the terminal moded was started from. false without one, like when
+// it's started from a desktop launcher or runs the tests
+fn ring_terminal_bell() -> bool {
+    let mut stdout = io::stdout();
+    stdout.is_terminal() && stdout.write_all(b"\x07").and_then(|_| stdout.flush()).is_ok()
+}
+
+// with :set backup the file is copied aside first, the save stops if that fails
+fn write_file(buffer: &TextBuffer, path: &Path, options: &Options) -> io::Result<()> {
+    if options.backup {
+        backup_file(path, &options.backup_dir).map_err(|e| io::Error::new(e.kind(), format!("backup failed: {e}")))?;
+    }
+    buffer.write_to(path, options.fix_eol)
 }
 
-fn closest_position(cursor: LinePos, positions: &[LinePos]) -> Option<LinePos> {
+// the x on line to that is on the same screen column as x on line from, so the
+// cursor doesn't drift moving over wide chars and tabs. x counts from 1 like CursorPos
+fn same_screen_col(buffer: &TextBuffer, from: usize, to: usize, x: usize, tabstop: usize) -> usize {
+    let display = width::display_col(&buffer.line(from), x - 1, tabstop);
+    width::col_at_display(&buffer.line(to), display, tabstop) + 1
+}
+
+// the identifier the column is on, like what ctrl-] looks up in vim
+fn identifier_at(line: &str, col: usize) -> Option<String> {
+    let (start, end) = word_bounds(line, col)?;
+    Some(line.chars().skip(start).take(end + 1 - start).collect())
+}
+
+// first and last column of the word of letters, digits and _ at col
+fn word_bounds(line: &str, col: usize) -> Option<(usize, usize)> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let is_ident = |c: &char| c.is_alphanumeric() || *c == '_';
+    if !chars.get(col).is_some_and(is_ident) {
+        return None
+    }
+
+    let start = chars[..col].iter().rposition(|c| !is_ident(c)).map_or(0, |i| i + 1);
+    let end = chars[col..].iter().position(|c| !is_ident(c)).map_or(chars.len(), |i| col + i);
+    Some((start, end - 1))
+}
+
+// with a count above one the insert starting at pos is typed count - 1 more times
+fn insert_repeat(modifier: Option<Modifier>, pos: LinePos) -> Option<(usize, LinePos)> {
+    match modifier {
+        Some(Modifier::Count(n)) if n > 1 => Some((n as usize - 1, pos)),
+        _ => None,
+    }
+}
+
+// the closing half autopairs puts in after typed. quotes aren't paired next
+// to a word char, so don't and foo" stay as they are
+fn auto_pair(typed: char, before: Option<char>, after: Option<char>) -> Option<char> {
+    let close = match typed {
+        '(' => ')',
+        '[' => ']',
+        '{' => '}',
+        '"' | '\'' => typed,
+        _ => return None,
+    };
+    let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    if close == typed && (word(before) || word(after)) {
+        return None
+    }
+
+    Some(close)
+}
+
+fn char_before(buffer: &TextBuffer, cursor: &CursorPos) -> Option<char> {
+    if cursor.x < 2 {
+        return None
+    }
+    buffer.line(cursor.y - 1).chars().nth(cursor.x - 2)
+}
+
+// where an insert session gets split into separate undo steps
+fn breaks_undo(chunk: UndoChunk, previous: Option<char>, typed: char) -> bool {
+    let Some(previous) = previous else { return false };
+    match chunk {
+        UndoChunk::Insert => false,
+        UndoChunk::Word => typed.is_whitespace() && !previous.is_whitespace(),
+        UndoChunk::Sentence => typed.is_whitespace() && matches!(previous, '.' | '!' | '?'),
+    }
+}
+
+// finds the language server for the buffer's file type, starting it if needed
+fn lsp_client<'a>(clients: &'a mut Vec<LspClient>, buffer: &TextBuffer, root: &Path) -> Option<&'a mut LspClient> {
+    let command = lsp::server_for(buffer.file_path.as_ref()?)?;
+    if let Some(i) = clients.iter().position(|client| client.command == command) {
+        return Some(&mut clients[i])
+    }
+
+    let client = LspClient::start(command, root).ok()?;
+    log::info(&format!("Started language server {command}"));
+    clients.push(client);
+    clients.last_mut()
+}
+
+// line editing shared by the command bar and search prompts, input[0] is the prompt
+// character and cmd_bar_cursor_x is a char index into input. returns true if input changed
+fn edit_prompt_input(input: &mut String, state: &mut State) -> bool {
+    if input.is_empty() {
+        return false
+    }
+
+    let mut chars = input.chars().collect::<Vec<_>>();
+    let mut cursor = state.cmd_bar_cursor_x.clamp(1, chars.len());
+    let mut changed = false;
+
+    if state.io.pressed_special(SpecialKey::Control) {
+        // ctrl + letter comes in as the plain letter
+        if state.io.pressed_char('w') {
+            let start = previous_word_start_in_line(&chars, cursor).max(1);
+            chars.drain(start..cursor);
+            cursor = start;
+            changed = true;
+        }
+        if state.io.pressed_char('u') {
+            chars.drain(1..cursor);
+            cursor = 1;
+            changed = true;
+        }
+    } else if !state.io.chars.is_empty() {
+        for (i, char) in state.io.chars.chars().enumerate() {
+            chars.insert(cursor + i, char);
+        }
+        cursor += state.io.chars.chars().count();
+        changed = true;
+    }
+
+    if state.io.pressed_special(SpecialKey::Backspace) {
+        if cursor > 1 {
+            chars.remove(cursor - 1);
+            cursor -= 1;
+        } else if chars.len() == 1 {
+            chars.clear();
+        }
+        changed = true;
+    }
+
+    if state.io.pressed_special_with_modifiers(SpecialKey::Left, Modifiers::CONTROL) {
+        cursor = previous_word_start_in_line(&chars, cursor).max(1);
+    } else if state.io.pressed_special(SpecialKey::Left) {
+        cursor = (cursor - 1).max(1);
+    }
+    if state.io.pressed_special_with_modifiers(SpecialKey::Right, Modifiers::CONTROL) {
+        cursor = next_word_start_in_line(&chars, cursor);
+    } else if state.io.pressed_special(SpecialKey::Right) {
+        cursor = (cursor + 1).min(chars.len());
+    }
+
+    if changed {
+        *input = chars.into_iter().collect();
+    }
+    state.cmd_bar_cursor_x = cursor;
+
+    changed
+}
+
+// the first position at or after the cursor, true if it wrapped around to the top
+fn closest_position(cursor: LinePos, positions: &[LinePos]) -> Option<(LinePos, bool)> {
     if positions.is_empty() {
         return None
     }
-    let mut pos = positions.binary_search(&cursor).unwrap_or_else(|e| e);
+    let pos = positions.binary_search(&cursor).unwrap_or_else(|e| e);
     if pos == positions.len() {
-        pos = 0;
+        return Some((positions[0], true))
     }
-    Some(positions[pos])
+    Some((positions[pos], false))
 }
 
-fn next_position(cursor: LinePos, positions: &[LinePos]) -> Option<LinePos> {
+fn next_position(cursor: LinePos, positions: &[LinePos]) -> Option<(LinePos, bool)> {
     if positions.is_empty() {
         return None
     }
@@ -581,23 +2662,957 @@ fn next_position(cursor: LinePos, positions: &[LinePos]) -> Option<LinePos> {
     };
 
     if let Some(pos) = positions.get(pos) {
-        return Some(*pos)
+        return Some((*pos, false))
     }
 
-    Some(positions[0])
+    Some((positions[0], true))
 }
 
-fn previous_position(cursor: LinePos, positions: &[LinePos]) -> Option<LinePos> {
+fn previous_position(cursor: LinePos, positions: &[LinePos]) -> Option<(LinePos, bool)> {
     if positions.is_empty() {
         return None
     }
 
-    let mut pos = positions.binary_search(&cursor).unwrap_or_else(|n| n);
+    let pos = positions.binary_search(&cursor).unwrap_or_else(|n| n);
     if pos == 0 {
-        pos = positions.len() - 1
-    } else {
-        pos -= 1;
+        return Some((positions[positions.len() - 1], true))
+    }
+
+    Some((positions[pos - 1], false))
+}
+
+// what g ctrl-g shows for text
+fn counts(text: &str) -> String {
+    let lines = text.lines().count();
+    let words = text.split_whitespace().count();
+    format!("{lines} lines, {words} words, {} chars, {} bytes", text.chars().count(), text.len())
+}
+
+// vim's messages for a search going past the end of the buffer
+fn wrap_message(forward: bool, wrap_scan: bool) -> String {
+    let message = match (forward, wrap_scan) {
+        (true, true) => "search hit BOTTOM, continuing at TOP",
+        (false, true) => "search hit TOP, continuing at BOTTOM",
+        (true, false) => "Search hit BOTTOM without match",
+        (false, false) => "Search hit TOP without match",
+    };
+
+    message.to_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::VirtualEdit;
+
+    fn editor_with(text: &str) -> (Editor, State) {
+        let buffer = TextBuffer::from_data(next_buffer_id(), text.as_bytes().to_vec());
+        let mut state = State::new(800, 600);
+        state.char_width = 10.0;
+        state.char_height = 20.0;
+
+        (Editor::from_buffer(buffer), state)
+    }
+
+    fn text(editor: &Editor) -> String {
+        let buffer = &editor.buffers[editor.current_buffer];
+        (0..buffer.total_lines()).map(|line| buffer.raw_line(line)).collect()
+    }
+
+    #[test]
+    fn test_feed_keys() {
+        let (mut editor, mut state) = editor_with("one\ntwo\nthree\n");
+        editor.feed_keys(&mut state, "ddihey <Esc>");
+        assert_eq!(text(&editor), "hey two\nthree\n");
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), "two\nthree\n");
+
+        // a recorded macro replays the same keys
+        editor.feed_keys(&mut state, "qa0ix<Esc>jq");
+        assert_eq!(editor.macros.get(&'a').map(|keys| keys.as_str()), Some("0ix<Esc>j"));
+        editor.feed_keys(&mut state, "k@a");
+        assert_eq!(text(&editor), "xxtwo\nthree\n");
+
+        editor.feed_keys(&mut state, ":%normal 0iy<CR>");
+        assert_eq!(text(&editor), "yxxtwo\nythree\n");
+        assert!(editor.mode == EditorMode::Normal);
+    }
+
+    #[test]
+    fn test_windows() {
+        let (mut editor, mut state) = editor_with("one\ntwo\nthree\n");
+        editor.feed_keys(&mut state, "j:split<CR>");
+        assert_eq!(editor.layout.windows().len(), 2);
+        // the same buffer in both, each window with its own cursor
+        editor.feed_keys(&mut state, "j<C-w>j");
+        assert_eq!(editor.cursors[0].y, 2);
+        editor.feed_keys(&mut state, "<C-w>k");
+        assert_eq!(editor.cursors[0].y, 3);
+
+        // a buffer opened in one window stays in it
+        editor.feed_keys(&mut state, ":enew<CR><C-w><C-w>");
+        assert_eq!(editor.current_buffer, 0);
+        editor.feed_keys(&mut state, "<C-w>w");
+        assert_eq!(editor.current_buffer, 1);
+
+        // :q closes the window while there are a few
+        editor.feed_keys(&mut state, ":q<CR>");
+        assert!(!editor.layout.is_split());
+        assert_eq!((editor.current_buffer, editor.cursors[0].y), (0, 2));
+        editor.feed_keys(&mut state, ":vsplit<CR>:only<CR>");
+        assert!(!editor.layout.is_split());
+        editor.feed_keys(&mut state, "<C-w>c");
+        assert_eq!(editor.message, "Can't close the last window");
+    }
+
+    #[test]
+    fn test_resize_mode() {
+        let (mut editor, mut state) = editor_with("one\ntwo\n");
+        editor.feed_keys(&mut state, "<C-w>r");
+        assert_eq!(editor.message, "No split to resize");
+
+        editor.feed_keys(&mut state, ":split<CR>");
+        let rows = editor.layout.rect(editor.layout.focused).unwrap().rows;
+        editor.feed_keys(&mut state, "<C-w>rjjk");
+        assert_eq!(editor.message, "-- RESIZE --");
+        assert_eq!(editor.layout.rect(editor.layout.focused).unwrap().rows, rows + RESIZE_STEP);
+        // the keys after escape move the cursor again
+        editor.feed_keys(&mut state, "<Esc>j");
+        assert_eq!(editor.cursors[0].y, 2);
+        assert_eq!(editor.layout.rect(editor.layout.focused).unwrap().rows, rows + RESIZE_STEP);
     }
 
-    Some(positions[pos])
+    #[test]
+    fn test_click() {
+        let (mut editor, mut state) = editor_with("one\ntwo\nthree\n");
+        state.io.mouse.push(MouseEvent::Press(25.0, 45.0));
+        editor.handle_input(&mut state);
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 2, col: 2 });
+
+        // past the end of the line lands on its last char
+        editor.feed_keys(&mut state, "v");
+        state.io.mouse.push(MouseEvent::Press(500.0, 5.0));
+        editor.handle_input(&mut state);
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 2 });
+        assert!(editor.mode == EditorMode::Normal);
+
+        // a double click selects the word, dragging selects from the press
+        for _ in 0..2 {
+            state.io.mouse.push(MouseEvent::Press(45.0, 25.0));
+            editor.handle_input(&mut state);
+        }
+        assert!(editor.mode == EditorMode::Visual);
+        assert_eq!(editor.visual_range_anchor, LinePos { line: 1, col: 0 });
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 1, col: 2 });
+
+        state.io.mouse.extend([MouseEvent::Press(5.0, 45.0), MouseEvent::Drag(5.0, 5.0), MouseEvent::Release]);
+        editor.handle_input(&mut state);
+        assert!(editor.mode == EditorMode::Visual);
+        assert_eq!(editor.visual_range_anchor, LinePos { line: 2, col: 0 });
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 0 });
+
+        // the scrollbar jumps that far into the file
+        state.io.mouse.push(MouseEvent::Press(798.0, 450.0));
+        editor.handle_input(&mut state);
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 2, col: 0 });
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let (mut editor, mut state) = editor_with("a漢b\nabcd\n");
+        // b is on screen column 3, under the d
+        editor.feed_keys(&mut state, "llj");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 1, col: 3 });
+        editor.feed_keys(&mut state, "hk");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 1 });
+
+        // both cells of 漢 click onto it
+        state.io.mouse.push(MouseEvent::Press(25.0, 5.0));
+        editor.handle_input(&mut state);
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 1 });
+    }
+
+    #[test]
+    fn test_replace_mode() {
+        let (mut editor, mut state) = editor_with("abc\n");
+        editor.feed_keys(&mut state, "Rxy");
+        assert!(editor.mode == EditorMode::Replace);
+        assert_eq!(editor.buffers[0].line(0), "xyc");
+        // typing past the end appends
+        editor.feed_keys(&mut state, "zw<Esc>");
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(editor.buffers[0].line(0), "xyzw");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 3 });
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let (mut editor, mut state) = editor_with("f(a[0], {\n  b })\n");
+        // from before the first bracket on the line
+        editor.feed_keys(&mut state, "%");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 1, col: 5 });
+        editor.feed_keys(&mut state, "%");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos { line: 0, col: 1 });
+        assert_eq!(matching_bracket(LinePos::new(0, 8), &editor.buffers[0], 1), Some(LinePos::new(1, 4)));
+        assert_eq!(matching_bracket(LinePos::new(0, 8), &editor.buffers[0], 0), None);
+
+        editor.feed_keys(&mut state, "ld%");
+        assert_eq!(editor.buffers[0].line(0), "f(, {");
+    }
+
+    #[test]
+    fn test_title() {
+        let (mut editor, mut state) = editor_with("one\n");
+        assert_eq!(editor.title("%f%m - moded"), "[No Name] - moded");
+        editor.buffers[0].file_path = Some(PathBuf::from("/tmp/a.rs"));
+        editor.feed_keys(&mut state, "x");
+        assert_eq!(editor.title("%F%m 100%%"), "/tmp/a.rs [+] 100%");
+        editor.feed_keys(&mut state, "u");
+        assert!(editor.buffers[0].is_modified());
+    }
+
+    #[test]
+    fn test_enew_and_write() {
+        let (mut editor, mut state) = editor_with("one\n");
+        editor.run_command(&mut state, "w");
+        assert_eq!(editor.message, "No file name, use :w {path}");
+
+        editor.run_command(&mut state, "enew");
+        assert_eq!(editor.buffers.len(), 2);
+        assert_eq!(editor.current_buffer, 1);
+        editor.feed_keys(&mut state, "iscratch<Esc>");
+        assert!(editor.buffers[1].is_modified());
+
+        let path = env::temp_dir().join(format!("moded_enew_{}", std::process::id()));
+        editor.run_command(&mut state, &format!("w {}", path.display()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "scratch\n");
+        assert_eq!(editor.buffers[1].file_path.as_deref(), Some(path.as_path()));
+        assert!(!editor.buffers[1].is_modified());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_diff_waits_for_edits_to_stop() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\n");
+        let path = env::temp_dir().join(format!("moded_diff_{}", std::process::id()));
+        fs::write(&path, "a\nb\nc\n").unwrap();
+        editor.run_command(&mut state, &format!("diffsplit {}", path.display()));
+        fs::remove_file(&path).unwrap();
+        // the file is in a new window on the left
+        assert!(editor.layout.is_split());
+        assert_eq!(editor.current_buffer, 1);
+        let diff = editor.diff.as_ref().unwrap();
+        assert_eq!(diff.buffers, [editor.buffers[0].id, editor.buffers[1].id]);
+        assert!(diff.hunks.is_empty());
+
+        editor.feed_keys(&mut state, "jdd");
+        let diff = editor.diff.as_ref().unwrap();
+        assert!(diff.hunks.is_empty());
+        assert!(diff.due.is_some());
+
+        // the next frame after the delay diffs the edit
+        editor.diff.as_mut().unwrap().due.as_mut().unwrap().1 = Instant::now();
+        editor.handle_input(&mut state);
+        let diff = editor.diff.as_ref().unwrap();
+        assert_eq!(diff.hunks, [diff::Hunk { old: 1..2, new: 1..1 }]);
+        assert!(diff.due.is_none());
+
+        // the other window jumps to the same hunks in its own lines
+        editor.feed_keys(&mut state, "<C-w>w");
+        assert_eq!(editor.current_buffer, 0);
+        editor.feed_keys(&mut state, "gg]c");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 0));
+
+        // jumping between hunks doesn't wait
+        editor.feed_keys(&mut state, "<C-w>w");
+        editor.feed_keys(&mut state, "ggix<Esc>");
+        editor.feed_keys(&mut state, "G[c");
+        assert_eq!(editor.cursors[1].to_linepos(), LinePos::new(0, 0));
+
+        editor.run_command(&mut state, "diffoff");
+        assert!(editor.diff.is_none());
+    }
+
+    #[test]
+    fn test_diffsplit_saved() {
+        let path = env::temp_dir().join(format!("moded_diff_saved_{}", std::process::id()));
+        fs::write(&path, "a\nb\n").unwrap();
+        let (_, mut state) = editor_with("");
+        let mut editor = Editor::from_path(&path).unwrap();
+        editor.feed_keys(&mut state, "ddix<Esc>");
+        // without a file the saved text is read into a buffer of its own
+        editor.run_command(&mut state, "diffsplit");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(editor.buffers.len(), 2);
+        assert!(editor.buffers[1].readonly);
+        assert_eq!(editor.diff.as_ref().unwrap().hunks, [diff::Hunk { old: 0..1, new: 0..2 }]);
+
+        editor.run_command(&mut state, &format!("diffsplit {}_missing", path.display()));
+        assert!(editor.message.starts_with("Can't read"));
+    }
+
+    #[test]
+    fn test_recent_positions() {
+        let (mut editor, mut state) = editor_with("scratch\n");
+        let path = env::temp_dir().join(format!("moded_recent_{}", std::process::id()));
+        fs::write(&path, "line\n".repeat(30)).unwrap();
+        editor.recent.remember(&path, LinePos::new(20, 2), 10);
+
+        // opening it goes back to where it was left
+        editor.run_command(&mut state, &format!("e {}", path.display()));
+        assert_eq!(editor.cursors[1].to_linepos(), LinePos::new(20, 2));
+        assert_eq!(state.start_line, 10);
+
+        // switching buffers keeps each one's view
+        editor.feed_keys(&mut state, "k");
+        state.start_line = 5;
+        editor.run_command(&mut state, "b 1");
+        assert_eq!(state.start_line, 0);
+        assert_eq!(editor.recent.files[0].pos, LinePos::new(19, 2));
+        editor.run_command(&mut state, "b 2");
+        assert_eq!((editor.cursors[1].to_linepos(), state.start_line), (LinePos::new(19, 2), 5));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_oldfiles() {
+        let (mut editor, mut state) = editor_with("scratch\n");
+        editor.run_command(&mut state, "oldfiles");
+        assert_eq!(editor.message, "No recent files");
+
+        let paths = ["a", "b"].map(|name| env::temp_dir().join(format!("moded_oldfiles_{name}_{}", std::process::id())));
+        for path in paths.iter().rev() {
+            fs::write(path, "text\n").unwrap();
+            editor.recent.remember(path, LinePos::new(0, 0), 0);
+        }
+        let paths = paths.map(|path| path.canonicalize().unwrap());
+        editor.run_command(&mut state, "oldfiles _b_");
+        assert_eq!(text(&editor), format!("2: {}\n", paths[1].display()));
+
+        // opening the second one and switching away keeps the numbers
+        editor.run_command(&mut state, "e #2");
+        assert_eq!(editor.buffers[editor.current_buffer].file_path.as_ref().map(|path| path.canonicalize().unwrap()).as_ref(), Some(&paths[1]));
+        editor.run_command(&mut state, "b 1");
+        editor.run_command(&mut state, "e #2");
+        assert_eq!((editor.buffers.len(), editor.current_buffer), (3, 2));
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_change_dir() {
+        let (mut editor, mut state) = editor_with("scratch\n");
+        let start = env::current_dir().unwrap();
+        let dir = env::temp_dir().join(format!("moded_cd_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        fs::write(dir.join("sub/file"), "text\n").unwrap();
+
+        editor.run_command(&mut state, &format!("cd {}", dir.display()));
+        editor.run_command(&mut state, "e sub/file");
+        assert_eq!(editor.dir(), dir);
+        assert_eq!(editor.buffers[1].file_path.as_deref(), Some(dir.join("sub/file").as_path()));
+
+        // :lcd holds only while its buffer is the current one
+        editor.run_command(&mut state, "lcd %:h");
+        assert_eq!(editor.dir(), dir.join("sub"));
+        editor.run_command(&mut state, "b 1");
+        assert_eq!(editor.dir(), dir);
+        editor.run_command(&mut state, "b 2");
+        editor.run_command(&mut state, "pwd");
+        assert_eq!(editor.message, dir.join("sub").display().to_string());
+        editor.run_command(&mut state, "cd missing");
+        assert_eq!(editor.message, "No such folder: missing");
+        // back to the project root, the process stays where it was the whole time
+        editor.run_command(&mut state, "cd");
+        assert_eq!(editor.dir(), editor.root_folder);
+        assert_eq!(env::current_dir().unwrap(), start);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wildignore() {
+        let (mut editor, mut state) = editor_with("scratch\n");
+        let dir = env::temp_dir().join(format!("moded_wildignore_{}", std::process::id()));
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("tags"), "").unwrap();
+        fs::write(dir.join("tags.o"), "").unwrap();
+
+        // target/ and *.o are left out, so tags is the only match
+        editor.feed_keys(&mut state, &format!(":e {}/t<Tab>", dir.display()));
+        assert_eq!(editor.command_bar_input, format!(":e {}/tags", dir.display()));
+        editor.feed_keys(&mut state, "<Esc>");
+        editor.run_command(&mut state, "set wildignore=");
+        editor.feed_keys(&mut state, &format!(":e {}/t<Tab>", dir.display()));
+        assert_eq!(editor.wildmenu.as_ref().map(|wildmenu| wildmenu.candidates.len()), Some(3));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_trim_on_save() {
+        let (mut editor, mut state) = editor_with("one  \n\t\ntwo\t \n");
+        editor.feed_keys(&mut state, "jj$");
+        state.options.trim_on_save = true;
+        editor.save_to_file(&state);
+        assert_eq!(text(&editor), "one\n\ntwo\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(2, 2));
+
+        // one undo brings it all back
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), "one  \n\t\ntwo\t \n");
+    }
+
+    #[test]
+    fn test_backup() {
+        let (mut editor, mut state) = editor_with("new\n");
+        let dir = env::temp_dir().join(format!("moded_backup_{}", std::process::id()));
+        fs::create_dir_all(dir.join("backups")).unwrap();
+        let path = dir.join("file");
+        fs::write(&path, "old\n").unwrap();
+        editor.buffers[0].file_path = Some(path.clone());
+
+        state.options.backup = true;
+        editor.save_to_file(&state);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(dir.join("file~")).unwrap(), "old\n");
+
+        state.options.set(&format!("backupdir={}", dir.join("backups").display())).unwrap();
+        editor.save_to_file(&state);
+        assert_eq!(fs::read_to_string(dir.join("backups/file~")).unwrap(), "new\n");
+
+        // a backup that can't be made stops the save
+        state.options.set(&format!("backupdir={}", dir.join("missing").display())).unwrap();
+        editor.buffers[0].replace_lines(0, 0, "newer");
+        editor.save_to_file(&state);
+        assert!(editor.message.contains("backup failed"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_readonly() {
+        let (mut editor, mut state) = editor_with("one two\n");
+        editor.buffers[0].readonly = true;
+        editor.feed_keys(&mut state, "wxdwiabc<Esc>ou");
+        assert_eq!(text(&editor), "one two\n");
+        assert_eq!(editor.message, "Buffer is read-only");
+        assert!(editor.mode == EditorMode::Normal);
+        assert_eq!(editor.title("%f%r"), "[No Name] [RO]");
+        editor.buffers[0].encoding = Encoding::Latin1;
+        assert_eq!(editor.title("%f%e"), "[No Name] [latin1]");
+
+        // moving and searching still work
+        editor.message.clear();
+        editor.feed_keys(&mut state, "0/two<Enter>");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 4));
+        editor.run_command(&mut state, "s/one/three/");
+        assert_eq!(text(&editor), "one two\n");
+    }
+
+    #[test]
+    fn test_retab() {
+        let (mut editor, mut state) = editor_with("a\n\tb\n\t  c\n\td\n");
+        editor.run_command(&mut state, "set tabstop=4");
+        editor.feed_keys(&mut state, ":2,3retab<Enter>");
+        assert_eq!(text(&editor), "a\n    b\n      c\n\td\n");
+
+        // one undo puts the whole buffer back
+        editor.run_command(&mut state, "set noexpandtab");
+        editor.feed_keys(&mut state, ":retab 2<Enter>");
+        assert_eq!(text(&editor), "a\n\t\tb\n\t\t\tc\n\t\td\n");
+        assert_eq!(state.options.tabstop, 2);
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), "a\n    b\n      c\n\td\n");
+
+        // a readonly buffer keeps its text and the tabstop stays with it
+        editor.buffers[0].readonly = true;
+        editor.feed_keys(&mut state, ":retab 8<Enter>");
+        assert_eq!(editor.message, "Buffer is read-only");
+        assert_eq!(state.options.tabstop, 2);
+    }
+
+    #[test]
+    fn test_ex_ranges() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd\ne\n");
+        editor.run_command(&mut state, "2,3d");
+        assert_eq!(text(&editor), "a\nd\ne\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 0));
+
+        // : from visual mode fills in the selection's range
+        editor.feed_keys(&mut state, "ggVj:");
+        assert_eq!(editor.command_bar_input, ":'<,'>");
+        editor.feed_keys(&mut state, "s/$/!/<Enter>");
+        assert_eq!(text(&editor), "a!\nd!\ne\n");
+        editor.run_command(&mut state, "'>+1d");
+        assert_eq!(text(&editor), "a!\nd!\n");
+    }
+    #[test]
+    fn test_accept_completion() {
+        let (mut editor, mut state) = editor_with("fn main() {\n    foo.baqux()\n}\n");
+        // asked for after "foo.b", the "a" was typed since
+        editor.feed_keys(&mut state, "i");
+        editor.cursors[0].from_linepos(LinePos::new(1, 10));
+        let mut item = CompletionItem::new("bar");
+        item.replace_start = Some(LinePos::new(1, 8));
+        item.replace_after = 3;
+        item.additional_edits = vec![lsp::TextEdit { start: LinePos::new(0, 0), end: LinePos::new(0, 0), text: "use foo::Bar;\n".into() }];
+        editor.completion = CompletionMenu::new(vec![item], LinePos::new(1, 8), "ba");
+
+        // the rest of the word goes too and the import moves the cursor down
+        editor.accept_completion();
+        assert_eq!(text(&editor), "use foo::Bar;\nfn main() {\n    foo.bar()\n}\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(2, 11));
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let (mut editor, mut state) = editor_with("one\n");
+        editor.feed_keys(&mut state, ":foo bar<CR>");
+        assert_eq!(editor.message, "Not an editor command: foo bar");
+        assert!(editor.mode == EditorMode::Normal);
+        assert!(editor.command_bar_input.is_empty());
+
+        editor.feed_keys(&mut state, ":normal<CR>");
+        assert_eq!(editor.message, "Invalid command: normal");
+        assert_eq!(text(&editor), "one\n");
+
+        // files that can't be opened or made
+        let dir = env::temp_dir();
+        let missing = dir.join(format!("moded_missing_{}/x", std::process::id()));
+        editor.run_command(&mut state, &format!("e {}", missing.display()));
+        assert!(editor.message.starts_with("Can't open"));
+        editor.run_command(&mut state, &format!("view {}", dir.display()));
+        assert!(editor.message.starts_with("Can't open"));
+        assert_eq!(editor.buffers.len(), 1);
+    }
+
+    #[test]
+    fn test_wildmenu() {
+        let (mut editor, mut state) = editor_with("one\n");
+        editor.feed_keys(&mut state, ":e<Tab>");
+        assert_eq!(editor.command_bar_input, ":e");
+        assert_eq!(editor.wildmenu.as_ref().unwrap().candidates, ["edit", "enew"]);
+        editor.feed_keys(&mut state, "<Tab><Tab>");
+        assert_eq!(editor.command_bar_input, ":enew");
+        editor.feed_keys(&mut state, "<Left>");
+        assert_eq!(editor.command_bar_input, ":edit");
+
+        // typing closes it
+        editor.feed_keys(&mut state, "<BS>");
+        assert!(editor.wildmenu.is_none());
+        assert_eq!(editor.command_bar_input, ":edi");
+    }
+
+    #[test]
+    fn test_wrapscan() {
+        let (mut editor, mut state) = editor_with("a x\nb x\n");
+        editor.feed_keys(&mut state, "j$/x<CR>");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 2));
+        editor.feed_keys(&mut state, "n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 2));
+        assert_eq!(editor.message, "search hit BOTTOM, continuing at TOP");
+        editor.feed_keys(&mut state, "N");
+        assert_eq!(editor.message, "search hit TOP, continuing at BOTTOM");
+
+        state.options.wrap_scan = false;
+        editor.feed_keys(&mut state, "n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 2));
+        assert_eq!(editor.message, "Search hit BOTTOM without match");
+        editor.feed_keys(&mut state, "Nn");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 2));
+        assert!(editor.message.is_empty());
+    }
+
+    #[test]
+    fn test_indent_options() {
+        let (mut editor, mut state) = editor_with("\n");
+        editor.feed_keys(&mut state, "ia<Tab>b<Tab><CR>c<Esc>");
+        assert_eq!(text(&editor), "a   b   \nc\n");
+
+        state.options.shiftwidth = 2;
+        editor.feed_keys(&mut state, "kox<Tab><Tab>y<Esc>");
+        assert_eq!(text(&editor), "a   b   \nx   y\nc\n");
+
+        // noexpandtab only for this buffer, indents are tabs then spaces
+        editor.run_command(&mut state, "setlocal noexpandtab");
+        assert_eq!(editor.buffers[0].options.expandtab, Some(false));
+        state.options.tabstop = 4;
+        editor.feed_keys(&mut state, "o<Tab><Tab>  z<CR>w<Esc>");
+        assert_eq!(text(&editor), "a   b   \nx   y\n\t\t  z\n\t\t  w\nc\n");
+        editor.run_command(&mut state, "setlocal shiftwidth=0");
+        assert_eq!(editor.message, "Invalid command: setlocal shiftwidth=0");
+        editor.run_command(&mut state, "set expandtab");
+        assert_eq!(editor.buffers[0].options.expandtab, None);
+    }
+
+    #[test]
+    fn test_autopairs() {
+        let (mut editor, mut state) = editor_with("\n");
+        editor.feed_keys(&mut state, "if(a<Esc>");
+        assert_eq!(text(&editor), "f(a\n");
+
+        let (mut editor, mut state) = editor_with("\n");
+        editor.run_command(&mut state, "set autopairs");
+        editor.feed_keys(&mut state, "if(a[\"b\"]) don't<Esc>");
+        assert_eq!(text(&editor), "f(a[\"b\"]) don't\n");
+
+        // backspace in an empty pair takes both
+        editor.feed_keys(&mut state, "a {<BS>x'<BS><Esc>");
+        assert_eq!(text(&editor), "f(a[\"b\"]) don't x\n");
+        editor.feed_keys(&mut state, "a (<Esc>");
+        assert_eq!(text(&editor), "f(a[\"b\"]) don't x ()\n");
+    }
+
+    #[test]
+    fn test_folding() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd\ne\nf\n\n");
+        let cursor = |editor: &Editor| editor.cursors[0].to_linepos().line;
+        editor.feed_keys(&mut state, "jVjjzf");
+        assert!(editor.buffers[0].folds.is_hidden(3));
+        assert_eq!(cursor(&editor), 1);
+
+        // j and k go over the closed fold, zo opens it
+        editor.feed_keys(&mut state, "j");
+        assert_eq!(cursor(&editor), 4);
+        editor.feed_keys(&mut state, "k");
+        assert_eq!(cursor(&editor), 1);
+        editor.feed_keys(&mut state, "zoj");
+        assert_eq!(cursor(&editor), 2);
+        editor.feed_keys(&mut state, "zc");
+        assert_eq!(cursor(&editor), 1);
+        editor.feed_keys(&mut state, "zaza");
+        assert!(editor.buffers[0].folds.is_hidden(2));
+
+        // a line put in above moves the fold down, typing in it opens it
+        editor.feed_keys(&mut state, "ggOx<Esc>");
+        assert_eq!(editor.buffers[0].folds.shown_line(4), 2);
+        editor.feed_keys(&mut state, "jjiy<Esc>");
+        assert!(!editor.buffers[0].folds.is_hidden(4));
+        editor.feed_keys(&mut state, "ggzo");
+        assert_eq!(editor.message, "No fold found");
+    }
+
+    #[test]
+    fn test_which_key() {
+        let (mut editor, mut state) = editor_with("one\n");
+        editor.feed_keys(&mut state, "z");
+        assert!(editor.which_key(&state).is_none());
+        editor.pending_since = Some(Instant::now() - Duration::from_secs(1));
+        assert!(editor.which_key(&state).unwrap().contains(&"o  open fold".to_string()));
+        editor.feed_keys(&mut state, "<Esc>");
+        assert!(editor.pending_since.is_none());
+
+        // maps waiting for more keys list what they'd run
+        editor.run_command(&mut state, "nnoremap ,w :w<CR>");
+        editor.feed_keys(&mut state, ",");
+        editor.pending_since = Some(Instant::now() - Duration::from_secs(1));
+        assert_eq!(editor.which_key(&state).unwrap(), ["w  :w<CR>"]);
+    }
+
+    #[test]
+    fn test_literal_chars() {
+        let (mut editor, mut state) = editor_with("\n\n");
+        editor.feed_keys(&mut state, "i<C-k>a:<C-k>Eu<C-v>u03bb<C-v>65x<C-v>x2<Esc>");
+        assert_eq!(text(&editor), "ä€λAx\u{2}\n\n");
+        assert!(editor.mode == EditorMode::Normal && editor.literal.is_none());
+    }
+
+    #[test]
+    fn test_char_info() {
+        let (mut editor, mut state) = editor_with("né\n\n");
+        editor.feed_keys(&mut state, "lga");
+        assert_eq!(editor.message, "<é> 233, Hex e9, Oct 351, Digr e', UTF-8 c3 a9");
+        editor.feed_keys(&mut state, "jga");
+        assert_eq!(editor.message, "NUL");
+    }
+
+    #[test]
+    fn test_counts() {
+        let (mut editor, mut state) = editor_with("one two\nthree fünf\n");
+        editor.feed_keys(&mut state, "g<C-g>");
+        assert_eq!(editor.message, "2 lines, 4 words, 19 chars, 20 bytes");
+        editor.feed_keys(&mut state, "wvjg<C-g>");
+        assert_eq!(editor.message, "Selected 2 lines, 2 words, 9 chars, 9 bytes");
+        editor.feed_keys(&mut state, "<Esc>Vg<C-g>");
+        assert_eq!(editor.message, "Selected 1 lines, 2 words, 11 chars, 12 bytes");
+    }
+
+    #[test]
+    fn test_indent_folding() {
+        let (mut editor, mut state) = editor_with("fn a() {\n    b();\n    c();\n}\nfn d() {\n    e();\n    f();\n}\n\n");
+        editor.run_command(&mut state, "set foldmethod=indent");
+        editor.feed_keys(&mut state, "zM");
+        assert!(editor.buffers[0].folds.is_hidden(2) && editor.buffers[0].folds.is_hidden(6));
+        editor.feed_keys(&mut state, "jj");
+        assert_eq!(editor.cursors[0].to_linepos().line, 3);
+        editor.feed_keys(&mut state, "zR");
+        assert!(!editor.buffers[0].folds.is_hidden(6));
+
+        // the folds follow edits, zf can't add any
+        editor.feed_keys(&mut state, "ggo    x();<Esc>ggzM");
+        assert!(editor.buffers[0].folds.is_hidden(3));
+        editor.feed_keys(&mut state, "Gzfk");
+        assert_eq!(editor.message, "Cannot create fold with current foldmethod");
+    }
+
+    #[test]
+    fn test_surround() {
+        let (mut editor, mut state) = editor_with("one two three\n  four\n");
+        editor.feed_keys(&mut state, "wysiw\"");
+        assert_eq!(text(&editor), "one \"two\" three\n  four\n");
+        editor.feed_keys(&mut state, "lcs\"(");
+        assert_eq!(text(&editor), "one ( two ) three\n  four\n");
+        editor.feed_keys(&mut state, "ds(");
+        assert_eq!(text(&editor), "one  two  three\n  four\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 4));
+
+        // yss is the line without its indent, S the visual selection
+        editor.feed_keys(&mut state, "jyss]");
+        assert_eq!(text(&editor), "one  two  three\n  [four]\n");
+        editor.feed_keys(&mut state, "k0veS'");
+        assert_eq!(text(&editor), "'one'  two  three\n  [four]\n");
+        assert!(editor.mode == EditorMode::Normal);
+        editor.feed_keys(&mut state, "ys$B");
+        assert_eq!(text(&editor), "{'one'  two  three}\n  [four]\n");
+
+        // tags, and c on its own isn't a command
+        let (mut editor, mut state) = editor_with("<p>a <b>b</b></p>\n");
+        editor.feed_keys(&mut state, "dst");
+        assert_eq!(text(&editor), "a <b>b</b>\n");
+        editor.feed_keys(&mut state, "cw");
+        assert_eq!(editor.message, "Unknown key sequence: cw");
+    }
+
+    #[test]
+    fn test_format_operator() {
+        let (mut editor, mut state) = editor_with("// one two three four\n// five\n\nsix seven eight\n");
+        editor.run_command(&mut state, "set textwidth=13");
+        editor.feed_keys(&mut state, "gqj");
+        assert_eq!(text(&editor), "// one two\n// three four\n// five\n\nsix seven eight\n");
+        editor.feed_keys(&mut state, "gqG");
+        assert_eq!(text(&editor), "// one two\n// three four\n// five\n\nsix seven\neight\n");
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), "// one two\n// three four\n// five\n\nsix seven eight\n");
+
+        // gqq is the line, in visual mode the selection
+        editor.feed_keys(&mut state, "Ggqq");
+        assert_eq!(text(&editor), "// one two\n// three four\n// five\n\nsix seven\neight\n");
+        editor.run_command(&mut state, "set textwidth=20");
+        editor.feed_keys(&mut state, "Vjgq");
+        assert_eq!(text(&editor), "// one two\n// three four\n// five\n\nsix seven eight\n");
+        assert!(editor.mode == EditorMode::Normal);
+    }
+
+    #[test]
+    fn test_operator_search() {
+        let (mut editor, mut state) = editor_with("one two three\nfour\n");
+        editor.feed_keys(&mut state, "d/thr<CR>");
+        assert_eq!(text(&editor), "three\nfour\n");
+        assert_eq!(editor.deleted[0], "one two ");
+        assert!(editor.mode == EditorMode::Normal);
+        editor.feed_keys(&mut state, "d/fo<CR>");
+        assert_eq!(text(&editor), "four\n");
+
+        // escape drops the operator with the search
+        editor.feed_keys(&mut state, "d/ou<Esc>/r<CR>");
+        assert_eq!(text(&editor), "four\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 3));
+
+        let (mut editor, mut state) = editor_with("fn a() {\nb();\nc();\n}\n");
+        editor.feed_keys(&mut state, "=/c<CR>");
+        assert_eq!(text(&editor), "fn a() {\n    b();\n    c();\n}\n");
+        assert_eq!(editor.message, "3 lines indented");
+    }
+
+    #[test]
+    fn test_change_commands() {
+        let (mut editor, mut state) = editor_with("one two\n  three\nfour\nfive\n");
+        editor.feed_keys(&mut state, "wD");
+        assert_eq!(text(&editor), "one \n  three\nfour\nfive\n");
+        assert_eq!(editor.deleted[0], "two");
+        editor.feed_keys(&mut state, "0lCne<Esc>");
+        assert_eq!(text(&editor), "one\n  three\nfour\nfive\n");
+
+        // S keeps the indent
+        editor.feed_keys(&mut state, "jSx<Esc>");
+        assert_eq!(text(&editor), "one\n  x\nfour\nfive\n");
+        editor.feed_keys(&mut state, "j2Sy<Esc>");
+        assert_eq!(text(&editor), "one\n  x\ny\n");
+        editor.feed_keys(&mut state, "k02sab<Esc>");
+        assert_eq!(text(&editor), "one\nabx\ny\n");
+        assert!(editor.mode == EditorMode::Normal);
+    }
+
+    #[test]
+    fn test_append_insert() {
+        let (mut editor, mut state) = editor_with("  one\ntwo\n");
+        editor.feed_keys(&mut state, "$IX<Esc>");
+        assert_eq!(text(&editor), "  Xone\ntwo\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 2));
+        editor.feed_keys(&mut state, "0A!<Esc>");
+        assert_eq!(text(&editor), "  Xone!\ntwo\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 6));
+
+        // a count types the insert that many times
+        editor.feed_keys(&mut state, "j3Aab<Esc>");
+        assert_eq!(text(&editor), "  Xone!\ntwoababab\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 8));
+        editor.feed_keys(&mut state, "2Ix<CR><Esc>");
+        assert_eq!(text(&editor), "  Xone!\nx\nx\ntwoababab\n");
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), "  Xone!\ntwoababab\n");
+    }
+
+    #[test]
+    fn test_first_nonblank() {
+        let (mut editor, mut state) = editor_with("    one two\n  three\n\n");
+        editor.feed_keys(&mut state, "$^");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 4));
+        editor.feed_keys(&mut state, "0");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 0));
+        editor.feed_keys(&mut state, "2_");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 2));
+        editor.feed_keys(&mut state, "k$d^");
+        assert_eq!(text(&editor), "    o\n  three\n\n");
+        editor.feed_keys(&mut state, "jd_");
+        assert_eq!(text(&editor), "    o\n\n");
+
+        // smarthome toggles between the first non-blank and col 0
+        editor.run_command(&mut state, "set smarthome");
+        editor.feed_keys(&mut state, "k^0");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 0));
+        editor.feed_keys(&mut state, "0");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 4));
+        editor.feed_keys(&mut state, "<Home>");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 0));
+    }
+
+    #[test]
+    fn test_auto_dedent() {
+        let (mut editor, mut state) = editor_with("\n");
+        editor.feed_keys(&mut state, "iif a {<CR>b({<CR>c<CR>})<CR>}<Esc>");
+        assert_eq!(text(&editor), "if a {\n    b({\n        c\n    })\n}\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(4, 0));
+
+        // without a match it's one shiftwidth back, after other text it stays
+        let (mut editor, mut state) = editor_with("        x\n");
+        editor.feed_keys(&mut state, "oa)<Esc>o]<Esc>");
+        assert_eq!(text(&editor), "        x\n        a)\n    ]\n");
+    }
+
+    #[test]
+    fn test_reindent_operator() {
+        let original = "fn a() {\nb();\nif c {\nd();\n}\n}\n";
+        let (mut editor, mut state) = editor_with(original);
+        editor.feed_keys(&mut state, "G=gg");
+        assert_eq!(text(&editor), "fn a() {\n    b();\n    if c {\n        d();\n    }\n}\n");
+        assert_eq!(editor.message, "6 lines indented");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(0, 0));
+
+        // one undo takes it all back
+        editor.feed_keys(&mut state, "u");
+        assert_eq!(text(&editor), original);
+
+        editor.feed_keys(&mut state, "ggj==");
+        assert_eq!(text(&editor), "fn a() {\n    b();\nif c {\nd();\n}\n}\n");
+        assert_eq!(editor.cursors[0].to_linepos(), LinePos::new(1, 4));
+        editor.feed_keys(&mut state, "jVj=");
+        assert_eq!(text(&editor), "fn a() {\n    b();\n    if c {\n        d();\n}\n}\n");
+        assert!(editor.mode == EditorMode::Normal);
+        editor.feed_keys(&mut state, "gg=G");
+        assert_eq!(text(&editor), "fn a() {\n    b();\n    if c {\n        d();\n    }\n}\n");
+    }
+
+    #[test]
+    fn test_key_maps() {
+        let (mut editor, mut state) = editor_with("one two three\n");
+        editor.run_command(&mut state, "nnoremap x dw");
+        editor.run_command(&mut state, "nmap Q x");
+        editor.run_command(&mut state, "inoremap jk <Esc>");
+        editor.feed_keys(&mut state, "Q");
+        assert_eq!(text(&editor), "two three\n");
+
+        // a held j is typed once the next key can't make jk
+        editor.feed_keys(&mut state, "ijjajk");
+        assert_eq!(text(&editor), "jjatwo three\n");
+        assert!(editor.mode == EditorMode::Normal);
+
+        // noremap keys aren't mapped again, x is still dw for Q
+        editor.run_command(&mut state, "nnoremap dw x");
+        editor.feed_keys(&mut state, "0dw");
+        assert_eq!(text(&editor), "jatwo three\n");
+        editor.feed_keys(&mut state, "Q");
+        assert_eq!(text(&editor), "three\n");
+
+        editor.run_command(&mut state, "nunmap x");
+        assert_eq!(editor.key_maps.len(), 3);
+        editor.run_command(&mut state, "iunmap x");
+        assert_eq!(editor.message, "No such mapping: x");
+        editor.run_command(&mut state, "map");
+        assert_eq!(text(&editor), "n  Q  x\nn  dw  *x\n");
+    }
+
+    #[test]
+    fn test_save_recovery() {
+        let (mut editor, mut state) = editor_with("one\ntwo\n");
+        let dir = env::temp_dir().join(format!("moded_recovery_{}", std::process::id()));
+        assert!(editor.save_recovery(&dir).is_empty());
+
+        editor.feed_keys(&mut state, "x");
+        editor.run_command(&mut state, "enew");
+        let saved = editor.save_recovery(&dir);
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].to_string_lossy().ends_with(&format!("-{}-unnamed", editor.buffers[0].id)));
+        assert_eq!(fs::read_to_string(&saved[0]).unwrap(), "ne\ntwo\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_physical_keys() {
+        let (mut editor, mut state) = editor_with("one\ntwo\n");
+        let typed = |editor: &mut Editor, state: &mut State, chars: &str, physical: &str| {
+            state.io.chars = chars.to_owned();
+            state.io.physical_chars = physical.to_owned();
+            editor.handle_input(state);
+            state.io.reset();
+        };
+
+        // on Dvorak the key at j types h
+        typed(&mut editor, &mut state, "h", "j");
+        assert_eq!(editor.cursors[0].y, 1);
+        editor.run_command(&mut state, "set physicalkeys");
+        typed(&mut editor, &mut state, "h", "j");
+        assert_eq!(editor.cursors[0].y, 2);
+
+        // insert mode types what the layout says
+        typed(&mut editor, &mut state, "i", "i");
+        typed(&mut editor, &mut state, "é", "2");
+        assert_eq!(text(&editor), "one\nétwo\n");
+    }
+
+    #[test]
+    fn test_virtualedit() {
+        let (mut editor, mut state) = editor_with("long line\nab\n");
+        editor.feed_keys(&mut state, "$jl");
+        assert_eq!((editor.cursors[0].y, editor.cursors[0].x), (2, 2));
+
+        editor.run_command(&mut state, "set virtualedit=block,all");
+        assert_eq!(state.options.virtualedit, VirtualEdit { block: true, all: true });
+        editor.feed_keys(&mut state, "k$jl");
+        assert_eq!((editor.cursors[0].y, editor.cursors[0].x), (2, 10));
+        // commands past the end work from the end, typing fills the gap
+        editor.feed_keys(&mut state, "x");
+        assert_eq!(text(&editor), "long line\nab\n");
+        editor.feed_keys(&mut state, "iX<Esc>");
+        assert_eq!(text(&editor), "long line\nab       X\n");
+
+        editor.run_command(&mut state, "set virtualedit=");
+        assert_eq!(state.options.virtualedit, VirtualEdit::default());
+        editor.run_command(&mut state, "set virtualedit=bogus");
+        assert_eq!(state.options.virtualedit, VirtualEdit::default());
+    }
 }
@@ -0,0 +1,94 @@
+// buffers are always utf-8, files in other encodings are turned into it when
+// they're read and back when they're written
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    // utf-8 starting with a byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8 bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+}
+
+// utf-16 is only recognized by its byte order mark. invalid utf-8 without NUL
+// bytes is latin1, anything else is left as it is for the hex view
+pub fn decode(data: Vec<u8>) -> (Encoding, Vec<u8>) {
+    if let Some(rest) = data.strip_prefix(b"\xef\xbb\xbf") {
+        if std::str::from_utf8(rest).is_ok() {
+            return (Encoding::Utf8Bom, rest.to_vec())
+        }
+    }
+
+    for (bom, encoding) in [(b"\xff\xfe", Encoding::Utf16Le), (b"\xfe\xff", Encoding::Utf16Be)] {
+        let Some(rest) = data.strip_prefix(bom) else { continue };
+        if rest.len() % 2 != 0 {
+            break
+        }
+        let units = rest.chunks(2).map(|pair| match encoding {
+            Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+            _ => u16::from_be_bytes([pair[0], pair[1]]),
+        });
+        if let Ok(text) = char::decode_utf16(units).collect::<Result<String, _>>() {
+            return (encoding, text.into_bytes())
+        }
+    }
+
+    if std::str::from_utf8(&data).is_err() && !data.contains(&0) {
+        let text = data.iter().map(|&byte| byte as char).collect::<String>();
+        return (Encoding::Latin1, text.into_bytes())
+    }
+
+    (Encoding::Utf8, data)
+}
+
+// Err with the first char the encoding can't hold
+pub fn encode(text: &str, encoding: Encoding) -> Result<Vec<u8>, char> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        Encoding::Utf8Bom => Ok([b"\xef\xbb\xbf", text.as_bytes()].concat()),
+        Encoding::Utf16Le => Ok([0xff, 0xfe].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()),
+        Encoding::Utf16Be => Ok([0xfe, 0xff].into_iter().chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()),
+        Encoding::Latin1 => text.chars().map(|c| u8::try_from(c).map_err(|_| c)).collect(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let files: [(&[u8], Encoding, &str); 5] = [
+            (b"abc\n", Encoding::Utf8, "abc\n"),
+            (b"\xef\xbb\xbfabc\n", Encoding::Utf8Bom, "abc\n"),
+            (b"\xff\xfea\x00\xe4\x00\n\x00", Encoding::Utf16Le, "aä\n"),
+            (b"\xfe\xff\x00a\x00\xe4\x00\n", Encoding::Utf16Be, "aä\n"),
+            (b"caf\xe9\n", Encoding::Latin1, "café\n"),
+        ];
+        for (data, encoding, text) in files {
+            let (found, decoded) = decode(data.to_vec());
+            assert_eq!(found, encoding);
+            assert_eq!(decoded, text.as_bytes());
+            assert_eq!(encode(text, encoding).unwrap(), data);
+        }
+
+        // binary files stay as they are
+        assert_eq!(decode(b"\xff\0".to_vec()), (Encoding::Utf8, b"\xff\0".to_vec()));
+        assert_eq!(encode("€", Encoding::Latin1), Err('€'));
+    }
+}
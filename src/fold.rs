@@ -0,0 +1,230 @@
+use std::{cmp::Reverse, collections::HashSet};
+
+use crate::{gap_buffer::TextBuffer, indent::indent_width};
+
+
+// a closed fold shows its lines as one summary line, an open one is kept to be
+// closed again. folds can nest, lines start..=end of a buffer, 0 indexed
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+// sorted by start, a fold comes before the ones nested in it
+#[derive(Debug, Default)]
+pub struct Folds {
+    folds: Vec<Fold>,
+    // the buffer's changes count the folds were last worked out at, with a
+    // foldmethod other than manual
+    pub computed: Option<u64>,
+}
+
+impl Folds {
+    // zf, the new fold starts closed
+    pub fn add(&mut self, start: usize, end: usize) {
+        if end <= start {
+            return
+        }
+        let fold = Fold { start, end, closed: true };
+        let i = self.folds.partition_point(|other| (other.start, Reverse(other.end)) < (start, Reverse(end)));
+        self.folds.insert(i, fold);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    // the outermost closed fold line is in, the one that's shown
+    pub fn closed_at(&self, line: usize) -> Option<Fold> {
+        self.folds.iter().find(|fold| fold.closed && fold.start <= line && line <= fold.end).copied()
+    }
+
+    // lines of a closed fold other than its first aren't shown
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.closed_at(line).is_some_and(|fold| fold.start < line)
+    }
+
+    // the line line is shown on
+    pub fn shown_line(&self, line: usize) -> usize {
+        self.closed_at(line).map_or(line, |fold| fold.start)
+    }
+
+    // zo opens the closed fold that's shown, zc closes the innermost open one.
+    // false if there's no fold to open or close at line
+    pub fn open(&mut self, line: usize) -> bool {
+        let fold = self.folds.iter_mut().find(|fold| fold.closed && fold.start <= line && line <= fold.end);
+        fold.map(|fold| fold.closed = false).is_some()
+    }
+
+    pub fn close(&mut self, line: usize) -> bool {
+        let fold = self.folds.iter_mut().rev().find(|fold| !fold.closed && fold.start <= line && line <= fold.end);
+        fold.map(|fold| fold.closed = true).is_some()
+    }
+
+    // zM and zR
+    pub fn close_all(&mut self) {
+        self.folds.iter_mut().for_each(|fold| fold.closed = true);
+    }
+
+    pub fn open_all(&mut self) {
+        self.folds.iter_mut().for_each(|fold| fold.closed = false);
+    }
+
+    // za
+    pub fn toggle(&mut self, line: usize) -> bool {
+        if self.closed_at(line).is_some() {
+            self.open(line)
+        } else {
+            self.close(line)
+        }
+    }
+
+    // opens every fold holding line, for when it gets edited
+    pub fn reveal(&mut self, line: usize) {
+        for fold in self.folds.iter_mut().filter(|fold| fold.start <= line && line <= fold.end) {
+            fold.closed = false;
+        }
+    }
+
+    // n lines were put in before line at, folds after it move down and ones
+    // around it get longer
+    pub fn lines_inserted(&mut self, at: usize, n: usize) {
+        for fold in &mut self.folds {
+            if fold.start >= at {
+                fold.start += n;
+                fold.end += n;
+            } else if fold.end >= at {
+                fold.end += n;
+            }
+        }
+    }
+
+    // lines at..at + n were removed, folds left with one line go away
+    pub fn lines_removed(&mut self, at: usize, n: usize) {
+        let map = |line: usize| if line >= at + n { line - n } else { line.min(at) };
+        for fold in &mut self.folds {
+            fold.start = map(fold.start);
+            // an end inside the removed lines goes to the line before them
+            fold.end = if fold.end >= at && fold.end < at + n { at.saturating_sub(1).max(fold.start) } else { map(fold.end) };
+        }
+        self.folds.retain(|fold| fold.end > fold.start);
+    }
+
+    // swaps in folds worked out from the text, new ones start open and ones
+    // that were there before stay as they were
+    pub fn replace(&mut self, ranges: Vec<(usize, usize)>) {
+        let closed = self.folds.iter().filter(|fold| fold.closed).map(|fold| (fold.start, fold.end)).collect::<HashSet<_>>();
+        self.folds = ranges.into_iter().filter(|(start, end)| end > start)
+            .map(|(start, end)| Fold { start, end, closed: closed.contains(&(start, end)) })
+            .collect();
+        self.folds.sort_by_key(|fold| (fold.start, Reverse(fold.end)));
+    }
+
+    // after undo the lines are counted again, folds past the end go
+    pub fn clamp(&mut self, total_lines: usize) {
+        for fold in &mut self.folds {
+            fold.end = fold.end.min(total_lines.saturating_sub(1));
+        }
+        self.folds.retain(|fold| fold.end > fold.start);
+    }
+}
+
+// foldmethod=indent, lines indented more than the line above them fold, without
+// that line so a closed function body leaves its signature shown. blank lines
+// go with the less indented of the lines around them
+pub fn indent_folds(buf: &TextBuffer, tabstop: usize) -> Vec<(usize, usize)> {
+    let total = buf.total_lines();
+    let widths = (0..total).map(|line| {
+        let text = buf.line(line);
+        (!text.trim().is_empty()).then(|| indent_width(&text, tabstop))
+    }).collect::<Vec<_>>();
+    let mut indents = vec![0; total];
+    let mut above = 0;
+    for (line, width) in widths.iter().enumerate() {
+        above = width.unwrap_or(above);
+        indents[line] = above;
+    }
+    let mut below = 0;
+    for (line, width) in widths.iter().enumerate().rev() {
+        match width {
+            Some(width) => below = *width,
+            None => indents[line] = indents[line].min(below),
+        }
+    }
+
+    let mut ranges = Vec::new();
+    // the indent and first line of the folds the line is in
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    for line in 0..=total {
+        let indent = indents.get(line).copied().unwrap_or(0);
+        let mut start = line;
+        while let Some(&(outer, outer_start)) = open.last() {
+            if outer <= indent {
+                break
+            }
+            open.pop();
+            ranges.push((outer_start, line - 1));
+            start = outer_start;
+        }
+        if indent > open.last().map_or(0, |(outer, _)| *outer) {
+            open.push((indent, start));
+        }
+    }
+
+    ranges
+}
+
+// the line a closed fold shows, like vim's "+--  5 lines: fn main() {"
+pub fn summary(buf: &TextBuffer, fold: Fold) -> String {
+    format!("+--{:>3} lines: {}", fold.end - fold.start + 1, buf.line(fold.start).trim())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folds() {
+        let mut folds = Folds::default();
+        folds.add(2, 8);
+        folds.add(4, 5);
+        assert!(folds.is_hidden(5));
+        assert!(!folds.is_hidden(2));
+        assert_eq!(folds.shown_line(5), 2);
+
+        // zo opens the outer fold, the inner one still hides line 5
+        assert!(folds.open(4));
+        assert_eq!(folds.shown_line(5), 4);
+        assert!(folds.toggle(5));
+        assert_eq!(folds.shown_line(5), 5);
+        assert!(folds.close(5));
+        assert_eq!(folds.shown_line(5), 4);
+        assert!(!folds.open(0));
+
+        // edits move folds along
+        folds.lines_inserted(3, 2);
+        assert_eq!(folds.folds, [Fold { start: 2, end: 10, closed: false }, Fold { start: 6, end: 7, closed: true }]);
+        folds.lines_removed(7, 3);
+        assert_eq!(folds.folds, [Fold { start: 2, end: 7, closed: false }]);
+        folds.clamp(5);
+        assert_eq!(folds.folds, [Fold { start: 2, end: 4, closed: false }]);
+    }
+
+    #[test]
+    fn test_indent_folds() {
+        let text = "fn a() {\n    if b {\n        c();\n\n    }\n      d();\n}\n\nfn e() {}\n\n";
+        let buf = TextBuffer::from_data(0, text.as_bytes().to_vec());
+        let mut folds = Folds::default();
+        folds.replace(indent_folds(&buf, 8));
+        assert_eq!(folds.folds, [Fold { start: 1, end: 5, closed: false }]);
+
+        // closed folds stay closed when worked out again
+        folds.close_all();
+        folds.replace(vec![(1, 5), (2, 3)]);
+        assert_eq!(folds.folds, [Fold { start: 1, end: 5, closed: true }, Fold { start: 2, end: 3, closed: false }]);
+    }
+}
@@ -82,6 +82,11 @@ impl CharacterCache {
         self.map.get(&ch)
     }
 
+    // glyphs loaded so far
+    pub fn glyphs(&self) -> usize {
+        self.map.len()
+    }
+
     pub fn try_insert(&mut self, ch: char) {
         let glyph = self.font.glyph_id(ch).with_scale(self.char_scale);
 
@@ -141,3 +146,11 @@ impl CharacterCache {
         texture
     }
 }
+
+// the glyphs are rebuilt when the font size changes
+impl Drop for CharacterCache {
+    fn drop(&mut self) {
+        let textures = self.map.values().map(|c| c.texture_id).collect::<Vec<_>>();
+        unsafe { gl::DeleteTextures(textures.len() as i32, textures.as_ptr()) };
+    }
+}
@@ -0,0 +1,122 @@
+use std::{ffi::OsString, path::Path};
+
+use crate::{gap_buffer::TextBuffer, shell, width};
+
+
+// formatprg and :format {cmd} go through the shell like :!, the defaults are
+// run without it since they get the file's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Formatter {
+    Shell(String),
+    Program(&'static str, Vec<OsString>),
+}
+
+// formatter to use when formatprg isn't set, by file extension
+fn default_formatter(path: &Path) -> Option<Formatter> {
+    let ext = path.extension()?.to_str()?;
+    let (program, args) = match ext {
+        "rs" => ("rustfmt", vec!["--edition".into(), "2021".into()]),
+        "c" | "h" | "cc" | "cpp" | "hpp" => {
+            let mut arg = OsString::from("--assume-filename=");
+            arg.push(path);
+            ("clang-format", vec![arg])
+        },
+        "js" | "jsx" | "ts" | "tsx" | "json" | "css" | "html" | "md" => ("prettier", vec!["--stdin-filepath".into(), path.into()]),
+        _ => return None,
+    };
+
+    Some(Formatter::Program(program, args))
+}
+
+pub fn formatter_for(formatprg: &str, path: Option<&Path>) -> Option<Formatter> {
+    if !formatprg.is_empty() {
+        return Some(Formatter::Shell(formatprg.to_owned()))
+    }
+
+    default_formatter(path?)
+}
+
+// the buffer's text after going through the formatter
+pub fn run(formatter: &Formatter, buffer: &TextBuffer) -> Result<String, String> {
+    let text = (0..buffer.total_lines()).map(|line| buffer.raw_line(line)).collect::<String>();
+    match formatter {
+        Formatter::Shell(command) => shell::filter(command, &text),
+        Formatter::Program(program, args) => shell::filter_program(program, args, &text),
+    }
+}
+
+// comment starts gq keeps at the front of every line, longest first
+const COMMENT_LEADERS: [&str; 4] = ["///", "//!", "//", "#"];
+
+// the blanks and comment leader line starts with, and the blanks after it
+fn leader(line: &str) -> &str {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let Some(comment) = COMMENT_LEADERS.iter().find(|comment| rest.starts_with(**comment)) else { return &line[..indent] };
+    let after = &rest[comment.len()..];
+
+    &line[..indent + comment.len() + after.len() - after.trim_start().len()]
+}
+
+// joins the paragraphs of lines and breaks them again so they're at most
+// width cells wide, a word wider than that gets a line of its own. lines
+// with only a leader end a paragraph, so does a change of leader
+pub fn reflow(lines: &[String], width: usize, tabstop: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let first = leader(&lines[i]);
+        if lines[i].len() == first.len() {
+            out.push(lines[i].trim_end().to_owned());
+            i += 1;
+            continue
+        }
+
+        let mut words = Vec::new();
+        while i < lines.len() && leader(&lines[i]).trim_end() == first.trim_end() && lines[i].len() > leader(&lines[i]).len() {
+            words.extend(lines[i][leader(&lines[i]).len()..].split_whitespace());
+            i += 1;
+        }
+
+        let mut line = first.to_owned();
+        for word in words {
+            let cells = width::display_col(&line, line.chars().count(), tabstop);
+            if line.len() > first.len() && cells + 1 + width::str_width(word) > width {
+                out.push(std::mem::replace(&mut line, first.to_owned()));
+            }
+            if line.len() > first.len() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        out.push(line);
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_formatter() {
+        let path = Path::new("src/it's.c");
+        let expected = Formatter::Program("clang-format", vec![OsString::from("--assume-filename=src/it's.c")]);
+        assert_eq!(formatter_for("", Some(path)), Some(expected));
+        assert_eq!(formatter_for("astyle", Some(path)), Some(Formatter::Shell("astyle".to_owned())));
+        assert_eq!(formatter_for("", Some(Path::new("notes.txt"))), None);
+    }
+
+    #[test]
+    fn test_reflow() {
+        let lines = |text: &str| text.lines().map(str::to_owned).collect::<Vec<_>>();
+        let text = "  one two three four\n  five six\n\n    // a b c\n    // d e\n    //\n    // f\n# g h i j\n";
+        let expected = "  one two three\n  four five six\n\n    // a b c d\n    // e\n    //\n    // f\n# g h i j\n";
+        assert_eq!(reflow(&lines(text), 15, 8), lines(expected));
+
+        // long words still go on a line of their own, tabs count to the tabstop
+        assert_eq!(reflow(&lines("\tabcdefghij k\n"), 12, 4), lines("\tabcdefghij\n\tk\n"));
+    }
+}
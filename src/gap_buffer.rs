@@ -1,4 +1,6 @@
-use std::{fmt::Debug, fs, io::{self, Read}, path::{Path, PathBuf}};
+use std::{fmt::Debug, fs, io::{self, BufRead, Read, Write}, ops::{Deref, DerefMut}, path::{Path, PathBuf}, sync::{mpsc::{self, Receiver, TryRecvError}, Arc}, thread};
+
+use crate::{buffer_vars::BufferVars, encoding::{self, Encoding}, fold::Folds, hex, indent, line_index::{self, LineIndex}, log, options::LocalOptions, undo::{Change, History}, virtual_text::VirtualText};
 
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -61,64 +63,229 @@ pub enum LineView<'a> {
     Parts(&'a str, &'a str),
 }
 
+impl LineView<'_> {
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        let (first, second) = match self {
+            LineView::Contiguous(s) => (*s, ""),
+            LineView::Parts(s1, s2) => (*s1, *s2),
+        };
+        first.chars().chain(second.chars())
+    }
+}
+
+impl std::fmt::Display for LineView<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineView::Contiguous(s) => write!(f, "{s}"),
+            LineView::Parts(s1, s2) => write!(f, "{s1}{s2}"),
+        }
+    }
+}
+
+
+// files bigger than this are read in LOAD_CHUNK pieces on another thread, the
+// buffer shows what's read so far
+const LAZY_LOAD_THRESHOLD: u64 = 64 << 20;
+const LOAD_CHUNK: u64 = 1 << 20;
+
+// what the loading thread sends for each piece of the file
+pub enum Chunk {
+    Text(Vec<u8>),
+    // wasn't utf-8 and had chars replaced
+    Lossy(Vec<u8>),
+    // the file couldn't be read any further
+    Failed(io::Error),
+}
 
 pub struct TextBuffer {
     pub chars: GapBuffer<u8>,
-    pub lines: GapBuffer<usize>,
+    pub lines: Box<dyn LineIndex>,
     pub file_path: Option<PathBuf>,
     pub id: usize,
     pub line_sep: LineSeparator,
+    pub vars: BufferVars,
+    pub history: History,
+    // bumped by every edit, undo and redo. the buffer is modified while it's
+    // not what it was at the last save
+    pub changes: u64,
+    pub saved_changes: u64,
+    // the rest of a big file while it's being read
+    pub loading: Option<Receiver<Chunk>>,
+    // files without write permission and ones opened with :view, edits are refused
+    pub readonly: bool,
+    // part of a big file wasn't utf-8 or couldn't be read, the buffer is made
+    // readonly so saving can't write what's in it over the file
+    pub lossy: bool,
+    // a binary file shown as a hex dump, see hex.rs
+    pub hex: bool,
+    // what the file is written back as
+    pub encoding: Encoding,
+    // set with :setlocal
+    pub options: LocalOptions,
+    // the file ended with a line separator
+    pub eol: bool,
+    // made with zf, they move with the lines around them
+    pub folds: Folds,
+    // drawn after line ends, moves like folds
+    pub virtual_text: VirtualText,
 }
 
 // everything is 0-indexed
 impl TextBuffer {
-    pub fn from_data(id: usize, mut chars: Vec<u8>) -> Self {
+    pub fn from_data(id: usize, chars: Vec<u8>) -> Self {
+        let size = chars.len();
+        Self::from_data_sized(id, chars, size)
+    }
+
+    // size is how big the buffer is going to be, for picking the line index
+    fn from_data_sized(id: usize, mut chars: Vec<u8>, size: usize) -> Self {
+        let eol = chars.ends_with(b"\n");
         if chars.len() == 0 {
             chars.push(b'\n');
         }
 
-        let mut lines = Vec::new();
         let st = unsafe {std::str::from_utf8_unchecked(&chars)};
-        // assuming newlines for now
-        let mut start = 0;
         let line_sep = if st.contains("\r\n") {
             LineSeparator::CRLF
         } else {
             LineSeparator::LF
         };
 
-        for line in st.lines() {
-            lines.push(start);
-            start += line.len() + line_sep as usize;
-        }
-        let lines = GapBuffer::new(lines);
-        println!("Using {:?} line separator", line_sep);
+        let lines = line_index::new_index(line_starts(&chars, line_sep), size.max(chars.len()));
+        log::debug(&format!("Using {:?} line separator", line_sep));
 
         Self { 
             id,
             chars: GapBuffer::new(chars),
             lines, line_sep,
-            file_path: None
+            file_path: None,
+            vars: BufferVars::new(),
+            options: LocalOptions::default(),
+            history: History::new(),
+            changes: 0,
+            saved_changes: 0,
+            loading: None,
+            readonly: false,
+            lossy: false,
+            hex: false,
+            encoding: Encoding::Utf8,
+            eol,
+            folds: Folds::default(),
+            virtual_text: VirtualText::default(),
         }
     }
 
-    pub fn from_path(id: usize, path: &Path) -> Self {
+    // reads path, or creates it if it doesn't exist
+    pub fn from_path(id: usize, path: &Path) -> io::Result<Self> {
         let mut lines: Vec<_> = Vec::new();
         if path.is_file() {
-            let file = fs::File::open(path).unwrap();
+            let file = fs::File::open(path)?;
+            let size = file.metadata().map_or(0, |metadata| metadata.len());
+            let readonly = file.metadata().is_ok_and(|metadata| metadata.permissions().readonly());
             let mut reader = io::BufReader::new(file);
-            reader.read_to_end(&mut lines).expect("can't read file to end");
+            if size > LAZY_LOAD_THRESHOLD {
+                lines = read_chunk(&mut reader)?;
+            }
+            // binary files and ones in other encodings are read whole
+            let plain = !hex::is_binary(&lines) && !lines.starts_with(b"\xef\xbb\xbf");
+            if size > LAZY_LOAD_THRESHOLD && plain {
+                let mut me = TextBuffer::from_data_sized(id, lines, size as usize);
+                me.file_path = Some(path.to_owned());
+                me.readonly = readonly;
+                me.options = indent::detect(&me);
+                let (sender, chunks) = mpsc::channel();
+                thread::spawn(move || loop {
+                    let chunk = match read_chunk(&mut reader) {
+                        Ok(chunk) if chunk.is_empty() => break,
+                        Ok(chunk) => match std::str::from_utf8(&chunk) {
+                            Ok(_) => Chunk::Text(chunk),
+                            // invalid utf-8 further in the file can't go in the buffer as it is
+                            Err(_) => Chunk::Lossy(String::from_utf8_lossy(&chunk).into_owned().into_bytes()),
+                        },
+                        Err(err) => Chunk::Failed(err),
+                    };
+                    let failed = matches!(chunk, Chunk::Failed(_));
+                    if sender.send(chunk).is_err() || failed {
+                        break
+                    }
+                });
+                me.loading = Some(chunks);
+
+                return Ok(me)
+            }
+            reader.read_to_end(&mut lines)?;
+            let (encoding, mut lines) = encoding::decode(lines);
+            let binary = hex::is_binary(&lines);
+            if binary {
+                lines = hex::dump(&lines).into_bytes();
+            }
             let mut me = TextBuffer::from_data(id, lines);
             me.file_path = Some(path.to_owned());
+            me.readonly = readonly;
+            me.hex = binary;
+            me.encoding = encoding;
+            if !binary {
+                me.options = indent::detect(&me);
+            }
 
-            return me
+            return Ok(me)
         }
 
-        fs::File::create(path).expect("Couldn't create a file");
+        fs::File::create(path)?;
         let mut me = TextBuffer::from_data(id, lines);
         me.file_path = Some(path.to_owned());
 
-        me
+        Ok(me)
+    }
+
+    // appends what the loading thread has read since the last call, true if
+    // anything changed. a read error stops the loading and leaves the buffer
+    // readonly, the rest of the file isn't in it
+    pub fn poll_loading(&mut self) -> io::Result<bool> {
+        let mut loaded = false;
+        while let Some(chunks) = &self.loading {
+            match chunks.try_recv() {
+                Ok(Chunk::Text(chunk)) => self.append_loaded(&chunk),
+                Ok(Chunk::Lossy(chunk)) => {
+                    self.append_loaded(&chunk);
+                    self.lossy = true;
+                    self.readonly = true;
+                },
+                Ok(Chunk::Failed(err)) => {
+                    self.loading = None;
+                    self.lossy = true;
+                    self.readonly = true;
+                    return Err(err)
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.loading = None;
+                    break
+                },
+            }
+            loaded = true;
+        }
+
+        Ok(loaded)
+    }
+
+    // loaded text isn't an edit, it stays out of the undo history
+    fn append_loaded(&mut self, chunk: &[u8]) {
+        let at = self.chars.len();
+        let mut starts = line_starts(chunk, self.line_sep).into_iter().map(|start| start + at).collect::<Vec<_>>();
+        // the chunk goes on the last line if an edit took its newline away
+        if at > 0 && self.chars.get_one(at - 1) != b'\n' && !starts.is_empty() {
+            starts.remove(0);
+        }
+        self.chars.insert(at, chunk);
+        self.lines.insert(self.lines.len(), &starts);
+        self.after_change();
+    }
+
+    // the text as it is now for another thread, the next edit copies the data
+    // first if the snapshot is still around
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { data: Arc::clone(&self.chars.data.0), gap_start: self.chars.gap_start, gap_end: self.chars.gap_end }
     }
 
     pub fn full_view(&self) -> LineView {
@@ -135,10 +302,43 @@ impl TextBuffer {
         LineView::Parts(first, second)
     }
 
-    //pub fn line_view(&self, line: usize) -> LineView {
-    //    let start = self.lines.get_one(line);
-    //    let len = self.raw_line_len(line);
-    //}
+    // the bytes the buffer is saved as. the last line ends with a line separator
+    // if the file had one or fix_eol is set, an empty buffer stays empty
+    pub fn file_data(&self, fix_eol: bool) -> io::Result<Vec<u8>> {
+        if self.hex {
+            return hex::parse(&self.full_view().to_string())
+                .map_err(|line| io::Error::new(io::ErrorKind::InvalidData, format!("line {} isn't a hex dump", line + 1)))
+        }
+
+        let mut text = self.full_view().to_string();
+        let sep = self.line_sep.as_str();
+        if text.ends_with(sep) {
+            text.truncate(text.len() - sep.len());
+        }
+        if self.eol || (fix_eol && !text.is_empty()) {
+            text.push_str(sep);
+        }
+
+        encoding::encode(&text, self.encoding)
+            .map_err(|c| io::Error::new(io::ErrorKind::InvalidData, format!("{c} can't be written as {}", self.encoding.name())))
+    }
+
+    pub fn write_to(&self, path: &Path, fix_eol: bool) -> io::Result<()> {
+        write_atomic(path, &self.file_data(fix_eol)?)
+    }
+
+    // the text as it's kept, without the line index, which can be off after a panic
+    pub fn recovery_data(&self) -> Vec<u8> {
+        self.chars.get_to_end(0)
+    }
+
+    // views of the lines in range without their line separators. only the
+    // first line is looked up in the index, the rest are found by their newlines
+    pub fn lines_in(&self, range: std::ops::Range<usize>) -> LinesIn<'_> {
+        let end = range.end.min(self.total_lines());
+        let byte = if range.start < end { self.lines.get_one(range.start) } else { 0 };
+        LinesIn { buffer: self, line: range.start, end, byte }
+    }
 
     // TODO: maybe make this work with references
     pub fn line(&self, line: usize) -> String {
@@ -210,16 +410,20 @@ impl TextBuffer {
         let start = self.lines.get_one(line);
         let actual_bytes = self.screen_index_to_bytes_index(line, index);
 
-        self.chars.insert(start + actual_bytes, data);
+        self.insert_bytes(start + actual_bytes, data);
         self.lines.increment_range_by((line + 1)..self.lines.len(), data.len());
+        self.after_change();
     }
 
     pub fn insert_empty_line(&mut self, line: usize) {
         if line < self.total_lines() {
             let index = self.lines.get_one(line);
-            self.chars.insert(index, self.line_sep.as_str().as_bytes());
+            self.insert_bytes(index, self.line_sep.as_str().as_bytes());
             self.lines.insert(line, &[index]);
             self.lines.increment_range_by((line+1)..self.lines.len(), self.line_sep as usize);
+            self.folds.lines_inserted(line, 1);
+            self.virtual_text.lines_inserted(line, 1);
+            self.after_change();
             return;
         }
 
@@ -228,9 +432,10 @@ impl TextBuffer {
         }
 
         let index = self.lines.get_one(line - 1) + self.raw_line_len(line - 1);
-        self.chars.insert(index, self.line_sep.as_str().as_bytes());
+        self.insert_bytes(index, self.line_sep.as_str().as_bytes());
         let before = self.lines.get_one(line - 1) + self.raw_line_len(line - 1) - self.line_sep as usize;
         self.lines.insert(line, &[before]);
+        self.after_change();
     }
 
     pub fn remove_from_line(&mut self, line: usize, index: usize, len: usize) {
@@ -245,8 +450,9 @@ impl TextBuffer {
             if i >= index { actual_len += char.len_utf8(); }
         }
 
-        self.chars.remove(start + actual_index, actual_len);
+        self.remove_bytes(start + actual_index, actual_len);
         self.lines.decrement_range_by((line + 1)..self.lines.len(), actual_len);
+        self.after_change();
     }
 
     pub fn remove_by_range(&mut self, start: LinePos, end: LinePos) {
@@ -275,26 +481,109 @@ impl TextBuffer {
         }
     }
 
+    // replaces the text from start up to end with text, returns the position right after it
+    pub fn replace_range(&mut self, start: LinePos, end: LinePos, text: &str) -> LinePos {
+        if end.line > start.line {
+            self.remove_from_line(end.line, 0, end.col);
+            for _ in (start.line + 1)..end.line {
+                self.remove_line(start.line + 1);
+            }
+            let line_len = self.line_len(start.line);
+            self.remove_from_line(start.line, start.col, line_len - start.col);
+            self.remove_line_sep(start.line);
+        } else {
+            self.remove_from_line(start.line, start.col, end.col - start.col);
+        }
+
+        self.insert_text(start, text)
+    }
+
     pub fn remove_line(&mut self, line: usize) {
         let start = self.lines.get_one(line);
         let len = self.raw_line_len(line);
-        self.chars.remove(start, len);
+        self.remove_bytes(start, len);
         if line < self.total_lines() - 1 {
             self.lines.decrement_range_by((line + 1)..self.lines.len(), len);
         }
         if self.total_lines() > 1 {
             self.lines.remove(line, 1);
+            self.folds.lines_removed(line, 1);
+            self.virtual_text.lines_removed(line, 1);
         }
+        self.after_change();
     }
 
     pub fn remove_line_sep(&mut self, line: usize) {
         let start = self.lines.get_one(line);
         let len = self.raw_line_len(line);
-        self.chars.remove(start + len - self.line_sep as usize, self.line_sep as usize);
+        self.remove_bytes(start + len - self.line_sep as usize, self.line_sep as usize);
         if line + 1 < self.total_lines() {
             self.lines.decrement_range_by((line + 1)..self.lines.len(), self.line_sep as usize);
             self.lines.remove(line + 1, 1);
+            self.folds.lines_removed(line + 1, 1);
+            self.virtual_text.lines_removed(line + 1, 1);
+        }
+        self.after_change();
+    }
+
+    // replaces lines start..=end with text, keeps the buffer's line separator
+    // and whether the last line ends with one
+    pub fn replace_lines(&mut self, start: usize, end: usize, text: &str) {
+        let byte_start = self.lines.get_one(start);
+        let byte_end = if end + 1 < self.total_lines() {
+            self.lines.get_one(end + 1)
+        } else {
+            self.chars.len()
+        };
+        let is_last = end + 1 == self.total_lines();
+        let had_last_sep = self.raw_line(self.total_lines() - 1).ends_with('\n');
+
+        let mut data = text.replace("\r\n", "\n");
+        if !data.is_empty() && !data.ends_with('\n') && (!is_last || had_last_sep) {
+            data.push('\n');
+        }
+        if is_last && !had_last_sep && data.ends_with('\n') {
+            data.pop();
+        }
+        if self.line_sep == LineSeparator::CRLF {
+            data = data.replace('\n', "\r\n");
+        }
+
+        let mut starts = Vec::new();
+        let mut offset = byte_start;
+        for line in data.split_inclusive('\n') {
+            starts.push(offset);
+            offset += line.len();
+        }
+
+        if byte_end > byte_start {
+            self.remove_bytes(byte_start, byte_end - byte_start);
+        }
+        self.insert_bytes(byte_start, data.as_bytes());
+        self.lines.remove(start, end + 1 - start);
+        self.lines.insert(start, &starts);
+        // folds and virtual text take the lines as changed in place, with the extra ones at the end
+        let old = end + 1 - start;
+        if starts.len() > old {
+            self.folds.lines_inserted(start + old, starts.len() - old);
+            self.virtual_text.lines_inserted(start + old, starts.len() - old);
+        } else {
+            self.folds.lines_removed(start + starts.len(), old - starts.len());
+            self.virtual_text.lines_removed(start + starts.len(), old - starts.len());
+        }
+
+        let after = start + starts.len();
+        if data.len() > byte_end - byte_start {
+            self.lines.increment_range_by(after..self.lines.len(), data.len() - (byte_end - byte_start));
+        } else {
+            self.lines.decrement_range_by(after..self.lines.len(), (byte_end - byte_start) - data.len());
         }
+
+        if self.lines.is_empty() {
+            self.insert_bytes(0, self.line_sep.as_str().as_bytes());
+            self.lines.insert(0, &[0]);
+        }
+        self.after_change();
     }
 
     pub fn split_line_at_index(&mut self, line: usize, index: usize) {
@@ -302,9 +591,28 @@ impl TextBuffer {
 
         let actual_index = self.screen_index_to_bytes_index(line, index);
 
-        self.chars.insert(start + actual_index, self.line_sep.as_str().as_bytes());
+        self.insert_bytes(start + actual_index, self.line_sep.as_str().as_bytes());
         self.lines.insert(line + 1, &[start + actual_index]);
         self.lines.increment_range_by((line + 1)..self.lines.len(), self.line_sep as usize);
+        self.folds.lines_inserted(line + 1, 1);
+        self.virtual_text.lines_inserted(line + 1, 1);
+        self.after_change();
+    }
+
+    // inserts text that may span lines, returns the position right after it
+    pub fn insert_text(&mut self, pos: LinePos, text: &str) -> LinePos {
+        let mut pos = pos;
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.split_line_at_index(pos.line, pos.col);
+                pos = LinePos { line: pos.line + 1, col: 0 };
+            }
+            let part = part.strip_suffix('\r').unwrap_or(part);
+            self.insert_into_line(pos.line, pos.col, part.as_bytes());
+            pos.col += part.chars().count();
+        }
+
+        pos
     }
 
     pub fn utf8_iter(&self, pos: LinePos) -> Utf8Iter {
@@ -357,32 +665,301 @@ impl TextBuffer {
         actual_index
     }
 
+    // the position of the char byte is in. bytes past the end are at the end
+    // and line separators are one past their line's last char
     pub fn byte_to_linepos(&self, pos: usize) -> LinePos {
-        let first = &self.lines.data[0..self.lines.gap_start];
-
-        let (target, add) = if first.len() == self.lines.len() {
-            (first, 0)
-        } else {
-            let second = &self.lines.data[self.lines.gap_end..self.lines.data.len()];
-            if second[0] <= pos { 
-                (second, first.len()) 
-            } else {
-                (first, 0)
-            }
-        };
-
-        let line = target.binary_search(&pos).unwrap_or_else(|e| e - 1) + add;
+        let pos = pos.min(self.chars.len());
+        let line = self.lines.line_at(pos);
         let start = self.lines.get_one(line);
         let mut col = 0;
         let iter = self.utf8_iter(LinePos{ line, col: 0 });
         let mut actual_index = 0;
         for ch in iter {
-            if actual_index >= pos - start { break }
+            if actual_index + ch.len_utf8() > pos - start { break }
             actual_index += ch.len_utf8();
             col += 1;
         }
 
-        LinePos { line, col }
+        LinePos { line, col: col.min(self.line_len(line)) }
+    }
+
+    // the first byte of the char at pos, cols past the end of the line are at its separator
+    pub fn linepos_to_byte(&self, pos: LinePos) -> usize {
+        let pos = self.clamp_pos(pos);
+        self.lines.get_one(pos.line) + self.screen_index_to_bytes_index(pos.line, pos.col.min(self.line_len(pos.line)))
+    }
+
+    // byte moved back onto the start of its char and into the buffer
+    pub fn clamp_byte(&self, byte: usize) -> usize {
+        let mut byte = byte.min(self.chars.len());
+        while byte > 0 && byte < self.chars.len() && self.chars.get_one(byte) & 0b1100_0000 == 0b1000_0000 {
+            byte -= 1;
+        }
+
+        byte
+    }
+
+    // pos moved into the buffer, the col can be one past the last char like in insert mode
+    pub fn clamp_pos(&self, pos: LinePos) -> LinePos {
+        let line = pos.line.min(self.total_lines() - 1);
+        LinePos { line, col: pos.col.min(self.line_len(line)) }
+    }
+
+    // pos moved onto a char, where the cursor can be in normal mode
+    pub fn clamp_to_char(&self, pos: LinePos) -> LinePos {
+        let line = pos.line.min(self.total_lines() - 1);
+        LinePos { line, col: pos.col.min(self.line_len(line).max(1) - 1) }
+    }
+
+    // what has to hold between changes: the gap inside the data, the line
+    // starts going up from 0 each right after a newline, as many lines as
+    // newlines and the text being utf-8
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.chars.check_invariants()?;
+        if self.lines.is_empty() {
+            return Err("the line index is empty".to_owned())
+        }
+
+        let len = self.chars.len();
+        for line in 0..self.lines.len() {
+            let start = self.lines.get_one(line);
+            if start > len {
+                return Err(format!("line {line} starts at {start}, past the end at {len}"))
+            }
+            if line == 0 {
+                if start != 0 {
+                    return Err(format!("line 0 starts at {start}"))
+                }
+                continue
+            }
+            let previous = self.lines.get_one(line - 1);
+            if start <= previous {
+                return Err(format!("line {line} starts at {start}, line {} at {previous}", line - 1))
+            }
+            if self.chars.get_one(start - 1) != b'\n' {
+                return Err(format!("line {line} at {start} doesn't follow a newline"))
+            }
+        }
+
+        let text = self.chars.get_to_end(0);
+        let newlines = text.iter().filter(|&&byte| byte == b'\n').count();
+        if self.lines.len() > newlines + 1 || self.lines.len() < newlines {
+            return Err(format!("{} lines in the index for {newlines} newlines", self.lines.len()))
+        }
+        std::str::from_utf8(&text).map_err(|e| format!("the text isn't utf-8 at byte {}", e.valid_up_to()))?;
+
+        Ok(())
+    }
+
+    // with the check-invariants feature a change that breaks them panics
+    // right away instead of somewhere later
+    fn after_change(&self) {
+        #[cfg(feature = "check-invariants")]
+        if let Err(e) = self.check_invariants() {
+            panic!("{e}");
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.changes != self.saved_changes
+    }
+
+    // all changes to the text go through these two so they end up in the undo history
+    fn insert_bytes(&mut self, at: usize, data: &[u8]) {
+        if !data.is_empty() {
+            self.history.record(Change::Insert { at, data: data.to_vec() });
+            self.changes += 1;
+        }
+        self.chars.insert(at, data);
+    }
+
+    fn remove_bytes(&mut self, at: usize, len: usize) {
+        if len > 0 {
+            let data = self.chars.get_by_range(at..(at + len));
+            self.history.record(Change::Remove { at, data });
+            self.changes += 1;
+        }
+        self.chars.remove(at, len);
+    }
+
+    // undoes everything since the last break point, returns where the change was
+    pub fn undo(&mut self) -> Option<LinePos> {
+        let changes = self.history.take_undo()?;
+        for change in changes.iter().rev() {
+            match change {
+                Change::Insert { at, data } => {
+                    self.chars.remove(*at, data.len());
+                    self.reindex(*at, at + data.len(), *at);
+                },
+                Change::Remove { at, data } => {
+                    self.chars.insert(*at, data);
+                    self.reindex(*at, *at, at + data.len());
+                },
+            }
+        }
+
+        let pos = self.after_history_change(&changes);
+        self.history.push_redo(changes);
+        self.changes += 1;
+        self.after_change();
+        Some(pos)
+    }
+
+    pub fn redo(&mut self) -> Option<LinePos> {
+        let changes = self.history.take_redo()?;
+        for change in changes.iter() {
+            match change {
+                Change::Insert { at, data } => {
+                    self.chars.insert(*at, data);
+                    self.reindex(*at, *at, at + data.len());
+                },
+                Change::Remove { at, data } => {
+                    self.chars.remove(*at, data.len());
+                    self.reindex(*at, at + data.len(), *at);
+                },
+            }
+        }
+
+        let pos = self.after_history_change(&changes);
+        self.history.push_undo(changes);
+        self.changes += 1;
+        self.after_change();
+        Some(pos)
+    }
+
+    // after undo or redo replayed the bytes from..old_end as from..new_end, finds
+    // the starts of the lines they touch again and moves the ones after them
+    fn reindex(&mut self, from: usize, old_end: usize, new_end: usize) {
+        // everything was removed on the way
+        if self.lines.is_empty() {
+            let data = self.chars.get_to_end(0);
+            self.lines = line_index::new_index(line_starts(&data, self.line_sep), data.len());
+            return
+        }
+
+        let first = self.lines.line_at(from);
+        let last = self.lines.line_at(old_end);
+        let start = self.lines.get_one(first);
+        let end = if last + 1 < self.lines.len() {
+            (self.lines.get_one(last + 1) + new_end - old_end).min(self.chars.len())
+        } else {
+            self.chars.len()
+        };
+        let starts = line_starts(&self.chars.get_by_range(start..end), self.line_sep).into_iter().map(|line| line + start).collect::<Vec<_>>();
+        self.lines.remove(first, last + 1 - first);
+        self.lines.insert(first, &starts);
+
+        let after = first + starts.len();
+        if new_end > old_end {
+            self.lines.increment_range_by(after..self.lines.len(), new_end - old_end);
+        } else if new_end < old_end {
+            self.lines.decrement_range_by(after..self.lines.len(), old_end - new_end);
+        }
+    }
+
+    fn after_history_change(&mut self, changes: &[Change]) -> LinePos {
+        if self.chars.len() == 0 {
+            self.chars.insert(0, b"\n");
+            self.lines = line_index::new_index(vec![0], 1);
+        }
+        // like a freshly read file, no empty line after the last line separator
+        // and none past the end
+        while self.lines.len() > 1 && self.lines.get_one(self.lines.len() - 1) >= self.chars.len() {
+            self.lines.remove(self.lines.len() - 1, 1);
+        }
+        self.folds.clamp(self.total_lines());
+        self.virtual_text.clamp(self.total_lines());
+
+        let at = changes.iter().map(|change| change.at()).min().unwrap_or(0);
+        self.byte_to_linepos(at.min(self.chars.len() - 1))
+    }
+}
+
+// writes a temporary file next to path and renames it over path, so a failed
+// save leaves the old file as it was. symlinks are followed and the old
+// file's permissions are kept
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp = dir.join(format!(".{}.moded-{}", name.to_string_lossy(), std::process::id()));
+
+    let write = || -> io::Result<()> {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(data)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+// copies path to {name}~ in dir, or next to it if dir is empty. a file that
+// doesn't exist yet has nothing to back up
+pub fn backup_file(path: &Path, dir: &str) -> io::Result<()> {
+    if !path.is_file() {
+        return Ok(())
+    }
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let backup = format!("{}~", name.to_string_lossy());
+    let backup = if dir.is_empty() { path.with_file_name(backup) } else { Path::new(dir).join(backup) };
+    fs::copy(path, backup)?;
+
+    Ok(())
+}
+
+// about LOAD_CHUNK bytes up to the end of a line, empty at the end of the file
+fn read_chunk(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    reader.by_ref().take(LOAD_CHUNK).read_to_end(&mut chunk)?;
+    reader.read_until(b'\n', &mut chunk)?;
+
+    Ok(chunk)
+}
+
+// byte offsets of where each line starts
+fn line_starts(chars: &[u8], line_sep: LineSeparator) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let st = unsafe {std::str::from_utf8_unchecked(chars)};
+    // assuming newlines for now
+    let mut start = 0;
+    for line in st.lines() {
+        lines.push(start);
+        start += line.len() + line_sep as usize;
+    }
+
+    lines
+}
+
+pub struct LinesIn<'a> {
+    buffer: &'a TextBuffer,
+    line: usize,
+    end: usize,
+    byte: usize,
+}
+
+impl<'a> Iterator for LinesIn<'a> {
+    type Item = LineView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.line >= self.end { return None }
+        let chars = &self.buffer.chars;
+        let (next, end) = match chars.find(self.byte, b'\n') {
+            Some(newline) => (newline + 1, newline + 1 - self.buffer.line_sep as usize),
+            None => (chars.len(), chars.len()),
+        };
+        let view = chars.view(self.byte..end);
+        self.byte = next;
+        self.line += 1;
+
+        Some(view)
     }
 }
 
@@ -474,11 +1051,43 @@ impl<'a> Iterator for Utf8RevIter<'a> {
 
 
 pub struct GapBuffer<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign> {
-    data: Vec<T>,
+    data: Shared<T>,
     gap_start: usize,
     gap_end: usize,
 }
 
+// the data of a gap buffer, shared with snapshots. changing it while a snapshot
+// still has it makes a copy first, like Arc::make_mut
+struct Shared<T>(Arc<Vec<T>>);
+
+impl<T> Deref for Shared<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T: Clone> DerefMut for Shared<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+// a buffer's text at one point, see TextBuffer::snapshot
+pub struct Snapshot {
+    data: Arc<Vec<u8>>,
+    gap_start: usize,
+    gap_end: usize,
+}
+
+impl Snapshot {
+    // the text before and after the gap
+    pub fn halves(&self) -> (&[u8], &[u8]) {
+        (&self.data[..self.gap_start], &self.data[self.gap_end..])
+    }
+}
+
 impl<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign> GapBuffer<T> {
     pub fn new(mut data: Vec<T>) -> Self {
         let gap_start = data.len();
@@ -487,7 +1096,7 @@ impl<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign
             data.set_len(gap_end);
         }
         
-        Self { data, gap_start, gap_end }
+        Self { data: Shared(Arc::new(data)), gap_start, gap_end }
     }
 
     pub fn insert(&mut self, index: usize, data: &[T]) {
@@ -499,7 +1108,7 @@ impl<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign
             self.data.reserve(data.len());
             let added_size = self.data.capacity() - old_len;
             unsafe {
-                self.data.set_len(self.data.capacity());
+                self.data.set_len(old_len + added_size);
             }
             if self.gap_end > old_len {
                 self.move_bytes(self.gap_end, self.gap_end + added_size, self.gap_end - old_len);
@@ -622,7 +1231,7 @@ impl<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign
             self.data.reserve(data.len());
             let added_size = self.data.capacity() - old_len;
             unsafe {
-                self.data.set_len(self.data.capacity());
+                self.data.set_len(old_len + added_size);
             }
             if self.gap_end > old_len {
                 self.move_bytes(self.gap_end, self.gap_end + added_size, self.gap_end - old_len);
@@ -660,6 +1269,14 @@ impl<T: Copy + Debug + std::ops::Add + std::ops::AddAssign + std::ops::SubAssign
         self.data.len() - (self.gap_end - self.gap_start)
     }
 
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.gap_start > self.gap_end || self.gap_end > self.data.len() {
+            return Err(format!("gap {}..{} in {} bytes", self.gap_start, self.gap_end, self.data.len()))
+        }
+
+        Ok(())
+    }
+
     fn move_bytes(&mut self, from: usize, to: usize, len: usize) {
         //println!("moving: from: {from}, to: {to}, len: {len}, data.len: {}, gap_start: {}, gap_end: {}, data: {:?}", self.data.len(), self.gap_start, self.gap_end, self.data);
         self.data.copy_within(from..(from+len), to);
@@ -712,7 +1329,79 @@ impl GapBuffer<usize> {
     }
 }
 
+impl LineIndex for GapBuffer<usize> {
+    fn get_one(&self, line: usize) -> usize {
+        GapBuffer::get_one(self, line)
+    }
+
+    fn len(&self) -> usize {
+        GapBuffer::len(self)
+    }
+
+    fn insert(&mut self, line: usize, starts: &[usize]) {
+        GapBuffer::insert(self, line, starts)
+    }
+
+    fn remove(&mut self, line: usize, len: usize) {
+        GapBuffer::remove(self, line, len)
+    }
+
+    fn increment_range_by(&mut self, range: std::ops::Range<usize>, by: usize) {
+        GapBuffer::increment_range_by(self, range, by)
+    }
+
+    fn decrement_range_by(&mut self, range: std::ops::Range<usize>, by: usize) {
+        GapBuffer::decrement_range_by(self, range, by)
+    }
+
+    fn line_at(&self, byte: usize) -> usize {
+        let first = &self.data[0..self.gap_start];
+
+        let (target, add) = if first.len() == GapBuffer::len(self) {
+            (first, 0)
+        } else {
+            let second = &self.data[self.gap_end..self.data.len()];
+            if second[0] <= byte {
+                (second, first.len())
+            } else {
+                (first, 0)
+            }
+        };
+
+        target.binary_search(&byte).unwrap_or_else(|e| e - 1) + add
+    }
+}
+
 impl GapBuffer<u8> {
+    fn view(&self, range: std::ops::Range<usize>) -> LineView<'_> {
+        let gap_size = self.gap_end - self.gap_start;
+        let (first, second) = if range.end <= self.gap_start {
+            (&self.data[range], &[][..])
+        } else if range.start >= self.gap_start {
+            (&self.data[(range.start + gap_size)..(range.end + gap_size)], &[][..])
+        } else {
+            (&self.data[range.start..self.gap_start], &self.data[self.gap_end..(range.end + gap_size)])
+        };
+        let first = unsafe { std::str::from_utf8_unchecked(first) };
+        if second.is_empty() {
+            return LineView::Contiguous(first)
+        }
+
+        LineView::Parts(first, unsafe { std::str::from_utf8_unchecked(second) })
+    }
+
+    // the index of the first byte at or after from
+    fn find(&self, from: usize, byte: u8) -> Option<usize> {
+        let gap_size = self.gap_end - self.gap_start;
+        if from < self.gap_start {
+            if let Some(i) = self.data[from..self.gap_start].iter().position(|&b| b == byte) {
+                return Some(from + i)
+            }
+        }
+        let from = from.max(self.gap_start);
+        self.data[(from + gap_size)..].iter().position(|&b| b == byte).map(|i| from + i)
+    }
+
     fn into_iterator(&self, index: usize) -> GapBufferIter {
         GapBufferIter { index, inner: self }
     }
@@ -951,4 +1640,308 @@ mod tests {
 
         assert!(str == st, "{} != {}", str, st);
     }
+
+    #[test]
+    fn test_lines_in() {
+        let mut buffer = TextBuffer::from_data(0, b"one\ntwo\nthree\nfour".to_vec());
+        let lines = buffer.lines_in(1..10).map(|view| view.to_string()).collect::<Vec<_>>();
+        assert_eq!(lines, ["two", "three", "four"]);
+
+        // a line split by the gap
+        buffer.insert_into_line(1, 1, b"x");
+        buffer.insert_into_line(0, 0, b"y");
+        let lines = buffer.lines_in(0..4).map(|view| view.to_string()).collect::<Vec<_>>();
+        assert_eq!(lines, ["yone", "txwo", "three", "four"]);
+        assert_eq!(buffer.lines_in(2..2).count(), 0);
+
+        let buffer = TextBuffer::from_data(0, b"a\r\nb\r\n".to_vec());
+        let lines = buffer.lines_in(0..2).map(|view| view.chars().collect::<String>()).collect::<Vec<_>>();
+        assert_eq!(lines, ["a", "b"]);
+    }
+
+    #[test]
+    fn test_append_loaded() {
+        let mut reader = io::Cursor::new(b"one\ntwo\nthree\n".to_vec());
+        let mut buffer = TextBuffer::from_data(0, read_chunk(&mut reader).unwrap());
+        assert_eq!(buffer.total_lines(), 3);
+        assert!(read_chunk(&mut reader).unwrap().is_empty());
+
+        let (sender, chunks) = mpsc::channel();
+        buffer.loading = Some(chunks);
+        sender.send(Chunk::Text(b"four\nfive\n".to_vec())).unwrap();
+        assert!(buffer.poll_loading().unwrap());
+        assert!(buffer.loading.is_some());
+        assert!(!buffer.poll_loading().unwrap());
+        assert_eq!(buffer.line(3), "four");
+        assert_eq!(buffer.line(4), "five");
+
+        // an edit took the last newline away
+        buffer.remove_line_sep(4);
+        sender.send(Chunk::Text(b"six\nseven\n".to_vec())).unwrap();
+        assert!(buffer.poll_loading().unwrap());
+        assert_eq!(buffer.total_lines(), 6);
+        assert_eq!(buffer.line(4), "fivesix");
+        assert_eq!(buffer.line(5), "seven");
+        assert!(!buffer.readonly);
+
+        // a chunk that had to be fixed up keeps the file from being saved over
+        sender.send(Chunk::Lossy("eight\u{fffd}\n".as_bytes().to_vec())).unwrap();
+        assert!(buffer.poll_loading().unwrap());
+        assert_eq!(buffer.line(6), "eight\u{fffd}");
+        assert!(buffer.lossy && buffer.readonly);
+
+        // the end of the file brings nothing new
+        drop(sender);
+        assert!(!buffer.poll_loading().unwrap());
+        assert!(buffer.loading.is_none());
+
+        // a read error stops the loading instead of looking like the end of the file
+        let mut buffer = TextBuffer::from_data(0, b"one\n".to_vec());
+        let (sender, chunks) = mpsc::channel();
+        buffer.loading = Some(chunks);
+        sender.send(Chunk::Failed(io::Error::other("disk gone"))).unwrap();
+        assert!(buffer.poll_loading().is_err());
+        assert!(buffer.loading.is_none());
+        assert!(buffer.lossy && buffer.readonly);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut buf = TextBuffer::from_data(0, b"one\ntwo\n".to_vec());
+        buf.insert_text(LinePos::new(1, 0), "x");
+        let snapshot = buf.snapshot();
+        buf.insert_text(LinePos::new(0, 0), "y");
+        let (first, second) = snapshot.halves();
+        assert_eq!([first, second].concat(), b"one\nxtwo\n");
+        assert_eq!(buf.line(0), "yone");
+    }
+
+    #[test]
+    fn test_byte_linepos() {
+        let buffer = TextBuffer::from_data(0, "aé\n漢b\n\nc".as_bytes().to_vec());
+        let positions = [(0, 0, 0), (0, 1, 1), (0, 2, 3), (1, 0, 4), (1, 1, 7), (1, 2, 8), (2, 0, 9), (3, 0, 10), (3, 1, 11)];
+        for (line, col, byte) in positions {
+            assert_eq!(buffer.linepos_to_byte(LinePos::new(line, col)), byte);
+            assert_eq!(buffer.byte_to_linepos(byte), LinePos::new(line, col));
+        }
+
+        // bytes inside a char are on that char
+        assert_eq!(buffer.byte_to_linepos(2), LinePos::new(0, 1));
+        assert_eq!(buffer.byte_to_linepos(6), LinePos::new(1, 0));
+        assert_eq!(buffer.clamp_byte(6), 4);
+        assert_eq!(buffer.clamp_byte(100), 11);
+        assert_eq!(buffer.byte_to_linepos(100), LinePos::new(3, 1));
+
+        // cols past the end don't go on to the next line
+        assert_eq!(buffer.linepos_to_byte(LinePos::new(0, 10)), 3);
+        assert_eq!(buffer.clamp_pos(LinePos::new(9, 9)), LinePos::new(3, 1));
+        assert_eq!(buffer.clamp_to_char(LinePos::new(0, 9)), LinePos::new(0, 1));
+        assert_eq!(buffer.clamp_to_char(LinePos::new(2, 3)), LinePos::new(2, 0));
+
+        let buffer = TextBuffer::from_data(0, b"ab\r\ncd\r\n".to_vec());
+        assert_eq!(buffer.linepos_to_byte(LinePos::new(1, 1)), 5);
+        assert_eq!(buffer.byte_to_linepos(3), LinePos::new(0, 2));
+        assert_eq!(buffer.byte_to_linepos(4), LinePos::new(1, 0));
+    }
+
+    #[test]
+    fn test_file_data() {
+        let buffer = TextBuffer::from_data(0, b"a\nb".to_vec());
+        assert!(!buffer.eol);
+        assert_eq!(buffer.file_data(false).unwrap(), b"a\nb");
+        assert_eq!(buffer.file_data(true).unwrap(), b"a\nb\n");
+
+        // a separator the buffer got by an edit doesn't count
+        let mut buffer = TextBuffer::from_data(0, b"a".to_vec());
+        buffer.insert_empty_line(1);
+        assert_eq!(buffer.file_data(false).unwrap(), b"a\n");
+        let buffer = TextBuffer::from_data(0, b"a\r\n".to_vec());
+        assert_eq!(buffer.file_data(false).unwrap(), b"a\r\n");
+
+        // empty files stay empty, a lone newline is kept
+        assert_eq!(TextBuffer::from_data(0, Vec::new()).file_data(true).unwrap(), b"");
+        assert_eq!(TextBuffer::from_data(0, b"\n".to_vec()).file_data(false).unwrap(), b"\n");
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("moded_atomic_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        fs::write(&path, "old").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        }
+
+        assert!(write_atomic(&dir.join("missing/file"), b"x").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let path = std::env::temp_dir().join(format!("moded_hex_{}", std::process::id()));
+        fs::write(&path, b"\x7fELF\0\x01\xff").unwrap();
+        let mut buffer = TextBuffer::from_path(0, &path).unwrap();
+        assert!(buffer.hex);
+        assert_eq!(buffer.line(0), "00000000: 7f 45 4c 46 00 01 ff                             .ELF...");
+
+        // R over the first byte
+        buffer.remove_from_line(0, 10, 2);
+        buffer.insert_into_line(0, 10, b"00");
+        buffer.write_to(&path, true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\0ELF\0\x01\xff");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replace_lines() {
+        let str = "c\nb\na\nend";
+        let mut buf = TextBuffer::from_data(0, str.as_bytes().to_vec());
+
+        buf.replace_lines(0, 2, "a\nb\nc\nd\n");
+        assert_eq!(buf.total_lines(), 5);
+        assert_eq!(buf.line(0), "a");
+        assert_eq!(buf.line(3), "d");
+        assert_eq!(buf.line(4), "end");
+
+        // no trailing newline in the file, don't add one
+        buf.replace_lines(3, 4, "last\n");
+        assert_eq!(buf.total_lines(), 4);
+        assert_eq!(buf.raw_line(3), "last");
+
+        buf.replace_lines(0, 3, "");
+        assert_eq!(buf.total_lines(), 1);
+        assert_eq!(buf.line(0), "");
+    }
+
+    #[test]
+    fn test_insert_text() {
+        let mut buf = TextBuffer::from_data(0, "fn main() {}\nend".as_bytes().to_vec());
+
+        let end = buf.insert_text(LinePos::new(0, 11), "\n    äö\n");
+        assert_eq!(end, LinePos::new(2, 0));
+        assert_eq!(buf.total_lines(), 4);
+        assert_eq!(buf.line(1), "    äö");
+        assert_eq!(buf.line(2), "}");
+        assert_eq!(buf.line(3), "end");
+
+        let end = buf.insert_text(LinePos::new(3, 3), "!");
+        assert_eq!(end, LinePos::new(3, 4));
+        assert_eq!(buf.raw_line(3), "end!");
+
+        // the end of a range isn't replaced
+        let end = buf.replace_range(LinePos::new(1, 4), LinePos::new(3, 1), "x");
+        assert_eq!(end, LinePos::new(1, 5));
+        assert_eq!(buf.line(1), "    xnd!");
+        assert_eq!(buf.total_lines(), 2);
+        buf.replace_range(LinePos::new(1, 5), LinePos::new(1, 7), "");
+        assert_eq!(buf.line(1), "    x!");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut buf = TextBuffer::from_data(0, "one\ntwo\n".as_bytes().to_vec());
+
+        buf.insert_into_line(0, 3, b" a");
+        buf.insert_into_line(0, 5, b"b");
+        buf.history.break_point();
+        buf.remove_line(1);
+        buf.split_line_at_index(0, 3);
+        assert_eq!(buf.total_lines(), 2);
+
+        assert_eq!(buf.undo(), Some(LinePos::new(0, 3)));
+        assert_eq!(buf.total_lines(), 2);
+        assert_eq!(buf.line(0), "one ab");
+        assert_eq!(buf.line(1), "two");
+
+        // both inserts were glued into one change
+        buf.undo();
+        assert_eq!(buf.line(0), "one");
+        assert_eq!(buf.undo(), None);
+
+        buf.redo();
+        buf.redo();
+        assert_eq!(buf.line(0), "one");
+        assert_eq!(buf.line(1), " ab");
+        assert_eq!(buf.redo(), None);
+    }
+
+    // undo and redo only touch the line starts around the change
+    #[test]
+    fn test_undo_line_index() {
+        let text = (0..3000).map(|i| format!("line {i}\n")).collect::<String>();
+        let mut buf = TextBuffer::from_data(0, text.into_bytes());
+        let starts = |buf: &TextBuffer| (0..buf.lines.len()).map(|line| buf.lines.get_one(line)).collect::<Vec<_>>();
+        let fresh = |buf: &TextBuffer| line_starts(&buf.chars.get_to_end(0), buf.line_sep);
+
+        buf.insert_text(LinePos::new(10, 2), "a\nb\nc");
+        buf.history.break_point();
+        buf.remove_by_range(LinePos::new(100, 3), LinePos::new(2500, 1));
+        buf.history.break_point();
+        buf.insert_text(LinePos::new(0, 0), "x\n");
+        let edited = starts(&buf);
+
+        for _ in 0..3 {
+            buf.undo();
+            assert_eq!(starts(&buf), fresh(&buf));
+        }
+        assert_eq!(buf.total_lines(), 3000);
+        assert_eq!(buf.line(2999), "line 2999");
+        for _ in 0..3 {
+            buf.redo();
+            assert_eq!(starts(&buf), fresh(&buf));
+        }
+        assert_eq!(starts(&buf), edited);
+    }
+
+    #[test]
+    fn test_take_removed() {
+        let mut buf = TextBuffer::from_data(0, "one\ntwo\nthree\n".as_bytes().to_vec());
+
+        buf.remove_line(0);
+        buf.history.collect_removed();
+        buf.remove_line(0);
+        buf.remove_line(0);
+        assert_eq!(buf.history.take_removed(), b"two\nthree\n");
+        assert!(buf.history.take_removed().is_empty());
+
+        // removes glued into the change before still count, backwards ones go in front
+        let mut buf = TextBuffer::from_data(0, b"abcdef\n".to_vec());
+        buf.remove_from_line(0, 5, 1);
+        buf.history.collect_removed();
+        buf.remove_from_line(0, 4, 1);
+        buf.remove_from_line(0, 3, 1);
+        assert_eq!(buf.history.take_removed(), b"de");
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut buf = TextBuffer::from_data(0, "one\ntwö\nthree\n".as_bytes().to_vec());
+        assert_eq!(buf.check_invariants(), Ok(()));
+        buf.insert_text(LinePos::new(1, 2), "a\nb");
+        buf.remove_by_range(LinePos::new(0, 1), LinePos::new(1, 0));
+        buf.replace_lines(0, 1, "x\ny\nz");
+        buf.undo();
+        assert_eq!(buf.check_invariants(), Ok(()));
+
+        // a line start off by one no longer follows a newline
+        buf.lines.increment_range_by(1..2, 1);
+        assert!(buf.check_invariants().unwrap_err().contains("doesn't follow a newline"));
+        buf.lines.decrement_range_by(1..2, 1);
+        let end = buf.chars.len();
+        buf.chars.insert(end, &[0xff]);
+        assert!(buf.check_invariants().unwrap_err().contains("isn't utf-8"));
+    }
 }
@@ -0,0 +1,67 @@
+// binary files are shown as a hex dump like xxd's, one line per 16 bytes:
+// "00000010: 48 65 6c 6c 6f 00 ...  Hello.". bytes are edited by changing their
+// hex digits, the dump is turned back into bytes on save
+
+
+const BYTES_PER_LINE: usize = 16;
+// where the hex column starts and how wide it is
+const HEX_START: usize = 10;
+const HEX_WIDTH: usize = BYTES_PER_LINE * 3;
+
+// files with NUL bytes or invalid utf-8 aren't text
+pub fn is_binary(data: &[u8]) -> bool {
+    data.contains(&0) || std::str::from_utf8(data).is_err()
+}
+
+pub fn dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, bytes) in data.chunks(BYTES_PER_LINE).enumerate() {
+        dump.push_str(&format!("{:08x}: ", i * BYTES_PER_LINE));
+        let hex = bytes.iter().map(|byte| format!("{byte:02x} ")).collect::<String>();
+        dump.push_str(&format!("{hex:HEX_WIDTH$} "));
+        dump.extend(bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push('\n');
+    }
+
+    dump
+}
+
+// the bytes in the hex columns, the offsets and the ascii column are ignored.
+// Err with the 0 indexed line that isn't hex
+pub fn parse(dump: &str) -> Result<Vec<u8>, usize> {
+    let mut data = Vec::new();
+    for (i, line) in dump.lines().enumerate() {
+        let hex = line.chars().skip(HEX_START).take(HEX_WIDTH).collect::<String>();
+        for byte in hex.split_whitespace() {
+            data.push(u8::from_str_radix(byte, 16).map_err(|_| i)?);
+        }
+    }
+
+    Ok(data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_and_parse() {
+        assert!(is_binary(b"a\0b"));
+        assert!(is_binary(&[0x66, 0xff]));
+        assert!(!is_binary("ä\n".as_bytes()));
+
+        let data = (0..40u8).chain(b"Hello, world".iter().copied()).collect::<Vec<_>>();
+        let dump = dump(&data);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "00000000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f  ................");
+        assert_eq!(lines[3], "00000030: 6f 72 6c 64                                      orld");
+        assert_eq!(parse(&dump), Ok(data));
+
+        // an edited byte, the ascii column doesn't matter
+        let edited = dump.replacen("00 01", "ff 01", 1).replacen("orld", "xxxx", 1);
+        assert_eq!(parse(&edited).unwrap()[0], 0xff);
+        assert_eq!(parse(&dump.replacen("00 01", "zz 01", 1)), Err(0));
+    }
+}
@@ -0,0 +1,69 @@
+// the wildignore patterns, for files left out when going through folders like
+// completing file names does. * is any run of chars and ? is one. a pattern
+// ending in / only matches folders, one with a / elsewhere is matched against
+// the whole path and one without against the file's name
+
+pub fn is_ignored(patterns: &[String], path: &str, is_dir: bool) -> bool {
+    let path = path.trim_end_matches('/');
+    let name = path.rsplit('/').next().unwrap_or(path);
+    patterns.iter().any(|pattern| {
+        let (pattern, dirs_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern.as_str(), false),
+        };
+        if dirs_only && !is_dir {
+            return false
+        }
+        glob_match(pattern, if pattern.contains('/') { path } else { name })
+    })
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    // where the last * was and the text it's matched up to, to try more if what follows fails
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => {
+                let Some((star_p, star_t)) = star else { return false };
+                star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        assert!(glob_match("*.o", "main.o"));
+        assert!(glob_match("a*b?d", "aXXbcd"));
+        assert!(!glob_match("*.o", "main.rs"));
+        assert!(glob_match("*", ""));
+
+        let patterns = ["target/", "*.o", "src/gen*"].map(str::to_owned);
+        assert!(is_ignored(&patterns, "target/", true));
+        assert!(is_ignored(&patterns, "crates/core/target", true));
+        assert!(!is_ignored(&patterns, "target", false));
+        assert!(is_ignored(&patterns, "build/main.o", false));
+        assert!(is_ignored(&patterns, "src/generated.rs", false));
+        assert!(!is_ignored(&patterns, "lib/generated.rs", false));
+    }
+}
@@ -1,14 +1,276 @@
-use crate::gap_buffer::{LinePos, TextBuffer};
+use std::{cmp::Reverse, path::Path};
 
-pub fn indent_wanted(line: usize, buf: &TextBuffer) -> Option<usize> {
+use crate::{gap_buffer::{LinePos, TextBuffer}, options::LocalOptions, vim_commands::matching_bracket};
+
+
+// lines looked at to guess how a file is indented
+const DETECT_LINES: usize = 1000;
+// how far up the bracket a typed closing one matches is looked for
+const MATCH_LINES: usize = 1000;
+
+// works out the indent in cells a line should get, None for no opinion
+type IndentRule = fn(line: usize, buf: &TextBuffer, tabstop: usize, shiftwidth: usize) -> Option<usize>;
+
+// rules for languages the bracket ones get wrong, by file extension
+fn rule_for(path: Option<&Path>) -> IndentRule {
+    match path.and_then(|path| path.extension()?.to_str()) {
+        Some("py") => python_indent,
+        Some("yaml") | Some("yml") => yaml_indent,
+        _ => bracket_indent,
+    }
+}
+
+// the indent line should get in cells, by the rules of the buffer's language
+pub fn indent_wanted(line: usize, buf: &TextBuffer, tabstop: usize, shiftwidth: usize) -> Option<usize> {
+    rule_for(buf.file_path.as_deref())(line, buf, tabstop, shiftwidth)
+}
+
+fn last_nonblank(buf: &TextBuffer, line: usize) -> Option<usize> {
+    (0..line).rev().find(|&line| !buf.line(line).trim().is_empty())
+}
+
+// the indent of the last non-blank line above line, a shiftwidth more after
+// an open bracket and a shiftwidth less if line starts with a closing one. a
+// line above that closes brackets opened further up counts with the indent of
+// the line that opened them
+fn bracket_indent(line: usize, buf: &TextBuffer, tabstop: usize, shiftwidth: usize) -> Option<usize> {
     if line == 0 { return None }
-    let iter = buf.bytes_iter(LinePos{ line: line - 1, col: 0 });
+    let Some(above_line) = last_nonblank(buf, line) else { return Some(0) };
+    let above = buf.line(above_line);
 
-    let mut indent = 0;
-    for byte in iter {
-        if byte != b' ' { break }
-        indent += 1;
+    let mut indent = indent_width(&buf.line(opening_line(buf, above_line)), tabstop);
+    if above.trim_end().ends_with(['{', '[', '(']) {
+        indent += shiftwidth;
+    }
+    if buf.line(line).trim_start().starts_with(['}', ']', ')']) {
+        indent = indent.saturating_sub(shiftwidth);
     }
 
     Some(indent)
 }
+
+// brackets like everywhere else, and a block after a line ending with ':'.
+// the line after a return and friends goes back a level, so does an else that
+// isn't already back
+fn python_indent(line: usize, buf: &TextBuffer, tabstop: usize, shiftwidth: usize) -> Option<usize> {
+    let mut indent = bracket_indent(line, buf, tabstop, shiftwidth)?;
+    let Some(above_line) = last_nonblank(buf, line) else { return Some(indent) };
+    let above = buf.line(above_line);
+    let above = above.split(" #").next().unwrap_or_default().trim();
+
+    if above.ends_with(':') {
+        indent += shiftwidth;
+    } else if ["return", "pass", "break", "continue", "raise"].contains(&first_word(above))
+        || ["else", "elif", "except", "finally"].contains(&first_word(buf.line(line).trim_start())) {
+        indent = indent.saturating_sub(shiftwidth);
+    }
+
+    Some(indent)
+}
+
+fn first_word(text: &str) -> &str {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or_default()
+}
+
+// no brackets, a nested block after a line ending with ':'. the "- " of a
+// list item counts as indent when the item is a mapping, so its other keys
+// line up under the first one
+fn yaml_indent(line: usize, buf: &TextBuffer, tabstop: usize, shiftwidth: usize) -> Option<usize> {
+    if line == 0 { return None }
+    let Some(above_line) = last_nonblank(buf, line) else { return Some(0) };
+    let above = buf.line(above_line);
+    let above_text = above.trim();
+
+    let mut indent = indent_width(&above, tabstop);
+    if let Some(item) = above_text.strip_prefix("- ") {
+        if item.contains(": ") || item.ends_with(':') {
+            indent += above_text.len() - item.trim_start().len();
+        }
+    }
+    if above_text.ends_with(':') {
+        indent += shiftwidth;
+    }
+
+    Some(indent)
+}
+
+// the line with the bracket the last unmatched closing bracket of line matches
+fn opening_line(buf: &TextBuffer, line: usize) -> usize {
+    let mut depth = 0;
+    for line in (0..=line).rev() {
+        for char in buf.line(line).chars().rev() {
+            match char {
+                '}' | ']' | ')' => depth += 1,
+                '{' | '[' | '(' if depth > 0 => depth -= 1,
+                _ => {},
+            }
+        }
+        if depth == 0 {
+            return line
+        }
+    }
+
+    0
+}
+
+// the cells the leading blanks of text take, tabs go to the next tabstop
+pub fn indent_width(text: &str, tabstop: usize) -> usize {
+    let mut indent = 0;
+    for char in text.chars() {
+        match char {
+            ' ' => indent += 1,
+            '\t' => indent += tabstop - indent % tabstop,
+            _ => break,
+        }
+    }
+
+    indent
+}
+
+// gives the lines start..=end the indent indent_wanted says, line by line so
+// each one builds on the one above. blank lines are left alone
+pub fn reindent(buf: &mut TextBuffer, start: usize, end: usize, tabstop: usize, shiftwidth: usize, expandtab: bool) {
+    for line in start..=end.min(buf.total_lines() - 1) {
+        let text = buf.line(line);
+        if text.trim().is_empty() {
+            continue
+        }
+        let old = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        let new = indent_text(indent_wanted(line, buf, tabstop, shiftwidth).unwrap_or(0), expandtab, tabstop);
+        if text[..old] != new {
+            buf.remove_from_line(line, 0, old);
+            buf.insert_into_line(line, 0, new.as_bytes());
+        }
+    }
+}
+
+// a closing bracket just typed at pos with only blanks before it lines up with
+// the line of the bracket it closes, or goes a shiftwidth back if there's none.
+// the col the bracket ends up at
+pub fn align_closing(buf: &mut TextBuffer, pos: LinePos, tabstop: usize, shiftwidth: usize, expandtab: bool) -> usize {
+    let text = buf.line(pos.line);
+    let old = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    if old != pos.col {
+        return pos.col
+    }
+
+    let indent = match matching_bracket(pos, buf, MATCH_LINES) {
+        Some(open) => indent_width(&buf.line(open.line), tabstop),
+        None => indent_width(&text, tabstop).saturating_sub(shiftwidth),
+    };
+    let new = indent_text(indent, expandtab, tabstop);
+    if text[..old] != new {
+        buf.remove_from_line(pos.line, 0, old);
+        buf.insert_into_line(pos.line, 0, new.as_bytes());
+    }
+
+    new.len()
+}
+
+// whitespace width cells wide, without expandtab as many tabs as fit
+pub fn indent_text(width: usize, expandtab: bool, tabstop: usize) -> String {
+    if expandtab {
+        return " ".repeat(width)
+    }
+
+    format!("{}{}", "\t".repeat(width / tabstop), " ".repeat(width % tabstop))
+}
+
+// guesses the file's indent from its first lines. more lines starting with a
+// tab than with spaces is noexpandtab, otherwise the most common step in from
+// one line to the next is the shiftwidth. dedents are left out since they can
+// skip levels, and so are steps of one, mostly the " *" of block comments
+pub fn detect(buf: &TextBuffer) -> LocalOptions {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps = [0; 9];
+    let mut last = 0;
+    for text in buf.lines_in(0..buf.total_lines().min(DETECT_LINES)).map(|line| line.to_string()) {
+        if text.trim().is_empty() {
+            continue
+        }
+        if text.starts_with('\t') {
+            tabs += 1;
+            continue
+        }
+        let indent = text.len() - text.trim_start_matches(' ').len();
+        if indent > 0 {
+            spaces += 1;
+        }
+        if let Some(step) = steps.get_mut(indent.saturating_sub(last)) {
+            *step += 1;
+        }
+        last = indent;
+    }
+
+    if tabs > spaces {
+        return LocalOptions { expandtab: Some(false), ..LocalOptions::default() }
+    }
+    // ties go to the smaller step
+    let shiftwidth = (2..steps.len()).filter(|&step| steps[step] > 0).max_by_key(|&step| (steps[step], Reverse(step)));
+    match shiftwidth {
+        Some(shiftwidth) => LocalOptions { shiftwidth: Some(shiftwidth), expandtab: Some(true) },
+        None => LocalOptions::default(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindent() {
+        let mut buf = TextBuffer::from_data(0, b"fn a() {\nif b {\n      c(\nd);\n\n}\n        }\n".to_vec());
+        reindent(&mut buf, 0, 6, 8, 4, true);
+        assert_eq!(buf.line(1), "    if b {");
+        assert_eq!(buf.line(2), "        c(");
+        assert_eq!(buf.line(3), "            d);");
+        assert_eq!(buf.line(4), "");
+        assert_eq!(buf.line(5), "    }");
+        assert_eq!(buf.line(6), "}");
+
+        reindent(&mut buf, 1, 2, 4, 4, false);
+        assert_eq!(buf.line(2), "\t\tc(");
+        assert_eq!(indent_wanted(3, &buf, 4, 4), Some(12));
+        assert_eq!(indent_width(" \t  x", 4), 6);
+    }
+
+    #[test]
+    fn test_language_rules() {
+        let buffer = |path: &str, text: &str| {
+            let mut buf = TextBuffer::from_data(0, text.as_bytes().to_vec());
+            buf.file_path = Some(path.into());
+            buf
+        };
+
+        let mut buf = buffer("a.py", "def a(b):\nif b:\nreturn c\nelse:\nd = [\n1]\nreturn d # done\n\n");
+        reindent(&mut buf, 0, 6, 8, 4, true);
+        assert_eq!(buf.line(1), "    if b:");
+        assert_eq!(buf.line(2), "        return c");
+        assert_eq!(buf.line(3), "    else:");
+        assert_eq!(buf.line(4), "        d = [");
+        assert_eq!(buf.line(5), "            1]");
+        assert_eq!(buf.line(6), "        return d # done");
+        assert_eq!(indent_wanted(7, &buf, 8, 4), Some(4));
+
+        let buf = buffer("a.yml", "a:\n  - name: b\n    c: d\n  - e\n  f:\n\n");
+        assert_eq!(indent_wanted(1, &buf, 8, 2), Some(2));
+        assert_eq!(indent_wanted(2, &buf, 8, 2), Some(4));
+        assert_eq!(indent_wanted(4, &buf, 8, 2), Some(2));
+        assert_eq!(indent_wanted(5, &buf, 8, 2), Some(4));
+
+        // anything else gets the bracket rules
+        assert_eq!(indent_wanted(1, &buffer("a.txt", "a:\n\n"), 8, 4), Some(0));
+    }
+
+    #[test]
+    fn test_detect() {
+        let detected = |text: &str| detect(&TextBuffer::from_data(0, text.as_bytes().to_vec()));
+        let two = "fn a() {\n  if b {\n    c();\n  }\n}\n/*\n * d\n */\n";
+        assert_eq!(detected(two), LocalOptions { shiftwidth: Some(2), expandtab: Some(true) });
+        assert_eq!(detected("a:\n\tb\n\t\tc\n    d\n"), LocalOptions { shiftwidth: None, expandtab: Some(false) });
+        assert_eq!(detected("a\nb\n"), LocalOptions::default());
+        assert_eq!(detected("a\n    b\n        c\nd\n        e\n"), LocalOptions { shiftwidth: Some(4), expandtab: Some(true) });
+        assert_eq!(detected("a\n    b\nc\n  d\n"), LocalOptions { shiftwidth: Some(2), expandtab: Some(true) });
+    }
+}
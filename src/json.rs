@@ -0,0 +1,291 @@
+use std::fmt::Write;
+
+
+// just enough json for talking to language servers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+    }
+
+    pub fn string(s: &str) -> Self {
+        Json::String(s.to_owned())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        let Json::Object(fields) = self else { return None };
+        fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        let Json::String(s) = self else { return None };
+        Some(s)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        let Json::Number(n) = self else { return None };
+        if *n < 0.0 {
+            return None
+        }
+        Some(*n as u64)
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        let Json::Array(items) = self else { return None };
+        Some(items)
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn parse(input: &str) -> Result<Self, ()> {
+        let mut parser = Parser { bytes: input.as_bytes(), pos: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(())
+        }
+
+        Ok(value)
+    }
+
+    pub fn write_to(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) => {
+                if n.fract() == 0.0 && n.abs() < 1e15 {
+                    let _ = write!(out, "{}", *n as i64);
+                } else {
+                    let _ = write!(out, "{n}");
+                }
+            },
+            Json::String(s) => write_string(s, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write_to(out);
+                }
+                out.push(']');
+            },
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write_to(out);
+                }
+                out.push('}');
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write_to(&mut out);
+        f.write_str(&out)
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for char in s.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), ()> {
+        if !self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(())
+        }
+        self.pos += literal.len();
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Json, ()> {
+        self.skip_whitespace();
+        let Some(&byte) = self.bytes.get(self.pos) else { return Err(()) };
+        match byte {
+            b'n' => self.expect("null").map(|_| Json::Null),
+            b't' => self.expect("true").map(|_| Json::Bool(true)),
+            b'f' => self.expect("false").map(|_| Json::Bool(false)),
+            b'"' => self.parse_string().map(Json::String),
+            b'[' => self.parse_array(),
+            b'{' => self.parse_object(),
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            _ => Err(()),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, ()> {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && matches!(self.bytes[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).map_err(|_| ())?;
+        text.parse::<f64>().map(Json::Number).map_err(|_| ())
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, ()> {
+        let hex = self.bytes.get(self.pos..(self.pos + 4)).ok_or(())?;
+        let hex = std::str::from_utf8(hex).map_err(|_| ())?;
+        self.pos += 4;
+        u32::from_str_radix(hex, 16).map_err(|_| ())
+    }
+
+    fn parse_string(&mut self) -> Result<String, ()> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else { return Err(()) };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escaped) = self.bytes.get(self.pos) else { return Err(()) };
+                    self.pos += 1;
+                    let char = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            // utf-16 surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        _ => return Err(()),
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(char.encode_utf8(&mut buf).as_bytes());
+                },
+                byte => out.push(byte),
+            }
+        }
+
+        String::from_utf8(out).map_err(|_| ())
+    }
+
+    fn parse_array(&mut self) -> Result<Json, ()> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items))
+        }
+
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => { self.pos += 1; break },
+                _ => return Err(()),
+            }
+        }
+
+        Ok(Json::Array(items))
+    }
+
+    fn parse_object(&mut self) -> Result<Json, ()> {
+        self.pos += 1;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields))
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(())
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            let value = self.parse_value()?;
+            fields.push((key, value));
+
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => { self.pos += 1; break },
+                _ => return Err(()),
+            }
+        }
+
+        Ok(Json::Object(fields))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let input = r#"{"id":1,"result":{"items":[{"label":"foo\n\"bar\"","kind":3}],"isIncomplete":false},"x":null}"#;
+        let json = Json::parse(input).unwrap();
+        assert_eq!(json.get("id").and_then(|id| id.as_u64()), Some(1));
+
+        let items = json.get("result").and_then(|r| r.get("items")).and_then(|i| i.as_array()).unwrap();
+        assert_eq!(items[0].get("label").and_then(|l| l.as_str()), Some("foo\n\"bar\""));
+        assert_eq!(json.to_string(), input);
+
+        assert_eq!(Json::parse(r#""😀 ä""#).unwrap(), Json::string("😀 ä"));
+        assert!(Json::parse("{\"a\":}").is_err());
+        assert!(Json::parse("[1,2").is_err());
+    }
+}
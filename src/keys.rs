@@ -0,0 +1,175 @@
+use crate::{Io, Modifiers, SpecialKey};
+
+
+// one key press, written in vim's notation like "x", "<Esc>", "<C-w>" or "<CR>"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Special(SpecialKey),
+    CtrlSpecial(SpecialKey),
+}
+
+impl Key {
+    // the input of a frame where only this key was pressed
+    pub fn to_io(self) -> Io {
+        let mut io = Io::new();
+        match self {
+            Key::Char(c) => io.chars.push(c),
+            Key::Ctrl(c) => {
+                io.chars.push(c);
+                io.special_keys.push(SpecialKey::Control);
+                io.modifiers = Modifiers::CONTROL;
+            },
+            Key::Special(key) => io.special_keys.push(key),
+            Key::CtrlSpecial(key) => {
+                io.special_keys.push(key);
+                io.modifiers = Modifiers::CONTROL;
+            },
+        }
+
+        io
+    }
+}
+
+fn special_name(key: SpecialKey) -> &'static str {
+    match key {
+        SpecialKey::Backspace => "BS",
+        SpecialKey::Enter => "CR",
+        SpecialKey::Escape => "Esc",
+        SpecialKey::Control => "Ctrl",
+        SpecialKey::Tab => "Tab",
+        SpecialKey::Left => "Left",
+        SpecialKey::Right => "Right",
+        SpecialKey::Home => "Home",
+    }
+}
+
+fn parse_special(name: &str) -> Option<SpecialKey> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "bs" | "backspace" => SpecialKey::Backspace,
+        "cr" | "enter" | "return" => SpecialKey::Enter,
+        "esc" => SpecialKey::Escape,
+        "tab" => SpecialKey::Tab,
+        "left" => SpecialKey::Left,
+        "right" => SpecialKey::Right,
+        "home" => SpecialKey::Home,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+// "<...>" that isn't a known key is typed as is
+pub fn parse(keys: &str) -> Vec<Key> {
+    let mut out = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some((key, after)) = rest[1..].split_once('>').and_then(|(name, after)| Some((parse_bracketed(name)?, after))) {
+                out.push(key);
+                rest = after;
+                continue
+            }
+        }
+        out.push(Key::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+fn parse_bracketed(name: &str) -> Option<Key> {
+    if name.eq_ignore_ascii_case("lt") {
+        return Some(Key::Char('<'))
+    }
+
+    if let Some(rest) = name.strip_prefix("C-").or_else(|| name.strip_prefix("c-")) {
+        let mut chars = rest.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(Key::Ctrl(c.to_ascii_lowercase())),
+            _ => parse_special(rest).map(Key::CtrlSpecial),
+        }
+    }
+
+    parse_special(name).map(Key::Special)
+}
+
+pub fn to_notation(keys: &[Key]) -> String {
+    let mut out = String::new();
+    for key in keys {
+        match key {
+            Key::Char('<') => out.push_str("<lt>"),
+            Key::Char(c) => out.push(*c),
+            Key::Ctrl(c) => out.push_str(&format!("<C-{c}>")),
+            Key::Special(key) => out.push_str(&format!("<{}>", special_name(*key))),
+            Key::CtrlSpecial(key) => out.push_str(&format!("<C-{}>", special_name(*key))),
+        }
+    }
+
+    out
+}
+
+// the keys pressed in a frame, pressing control on its own isn't a key
+pub fn from_io(io: &Io) -> Vec<Key> {
+    let control = io.pressed_special(SpecialKey::Control);
+    let mut keys = io.chars.chars().map(|c| if control { Key::Ctrl(c) } else { Key::Char(c) }).collect::<Vec<_>>();
+    for &key in &io.special_keys {
+        if key == SpecialKey::Control {
+            continue
+        }
+        if io.modifiers.contains(Modifiers::CONTROL) {
+            keys.push(Key::CtrlSpecial(key));
+        } else {
+            keys.push(Key::Special(key));
+        }
+    }
+
+    keys
+}
+
+// the modes a map applies in, :map is normal and visual
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    Insert,
+}
+
+impl MapMode {
+    // the letter :map lists the mode with
+    pub fn letter(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::Insert => 'i',
+        }
+    }
+}
+
+// typing lhs runs rhs instead. the keys of a noremap aren't mapped again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    pub mode: MapMode,
+    pub lhs: Vec<Key>,
+    pub rhs: String,
+    pub noremap: bool,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let keys = parse("ix<Esc><C-w><cr><lt><Foo>");
+        assert_eq!(keys[..5], [Key::Char('i'), Key::Char('x'), Key::Special(SpecialKey::Escape), Key::Ctrl('w'), Key::Special(SpecialKey::Enter)]);
+        assert_eq!(keys[5], Key::Char('<'));
+        assert_eq!(to_notation(&keys[6..]), "<lt>Foo>");
+
+        let text = "dd<C-r>:s/a/<lt>b>/<CR><C-Left>";
+        assert_eq!(to_notation(&parse(text)), text);
+        assert_eq!(from_io(&Key::Ctrl('r').to_io()), [Key::Ctrl('r')]);
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::BTreeMap;
+
+use crate::gap_buffer::LinePos;
+
+
+// the windows buffers are shown in, side by side or one above the other. the
+// layout is a tree of splits whose children share its space by weight, so they
+// keep their proportions when the whole window is resized. the focused window's
+// buffer, cursor and scroll are the editor's own while it's focused, the other
+// windows' are kept here
+
+// windows aren't made smaller than this in cells while there's room
+pub const MIN_ROWS: usize = 2;
+pub const MIN_COLS: usize = 8;
+// how many cells a key in resize mode grows or shrinks a window by
+pub const RESIZE_STEP: usize = 2;
+
+// what a window shows while it isn't focused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct View {
+    pub buffer: usize,
+    pub cursor: LinePos,
+    pub start_line: usize,
+}
+
+// in cells from the top left corner of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl Rect {
+    fn contains(&self, row: usize, col: usize) -> bool {
+        (self.row..self.row + self.rows).contains(&row) && (self.col..self.col + self.cols).contains(&col)
+    }
+}
+
+// how the children of a split are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    // one above the other, :split
+    Rows,
+    // side by side, :vsplit
+    Cols,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+// what ctrl-w and the window commands do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowCommand {
+    Split(Axis),
+    Close,
+    Only,
+    Next,
+    Focus(Direction),
+    // the focused window takes the whole screen, or gives it back
+    Zoom,
+    // h/j/k/l size the focused window until escape
+    Resize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Window(usize),
+    // the children's weights add up to 1
+    Split(Axis, Vec<(Node, f32)>),
+}
+
+pub struct Layout {
+    root: Node,
+    // the id of the window being typed in
+    pub focused: usize,
+    // of every window but the focused one, by id
+    views: BTreeMap<usize, View>,
+    next_id: usize,
+    // the whole tree while the focused window is zoomed, put back by the next toggle
+    zoomed: Option<Node>,
+    // the screen in rows and cols, set before each frame
+    pub size: (usize, usize),
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Self { root: Node::Window(0), focused: 0, views: BTreeMap::new(), next_id: 1, zoomed: None, size: (24, 80) }
+    }
+
+    // also while one of the windows is zoomed
+    pub fn is_split(&self) -> bool {
+        matches!(self.root, Node::Split(..)) || self.zoomed.is_some()
+    }
+
+    pub fn is_zoomed(&self) -> bool {
+        self.zoomed.is_some()
+    }
+
+    pub fn toggle_zoom(&mut self) {
+        match self.zoomed.take() {
+            Some(root) => self.root = root,
+            None if self.is_split() => self.zoomed = Some(std::mem::replace(&mut self.root, Node::Window(self.focused))),
+            None => (),
+        }
+    }
+
+    pub fn view(&self, id: usize) -> Option<&View> {
+        self.views.get(&id)
+    }
+
+    pub fn view_mut(&mut self, id: usize) -> Option<&mut View> {
+        self.views.get_mut(&id)
+    }
+
+    // every window with where it goes on the screen, in the order of the tree
+    pub fn windows(&self) -> Vec<(usize, Rect)> {
+        let mut windows = Vec::new();
+        place(&self.root, self.screen(), &mut windows, &mut Vec::new());
+        windows
+    }
+
+    // the cells between windows
+    pub fn separators(&self) -> Vec<Rect> {
+        let mut separators = Vec::new();
+        place(&self.root, self.screen(), &mut Vec::new(), &mut separators);
+        separators
+    }
+
+    pub fn rect(&self, id: usize) -> Option<Rect> {
+        self.windows().into_iter().find(|(window, _)| *window == id).map(|(_, rect)| rect)
+    }
+
+    pub fn window_at(&self, row: usize, col: usize) -> Option<usize> {
+        self.windows().into_iter().find(|(_, rect)| rect.contains(row, col)).map(|(id, _)| id)
+    }
+
+    // the focused window is split in two, the new one goes above or to the
+    // left and is focused, the one left keeps view
+    pub fn split(&mut self, axis: Axis, view: View) {
+        self.unzoom();
+        let id = self.next_id;
+        self.next_id += 1;
+        split_node(&mut self.root, self.focused, id, axis);
+        self.views.insert(self.focused, view);
+        self.focused = id;
+    }
+
+    // moves the focus to window id, the one left keeps view. returns what id shows
+    pub fn focus(&mut self, id: usize, view: View) -> Option<View> {
+        let shown = self.views.remove(&id)?;
+        self.views.insert(self.focused, view);
+        self.focused = id;
+        Some(shown)
+    }
+
+    // the focused window goes away and its space to the window before it, or
+    // after it for the first. returns what the window focused now shows, None
+    // if it's the last one
+    pub fn close(&mut self) -> Option<View> {
+        self.unzoom();
+        let next = remove_node(&mut self.root, self.focused)?;
+        self.focused = next;
+        self.views.remove(&next)
+    }
+
+    // every other window is closed
+    pub fn only(&mut self) {
+        self.zoomed = None;
+        self.root = Node::Window(self.focused);
+        self.views.clear();
+    }
+
+    // ctrl-w w, the window after the focused one or the first
+    pub fn next(&self) -> usize {
+        let windows = self.windows();
+        let i = windows.iter().position(|(id, _)| *id == self.focused).unwrap_or(0);
+        windows[(i + 1) % windows.len()].0
+    }
+
+    // the closest window next to the focused one in direction, the one level
+    // with its top left corner if there are a few
+    pub fn neighbor(&self, direction: Direction) -> Option<usize> {
+        let windows = self.windows();
+        let (_, from) = *windows.iter().find(|(id, _)| *id == self.focused)?;
+        let overlap = |a: usize, a_len: usize, b: usize, b_len: usize| a < b + b_len && b < a + a_len;
+        windows.iter()
+            .filter(|(id, _)| *id != self.focused)
+            .filter_map(|(id, rect)| {
+                let (gap, offset) = match direction {
+                    Direction::Left if rect.col + rect.cols <= from.col && overlap(rect.row, rect.rows, from.row, from.rows) => (from.col - rect.col - rect.cols, rect.row.abs_diff(from.row)),
+                    Direction::Right if from.col + from.cols <= rect.col && overlap(rect.row, rect.rows, from.row, from.rows) => (rect.col - from.col - from.cols, rect.row.abs_diff(from.row)),
+                    Direction::Up if rect.row + rect.rows <= from.row && overlap(rect.col, rect.cols, from.col, from.cols) => (from.row - rect.row - rect.rows, rect.col.abs_diff(from.col)),
+                    Direction::Down if from.row + from.rows <= rect.row && overlap(rect.col, rect.cols, from.col, from.cols) => (rect.row - from.row - from.rows, rect.col.abs_diff(from.col)),
+                    _ => return None,
+                };
+                Some(((gap, offset), *id))
+            })
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    // the focused window gets cells more along axis, fewer if negative, from
+    // the window next to it in the closest split that way. false if there's
+    // none or it can't get any smaller or bigger
+    pub fn resize(&mut self, axis: Axis, cells: isize) -> bool {
+        let screen = self.screen();
+        resize_node(&mut self.root, screen, self.focused, axis, cells) == Some(true)
+    }
+
+    fn unzoom(&mut self) {
+        if let Some(root) = self.zoomed.take() {
+            self.root = root;
+        }
+    }
+
+    fn screen(&self) -> Rect {
+        Rect { row: 0, col: 0, rows: self.size.0, cols: self.size.1 }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// rect split between node's windows, with a cell between every two
+fn place(node: &Node, rect: Rect, windows: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+    let (axis, children) = match node {
+        Node::Window(id) => {
+            windows.push((*id, rect));
+            return
+        },
+        Node::Split(axis, children) => (*axis, children),
+    };
+
+    for ((child, _), rect) in children.iter().zip(child_rects(rect, axis, children, separators)) {
+        place(child, rect, windows, separators);
+    }
+}
+
+// where each child of a split along axis goes in rect
+fn child_rects(rect: Rect, axis: Axis, children: &[(Node, f32)], separators: &mut Vec<Rect>) -> Vec<Rect> {
+    let space = match axis {
+        Axis::Rows => rect.rows,
+        Axis::Cols => rect.cols,
+    };
+    let weights = children.iter().map(|(_, weight)| *weight).collect::<Vec<_>>();
+    let sizes = share(space.saturating_sub(children.len() - 1), &weights, min_size(axis));
+    let mut at = 0;
+    let mut rects = Vec::new();
+    for (i, size) in sizes.into_iter().enumerate() {
+        if i > 0 {
+            separators.push(along(rect, axis, at, 1));
+            at += 1;
+        }
+        rects.push(along(rect, axis, at, size));
+        at += size;
+    }
+    rects
+}
+
+fn min_size(axis: Axis) -> usize {
+    match axis {
+        Axis::Rows => MIN_ROWS,
+        Axis::Cols => MIN_COLS,
+    }
+}
+
+// the part of rect from at for len cells along axis
+fn along(rect: Rect, axis: Axis, at: usize, len: usize) -> Rect {
+    match axis {
+        Axis::Rows => Rect { row: rect.row + at, rows: len, ..rect },
+        Axis::Cols => Rect { col: rect.col + at, cols: len, ..rect },
+    }
+}
+
+// space split by weight, every part at least min when there's room for it
+fn share(space: usize, weights: &[f32], min: usize) -> Vec<usize> {
+    let total = weights.iter().sum::<f32>();
+    let mut sizes = Vec::new();
+    let (mut sum, mut last_end) = (0.0, 0);
+    for weight in weights {
+        sum += weight;
+        let end = ((sum / total * space as f32).round() as usize).min(space);
+        sizes.push(end - last_end);
+        last_end = end;
+    }
+    if let Some(last) = sizes.last_mut() {
+        *last += space - last_end;
+    }
+
+    // small parts take from the biggest
+    let min = min.min(space / weights.len().max(1));
+    for i in 0..sizes.len() {
+        while sizes[i] < min {
+            let biggest = (0..sizes.len()).max_by_key(|&j| sizes[j]).unwrap_or(i);
+            if sizes[biggest] <= min {
+                break
+            }
+            sizes[biggest] -= 1;
+            sizes[i] += 1;
+        }
+    }
+
+    sizes
+}
+
+fn split_node(node: &mut Node, target: usize, new: usize, axis: Axis) -> bool {
+    match node {
+        Node::Window(id) if *id == target => {
+            *node = Node::Split(axis, vec![(Node::Window(new), 0.5), (Node::Window(target), 0.5)]);
+            true
+        },
+        Node::Window(_) => false,
+        Node::Split(split_axis, children) => {
+            // in a split the same way the new window is one more child, not a split of its own
+            let i = children.iter().position(|(child, _)| matches!(child, Node::Window(id) if *id == target));
+            if let (true, Some(i)) = (*split_axis == axis, i) {
+                let half = children[i].1 / 2.0;
+                children[i].1 = half;
+                children.insert(i, (Node::Window(new), half));
+                return true
+            }
+            children.iter_mut().any(|(child, _)| split_node(child, target, new, axis))
+        },
+    }
+}
+
+// returns the window that's focused instead, one from the child that got the space
+fn remove_node(node: &mut Node, target: usize) -> Option<usize> {
+    let Node::Split(_, children) = node else { return None };
+    let Some(i) = children.iter().position(|(child, _)| matches!(child, Node::Window(id) if *id == target)) else {
+        let next = children.iter_mut().find_map(|(child, _)| remove_node(child, target))?;
+        // a split left with one child is only that child
+        for (child, _) in children.iter_mut() {
+            if let Node::Split(_, grandchildren) = child {
+                if grandchildren.len() == 1 {
+                    *child = grandchildren.remove(0).0;
+                }
+            }
+        }
+        return Some(next)
+    };
+
+    let (_, weight) = children.remove(i);
+    let neighbor = i.saturating_sub(1);
+    children[neighbor].1 += weight;
+    let next = first_window(&children[neighbor].0);
+    if children.len() == 1 {
+        *node = children.remove(0).0;
+    }
+
+    Some(next)
+}
+
+// None if target isn't in node, otherwise whether it was resized. the
+// innermost split along axis does it
+fn resize_node(node: &mut Node, rect: Rect, target: usize, axis: Axis, cells: isize) -> Option<bool> {
+    let Node::Split(split_axis, children) = node else {
+        return matches!(node, Node::Window(id) if *id == target).then_some(false)
+    };
+    let rects = child_rects(rect, *split_axis, children, &mut Vec::new());
+    let sizes = rects.iter().map(|rect| match split_axis {
+        Axis::Rows => rect.rows,
+        Axis::Cols => rect.cols,
+    }).collect::<Vec<_>>();
+    let (i, resized) = children.iter_mut().zip(rects).enumerate()
+        .find_map(|(i, ((child, _), rect))| Some((i, resize_node(child, rect, target, axis, cells)?)))?;
+    if resized || *split_axis != axis {
+        return Some(resized)
+    }
+
+    // the cells go to or come from the next window, or the one before for the last
+    let neighbor = if i + 1 < children.len() { i + 1 } else { i - 1 };
+    let (pair, min) = (sizes[i] + sizes[neighbor], min_size(axis));
+    if pair < min * 2 {
+        return Some(false)
+    }
+    let size = sizes[i].saturating_add_signed(cells).clamp(min, pair - min);
+    // weights of whole cells, so the next step moves by exactly its cells
+    let space = sizes.iter().sum::<usize>() as f32;
+    for ((_, weight), size) in children.iter_mut().zip(&sizes) {
+        *weight = *size as f32 / space;
+    }
+    children[i].1 = size as f32 / space;
+    children[neighbor].1 = (pair - size) as f32 / space;
+    Some(size != sizes[i])
+}
+
+fn first_window(node: &Node) -> usize {
+    match node {
+        Node::Window(id) => *id,
+        Node::Split(_, children) => first_window(&children[0].0),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(buffer: usize) -> View {
+        View { buffer, cursor: LinePos::new(0, 0), start_line: 0 }
+    }
+
+    #[test]
+    fn test_share() {
+        assert_eq!(share(21, &[0.5, 0.5], 2), [11, 10]);
+        assert_eq!(share(10, &[1.0 / 3.0; 3], 2), [3, 4, 3]);
+        // the small part is kept at the minimum while there's room
+        assert_eq!(share(20, &[0.95, 0.05], 8), [12, 8]);
+        assert_eq!(share(10, &[0.95, 0.05], 8), [5, 5]);
+    }
+
+    #[test]
+    fn test_split() {
+        let mut layout = Layout::new();
+        layout.size = (21, 80);
+        assert!(!layout.is_split());
+
+        layout.split(Axis::Rows, view(0));
+        assert_eq!(layout.focused, 1);
+        assert_eq!(layout.windows(), [
+            (1, Rect { row: 0, col: 0, rows: 10, cols: 80 }),
+            (0, Rect { row: 11, col: 0, rows: 10, cols: 80 }),
+        ]);
+        assert_eq!(layout.separators(), [Rect { row: 10, col: 0, rows: 1, cols: 80 }]);
+
+        // the new window takes half of the focused one's space
+        layout.split(Axis::Rows, view(1));
+        assert_eq!(layout.windows().iter().map(|(id, rect)| (*id, rect.rows)).collect::<Vec<_>>(), [(2, 5), (1, 5), (0, 9)]);
+        layout.split(Axis::Cols, view(2));
+        assert_eq!(layout.windows().iter().map(|(id, rect)| (*id, rect.col, rect.cols)).collect::<Vec<_>>(), [(3, 0, 40), (2, 41, 39), (1, 0, 80), (0, 0, 80)]);
+
+        // resizing the screen keeps the proportions
+        layout.size = (42, 160);
+        assert_eq!(layout.rect(0), Some(Rect { row: 22, col: 0, rows: 20, cols: 160 }));
+    }
+
+    #[test]
+    fn test_focus() {
+        let mut layout = Layout::new();
+        layout.size = (21, 81);
+        layout.split(Axis::Cols, view(0));
+        layout.split(Axis::Rows, view(1));
+        // 2 above 1 on the left, 0 on the right
+        assert_eq!(layout.neighbor(Direction::Right), Some(0));
+        assert_eq!(layout.neighbor(Direction::Down), Some(1));
+        assert_eq!(layout.neighbor(Direction::Up), None);
+        assert_eq!(layout.next(), 1);
+
+        assert_eq!(layout.focus(0, view(2)), Some(view(0)));
+        assert_eq!(layout.neighbor(Direction::Left), Some(2));
+        assert_eq!(layout.next(), 2);
+        assert_eq!(layout.window_at(20, 0), Some(1));
+        assert_eq!(layout.window_at(20, 80), Some(0));
+        assert_eq!(layout.focus(5, view(0)), None);
+    }
+
+    #[test]
+    fn test_zoom() {
+        let mut layout = Layout::new();
+        layout.size = (21, 80);
+        layout.toggle_zoom();
+        assert!(!layout.is_zoomed());
+
+        layout.split(Axis::Rows, view(0));
+        layout.split(Axis::Cols, view(1));
+        let windows = layout.windows();
+        layout.toggle_zoom();
+        assert!(layout.is_zoomed() && layout.is_split());
+        assert_eq!(layout.windows(), [(2, Rect { row: 0, col: 0, rows: 21, cols: 80 })]);
+        layout.toggle_zoom();
+        assert_eq!(layout.windows(), windows);
+
+        // splitting or closing puts the layout back first
+        layout.toggle_zoom();
+        assert_eq!(layout.close(), Some(view(1)));
+        assert!(!layout.is_zoomed());
+        assert_eq!(layout.windows().len(), 2);
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut layout = Layout::new();
+        layout.size = (21, 81);
+        layout.split(Axis::Cols, view(0));
+        layout.split(Axis::Rows, view(1));
+        // 2 above 1 on the left, 0 on the right
+        assert!(layout.resize(Axis::Rows, 3));
+        assert_eq!(layout.rect(2).map(|rect| rect.rows), Some(13));
+        assert_eq!(layout.rect(1).map(|rect| rect.rows), Some(7));
+        // the split side by side is further up the tree
+        assert!(layout.resize(Axis::Cols, -4));
+        assert_eq!(layout.rect(2).map(|rect| rect.cols), Some(36));
+        assert_eq!(layout.rect(0).map(|rect| rect.cols), Some(44));
+
+        // not below the minimum, for the window next to it too
+        assert!(layout.resize(Axis::Rows, 100));
+        assert_eq!(layout.rect(1).map(|rect| rect.rows), Some(MIN_ROWS));
+        assert!(!layout.resize(Axis::Rows, 1));
+        layout.only();
+        assert!(!layout.resize(Axis::Cols, 1));
+    }
+
+    #[test]
+    fn test_close() {
+        let mut layout = Layout::new();
+        layout.size = (21, 81);
+        layout.split(Axis::Cols, view(0));
+        layout.split(Axis::Rows, view(1));
+        assert_eq!(layout.close(), Some(view(1)));
+        assert_eq!(layout.focused, 1);
+        assert_eq!(layout.windows(), [
+            (1, Rect { row: 0, col: 0, rows: 21, cols: 40 }),
+            (0, Rect { row: 0, col: 41, rows: 21, cols: 40 }),
+        ]);
+
+        layout.split(Axis::Rows, view(1));
+        layout.only();
+        assert_eq!(layout.windows(), [(3, Rect { row: 0, col: 0, rows: 21, cols: 81 })]);
+        assert_eq!(layout.close(), None);
+        assert!(layout.view(0).is_none());
+    }
+}
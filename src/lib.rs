@@ -0,0 +1,277 @@
+pub mod editor;
+pub mod encoding;
+pub mod gap_buffer;
+pub mod hex;
+pub mod line_index;
+pub mod vim_commands;
+pub mod search;
+pub mod indent;
+pub mod command_bar;
+pub mod options;
+pub mod buffer_vars;
+pub mod shell;
+pub mod substitute;
+pub mod regex;
+pub mod json;
+pub mod lsp;
+pub mod completion;
+pub mod undo;
+pub mod diff;
+pub mod format;
+pub mod blame;
+pub mod tags;
+pub mod keys;
+pub mod layout;
+pub mod scheduler;
+pub mod rpc;
+pub mod width;
+pub mod wrap;
+pub mod fold;
+pub mod popup;
+pub mod notification;
+pub mod which_key;
+pub mod virtual_text;
+pub mod digraph;
+pub mod recent;
+pub mod project;
+pub mod ignore;
+pub mod tasks;
+pub mod profile;
+pub mod log;
+pub mod startup;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "gui")]
+pub mod renderer;
+#[cfg(feature = "gui")]
+pub mod shader;
+#[cfg(feature = "gui")]
+pub mod font;
+
+use std::ops::BitOrAssign;
+use std::sync::atomic::AtomicBool;
+
+use gap_buffer::LinePos;
+use options::Options;
+use scheduler::Scheduler;
+
+
+pub static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
+
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SpecialKey {
+    Backspace,
+    Enter,
+    Escape,
+    Control,
+    Tab,
+    Left,
+    Right,
+    Home,
+}
+
+// held modifier keys, the window backend fills these in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const CONTROL: Modifiers = Modifiers(1 << 1);
+    pub const ALT: Modifiers = Modifiers(1 << 2);
+
+    pub const fn empty() -> Self {
+        Modifiers(0)
+    }
+
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, other: Modifiers) {
+        self.0 |= other.0;
+    }
+}
+
+// positions are in pixels from the top left of the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseEvent {
+    Press(f32, f32),
+    // moved with the left button held
+    Drag(f32, f32),
+    Release,
+}
+
+#[derive(Debug)]
+pub struct Io {
+    pub chars: String,
+    // what the keys pressed type on a US layout, for the physicalkeys option
+    pub physical_chars: String,
+    pub special_keys: Vec<SpecialKey>,
+    pub modifiers: Modifiers,
+    pub mouse: Vec<MouseEvent>,
+    // where the pointer is, kept between frames
+    pub mouse_pos: (f32, f32),
+}
+
+impl Io {
+    pub fn new() -> Self {
+        Self { chars: String::new(), physical_chars: String::new(), special_keys: Vec::new(), modifiers: Modifiers::empty(), mouse: Vec::new(), mouse_pos: (0.0, 0.0) }
+    }
+
+    pub fn pressed_char(&self, wanted: char) -> bool {
+        self.chars.contains(wanted)
+    }
+
+    pub fn pressed_special(&self, wanted: SpecialKey) -> bool {
+        for item in &self.special_keys {
+            if *item == wanted {
+                return true
+            }
+        }
+
+        false
+    }
+
+    pub fn pressed_char_and_special(&self, c: char, s: SpecialKey) -> bool {
+        self.pressed_char(c) && self.pressed_special(s)
+    }
+
+    pub fn pressed_char_with_modifiers(&self, wanted: char, modifiers: Modifiers) -> bool {
+        self.chars.contains(wanted) && self.modifiers.contains(modifiers)
+    }
+
+    pub fn pressed_special_with_modifiers(&self, wanted: SpecialKey, modifiers: Modifiers) -> bool {
+        for item in &self.special_keys {
+            if *item == wanted && self.modifiers.contains(modifiers) {
+                return true
+            }
+        }
+
+        false
+    }
+
+    // keys or clicks, pointer moves don't count
+    pub fn has_input(&self) -> bool {
+        !self.chars.is_empty() || !self.special_keys.is_empty() || !self.mouse.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.chars.clear();
+        self.physical_chars.clear();
+        self.special_keys.clear();
+        self.modifiers = Modifiers::empty();
+        self.mouse.clear();
+    }
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct CursorPos {
+    pub x: usize,
+    pub y: usize,
+    pub wanted_x: usize,
+    pub buffer: usize,
+    // the first line shown when the buffer was last on screen
+    pub start_line: usize,
+}
+
+impl CursorPos {
+    pub fn new(buffer: usize) -> Self {
+        Self { x: 1, y: 1, wanted_x: 1, buffer, start_line: 0 }
+    }
+
+    // line is the text of the cursor's line and rows the ones on screen, wide chars
+    // and tabs before the cursor take more than a cell
+    pub fn to_screen_position(&self, state: &State, rows: &[wrap::Row], line: &str) -> (f32, f32) {
+        let row = wrap::row_of(rows, self.to_linepos()).unwrap_or(0);
+        let start = rows.get(row).map_or(0, |row| row.start);
+        let text = line.chars().skip(start).collect::<String>();
+
+        // xpos, ypos
+        let xpos = width::display_col(&text, self.x - 1 - start, state.options.tabstop) as f32 * state.char_width;
+        let ypos = state.height as f32 - ((row + 1) as f32 * state.char_height);
+
+        (xpos, ypos)
+    }
+
+    pub fn to_linepos(&self) -> LinePos {
+        LinePos { line: self.y - 1, col: self.x - 1 }
+    }
+    
+    pub fn to_line_col(&self) -> (usize, usize) {
+        (self.y - 1, self.x - 1)
+    }
+
+    pub fn from_linepos(&mut self, pos: LinePos) {
+        self.x = pos.col + 1;
+        self.y = pos.line + 1;
+        self.wanted_x = self.x;
+    }
+}
+
+pub struct State {
+    pub width: i32,
+    pub height: i32,
+    pub window_changed_size: bool,
+    pub io: Io,
+    // 2.0 on most hidpi monitors, fonts and the ui are scaled by it
+    pub content_scale: f32,
+    // the pixel size glyphs were rasterized at
+    pub char_scale: f32,
+    pub char_width: f32,
+    pub char_height: f32,
+    pub cmd_bar_cursor_x: usize,
+    pub start_line: usize,
+    pub options: Options,
+    pub scheduler: Scheduler,
+}
+
+impl State {
+    // char sizes are known once the font is loaded
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width, height,
+            window_changed_size: true,
+            content_scale: 1.0,
+            char_scale: options::DEFAULT_FONT_SIZE,
+            char_width: 0.0,
+            char_height: 0.0,
+            io: Io::new(),
+            cmd_bar_cursor_x: 0,
+            start_line: 0,
+            options: Options::new(),
+            scheduler: Scheduler::new(),
+        }
+    }
+
+    pub fn max_rows(&self) -> usize {
+        (self.height as f32 / self.char_height).floor() as usize
+    }
+
+    pub fn max_cols(&self) -> usize {
+        (self.width as f32 / self.char_width) as usize
+    }
+
+    // the font size in pixels on this monitor
+    pub fn font_pixels(&self) -> f32 {
+        self.options.font_size * self.content_scale
+    }
+
+    // the bar on the right edge, in pixels
+    pub fn scrollbar_width(&self) -> f32 {
+        if self.options.scrollbar {
+            self.char_width / 2.0
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use crate::gap_buffer::GapBuffer;
+
+
+// where each line of a buffer starts. small buffers keep them in a gap buffer,
+// big ones in a rope where an edit shifts whole chunks at once instead of
+// every line after it. the text itself stays in the gap buffer for every size:
+// an edit there only moves the bytes between the gap and the cursor, it's the
+// line starts that cost a pass over every line, and the rest of the editor
+// borrows the text as the two halves of the gap (LineView, search, snapshots)
+
+// buffers with more bytes than this use the rope
+pub const ROPE_THRESHOLD: usize = 1 << 20;
+
+pub trait LineIndex {
+    fn get_one(&self, line: usize) -> usize;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn insert(&mut self, line: usize, starts: &[usize]);
+    fn remove(&mut self, line: usize, len: usize);
+    fn increment_range_by(&mut self, range: std::ops::Range<usize>, by: usize);
+    fn decrement_range_by(&mut self, range: std::ops::Range<usize>, by: usize);
+    // the line byte is on
+    fn line_at(&self, byte: usize) -> usize;
+}
+
+pub fn new_index(starts: Vec<usize>, size: usize) -> Box<dyn LineIndex> {
+    if size > ROPE_THRESHOLD {
+        Box::new(LineRope::new(starts))
+    } else {
+        Box::new(GapBuffer::new(starts))
+    }
+}
+
+
+const CHUNK: usize = 1024;
+
+// line starts in chunks of at most 2 * CHUNK. every start in a chunk is
+// shifted by the chunk's shift, so moving the lines after an edit is one
+// add per chunk
+struct Chunk {
+    starts: Vec<usize>,
+    shift: isize,
+}
+
+impl Chunk {
+    fn get(&self, i: usize) -> usize {
+        (self.starts[i] as isize + self.shift) as usize
+    }
+
+    fn first(&self) -> usize {
+        self.get(0)
+    }
+}
+
+// how many lines the chunks have, as a fenwick tree so the lines before a
+// chunk and the chunk a line is in take log(chunks) steps
+struct Counts {
+    // from 1, tree[i] adds up the i & -i counts up to chunk i - 1
+    tree: Vec<usize>,
+}
+
+impl Counts {
+    fn new(chunks: &[Chunk]) -> Self {
+        let mut tree = vec![0; chunks.len() + 1];
+        for (i, chunk) in chunks.iter().enumerate() {
+            let i = i + 1;
+            tree[i] += chunk.starts.len();
+            let parent = i + (i & i.wrapping_neg());
+            if parent < tree.len() {
+                tree[parent] += tree[i];
+            }
+        }
+
+        Self { tree }
+    }
+
+    fn add(&mut self, chunk: usize, by: isize) {
+        let mut i = chunk + 1;
+        while i < self.tree.len() {
+            self.tree[i] = (self.tree[i] as isize + by) as usize;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // the lines in the chunks before chunk
+    fn before(&self, chunk: usize) -> usize {
+        let mut sum = 0;
+        let mut i = chunk;
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+
+        sum
+    }
+
+    // how many chunks from the start hold no more than lines lines together
+    fn chunks_within(&self, mut lines: usize) -> usize {
+        let mut chunks = 0;
+        let mut step = (self.tree.len() - 1).checked_ilog2().map_or(0, |log| 1 << log);
+        while step > 0 {
+            if chunks + step < self.tree.len() && self.tree[chunks + step] <= lines {
+                chunks += step;
+                lines -= self.tree[chunks];
+            }
+            step >>= 1;
+        }
+
+        chunks
+    }
+}
+
+pub struct LineRope {
+    chunks: Vec<Chunk>,
+    counts: Counts,
+    len: usize,
+}
+
+impl LineRope {
+    pub fn new(starts: Vec<usize>) -> Self {
+        let len = starts.len();
+        let mut chunks = starts.chunks(CHUNK).map(|starts| Chunk { starts: starts.to_vec(), shift: 0 }).collect::<Vec<_>>();
+        if chunks.is_empty() {
+            chunks.push(Chunk { starts: Vec::new(), shift: 0 });
+        }
+        let counts = Counts::new(&chunks);
+
+        Self { chunks, counts, len }
+    }
+
+    // the chunk line is in and its index there, the end is in the last chunk
+    fn locate(&self, line: usize) -> (usize, usize) {
+        let c = self.counts.chunks_within(line).min(self.chunks.len() - 1);
+        (c, line - self.counts.before(c))
+    }
+
+    fn shift_range(&mut self, range: std::ops::Range<usize>, by: isize) {
+        let mut first = 0;
+        for chunk in &mut self.chunks {
+            let end = first + chunk.starts.len();
+            if range.start <= first && end <= range.end {
+                chunk.shift += by;
+            } else if range.start < end && first < range.end {
+                let from = range.start.max(first) - first;
+                let to = range.end.min(end) - first;
+                for start in &mut chunk.starts[from..to] {
+                    *start = (*start as isize + by) as usize;
+                }
+            }
+            first = end;
+        }
+    }
+}
+
+impl LineIndex for LineRope {
+    fn get_one(&self, line: usize) -> usize {
+        let (chunk, i) = self.locate(line);
+        self.chunks[chunk].get(i)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn insert(&mut self, line: usize, starts: &[usize]) {
+        let (c, i) = self.locate(line);
+        let chunk = &mut self.chunks[c];
+        let shift = chunk.shift;
+        chunk.starts.splice(i..i, starts.iter().map(|&start| (start as isize - shift) as usize));
+        self.len += starts.len();
+
+        if chunk.starts.len() > 2 * CHUNK {
+            let rest = chunk.starts.split_off(CHUNK);
+            let parts = rest.chunks(CHUNK).map(|starts| Chunk { starts: starts.to_vec(), shift });
+            self.chunks.splice((c + 1)..(c + 1), parts.collect::<Vec<_>>());
+            self.counts = Counts::new(&self.chunks);
+        } else {
+            self.counts.add(c, starts.len() as isize);
+        }
+    }
+
+    fn remove(&mut self, line: usize, len: usize) {
+        let mut left = len.min(self.len - line);
+        self.len -= left;
+        let (mut c, mut i) = self.locate(line);
+        let mut removed_chunks = false;
+        while left > 0 {
+            let chunk = &mut self.chunks[c];
+            let n = left.min(chunk.starts.len() - i);
+            chunk.starts.drain(i..(i + n));
+            left -= n;
+            if chunk.starts.is_empty() && self.chunks.len() > 1 {
+                self.chunks.remove(c);
+                removed_chunks = true;
+            } else {
+                if !removed_chunks {
+                    self.counts.add(c, -(n as isize));
+                }
+                c += 1;
+            }
+            i = 0;
+        }
+        // the chunks after a removed one moved down
+        if removed_chunks {
+            self.counts = Counts::new(&self.chunks);
+        }
+    }
+
+    fn increment_range_by(&mut self, range: std::ops::Range<usize>, by: usize) {
+        self.shift_range(range, by as isize);
+    }
+
+    fn decrement_range_by(&mut self, range: std::ops::Range<usize>, by: usize) {
+        self.shift_range(range, -(by as isize));
+    }
+
+    fn line_at(&self, byte: usize) -> usize {
+        let c = self.chunks.partition_point(|chunk| chunk.starts.is_empty() || chunk.first() <= byte).max(1) - 1;
+        let chunk = &self.chunks[c];
+        let i = chunk.starts.partition_point(|&start| (start as isize + chunk.shift) as usize <= byte);
+
+        (self.counts.before(c) + i).saturating_sub(1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_rope() {
+        let starts = (0..5000).map(|i| i * 10).collect::<Vec<_>>();
+        let mut rope = LineRope::new(starts.clone());
+        let mut gap = GapBuffer::new(starts);
+
+        let check = |rope: &LineRope, gap: &GapBuffer<usize>| {
+            assert_eq!(LineIndex::len(rope), LineIndex::len(gap));
+            let counted = Counts::new(&rope.chunks);
+            assert_eq!(rope.counts.tree, counted.tree);
+            for line in 0..LineIndex::len(gap) {
+                assert_eq!(LineIndex::get_one(rope, line), LineIndex::get_one(gap, line));
+                let start = LineIndex::get_one(gap, line);
+                assert_eq!(rope.line_at(start), line);
+                assert_eq!(rope.line_at(start + 1), gap.line_at(start + 1));
+            }
+        };
+        check(&rope, &gap);
+
+        // an edit on line 100 moves everything after it
+        for index in [&mut rope as &mut dyn LineIndex, &mut gap] {
+            index.increment_range_by(101..5000, 5);
+            index.insert(101, &[1015]);
+            index.increment_range_by(102..5001, 3);
+        }
+        check(&rope, &gap);
+
+        // enough lines in one place to split a chunk
+        for index in [&mut rope as &mut dyn LineIndex, &mut gap] {
+            let at = LineIndex::get_one(index, 3000);
+            let new = (0..3000).map(|i| at + i).collect::<Vec<_>>();
+            index.increment_range_by(3000..5001, 3000);
+            index.insert(3000, &new);
+        }
+        check(&rope, &gap);
+
+        // a remove inside one chunk
+        for index in [&mut rope as &mut dyn LineIndex, &mut gap] {
+            index.remove(200, 2);
+            index.decrement_range_by(200..LineIndex::len(index), 20);
+        }
+        check(&rope, &gap);
+
+        // removes across chunk borders
+        for index in [&mut rope as &mut dyn LineIndex, &mut gap] {
+            let removed = LineIndex::get_one(index, 4000) - LineIndex::get_one(index, 500);
+            index.remove(500, 3500);
+            index.decrement_range_by(500..LineIndex::len(index), removed);
+        }
+        check(&rope, &gap);
+        assert!(rope.chunks.iter().all(|chunk| chunk.starts.len() <= 2 * CHUNK));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::VecDeque, fs::{self, File}, io::Write, path::Path, sync::{Mutex, OnceLock}, time::Instant};
+
+
+// what the editor has to say that isn't for the message line, like a language
+// server that didn't start. entries at the level or more important ones are
+// kept for :messages and written to the log file if init opened one
+
+// older entries are dropped from :messages when there are more
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    // what --log-level takes
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+struct Log {
+    level: LogLevel,
+    entries: VecDeque<String>,
+    file: Option<File>,
+}
+
+static LOG: Mutex<Log> = Mutex::new(Log { level: LogLevel::Info, entries: VecDeque::new(), file: None });
+// entries are timed from here
+static START: OnceLock<Instant> = OnceLock::new();
+
+// keeps entries up to level and appends them to the file at path too
+pub fn init(level: LogLevel, path: Option<&Path>) {
+    START.get_or_init(Instant::now);
+    let file = path.and_then(|path| {
+        fs::create_dir_all(path.parent()?).ok()?;
+        File::options().create(true).append(true).open(path).ok()
+    });
+    if let Ok(mut log) = LOG.lock() {
+        log.level = level;
+        log.file = file;
+    }
+}
+
+pub fn log(level: LogLevel, text: &str) {
+    let Ok(mut log) = LOG.lock() else { return };
+    if level > log.level {
+        return
+    }
+
+    let seconds = START.get_or_init(Instant::now).elapsed().as_secs_f64();
+    let entry = format!("[{seconds:9.3}] {}: {text}", level.name());
+    if let Some(file) = &mut log.file {
+        let _ = writeln!(file, "{entry}");
+    }
+    if log.entries.len() == MAX_ENTRIES {
+        log.entries.pop_front();
+    }
+    log.entries.push_back(entry);
+}
+
+pub fn error(text: &str) {
+    log(LogLevel::Error, text);
+}
+
+pub fn warn(text: &str) {
+    log(LogLevel::Warn, text);
+}
+
+pub fn info(text: &str) {
+    log(LogLevel::Info, text);
+}
+
+pub fn debug(text: &str) {
+    log(LogLevel::Debug, text);
+}
+
+// oldest first, for :messages
+pub fn entries() -> Vec<String> {
+    LOG.lock().map(|log| log.entries.iter().cloned().collect()).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        assert_eq!(LogLevel::parse("warn"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("loud"), None);
+
+        // other tests log too, so only what this one wrote is looked at
+        warn("test_log warning");
+        debug("test_log detail");
+        let entries = entries();
+        let ours = entries.iter().filter(|entry| entry.contains("test_log")).collect::<Vec<_>>();
+        assert_eq!(ours.len(), 1);
+        assert!(ours[0].starts_with('[') && ours[0].ends_with("] warn: test_log warning"));
+    }
+}
@@ -0,0 +1,641 @@
+use std::{collections::HashMap, io::{BufRead, BufReader, Write}, path::{Path, PathBuf}, process::{Child, Command, Stdio}, sync::mpsc::{self, Receiver, Sender}, thread};
+
+use crate::{completion::CompletionItem, gap_buffer::{LinePos, TextBuffer}, json::Json, virtual_text::{Annotation, AnnotationKind}};
+
+
+// language server to start for a file, by extension
+pub fn server_for(path: &Path) -> Option<&'static str> {
+    let server = match path.extension()?.to_str()? {
+        "rs" => "rust-analyzer",
+        "c" | "h" | "cc" | "cpp" | "hpp" => "clangd",
+        "py" => "pylsp",
+        "go" => "gopls",
+        "js" | "jsx" | "ts" | "tsx" => "typescript-language-server --stdio",
+        _ => return None,
+    };
+
+    Some(server)
+}
+
+fn language_id(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("rs") => "rust",
+        Some("c") | Some("h") => "c",
+        Some("cc") | Some("cpp") | Some("hpp") => "cpp",
+        Some("py") => "python",
+        Some("go") => "go",
+        Some("js") | Some("jsx") => "javascript",
+        Some("ts") | Some("tsx") => "typescript",
+        _ => "plaintext",
+    }
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    uri
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded.get((i + 1)..(i + 3)).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if encoded[i] == b'%' => {
+                bytes.push(byte);
+                i += 3;
+            },
+            _ => {
+                bytes.push(encoded[i]);
+                i += 1;
+            },
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+// lsp positions count columns in utf-16 code units, LinePos counts chars
+pub fn col_to_utf16(line: &str, col: usize) -> usize {
+    line.chars().take(col).map(char::len_utf16).sum()
+}
+
+pub fn col_from_utf16(line: &str, utf16_col: usize) -> usize {
+    let mut units = 0;
+    let mut col = 0;
+    for char in line.chars() {
+        if units >= utf16_col {
+            break
+        }
+        units += char.len_utf16();
+        col += 1;
+    }
+
+    col
+}
+
+pub fn to_lsp_position(buffer: &TextBuffer, pos: LinePos) -> Json {
+    let character = col_to_utf16(&buffer.line(pos.line), pos.col);
+    Json::object(vec![
+        ("line", Json::Number(pos.line as f64)),
+        ("character", Json::Number(character as f64)),
+    ])
+}
+
+pub fn from_lsp_position(buffer: &TextBuffer, pos: &Json) -> Option<LinePos> {
+    let line = pos.get("line")?.as_u64()? as usize;
+    let character = pos.get("character")?.as_u64()? as usize;
+    if line >= buffer.total_lines() {
+        return None
+    }
+
+    Some(LinePos { line, col: col_from_utf16(&buffer.line(line), character) })
+}
+
+// text to put in place of start..end
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub start: LinePos,
+    pub end: LinePos,
+    pub text: String,
+}
+
+pub fn text_edit(buffer: &TextBuffer, edit: &Json) -> Option<TextEdit> {
+    let range = edit.get("range")?;
+    Some(TextEdit {
+        start: from_lsp_position(buffer, range.get("start")?)?,
+        end: from_lsp_position(buffer, range.get("end")?)?,
+        text: edit.get("newText")?.as_str()?.to_owned(),
+    })
+}
+
+// turns the result of textDocument/completion into menu items. pos is where
+// the cursor was when they were asked for, what's typed since goes before the
+// end of the ranges
+pub fn completion_items(result: &Json, buffer: &TextBuffer, pos: LinePos) -> Vec<CompletionItem> {
+    // either a plain list or a CompletionList
+    let items = result.as_array().or_else(|| result.get("items")?.as_array()).unwrap_or(&[]);
+
+    let mut out = Vec::new();
+    for item in items {
+        let Some(label) = item.get("label").and_then(|l| l.as_str()) else { continue };
+        let mut completion = CompletionItem::new(label);
+
+        if let Some(text) = item.get("insertText").and_then(|t| t.as_str()) {
+            completion.text = text.to_owned();
+        }
+        if let Some(filter) = item.get("filterText").and_then(|t| t.as_str()) {
+            completion.filter = filter.to_owned();
+        }
+        if let Some(edit) = item.get("textEdit") {
+            // TextEdit has a range, InsertReplaceEdit has insert and replace ranges
+            let range = edit.get("range").or_else(|| edit.get("replace"));
+            let start = range.and_then(|r| r.get("start")).and_then(|s| from_lsp_position(buffer, s));
+            let end = range.and_then(|r| r.get("end")).and_then(|e| from_lsp_position(buffer, e));
+            if let (Some(start), Some(text)) = (start, edit.get("newText").and_then(|t| t.as_str())) {
+                completion.replace_start = Some(start);
+                completion.replace_after = end.filter(|end| end.line == pos.line).map_or(0, |end| end.col.saturating_sub(pos.col));
+                completion.text = text.to_owned();
+            }
+        }
+        if let Some(edits) = item.get("additionalTextEdits").and_then(|e| e.as_array()) {
+            completion.additional_edits = edits.iter().filter_map(|edit| text_edit(buffer, edit)).collect();
+        }
+
+        out.push(completion);
+    }
+
+    out
+}
+
+// a position in some file, the column is still in utf-16 units because
+// the file might not be open yet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub character: usize,
+}
+
+// turns a Location, Location[] or LocationLink[] result into locations
+pub fn locations(result: &Json) -> Vec<Location> {
+    let items = match result {
+        Json::Array(items) => items.as_slice(),
+        Json::Null => &[],
+        single => std::slice::from_ref(single),
+    };
+
+    items.iter().filter_map(|item| {
+        let uri = item.get("uri").or_else(|| item.get("targetUri"))?.as_str()?;
+        let range = item.get("range").or_else(|| item.get("targetSelectionRange"))?;
+        let start = range.get("start")?;
+        Some(Location {
+            path: uri_to_path(uri)?,
+            line: start.get("line")?.as_u64()? as usize,
+            character: start.get("character")?.as_u64()? as usize,
+        })
+    }).collect()
+}
+
+// the text of a hover result, contents can be MarkupContent, a MarkedString or a list of them
+pub fn hover_lines(result: &Json) -> Vec<String> {
+    let Some(contents) = result.get("contents") else { return Vec::new() };
+    let parts = match contents {
+        Json::Array(parts) => parts.as_slice(),
+        single => std::slice::from_ref(single),
+    };
+
+    let mut text = String::new();
+    for part in parts {
+        let value = part.as_str().or_else(|| part.get("value")?.as_str()).unwrap_or("");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(value);
+    }
+
+    strip_markdown(&text)
+}
+
+// the diagnostics of a publishDiagnostics notification as virtual text, the
+// first line of each message on the line the diagnostic starts at
+pub fn diagnostics(params: &Json) -> Option<(PathBuf, Vec<Annotation>)> {
+    let path = uri_to_path(params.get("uri")?.as_str()?)?;
+    let annotations = params.get("diagnostics")?.as_array()?.iter().filter_map(|diagnostic| {
+        let line = diagnostic.get("range")?.get("start")?.get("line")?.as_u64()? as usize;
+        let message = diagnostic.get("message")?.as_str()?.lines().next().unwrap_or("");
+        let kind = match diagnostic.get("severity").and_then(|severity| severity.as_u64()) {
+            Some(2) => AnnotationKind::Warning,
+            Some(3) => AnnotationKind::Info,
+            Some(4) => AnnotationKind::Hint,
+            _ => AnnotationKind::Error,
+        };
+        Some(Annotation { line, text: message.to_owned(), kind, source: "lsp" })
+    }).collect();
+
+    Some((path, annotations))
+}
+
+// good enough for hover docs: drops code fences, heading markers, emphasis and backticks
+pub fn strip_markdown(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            continue
+        }
+        // a horizontal rule becomes an empty line
+        let is_rule = trimmed.starts_with("---") && trimmed.chars().all(|c| c == '-');
+        let line = if is_rule {
+            ""
+        } else if trimmed.starts_with('#') {
+            trimmed.trim_start_matches('#').trim_start()
+        } else {
+            line
+        };
+        let line = line.replace("**", "").replace("__", "").replace('`', "").replace("\\_", "_");
+        // no more than one empty line in a row
+        if line.trim().is_empty() && lines.last().is_some_and(|l| l.is_empty()) {
+            continue
+        }
+        lines.push(line.trim_end().to_owned());
+    }
+
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    while lines.first().is_some_and(|l| l.is_empty()) {
+        lines.remove(0);
+    }
+
+    lines
+}
+
+
+enum Request {
+    Initialize,
+    Completion { buffer: usize, pos: LinePos },
+    Locations { buffer: usize, what: &'static str },
+    Hover { buffer: usize },
+}
+
+pub enum LspEvent {
+    Completion { buffer: usize, pos: LinePos, result: Json },
+    // what is the kind of locations asked for, like "definition"
+    Locations { buffer: usize, what: &'static str, result: Json },
+    Hover { buffer: usize, result: Json },
+    // sent by the server whenever it likes, for a file that may not be open
+    Diagnostics { path: PathBuf, annotations: Vec<Annotation> },
+    // the server answered initialize and is ready
+    Started { command: String },
+}
+
+pub struct LspClient {
+    pub command: String,
+    child: Child,
+    // written to the server's stdin on another thread, a server that stops
+    // reading can't block the editor
+    outgoing: Sender<Json>,
+    messages: Receiver<Json>,
+    next_id: u64,
+    pending: HashMap<u64, Request>,
+    initialized: bool,
+    // messages waiting for the initialize response
+    queued: Vec<Json>,
+}
+
+impl LspClient {
+    pub fn start(command: &str, root: &Path) -> Result<Self, ()> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or(())?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|_| ())?;
+
+        let stdin = child.stdin.take().ok_or(())?;
+        let stdout = child.stdout.take().ok_or(())?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break
+                }
+            }
+        });
+        let (outgoing, to_write) = mpsc::channel();
+        thread::spawn(move || write_messages(stdin, to_write));
+
+        let mut client = Self {
+            command: command.to_owned(),
+            child,
+            outgoing,
+            messages,
+            next_id: 0,
+            pending: HashMap::new(),
+            initialized: false,
+            queued: Vec::new(),
+        };
+
+        let completion_item = Json::object(vec![("snippetSupport", Json::Bool(false))]);
+        let capabilities = Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("completion", Json::object(vec![("completionItem", completion_item)])),
+            ])),
+        ]);
+        let params = Json::object(vec![
+            ("processId", Json::Number(std::process::id() as f64)),
+            ("rootUri", Json::String(path_to_uri(root))),
+            ("capabilities", capabilities),
+        ]);
+        client.request("initialize", params, Request::Initialize);
+
+        Ok(client)
+    }
+
+    fn send(&mut self, message: Json) {
+        let is_initialize = message.get("method").and_then(|m| m.as_str()) == Some("initialize");
+        if !self.initialized && !is_initialize {
+            self.queued.push(message);
+            return
+        }
+
+        // if the server died there is nobody to tell
+        let _ = self.outgoing.send(message);
+    }
+
+    fn request(&mut self, method: &str, params: Json, kind: Request) {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, kind);
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("id", Json::Number(self.next_id as f64)),
+            ("method", Json::string(method)),
+            ("params", params),
+        ]));
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string(method)),
+            ("params", params),
+        ]));
+    }
+
+    // sends the whole text, the version is kept in the buffer's vars
+    pub fn sync_buffer(&mut self, buffer: &mut TextBuffer) {
+        let Some(path) = buffer.file_path.clone() else { return };
+        let uri = Json::String(path_to_uri(&path));
+        let text = Json::String((0..buffer.total_lines()).map(|line| buffer.raw_line(line)).collect());
+
+        if let Some(version) = buffer.vars.get_mut::<i64>("lsp_version") {
+            *version += 1;
+            let document = Json::object(vec![("uri", uri), ("version", Json::Number(*version as f64))]);
+            let changes = Json::Array(vec![Json::object(vec![("text", text)])]);
+            self.notify("textDocument/didChange", Json::object(vec![("textDocument", document), ("contentChanges", changes)]));
+        } else {
+            buffer.vars.set("lsp_version", 1i64);
+            let document = Json::object(vec![
+                ("uri", uri),
+                ("languageId", Json::string(language_id(&path))),
+                ("version", Json::Number(1.0)),
+                ("text", text),
+            ]);
+            self.notify("textDocument/didOpen", Json::object(vec![("textDocument", document)]));
+        }
+    }
+
+    pub fn request_completion(&mut self, buffer: &mut TextBuffer, pos: LinePos) {
+        let Some(path) = buffer.file_path.clone() else { return };
+        self.sync_buffer(buffer);
+        let params = Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::String(path_to_uri(&path)))])),
+            ("position", to_lsp_position(buffer, pos)),
+        ]);
+        self.request("textDocument/completion", params, Request::Completion { buffer: buffer.id, pos });
+    }
+
+    pub fn request_definition(&mut self, buffer: &mut TextBuffer, pos: LinePos) {
+        self.request_locations("textDocument/definition", Vec::new(), buffer, pos, "definition");
+    }
+
+    pub fn request_references(&mut self, buffer: &mut TextBuffer, pos: LinePos) {
+        let context = Json::object(vec![("includeDeclaration", Json::Bool(true))]);
+        self.request_locations("textDocument/references", vec![("context", context)], buffer, pos, "references");
+    }
+
+    pub fn request_hover(&mut self, buffer: &mut TextBuffer, pos: LinePos) {
+        let Some(path) = buffer.file_path.clone() else { return };
+        self.sync_buffer(buffer);
+        let params = Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", Json::String(path_to_uri(&path)))])),
+            ("position", to_lsp_position(buffer, pos)),
+        ]);
+        self.request("textDocument/hover", params, Request::Hover { buffer: buffer.id });
+    }
+
+    fn request_locations(&mut self, method: &str, extra: Vec<(&str, Json)>, buffer: &mut TextBuffer, pos: LinePos, what: &'static str) {
+        let Some(path) = buffer.file_path.clone() else { return };
+        self.sync_buffer(buffer);
+        let mut params = vec![
+            ("textDocument", Json::object(vec![("uri", Json::String(path_to_uri(&path)))])),
+            ("position", to_lsp_position(buffer, pos)),
+        ];
+        params.extend(extra);
+        self.request(method, Json::object(params), Request::Locations { buffer: buffer.id, what });
+    }
+
+    // handles everything the server has sent since last time
+    pub fn poll(&mut self) -> Vec<LspEvent> {
+        let mut events = Vec::new();
+        while let Ok(message) = self.messages.try_recv() {
+            let id = message.get("id").and_then(|id| id.as_u64());
+            if let Some(method) = message.get("method") {
+                if method.as_str() == Some("textDocument/publishDiagnostics") {
+                    if let Some((path, annotations)) = message.get("params").and_then(diagnostics) {
+                        events.push(LspEvent::Diagnostics { path, annotations });
+                    }
+                }
+                // requests from the server, answer so it doesn't wait on us
+                if let Some(id) = message.get("id") {
+                    self.send(Json::object(vec![
+                        ("jsonrpc", Json::string("2.0")),
+                        ("id", id.clone()),
+                        ("result", Json::Null),
+                    ]));
+                }
+                continue
+            }
+
+            let Some(request) = id.and_then(|id| self.pending.remove(&id)) else { continue };
+            let result = message.get("result").cloned().unwrap_or(Json::Null);
+            match request {
+                Request::Initialize => {
+                    self.initialized = true;
+                    self.notify("initialized", Json::object(Vec::new()));
+                    for message in std::mem::take(&mut self.queued) {
+                        self.send(message);
+                    }
+                    events.push(LspEvent::Started { command: self.command.clone() });
+                },
+                Request::Completion { buffer, pos } => events.push(LspEvent::Completion { buffer, pos, result }),
+                Request::Locations { buffer, what } => events.push(LspEvent::Locations { buffer, what, result }),
+                Request::Hover { buffer } => events.push(LspEvent::Hover { buffer, result }),
+            }
+        }
+
+        events
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+// writes what the client sends until it's dropped or the server goes away. each
+// change has the whole text, one followed by another change of the same file
+// isn't written
+fn write_messages(mut writer: impl Write, messages: Receiver<Json>) {
+    while let Ok(first) = messages.recv() {
+        let batch = std::iter::once(first).chain(messages.try_iter()).collect::<Vec<_>>();
+        for (i, message) in batch.iter().enumerate() {
+            if batch.get(i + 1).is_some_and(|next| changed_uri(next).is_some() && changed_uri(next) == changed_uri(message)) {
+                continue
+            }
+            let body = message.to_string();
+            if write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| writer.flush()).is_err() {
+                return
+            }
+        }
+    }
+}
+
+// the file a didChange notification is about
+fn changed_uri(message: &Json) -> Option<&str> {
+    if message.get("method")?.as_str()? != "textDocument/didChange" {
+        return None
+    }
+    message.get("params")?.get("textDocument")?.get("uri")?.as_str()
+}
+
+fn read_message(reader: &mut impl BufRead) -> Option<Json> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; len?];
+    reader.read_exact(&mut body).ok()?;
+    // skip anything we can't parse instead of giving up on the server
+    Some(Json::parse(&String::from_utf8_lossy(&body)).unwrap_or(Json::Null))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_columns() {
+        let line = "a😀ä b";
+        assert_eq!(col_to_utf16(line, 2), 3);
+        assert_eq!(col_to_utf16(line, 5), 6);
+        assert_eq!(col_from_utf16(line, 3), 2);
+        assert_eq!(col_from_utf16(line, 6), 5);
+        assert_eq!(col_from_utf16(line, 100), 5);
+    }
+
+    #[test]
+    fn test_locations() {
+        let result = Json::parse(r#"[
+            {"uri":"file:///src/my%20file.rs","range":{"start":{"line":3,"character":7},"end":{"line":3,"character":9}}},
+            {"targetUri":"file:///src/lib.rs","targetRange":{},"targetSelectionRange":{"start":{"line":0,"character":0},"end":{"line":0,"character":1}}}
+        ]"#).unwrap();
+
+        let found = locations(&result);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], Location { path: PathBuf::from("/src/my file.rs"), line: 3, character: 7 });
+        assert_eq!(found[1].path, PathBuf::from("/src/lib.rs"));
+        assert!(locations(&Json::Null).is_empty());
+    }
+
+    #[test]
+    fn test_completion_items() {
+        let buffer = TextBuffer::from_data(0, b"fn main() {\n    foo.baqux()\n}\n".to_vec());
+        let result = Json::parse(r#"{"items":[
+            {"label":"bar","textEdit":{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":12}},"newText":"bar"},
+             "additionalTextEdits":[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":0}},"newText":"use foo::Bar;\n"}]},
+            {"label":"baz","insertText":"baz()"}
+        ]}"#).unwrap();
+
+        // asked for after "foo.b", the "a" was typed since
+        let items = completion_items(&result, &buffer, LinePos::new(1, 9));
+        assert_eq!(items[0].replace_start, Some(LinePos::new(1, 8)));
+        assert_eq!(items[0].replace_after, 3);
+        assert_eq!(items[0].additional_edits, [TextEdit { start: LinePos::new(0, 0), end: LinePos::new(0, 0), text: "use foo::Bar;\n".into() }]);
+        assert_eq!(items[1].text, "baz()");
+        assert_eq!(items[1].replace_start, None);
+    }
+
+    #[test]
+    fn test_hover_lines() {
+        let result = Json::parse(r#"{"contents":{"kind":"markdown","value":"```rust\nfn foo(x: u32)\n```\n\n---\n\n# Docs\nDoes **things** with `x`\n"}}"#).unwrap();
+        assert_eq!(hover_lines(&result), ["fn foo(x: u32)", "", "Docs", "Does things with x"]);
+        assert!(hover_lines(&Json::Null).is_empty());
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let params = Json::parse(r#"{"uri":"file:///src/main.rs","diagnostics":[
+            {"range":{"start":{"line":4,"character":8},"end":{"line":4,"character":9}},"severity":2,"message":"unused variable: `x`\nhelp: prefix it"},
+            {"range":{"start":{"line":9,"character":0},"end":{"line":9,"character":1}},"message":"expected `;`"}
+        ]}"#).unwrap();
+        let (path, annotations) = diagnostics(&params).unwrap();
+        assert_eq!(path, PathBuf::from("/src/main.rs"));
+        assert_eq!(annotations[0], Annotation { line: 4, text: "unused variable: `x`".into(), kind: AnnotationKind::Warning, source: "lsp" });
+        assert_eq!(annotations[1].kind, AnnotationKind::Error);
+    }
+
+    #[test]
+    fn test_write_messages() {
+        let change = |uri: &str, text: &str| Json::object(vec![
+            ("method", Json::string("textDocument/didChange")),
+            ("params", Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::string(uri))])),
+                ("contentChanges", Json::Array(vec![Json::object(vec![("text", Json::string(text))])])),
+            ])),
+        ]);
+        let (sender, messages) = mpsc::channel();
+        for message in [change("a", "1"), change("a", "2"), change("b", "1"), change("a", "3"), Json::object(vec![("id", Json::Number(1.0))]), change("a", "4")] {
+            sender.send(message).unwrap();
+        }
+        drop(sender);
+
+        let mut written = Vec::new();
+        write_messages(&mut written, messages);
+        let mut reader = written.as_slice();
+        let text = |message: &Json| {
+            let changes = message.get("params").and_then(|params| params.get("contentChanges")).and_then(Json::as_array);
+            changes.and_then(|changes| changes.first()?.get("text")?.as_str()).unwrap_or_default().to_owned()
+        };
+        let texts = std::iter::from_fn(|| read_message(&mut reader)).map(|message| text(&message)).collect::<Vec<_>>();
+        // the request in between keeps the change before it
+        assert_eq!(texts, ["2", "1", "3", "", "4"]);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let message = read_message(&mut input.as_bytes()).unwrap();
+        assert_eq!(message.get("id").and_then(|id| id.as_u64()), Some(1));
+    }
+}
@@ -1,19 +1,17 @@
-pub mod renderer;
-pub mod shader;
-pub mod font;
-pub mod editor;
-pub mod gap_buffer;
-pub mod vim_commands;
-pub mod search;
-pub mod indent;
-pub mod command_bar;
-
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-use editor::{Editor, EditorMode};
-use font::CharacterCache;
-use gap_buffer::LinePos;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use moded::command_bar::parse_range;
+use moded::diff::DiffState;
+use moded::editor::{Editor, EditorMode};
+use moded::gap_buffer::{LinePos, TextBuffer};
+use moded::layout::Rect;
+use moded::vim_commands::matching_bracket;
+use moded::options::{config_dir, DEFAULT_FONT_SIZE, MAX_FONT_SIZE, MIN_FONT_SIZE};
+use moded::font::CharacterCache;
+use moded::{fold, startup, width, wrap, Modifiers, MouseEvent, SpecialKey, State, SHOULD_QUIT};
 use glfw::{self};
 use glfw::Context;
 use gl::{self};
@@ -21,11 +19,25 @@ use gl::{self};
 use ab_glyph::{self, Font, ScaleFont};
 
 use nalgebra::*;
-use renderer::{highlight_line, DrawLine, DrawRect, RectRenderer, TextRenderer};
-use shader::{RectShader, TextShader};
+use moded::notification::Level;
+use moded::popup::{self, Popup};
+use moded::profile::Profiler;
+use moded::log;
+use moded::renderer::{draw_popups, highlight_chars, highlight_line, DrawLine, DrawRect, RectRenderer, TextRenderer};
+use moded::shader::{uniform_location, RectShader, TextShader};
 
 
-pub static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
+// the window can't be resized smaller than this many cells
+const MIN_COLS: f32 = 20.0;
+const MIN_ROWS: f32 = 5.0;
+
+// how much ctrl + and ctrl - change the font size
+const FONT_STEP: f32 = 2.0;
+const FONT: &[u8] = include_bytes!("../fonts/JetBrainsMono-Regular.ttf");
+
+// language servers and big files being read change things without any input,
+// so they're checked this often
+const LSP_POLL: Duration = Duration::from_millis(50);
 
 
 const TEXT_VERTEX_SHADER_SOURCE: &str = "#version 330 core
@@ -46,11 +58,13 @@ out vec4 color;
 
 uniform sampler2D text;
 uniform vec3 textColor;
+// below 1 in the windows that aren't focused
+uniform float brightness;
 
 void main()
 {    
     vec4 sampled = vec4(1.0, 1.0, 1.0, texture(text, TexCoords).r);
-    color = vec4(textColor, 1.0) * sampled;
+    color = vec4(textColor * brightness, 1.0) * sampled;
 }";
 
 const RECT_VERTEX_SHADER_SOURCE: &str ="#version 330 core
@@ -72,311 +86,778 @@ void main()
     color = vec4(rectColor, 1.0);
 }";
 
+// used when the shaders above don't compile, without colors
+const FALLBACK_TEXT_FRAGMENT_SHADER_SOURCE: &str = "#version 330 core
+in vec2 TexCoords;
+out vec4 color;
 
+uniform sampler2D text;
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum SpecialKey {
-    Backspace,
-    Enter,
-    Escape,
-    Control,
-    Tab,
-}
+void main()
+{
+    color = vec4(1.0, 1.0, 1.0, texture(text, TexCoords).r);
+}";
 
-#[derive(Debug)]
-pub struct Io {
-    pub chars: String,
-    pub special_keys: Vec<SpecialKey>,
-    pub modifiers: glfw::Modifiers,
-}
+const FALLBACK_RECT_FRAGMENT_SHADER_SOURCE: &str = "#version 330 core
+out vec4 color;
 
-impl Io {
-    pub fn pressed_char(&self, wanted: char) -> bool {
-        self.chars.contains(wanted)
-    }
+void main()
+{
+    color = vec4(0.4, 0.4, 0.4, 1.0);
+}";
 
-    pub fn pressed_special(&self, wanted: SpecialKey) -> bool {
-        for item in &self.special_keys {
-            if *item == wanted {
-                return true
-            }
-        }
 
-        false
-    }
 
-    pub fn pressed_char_and_special(&self, c: char, s: SpecialKey) -> bool {
-        self.pressed_char(c) && self.pressed_special(s)
-    }
+// glyphs at the font size option, sets the cell size. returns the font's line height and ascent too
+fn load_font(state: &mut State) -> (CharacterCache, f32, f32) {
+    state.char_scale = state.font_pixels();
+    let char_cache = CharacterCache::from_font_bytes(state, FONT);
+    state.char_width = char_cache.get('W').unwrap().width;
+    state.char_height = char_cache.get(' ').unwrap().height;
 
-    pub fn pressed_char_with_modifiers(&self, wanted: char, modifiers: glfw::Modifiers) -> bool {
-        self.chars.contains(wanted) && self.modifiers.contains(modifiers)
-    }
+    let font = ab_glyph::FontRef::try_from_slice(FONT).unwrap();
+    let scaled = font.as_scaled(state.char_scale);
 
-    pub fn pressed_special_with_modifiers(&self, wanted: SpecialKey, modifiers: glfw::Modifiers) -> bool {
-        for item in &self.special_keys {
-            if *item == wanted && self.modifiers.contains(modifiers) {
-                return true
-            }
-        }
+    (char_cache, scaled.height(), scaled.ascent())
+}
 
-        false
-    }
+// limits are in window coordinates, chars are measured in framebuffer pixels
+fn set_size_limits(state: &State, window: &mut glfw::Window) {
+    let (scale_x, scale_y) = framebuffer_scale(window);
+    let (min_width, min_height) = (state.char_width * MIN_COLS / scale_x, state.char_height * MIN_ROWS / scale_y);
+    window.set_size_limits(Some(min_width.ceil() as u32), Some(min_height.ceil() as u32), None, None);
+}
 
-    pub fn reset(&mut self) {
-        self.chars.clear();
-        self.special_keys.clear();
-    }
+// framebuffer pixels per window coordinate, more than 1 where the system scales
+// windows itself like on macos
+fn framebuffer_scale(window: &glfw::Window) -> (f32, f32) {
+    let (framebuffer_width, framebuffer_height) = window.get_framebuffer_size();
+    let (width, height) = window.get_size();
+    (framebuffer_width as f32 / width.max(1) as f32, framebuffer_height as f32 / height.max(1) as f32)
 }
 
+// x, y, width and height of the window when it's not fullscreen
+type Geometry = (i32, i32, i32, i32);
 
-#[derive(Debug)]
-pub struct CursorPos {
-    pub x: usize,
-    pub y: usize,
-    pub wanted_x: usize,
-    pub buffer: usize,
+// kept in the config folder so the window opens where it was closed
+fn load_geometry() -> Option<Geometry> {
+    let text = fs::read_to_string(config_dir()?.join("window")).ok()?;
+    let mut numbers = text.split_whitespace().map(|n| n.parse::<i32>().ok());
+    let geometry = (numbers.next()??, numbers.next()??, numbers.next()??, numbers.next()??);
+
+    (geometry.2 > 0 && geometry.3 > 0).then_some(geometry)
 }
 
-impl CursorPos {
-    pub fn new(buffer: usize) -> Self {
-        Self { x: 1, y: 1, wanted_x: 1, buffer }
+fn save_geometry((x, y, width, height): Geometry) {
+    let Some(dir) = config_dir() else { return };
+    if fs::create_dir_all(&dir).is_ok() {
+        let _ = fs::write(dir.join("window"), format!("{x} {y} {width} {height}\n"));
     }
+}
 
-    pub fn to_screen_position(&self, state: &State, start_line: usize) -> (f32, f32) {
-        // xpos, ypos
-        let xpos = (self.x - 1) as f32 * state.char_width;
-        let ypos = state.height as f32 - ((self.y - start_line) as f32 * state.char_height);
-        
-        (xpos, ypos)
-    }
+fn window_geometry(window: &glfw::Window) -> Geometry {
+    let (x, y) = window.get_pos();
+    let (width, height) = window.get_size();
+    (x, y, width, height)
+}
 
-    pub fn to_linepos(&self) -> LinePos {
-        LinePos { line: self.y - 1, col: self.x - 1 }
-    }
-    
-    pub fn to_line_col(&self) -> (usize, usize) {
-        (self.y - 1, self.x - 1)
+// windowed is where the window was before going fullscreen, it goes back there
+fn toggle_fullscreen(glfw: &mut glfw::Glfw, window: &mut glfw::Window, windowed: &mut Option<Geometry>) {
+    if let Some((x, y, width, height)) = windowed.take() {
+        window.set_monitor(glfw::WindowMode::Windowed, x, y, width as u32, height as u32, None);
+        return
     }
 
-    pub fn from_linepos(&mut self, pos: LinePos) {
-        self.x = pos.col + 1;
-        self.y = pos.line + 1;
-        self.wanted_x = self.x;
-    }
+    glfw.with_primary_monitor(|_, monitor| {
+        let Some(monitor) = monitor else { return };
+        let Some(mode) = monitor.get_video_mode() else { return };
+        *windowed = Some(window_geometry(window));
+        window.set_monitor(glfw::WindowMode::FullScreen(monitor), 0, 0, mode.width, mode.height, Some(mode.refresh_rate));
+    });
 }
 
-pub struct State {
-    pub width: i32,
-    pub height: i32,
-    pub window_changed_size: bool,
-    pub io: Io,
-    pub char_scale: f32,
-    pub char_width: f32,
-    pub char_height: f32,
-    pub cmd_bar_cursor_x: usize,
-    pub start_line: usize,
+fn modifiers_from_glfw(modifiers: glfw::Modifiers) -> Modifiers {
+    let mut out = Modifiers::empty();
+    for (from, to) in [(glfw::Modifiers::Shift, Modifiers::SHIFT), (glfw::Modifiers::Control, Modifiers::CONTROL), (glfw::Modifiers::Alt, Modifiers::ALT)] {
+        if modifiers.contains(from) {
+            out |= to;
+        }
+    }
+
+    out
 }
 
-impl State {
-    pub fn max_rows(&self) -> usize {
-        (self.height as f32 / self.char_height).floor() as usize
+// glfw names keys by where they are, after what they type on a US layout
+fn us_char(key: glfw::Key, shift: bool) -> Option<char> {
+    use glfw::Key;
+    let code = key as i32;
+    if (Key::A as i32..=Key::Z as i32).contains(&code) {
+        let c = (b'a' + (code - Key::A as i32) as u8) as char;
+        return Some(if shift { c.to_ascii_uppercase() } else { c })
     }
-
-    pub fn max_cols(&self) -> usize {
-        (self.width as f32 / self.char_width) as usize
+    if (Key::Num0 as i32..=Key::Num9 as i32).contains(&code) {
+        let i = (code - Key::Num0 as i32) as usize;
+        return if shift { ")!@#$%^&*(".chars().nth(i) } else { char::from_digit(i as u32, 10) }
     }
-}
 
+    let (plain, shifted) = match key {
+        Key::Space => (' ', ' '),
+        Key::Minus => ('-', '_'),
+        Key::Equal => ('=', '+'),
+        Key::LeftBracket => ('[', '{'),
+        Key::RightBracket => (']', '}'),
+        Key::Backslash => ('\\', '|'),
+        Key::Semicolon => (';', ':'),
+        Key::Apostrophe => ('\'', '"'),
+        Key::GraveAccent => ('`', '~'),
+        Key::Comma => (',', '<'),
+        Key::Period => ('.', '>'),
+        Key::Slash => ('/', '?'),
+        _ => return None,
+    };
+    Some(if shift { shifted } else { plain })
+}
 
-fn process_event(state: &mut State, _window: &mut glfw::Window, event: glfw::WindowEvent) {
+fn process_event(state: &mut State, window: &mut glfw::Window, event: glfw::WindowEvent) {
     match event {
         glfw::WindowEvent::Key(key, _scancode, glfw::Action::Press | glfw::Action::Repeat, modifiers) => {
+            if !modifiers.contains(glfw::Modifiers::Control) {
+                if let Some(c) = us_char(key, modifiers.contains(glfw::Modifiers::Shift)) {
+                    state.io.physical_chars.push(c);
+                }
+            }
             match key {
                 glfw::Key::Backspace => state.io.special_keys.push(SpecialKey::Backspace),
                 glfw::Key::Enter => state.io.special_keys.push(SpecialKey::Enter),
                 glfw::Key::Tab => state.io.special_keys.push(SpecialKey::Tab),
                 glfw::Key::Escape => state.io.special_keys.push(SpecialKey::Escape),
+                glfw::Key::Left => state.io.special_keys.push(SpecialKey::Left),
+                glfw::Key::Right => state.io.special_keys.push(SpecialKey::Right),
+                glfw::Key::Home => state.io.special_keys.push(SpecialKey::Home),
                 glfw::Key::LeftControl | glfw::Key::RightControl => state.io.special_keys.push(SpecialKey::Control),
                 // dumb glfw doesn't report ctrl + char in charmods polling
                 key if key as i32 >= glfw::Key::A as i32 && key as i32 <= glfw::Key::Z as i32 => {
                     if modifiers.contains(glfw::Modifiers::Control) {
-                        state.io.chars.push((b'a' + (key as i32 - glfw::Key::A as i32) as u8) as char);
+                        let c = (b'a' + (key as i32 - glfw::Key::A as i32) as u8) as char;
+                        state.io.chars.push(c);
+                        state.io.physical_chars.push(c);
                         state.io.special_keys.push(SpecialKey::Control);
                     }
                 }
+                // ctrl with + - 0 sizes the font, glfw sends no chars for these
+                glfw::Key::Equal | glfw::Key::KpAdd if modifiers.contains(glfw::Modifiers::Control) => {
+                    state.options.font_size = (state.options.font_size + FONT_STEP).min(MAX_FONT_SIZE);
+                },
+                glfw::Key::Minus | glfw::Key::KpSubtract if modifiers.contains(glfw::Modifiers::Control) => {
+                    state.options.font_size = (state.options.font_size - FONT_STEP).max(MIN_FONT_SIZE);
+                },
+                glfw::Key::Num0 | glfw::Key::Kp0 if modifiers.contains(glfw::Modifiers::Control) => {
+                    state.options.font_size = DEFAULT_FONT_SIZE;
+                },
+                glfw::Key::RightBracket if modifiers.contains(glfw::Modifiers::Control) => {
+                    state.io.chars.push(']');
+                    state.io.physical_chars.push(']');
+                    state.io.special_keys.push(SpecialKey::Control);
+                }
                 _ => {},
             }
-            state.io.modifiers |= modifiers;
+            state.io.modifiers |= modifiers_from_glfw(modifiers);
         },
         glfw::WindowEvent::Char(c) => {
             state.io.chars.push(c);
         },
+        glfw::WindowEvent::CursorPos(x, y) => {
+            // the pointer is in window coordinates, everything else in pixels
+            let (scale_x, scale_y) = framebuffer_scale(window);
+            let (x, y) = (x as f32 * scale_x, y as f32 * scale_y);
+            state.io.mouse_pos = (x, y);
+            if window.get_mouse_button(glfw::MouseButton::Button1) == glfw::Action::Press {
+                state.io.mouse.push(MouseEvent::Drag(x, y));
+            }
+        },
+        glfw::WindowEvent::MouseButton(glfw::MouseButton::Button1, glfw::Action::Press, _) => {
+            let (x, y) = state.io.mouse_pos;
+            state.io.mouse.push(MouseEvent::Press(x, y));
+        },
+        glfw::WindowEvent::MouseButton(glfw::MouseButton::Button1, glfw::Action::Release, _) => {
+            state.io.mouse.push(MouseEvent::Release);
+        },
         glfw::WindowEvent::FramebufferSize(w, h) => {
             state.width = w;
             state.height = h;
             state.window_changed_size = true;
             unsafe { gl::Viewport(0, 0, w, h) };
         },
+        // moved to a monitor with another scale, the font is reloaded for it
+        glfw::WindowEvent::ContentScale(scale, _) => state.content_scale = scale,
         _ => {},
     }
 }
 
-//static mut WIDTH: u32 = 1280 * 2;
-//static mut HEIGHT: u32 = 720 * 2;
-
-fn main() {
-    let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
-    glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
-    glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
-    let (screen_width, screen_height) =
-    unsafe {
-        let vid_mode = glfw::ffi::glfwGetVideoMode(glfw::ffi::glfwGetPrimaryMonitor());
-        ((*vid_mode).width as u32, (*vid_mode).height as u32)
-    };
-
-    let (mut window, events) = glfw.create_window(screen_width / 2, screen_height / 2, "moded", glfw::WindowMode::Windowed).unwrap();
-    window.make_current();
-    window.set_key_polling(true);
-    window.set_char_polling(true);
-    //window.set_char_mods_polling(true);
-    window.set_framebuffer_size_polling(true);
-    glfw.set_swap_interval(glfw::SwapInterval::None);
-    //window.set_framebuffer_size_callback(frame_buffer_size_callback);
+// a window's part of the framebuffer in pixels, x and y from the bottom left like
+// gl. the windows at the right and bottom edges get what's past the last cell too
+type Viewport = (i32, i32, i32, i32);
 
-    gl::load_with(|ptr| window.get_proc_address(ptr) as *const _);
+fn window_pixels(state: &State, (rows, cols): (usize, usize), screen: (i32, i32), rect: Rect) -> Viewport {
+    let left = (rect.col as f32 * state.char_width) as i32;
+    let top = (rect.row as f32 * state.char_height) as i32;
+    let right = if rect.col + rect.cols >= cols { screen.0 } else { ((rect.col + rect.cols) as f32 * state.char_width) as i32 };
+    let bottom = if rect.row + rect.rows >= rows { screen.1 } else { ((rect.row + rect.rows) as f32 * state.char_height) as i32 };
+    (left, screen.1 - bottom, right - left, bottom - top)
+}
 
-    unsafe {
-        gl::Enable(gl::CULL_FACE);
-        gl::Enable(gl::BLEND);
-        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+// state has to have the size of the whole window
+fn focused_view(editor: &Editor, state: &State, screen: (i32, i32)) -> Viewport {
+    match editor.layout.rect(editor.layout.focused) {
+        Some(rect) => window_pixels(state, editor.layout.size, screen, rect),
+        None => (0, 0, screen.0, screen.1),
     }
+}
 
-    let text_shader = TextShader::new(TEXT_VERTEX_SHADER_SOURCE, TEXT_FRAGMENT_SHADER_SOURCE).unwrap();
-    let rect_shader = RectShader::new(RECT_VERTEX_SHADER_SOURCE, RECT_FRAGMENT_SHADER_SOURCE).unwrap();
+// draws into the viewport from here on, state has its size meanwhile
+fn set_view(state: &mut State, text_renderer: &mut TextRenderer, (x, y, width, height): Viewport) {
+    state.width = width;
+    state.height = height;
+    unsafe { gl::Viewport(x, y, width, height) };
+    let projection = Matrix4::new_orthographic(0.0f32, width as f32, 0.0, height as f32, -1.0, 1.0);
+    text_renderer.shader.use_program();
+    if let Some(location) = uniform_location(text_renderer.shader.id, c"projection") {
+        unsafe { gl::UniformMatrix4fv(location, 1, gl::FALSE, projection.as_ptr()) };
+    }
+    text_renderer.view = (x, y);
+}
 
-    let mut state = State { width: screen_width as i32 / 2, height: screen_height as i32 / 2, window_changed_size: true, char_scale: 35.0, char_width: 0.0, char_height: 0.0, io: Io { chars: String::new(), special_keys: Vec::new(), modifiers: glfw::Modifiers::empty() }, cmd_bar_cursor_x: 0, start_line: 0 };
+// a click in another window focuses it, then clicks are moved to be from the
+// focused window's top left corner like the editor takes them
+fn focus_clicked(editor: &mut Editor, state: &mut State) {
+    let press = state.io.mouse.iter().find_map(|event| match event {
+        MouseEvent::Press(x, y) => Some((*x, *y)),
+        _ => None,
+    });
+    if let Some((x, y)) = press.filter(|_| !matches!(editor.mode, EditorMode::CommandBar | EditorMode::Search)) {
+        if let Some(id) = editor.layout.window_at((y / state.char_height) as usize, (x / state.char_width) as usize) {
+            editor.focus_window(state, id);
+        }
+    }
 
-    let char_cache = CharacterCache::from_font_bytes(&state, include_bytes!("../fonts/JetBrainsMono-Regular.ttf"));
-    state.char_width = char_cache.get('W').unwrap().width;
-    state.char_height = char_cache.get(' ').unwrap().height;
+    let Some(rect) = editor.layout.rect(editor.layout.focused) else { return };
+    let (left, top) = (rect.col as f32 * state.char_width, rect.row as f32 * state.char_height);
+    for event in &mut state.io.mouse {
+        if let MouseEvent::Press(x, y) | MouseEvent::Drag(x, y) = event {
+            *x -= left;
+            *y -= top;
+        }
+    }
+}
 
-    let (font_ascent, _font_descent, font_height) = {
-        let font = ab_glyph::FontRef::try_from_slice(include_bytes!("../fonts/JetBrainsMono-Regular.ttf")).unwrap();
-        (font.as_scaled(state.char_scale).ascent(), font.as_scaled(state.char_scale).descent(), font.as_scaled(state.char_scale).height())
+// start_line moved so the cursor's line is on screen
+fn scroll_to_cursor(state: &mut State, buffer: &TextBuffer, cursor: LinePos) {
+    let y = cursor.line + 1;
+    state.start_line = if y > state.start_line && y - state.start_line > state.max_rows() {
+        y - state.max_rows()
+    } else if y <= state.start_line {
+        y - 1
+    } else {
+        state.start_line
     };
+    if state.options.wrap {
+        wrap::scroll_to(buffer, state, cursor);
+    }
+}
 
-    let mut text_renderer = TextRenderer::new(text_shader, char_cache, font_height, font_ascent);
-    let rect_renderer = RectRenderer::new(rect_shader);
+// the text of the rows on screen
+fn draw_rows(state: &State, buffer: &TextBuffer, rows: &[wrap::Row], text_renderer: &mut TextRenderer, rect_renderer: &RectRenderer) {
+    // the text of each row, the lines hidden in closed folds aren't looked at
+    let mut texts = Vec::with_capacity(rows.len());
+    for run in rows.chunk_by(|a, b| a.fold.is_none() && b.fold.is_none()) {
+        let (first, last) = (run[0].line, run[run.len() - 1].line);
+        if run[0].fold.is_some() {
+            texts.push(String::new());
+            continue
+        }
+        let lines = buffer.lines_in(first..(last + 1)).map(|view| view.to_string()).collect::<Vec<_>>();
+        texts.extend(run.iter().map(|row| row.text(&lines[row.line - first])));
+    }
+    for (i, (row, line)) in rows.iter().zip(texts).enumerate() {
+        // a closed fold is drawn as its summary on a line of its own color
+        if let Some(fold) = row.fold {
+            let mut rect = highlight_line(state, 0, state.max_cols(), state.start_line + i);
+            rect.color = (0.25, 0.25, 0.3);
+            rect_renderer.draw_rect(state, rect);
+            let summary = fold::summary(buffer, fold);
+            let draw_line = DrawLine::new(&summary, i + 1, (0.7, 0.7, 0.75));
+            text_renderer.draw_line(state, draw_line);
+            continue
+        }
+        let draw_line = DrawLine::new(&line, i + 1, (1.0, 1.0, 1.0));
+        text_renderer.draw_line(state, draw_line);
+        if let Some(markers) = state.options.list.then(|| width::list_markers(&line, state.options.tabstop)).flatten() {
+            let draw_line = DrawLine::new(&markers, i + 1, (0.4, 0.4, 0.45));
+            text_renderer.draw_line(state, draw_line);
+        }
+        // virtual text goes two cells after the end of a line's last row, cut off at the window's edge
+        let last_row = rows.get(i + 1).is_none_or(|next| next.line != row.line);
+        if let Some((text, kind)) = buffer.virtual_text.line_text(row.line).filter(|_| last_row) {
+            let col = width::display_col(&line, line.chars().count(), state.options.tabstop) + 2;
+            let text = popup::clip(&text, state.max_cols().saturating_sub(col));
+            if !text.is_empty() {
+                text_renderer.draw_line(state, DrawLine::new(&text, i + 1, kind.color()).at_col(col));
+            }
+        }
+    }
+}
 
-    println!("font_height: {font_height}");
-    let mut editor = if let Some(arg) = std::env::args().skip(1).next() {
-        let p = Path::new(&arg);
-        Editor::from_path(&p)
-    } else {
-        Editor::from_path(Path::new(&"./Cargo.toml"))
-    };
+// added lines are green, changed ones blue and a red mark shows where lines
+// were removed. in the other buffer of the diff the same hunks are the other
+// way around
+fn draw_diff(state: &State, diff: &DiffState, buffer: &TextBuffer, rows: &[wrap::Row], rect_renderer: &RectRenderer) {
+    let Some(side) = diff.side(buffer.id) else { return };
+    let last_shown = state.start_line + state.max_rows();
+    for hunk in &diff.hunks {
+        let (lines, other) = hunk.sides(side);
+        if lines.is_empty() {
+            let line = lines.start.min(buffer.total_lines() - 1);
+            if let Some(row) = wrap::rows_of(rows, line).next() {
+                let mut rect = highlight_line(state, 0, 0, state.start_line + row);
+                rect.color = (0.5, 0.15, 0.15);
+                rect_renderer.draw_rect(state, rect);
+            }
+            continue
+        }
 
-    while !window.should_close() && !SHOULD_QUIT.load(Ordering::Relaxed) {
-        glfw.poll_events();
-        for (_, event) in glfw::flush_messages(&events) {
-            process_event(&mut state, &mut window, event);
+        let color = if other.is_empty() { (0.15, 0.35, 0.15) } else { (0.15, 0.25, 0.4) };
+        for line in lines.start.max(state.start_line)..lines.end.min(last_shown + 1) {
+            for row in wrap::rows_of(rows, line) {
+                let mut rect = highlight_line(state, 0, state.max_cols(), state.start_line + row);
+                rect.color = color;
+                rect_renderer.draw_rect(state, rect);
+            }
         }
+    }
+}
 
-        unsafe { 
-            gl::ClearColor(0.16, 0.16, 0.16, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+// the windows that aren't focused, only their text, dimmed by
+// inactivebrightness. they're scrolled to their cursors too, which may be off
+// screen after the window was resized
+fn draw_other_windows(editor: &mut Editor, state: &mut State, text_renderer: &mut TextRenderer, rect_renderer: &RectRenderer, screen: (i32, i32), background: f32) {
+    let start_line = state.start_line;
+    let brightness = state.options.inactive_brightness;
+    text_renderer.set_brightness(brightness);
+    for (id, rect) in editor.layout.windows() {
+        let viewport = window_pixels(state, editor.layout.size, screen, rect);
+        let Some(view) = editor.layout.view_mut(id) else { continue };
+        let Some(buffer) = editor.buffers.get(view.buffer) else { continue };
+        set_view(state, text_renderer, viewport);
+        if brightness < 1.0 {
+            let dim = background * brightness;
+            rect_renderer.draw_rect(state, DrawRect::from_pixels(state, 0.0, 0.0, state.width as f32, state.height as f32, (dim, dim, dim)));
+        }
+        state.start_line = view.start_line.min(buffer.total_lines() - 1);
+        scroll_to_cursor(state, buffer, buffer.clamp_pos(view.cursor));
+        view.start_line = state.start_line;
+        let rows = wrap::screen_rows(buffer, state);
+        if let Some(diff) = &editor.diff {
+            draw_diff(state, diff, buffer, &rows, rect_renderer);
         }
+        draw_rows(state, buffer, &rows, text_renderer, rect_renderer);
+    }
+    state.start_line = start_line;
+    text_renderer.set_brightness(1.0);
+
+    set_view(state, text_renderer, (0, 0, screen.0, screen.1));
+    for rect in editor.layout.separators() {
+        let (x, y) = (rect.col as f32 * state.char_width, rect.row as f32 * state.char_height);
+        let (width, height) = (rect.cols as f32 * state.char_width, rect.rows as f32 * state.char_height);
+        rect_renderer.draw_rect(state, DrawRect::from_pixels(state, x, y, width, height, (0.25, 0.25, 0.3)));
+    }
+}
 
-        if state.window_changed_size {
-            let projection = Matrix4::new_orthographic(0.0f32, state.width as f32, 0.0, state.height as f32, -1.0, 1.0);
-            text_renderer.shader.use_program();
-            unsafe { 
-                gl::UniformMatrix4fv(gl::GetUniformLocation(text_renderer.shader.id, c"projection".as_ptr().cast()), 1, gl::FALSE, projection.as_ptr()) 
+// the main loop, until the window is closed or :q
+#[allow(clippy::too_many_arguments)]
+fn run(
+    glfw: &mut glfw::Glfw,
+    window: &mut glfw::Window,
+    events: &glfw::GlfwReceiver<(f64, glfw::WindowEvent)>,
+    state: &mut State,
+    editor: &mut Editor,
+    text_renderer: &mut TextRenderer,
+    rect_renderer: &mut RectRenderer,
+    windowed: &mut Option<Geometry>,
+) {
+    let mut last_gl_error = gl::NO_ERROR;
+
+    let mut vsync = false;
+    let mut title = String::new();
+    let mut last_frame = Instant::now();
+    // the cursor blinks from the last key press, so it stays on while typing
+    let mut last_input = Instant::now();
+    let mut needs_redraw = false;
+    let mut profiler = Profiler::new(Instant::now());
+    // the first frame is drawn right away
+    state.scheduler.redraw_at(Instant::now());
+    while !window.should_close() && !SHOULD_QUIT.load(Ordering::Relaxed) {
+        // sleep until there's input or something asked for a redraw
+        let mut timeout = state.scheduler.timeout(Instant::now());
+        if !editor.lsp_clients.is_empty() || editor.loading() || editor.search_job.is_some() {
+            timeout = Some(timeout.map_or(LSP_POLL, |timeout| timeout.min(LSP_POLL)));
+        }
+        match timeout {
+            Some(timeout) => glfw.wait_events_timeout(timeout.as_secs_f64()),
+            None => glfw.wait_events(),
+        }
+        profiler.begin_frame(Instant::now());
+        let due = state.scheduler.take_due(Instant::now());
+        for (_, event) in glfw::flush_messages(events) {
+            // going fullscreen needs the monitor from glfw
+            if let glfw::WindowEvent::Key(glfw::Key::F11, _, glfw::Action::Press, _) = event {
+                toggle_fullscreen(glfw, window, windowed);
+                continue
+            }
+            if let glfw::WindowEvent::Key(glfw::Key::F12, _, glfw::Action::Press, _) = event {
+                state.options.profile = !state.options.profile;
+                needs_redraw = true;
+                continue
             }
-            state.window_changed_size = false;
+            process_event(state, window, event);
         }
+        profiler.mark("events", Instant::now());
+        let lsp_events = editor.poll_lsp();
+        let loaded = editor.poll_loading();
+        let searched = editor.poll_search();
+        profiler.mark("poll", Instant::now());
 
         if state.io.pressed_char_and_special('q', SpecialKey::Control) {
             window.set_should_close(true);
         }
 
         if state.io.pressed_char_and_special('s', SpecialKey::Control) {
-            editor.save_to_file();
+            editor.save_to_file(state);
+        }
+
+        let had_input = state.io.has_input();
+        if had_input {
+            last_input = Instant::now();
+        }
+        // the editor works in the focused window, with its size and clicks from its corner
+        let screen = (state.width, state.height);
+        editor.layout.size = (state.max_rows(), state.max_cols());
+        focus_clicked(editor, state);
+        set_view(state, text_renderer, focused_view(editor, state, screen));
+        editor.enter_dir();
+        editor.handle_input(state);
+        set_view(state, text_renderer, (0, 0, screen.0, screen.1));
+        profiler.mark("input", Instant::now());
+
+        // :set fontsize or ctrl + - 0
+        let font_changed = state.font_pixels() != state.char_scale;
+        if font_changed {
+            let (char_cache, font_height, font_ascent) = load_font(state);
+            text_renderer.char_cache = char_cache;
+            text_renderer.font_height = font_height;
+            text_renderer.font_ascent = font_ascent;
+            set_size_limits(state, window);
+        }
+
+        let new_title = editor.title(&state.options.titlestring);
+        if new_title != title {
+            window.set_title(&new_title);
+            title = new_title;
+        }
+
+        if state.options.vsync != vsync {
+            vsync = state.options.vsync;
+            glfw.set_swap_interval(if vsync { glfw::SwapInterval::Sync(1) } else { glfw::SwapInterval::None });
+        }
+
+        // the last frame stays up if nothing changed, like when the pointer only moved
+        needs_redraw |= due || lsp_events || loaded || searched || font_changed || state.window_changed_size || had_input;
+        if !needs_redraw {
+            state.io.reset();
+            continue
+        }
+        // with maxfps set a frame that comes too soon waits for its turn
+        if state.options.max_fps > 0 {
+            let next_frame = last_frame + Duration::from_secs(1) / state.options.max_fps;
+            if Instant::now() < next_frame {
+                state.scheduler.redraw_at(next_frame);
+                state.io.reset();
+                continue
+            }
+        }
+        needs_redraw = false;
+        last_frame = Instant::now();
+
+        // the projection is set for each window it draws, see set_view
+        state.window_changed_size = false;
+
+        let flashing = editor.flash_until.is_some_and(|until| Instant::now() < until);
+        let background = if flashing { 0.4 } else { 0.16 };
+        unsafe { 
+            gl::ClearColor(background, background, background, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
-        editor.handle_input(&mut state);
+        // the input or a new font may have changed the layout
+        editor.layout.size = (state.max_rows(), state.max_cols());
+        if editor.layout.is_split() {
+            draw_other_windows(editor, state, text_renderer, rect_renderer, screen, background);
+        }
+        let focused = focused_view(editor, state, screen);
 
         let Some(buffer) = editor.buffers.get(editor.current_buffer) else { continue };
         let Some(current_cursor) = editor.cursors.get(editor.current_buffer) else { continue };
 
-        state.start_line = if current_cursor.y > state.start_line && current_cursor.y - state.start_line > state.max_rows() {
-            current_cursor.y - state.max_rows()
-        } else if current_cursor.y <= state.start_line {
-            //start_line - 1
-            current_cursor.y - 1
-        } else {
-            state.start_line
-        };
+        set_view(state, text_renderer, focused);
+        scroll_to_cursor(state, buffer, current_cursor.to_linepos());
+        let rows = wrap::screen_rows(buffer, state);
+
+        if let Some(diff) = &editor.diff {
+            draw_diff(state, diff, buffer, &rows, rect_renderer);
+        }
+
+        // guide lines at the left edge of each colorcolumn
+        for &column in &state.options.color_column {
+            let x = (column - 1) as f32 * state.char_width;
+            let rect = DrawRect::from_pixels(state, x, 0.0, (state.char_width / 8.0).max(1.0), state.height as f32, (0.3, 0.3, 0.33));
+            rect_renderer.draw_rect(state, rect);
+        }
+
+        // the bracket under or just before the cursor and its match, when it's on screen
+        let cursor = current_cursor.to_linepos();
+        let before = cursor.col.checked_sub(1).map(|col| LinePos::new(cursor.line, col));
+        let brackets = [Some(cursor), before].into_iter().flatten()
+            .find_map(|pos| Some((pos, matching_bracket(pos, buffer, state.max_rows())?)))
+            .filter(|(_, other)| wrap::row_of(&rows, *other).is_some());
+        if let Some((pos, other)) = brackets {
+            for pos in [pos, other] {
+                for mut rect in highlight_chars(state, &rows, &buffer.line(pos.line), pos.line, pos.col, pos.col) {
+                    rect.color = (0.3, 0.3, 0.45);
+                    rect_renderer.draw_rect(state, rect);
+                }
+            }
+        }
+
+        // trailing blanks, left alone on the line being typed on since they come and go there
+        if state.options.show_trailing {
+            let typing = matches!(editor.mode, EditorMode::Insert | EditorMode::Replace);
+            let mut shown = rows.iter().filter(|row| row.fold.is_none()).map(|row| row.line).collect::<Vec<_>>();
+            shown.dedup();
+            for line in shown.into_iter().filter(|&line| !(typing && line == cursor.line)) {
+                let text = buffer.line(line);
+                let (len, kept) = (text.chars().count(), text.trim_end_matches([' ', '\t']).chars().count());
+                if len > kept {
+                    for mut rect in highlight_chars(state, &rows, &text, line, kept, len - 1) {
+                        rect.color = (0.45, 0.18, 0.18);
+                        rect_renderer.draw_rect(state, rect);
+                    }
+                }
+            }
+        }
 
         if editor.mode == EditorMode::Visual {
             let cursor = current_cursor.to_linepos();
             let start = editor.visual_range_anchor.min(cursor);
             let end = editor.visual_range_anchor.max(cursor);
 
-            if start.line == end.line {
-                let rect = highlight_line(&state, start.col, end.col, start.line);
-                rect_renderer.draw_rect(&state, rect);
-            } else {
-                let line_len = buffer.line_len(start.line).max(1);
-                let first = highlight_line(&state, start.col, line_len - 1, start.line);
-                rect_renderer.draw_rect(&state, first);
-
-                for line in (start.line + 1)..end.line {
-                    let line_len = buffer.line_len(line).max(1);
-                    let rect = highlight_line(&state, 0, line_len - 1, line);
-                    rect_renderer.draw_rect(&state, rect);
+            // lines in the middle are selected whole
+            for line in start.line.max(state.start_line)..=end.line {
+                let first = if line == start.line { start.col } else { 0 };
+                let last = if line == end.line { end.col } else { buffer.line_len(line).max(1) - 1 };
+                for rect in highlight_chars(state, &rows, &buffer.line(line), line, first, last) {
+                    rect_renderer.draw_rect(state, rect);
                 }
-
-                let last = highlight_line(&state, 0, end.col, end.line);
-                rect_renderer.draw_rect(&state, last);
             }
         } else if editor.mode == EditorMode::VisualLine {
             let cursor = current_cursor.to_linepos().line;
             let start = editor.visual_range_anchor.line.min(cursor);
             let end = editor.visual_range_anchor.line.max(cursor);
 
-            for line in start..(end + 1) {
+            for line in start.max(state.start_line)..(end + 1) {
                 let line_len = buffer.line_len(line).max(1);
-                let rect = highlight_line(&state, 0, line_len - 1, line);
-                rect_renderer.draw_rect(&state, rect);
+                for rect in highlight_chars(state, &rows, &buffer.line(line), line, 0, line_len - 1) {
+                    rect_renderer.draw_rect(state, rect);
+                }
+            }
+        } else if editor.mode == EditorMode::CommandBar {
+            // show which lines a typed range like :10,25 covers before running the command
+            let input = editor.command_bar_input.get(1..).unwrap_or("");
+            let marks = editor.visual_marks.map(|(start, end)| (start.line, end.line));
+            if let (Some(range), _) = parse_range(input, current_cursor.y - 1, buffer.total_lines(), marks) {
+                let start = range.start.max(state.start_line);
+                let end = range.end.min(state.start_line + state.max_rows());
+                for line in start..(end + 1) {
+                    let line_len = buffer.line_len(line).max(1);
+                    for mut rect in highlight_chars(state, &rows, &buffer.line(line), line, 0, line_len - 1) {
+                        rect.color = (0.3, 0.3, 0.45);
+                        rect_renderer.draw_rect(state, rect);
+                    }
+                }
             }
         }
 
-        let end_line = state.start_line + state.max_rows() + 1;
-        for i in (state.start_line as usize)..(buffer.total_lines().min(end_line as usize)) {
-            let line = buffer.line(i);
-            let draw_line = DrawLine::new(&line, i + 1 - state.start_line, (1.0, 1.0, 1.0));
-            text_renderer.draw_line(&state, draw_line);
+        draw_rows(state, buffer, &rows, text_renderer, rect_renderer);
+
+        // the thumb covers the lines on screen, search results are marked on the track
+        if state.options.scrollbar {
+            let bar_width = state.scrollbar_width();
+            let x = state.width as f32 - bar_width;
+            let height = state.height as f32;
+            let total = buffer.total_lines() as f32;
+            rect_renderer.draw_rect(state, DrawRect::from_pixels(state, x, 0.0, bar_width, height, (0.2, 0.2, 0.22)));
+
+            let top = state.start_line as f32 / total * height;
+            let thumb = (state.max_rows() as f32 / total).min(1.0) * height;
+            let thumb = thumb.max(state.char_height / 2.0).min(height - top);
+            rect_renderer.draw_rect(state, DrawRect::from_pixels(state, x, top, bar_width, thumb, (0.4, 0.4, 0.45)));
+
+            let mut last_mark = None;
+            for pos in &editor.search_results {
+                let y = (pos.line as f32 / total * height).floor();
+                if last_mark != Some(y) {
+                    rect_renderer.draw_rect(state, DrawRect::from_pixels(state, x, y, bar_width, 2.0 * state.content_scale, (0.9, 0.8, 0.2)));
+                    last_mark = Some(y);
+                }
+            }
         }
 
+        // the command line and messages go across the bottom of the whole window
+        set_view(state, text_renderer, (0, 0, screen.0, screen.1));
         if editor.mode == EditorMode::CommandBar || editor.mode == EditorMode::Search {
             let line_len = state.max_cols();
-            let rect = highlight_line(&state, 0, line_len, state.start_line);
-            rect_renderer.draw_rect(&state, rect);
+            let rect = highlight_line(state, 0, line_len, state.start_line);
+            rect_renderer.draw_rect(state, rect);
             let draw_line = DrawLine::new(&editor.command_bar_input, 1, (1.0, 1.0, 0.0));
-            text_renderer.draw_line(&state, draw_line);
+            text_renderer.draw_line(state, draw_line);
             let xpos = state.cmd_bar_cursor_x as f32 * state.char_width;
             let ypos = state.height as f32 - (1f32 * state.char_height);
-            let rect = DrawRect::from_screen_points(&state, xpos, ypos, (1.0, 1.0, 1.0));
-            rect_renderer.draw_rect(&state, rect);
+            let rect = DrawRect::from_screen_points(state, xpos, ypos, (1.0, 1.0, 1.0));
+            rect_renderer.draw_rect(state, rect);
+
+            // tab completion candidates go right above the command bar
+            if let Some(wildmenu) = &editor.wildmenu {
+                let mut rect = highlight_line(state, 0, state.max_cols(), state.start_line + 1);
+                rect.color = (0.25, 0.25, 0.3);
+                rect_renderer.draw_rect(state, rect);
+                for (i, col) in wildmenu.visible(state.max_cols()) {
+                    let candidate = &wildmenu.candidates[i];
+                    let mut color = (1.0, 1.0, 1.0);
+                    if wildmenu.selected == Some(i) {
+                        let cells = width::str_width(candidate);
+                        let mut rect = highlight_line(state, col, col + cells.max(1) - 1, state.start_line + 1);
+                        rect.color = (0.9, 0.8, 0.2);
+                        rect_renderer.draw_rect(state, rect);
+                        color = (0.0, 0.0, 0.0);
+                    }
+                    let draw_line = DrawLine::new(candidate, 2, color).at_col(col);
+                    text_renderer.draw_line(state, draw_line);
+                }
+            } else if !editor.message.is_empty() {
+                let mut rect = highlight_line(state, 0, state.max_cols(), state.start_line + 1);
+                rect.color = (0.25, 0.25, 0.3);
+                rect_renderer.draw_rect(state, rect);
+                let draw_line = DrawLine::new(&editor.message, 2, (1.0, 1.0, 1.0));
+                text_renderer.draw_line(state, draw_line);
+            }
         } else {
-            let (xpos, ypos) = current_cursor.to_screen_position(&state, state.start_line);
-            let rect = DrawRect::from_screen_points(&state, xpos, ypos, (1.0, 1.0, 1.0));
-            rect_renderer.draw_rect(&state, rect);
+            if !editor.message.is_empty() {
+                let rect = highlight_line(state, 0, state.max_cols(), state.start_line);
+                rect_renderer.draw_rect(state, rect);
+                let draw_line = DrawLine::new(&editor.message, 1, (1.0, 1.0, 1.0));
+                text_renderer.draw_line(state, draw_line);
+            }
+
+            if state.options.byte_ruler {
+                let byte = buffer.linepos_to_byte(current_cursor.to_linepos()) + 1;
+                let ruler = format!("byte {byte} 0x{byte:x}");
+                let start_col = state.max_cols().saturating_sub(ruler.len() + 1);
+                let rect = highlight_line(state, start_col, state.max_cols(), state.start_line);
+                rect_renderer.draw_rect(state, rect);
+                let draw_line = DrawLine::new(&ruler, 1, (1.0, 1.0, 0.0)).at_col(start_col);
+                text_renderer.draw_line(state, draw_line);
+            }
+
+            set_view(state, text_renderer, focused);
+            let cursor_text = buffer.line(current_cursor.y - 1);
+            let cursor_row = wrap::row_of(&rows, cursor).unwrap_or(0);
+            let row_start = rows.get(cursor_row).map_or(0, |row| row.start);
+            let row_text = cursor_text.chars().skip(row_start).collect::<String>();
+            let (xpos, ypos) = current_cursor.to_screen_position(state, &rows, &cursor_text);
+            let mut rect = DrawRect::from_screen_points(state, xpos, ypos, (1.0, 1.0, 1.0));
+            // as wide as the char under it
+            let tabstop = state.options.tabstop;
+            let col = cursor.col - row_start;
+            let cells = (width::display_col(&row_text, col + 1, tabstop) - width::display_col(&row_text, col, tabstop)).max(1);
+            rect.width *= cells as f32;
+            // a bar between chars in insert mode, an underline in replace mode
+            match editor.mode {
+                EditorMode::Insert => rect.width = rect.width / cells as f32 * 0.15,
+                EditorMode::Replace => rect.height *= 0.15,
+                _ => (),
+            }
+
+            let blink = state.options.cursor_blink as u128;
+            let cursor_visible = match last_input.elapsed().as_millis().checked_div(blink) {
+                Some(phase) => {
+                    state.scheduler.redraw_at(last_input + Duration::from_millis((blink * (phase + 1)) as u64));
+                    phase % 2 == 0
+                },
+                None => true,
+            };
+            if cursor_visible {
+                rect_renderer.draw_rect(state, rect);
+            }
+
+            let mut popups = Vec::new();
+            if let Some(lines) = &editor.hover {
+                let col = width::display_col(&row_text, col, tabstop);
+                popups.push(Popup::new(lines.clone()).with_border().near(cursor_row, col, state.max_rows()));
+            }
+
+            if let Some((menu, menu_row)) = editor.completion.as_ref().and_then(|menu| Some((menu, wrap::row_of(&rows, menu.start)?))) {
+                // the items go under the row being completed, from where the word starts
+                let (first, shown) = menu.visible_items();
+                let row_start = rows[menu_row].start;
+                let menu_text = buffer.line(menu.start.line).chars().skip(row_start).collect::<String>();
+                let col = width::display_col(&menu_text, menu.start.col - row_start, tabstop);
+                let labels = shown.iter().map(|i| menu.items[*i].label.clone()).collect();
+                let mut popup = Popup::new(labels).near(menu_row, col, state.max_rows()).with_z(1);
+                popup.selected = menu.selected.checked_sub(first);
+                popups.push(popup);
+            }
+            draw_popups(state, rect_renderer, text_renderer, &popups);
+            set_view(state, text_renderer, (0, 0, screen.0, screen.1));
         }
 
 
+        // notifications stack down from the top right corner, newest first
+        let now = Instant::now();
+        profiler.mark("buffer", now);
+        let mut overlays = Vec::new();
+        let mut row = 0;
+        for notification in editor.notifications.visible(now) {
+            let mut popup = Popup::new(vec![notification.text.clone()]).with_border().with_z(2);
+            // moved in from the right edge when it's laid out
+            popup.col = state.max_cols();
+            popup.row = row;
+            popup.color = notification.color(now);
+            row += popup.size().0;
+            overlays.push(popup);
+        }
+        // what can follow a prefix that waited, right above the message line
+        if let Some(hints) = editor.which_key(state) {
+            let mut popup = Popup::new(hints).with_border().with_z(3);
+            popup.row = state.max_rows().saturating_sub(popup.size().0 + 1);
+            overlays.push(popup);
+        }
+        if state.options.profile {
+            overlays.push(Popup::new(profiler.lines()).with_border().with_z(4));
+        }
+        draw_popups(state, rect_renderer, text_renderer, &overlays);
+        profiler.mark("overlays", Instant::now());
+        if let Some(at) = editor.notifications.next_redraw(now) {
+            state.scheduler.redraw_at(at);
+        }
+
         //println!();
         //for line in 0..buffer.total_lines() {
         //    println!("{line}: {:?}", buffer.raw_line(line).as_bytes());
@@ -387,7 +868,126 @@ fn main() {
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
 
+        // a failing draw call is reported, not fatal, so the buffer can still be saved
+        let gl_error = unsafe { gl::GetError() };
+        if gl_error != last_gl_error {
+            if gl_error != gl::NO_ERROR {
+                editor.notify(format!("OpenGL error 0x{gl_error:x}, drawing may be broken"), Level::Error);
+                state.scheduler.redraw_at(Instant::now());
+            }
+            last_gl_error = gl_error;
+        }
+
+        text_renderer.end_frame();
         state.io.reset();
         window.swap_buffers();
+        profiler.mark("swap", Instant::now());
+        let draw_calls = text_renderer.take_draw_calls() + rect_renderer.take_draw_calls();
+        profiler.end_frame(Instant::now(), draw_calls, text_renderer.char_cache.glyphs());
+    }
+}
+
+//static mut WIDTH: u32 = 1280 * 2;
+//static mut HEIGHT: u32 = 720 * 2;
+
+fn main() {
+    let args = startup::init(std::env::args().skip(1));
+    if args.headless {
+        startup::run_headless(&args.path, args.socket.as_deref());
+        return
+    }
+
+    let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
+    glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
+    glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+    let (screen_width, screen_height) =
+    unsafe {
+        let vid_mode = glfw::ffi::glfwGetVideoMode(glfw::ffi::glfwGetPrimaryMonitor());
+        ((*vid_mode).width as u32, (*vid_mode).height as u32)
+    };
+
+    let geometry = load_geometry();
+    let (width, height) = geometry.map_or((screen_width / 2, screen_height / 2), |(_, _, width, height)| (width as u32, height as u32));
+    let (mut window, events) = glfw.create_window(width, height, "moded", glfw::WindowMode::Windowed).unwrap();
+    if let Some((x, y, _, _)) = geometry {
+        window.set_pos(x, y);
     }
+    window.make_current();
+    window.set_key_polling(true);
+    window.set_char_polling(true);
+    window.set_cursor_pos_polling(true);
+    window.set_mouse_button_polling(true);
+    //window.set_char_mods_polling(true);
+    window.set_framebuffer_size_polling(true);
+    window.set_content_scale_polling(true);
+    glfw.set_swap_interval(glfw::SwapInterval::None);
+    //window.set_framebuffer_size_callback(frame_buffer_size_callback);
+
+    gl::load_with(|ptr| window.get_proc_address(ptr) as *const _);
+
+    unsafe {
+        gl::Enable(gl::CULL_FACE);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    }
+
+    // shader errors are shown in the message area once the editor is up
+    let mut render_errors = Vec::new();
+    let text_shader = match TextShader::new(TEXT_VERTEX_SHADER_SOURCE, TEXT_FRAGMENT_SHADER_SOURCE) {
+        Ok(shader) => shader,
+        Err(e) => {
+            render_errors.push(e.to_string());
+            TextShader::new(TEXT_VERTEX_SHADER_SOURCE, FALLBACK_TEXT_FRAGMENT_SHADER_SOURCE).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1)
+            })
+        },
+    };
+    let rect_shader = match RectShader::new(RECT_VERTEX_SHADER_SOURCE, RECT_FRAGMENT_SHADER_SOURCE) {
+        Ok(shader) => shader,
+        Err(e) => {
+            render_errors.push(e.to_string());
+            RectShader::new(RECT_VERTEX_SHADER_SOURCE, FALLBACK_RECT_FRAGMENT_SHADER_SOURCE).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1)
+            })
+        },
+    };
+
+    let (width, height) = window.get_framebuffer_size();
+    let mut state = State::new(width, height);
+    state.content_scale = window.get_content_scale().0;
+    unsafe { gl::Viewport(0, 0, width, height) };
+
+    let (char_cache, font_height, font_ascent) = load_font(&mut state);
+    set_size_limits(&state, &mut window);
+    let mut text_renderer = TextRenderer::new(text_shader, char_cache, font_height, font_ascent);
+    let mut rect_renderer = RectRenderer::new(rect_shader);
+
+    let mut editor = startup::open(&args.path);
+    startup::enter_project(&mut editor);
+    editor.load_config(&mut state);
+    editor.load_recent(&mut state);
+    render_errors.extend(text_renderer.error.take());
+    render_errors.extend(rect_renderer.error.take());
+    if !render_errors.is_empty() {
+        for e in &render_errors {
+            log::error(e);
+        }
+        editor.message = render_errors.join(" | ").replace('\n', " ");
+    }
+    if fs::read_dir(startup::recovery_dir()).is_ok_and(|mut files| files.next().is_some()) {
+        editor.notify(format!("Text saved when moded crashed is in {}", startup::recovery_dir().display()), Level::Info);
+    }
+    let mut windowed = None;
+    // a panic in run ends up here with the buffers intact, see recover
+    let running = panic::catch_unwind(AssertUnwindSafe(|| {
+        run(&mut glfw, &mut window, &events, &mut state, &mut editor, &mut text_renderer, &mut rect_renderer, &mut windowed)
+    }));
+    if running.is_err() {
+        startup::recover(&editor);
+    }
+
+    editor.save_recent(&state);
+    save_geometry(windowed.unwrap_or_else(|| window_geometry(&window)));
 }
@@ -0,0 +1,99 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+
+// short notes about things that finished in the background, like a save or a
+// language server starting. they stack up in the top right corner apart from
+// the message line and fade out on their own
+
+// how long one is shown, the text fades out over the end of it
+pub const SHOWN_FOR: Duration = Duration::from_secs(4);
+const FADE: Duration = Duration::from_secs(1);
+// older ones are dropped when there are more
+const MAX_SHOWN: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub text: String,
+    pub level: Level,
+    pub at: Instant,
+}
+
+impl Notification {
+    // 1 while it's shown fully down to 0 when it goes
+    pub fn opacity(&self, now: Instant) -> f32 {
+        let left = SHOWN_FOR.saturating_sub(now.saturating_duration_since(self.at));
+        (left.as_secs_f32() / FADE.as_secs_f32()).min(1.0)
+    }
+
+    pub fn color(&self, now: Instant) -> (f32, f32, f32) {
+        let (r, g, b) = match self.level {
+            Level::Info => (1.0, 1.0, 1.0),
+            Level::Error => (0.9, 0.4, 0.4),
+        };
+        // faded towards the popup background
+        let opacity = self.opacity(now);
+        let fade = |color: f32, background: f32| background + (color - background) * opacity;
+        (fade(r, 0.25), fade(g, 0.25), fade(b, 0.3))
+    }
+}
+
+// newest first
+#[derive(Debug, Default)]
+pub struct Notifications {
+    shown: VecDeque<Notification>,
+}
+
+impl Notifications {
+    pub fn push(&mut self, text: String, level: Level, now: Instant) {
+        self.shown.push_front(Notification { text, level, at: now });
+        self.shown.truncate(MAX_SHOWN);
+    }
+
+    // drops the ones that are done, the rest newest first
+    pub fn visible(&mut self, now: Instant) -> impl Iterator<Item = &Notification> {
+        self.shown.retain(|notification| now.saturating_duration_since(notification.at) < SHOWN_FOR);
+        self.shown.iter()
+    }
+
+    // while one is fading the screen is redrawn often, otherwise once it starts to
+    pub fn next_redraw(&self, now: Instant) -> Option<Instant> {
+        self.shown.iter().map(|notification| {
+            let fade_start = notification.at + SHOWN_FOR - FADE;
+            if now >= fade_start { now + Duration::from_millis(50) } else { fade_start }
+        }).min()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications() {
+        let now = Instant::now();
+        let mut notifications = Notifications::default();
+        notifications.push("saved".into(), Level::Info, now);
+        notifications.push("server crashed".into(), Level::Error, now + Duration::from_secs(2));
+        let texts = |notifications: &mut Notifications, at: Instant| notifications.visible(at).map(|n| n.text.clone()).collect::<Vec<_>>();
+        assert_eq!(texts(&mut notifications, now), ["server crashed", "saved"]);
+        assert_eq!(notifications.next_redraw(now), Some(now + Duration::from_secs(3)));
+
+        // fading out over the last second
+        let fading = now + Duration::from_millis(3500);
+        assert_eq!(notifications.shown[1].opacity(fading), 0.5);
+        assert_eq!(notifications.next_redraw(fading), Some(fading + Duration::from_millis(50)));
+        assert_eq!(texts(&mut notifications, now + SHOWN_FOR), ["server crashed"]);
+
+        for i in 0..10 {
+            notifications.push(i.to_string(), Level::Info, now);
+        }
+        assert_eq!(notifications.shown.len(), MAX_SHOWN);
+    }
+}
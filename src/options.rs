@@ -0,0 +1,340 @@
+use std::{env, path::PathBuf};
+
+// how much of an insert session one undo takes back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoChunk {
+    Insert,
+    Word,
+    Sentence,
+}
+
+impl UndoChunk {
+    fn parse(value: &str) -> Result<Self, ()> {
+        match value {
+            "insert" => Ok(UndoChunk::Insert),
+            "word" => Ok(UndoChunk::Word),
+            "sentence" => Ok(UndoChunk::Sentence),
+            _ => Err(()),
+        }
+    }
+}
+
+// where folds come from, zf only makes them with manual
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldMethod {
+    Manual,
+    Indent,
+}
+
+// where the cursor can go past the end of its line. block is for a visual
+// block mode, all is everywhere but insert mode, where typing fills the gap
+// with spaces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VirtualEdit {
+    pub block: bool,
+    pub all: bool,
+}
+
+impl VirtualEdit {
+    // a comma separated list, virtualedit= or none clears it
+    fn parse(value: &str) -> Result<Self, ()> {
+        let mut virtual_edit = VirtualEdit::default();
+        for part in value.split(',').filter(|part| !part.is_empty()) {
+            match part {
+                "block" => virtual_edit.block = true,
+                "all" => virtual_edit.all = true,
+                "none" => (),
+                _ => return Err(()),
+            }
+        }
+
+        Ok(virtual_edit)
+    }
+}
+
+impl FoldMethod {
+    fn parse(value: &str) -> Result<Self, ()> {
+        match value {
+            "manual" => Ok(FoldMethod::Manual),
+            "indent" => Ok(FoldMethod::Indent),
+            _ => Err(()),
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/moded or ~/.config/moded
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(dir.join("moded"))
+}
+
+// ex commands run at startup
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("init"))
+}
+
+// the size glyphs are rasterized at, in pixels on a monitor without scaling
+pub const DEFAULT_FONT_SIZE: f32 = 18.0;
+pub const MIN_FONT_SIZE: f32 = 8.0;
+pub const MAX_FONT_SIZE: f32 = 120.0;
+
+// editor wide options, changed with :set
+pub struct Options {
+    pub byte_ruler: bool,
+    pub undo_chunk: UndoChunk,
+    // command the buffer is piped through by :format, picked by file type if empty
+    pub formatprg: String,
+    pub format_on_save: bool,
+    // remove trailing spaces and tabs when saving
+    pub trim_on_save: bool,
+    // flash the screen instead of beeping
+    pub visual_bell: bool,
+    pub font_size: f32,
+    // wait for the monitor's refresh when swapping frames
+    pub vsync: bool,
+    // frames drawn per second at most, 0 for no limit
+    pub max_fps: u32,
+    // ms the cursor stays on and off for, 0 keeps it on
+    pub cursor_blink: u32,
+    // show tabs, trailing spaces and non-breaking spaces
+    pub list: bool,
+    // trailing blanks get a red background, except on the line being typed on
+    pub show_trailing: bool,
+    // cells between tab stops
+    pub tabstop: usize,
+    // lines wider than the window go on over the rows below
+    pub wrap: bool,
+    // wrapped lines break after a blank instead of at the last cell
+    pub linebreak: bool,
+    // shows where the screen is in the file, with marks for search results
+    pub scrollbar: bool,
+    // screen columns guide lines are drawn at, counting from 1
+    pub color_column: Vec<usize>,
+    // the window title, see Editor::title
+    pub titlestring: String,
+    // saved files end with a line separator even if they didn't before
+    pub fix_eol: bool,
+    // copy a file to {name}~ before it's overwritten, in backup_dir if it's set
+    pub backup: bool,
+    pub backup_dir: String,
+    // searches go on from the other end of the buffer after the last match
+    pub wrap_scan: bool,
+    // cells an indent level is wide, tab in insert mode goes to the next one
+    pub shiftwidth: usize,
+    // indent with spaces, with noexpandtab tab inserts a real tab
+    pub expandtab: bool,
+    // typing an opening bracket or quote puts in the closing one too
+    pub autopairs: bool,
+    // cells gq wraps lines at, 0 for 79
+    pub textwidth: usize,
+    // 0 and Home go to the first non-blank, or to col 0 if already there
+    pub smart_home: bool,
+    pub fold_method: FoldMethod,
+    // ms a prefix like g or z waits before what can follow it is shown, 0 never
+    pub which_key: u32,
+    // patterns of files and folders file name completion leaves out, see ignore.rs
+    pub wildignore: Vec<String>,
+    // frame times and draw calls in the top left corner, F12 too
+    pub profile: bool,
+    // normal and visual mode keys go by where they are on the keyboard, read
+    // as a US layout, so hjkl stay on the home row with Dvorak or AZERTY
+    pub physical_keys: bool,
+    // where the cursor can go past line ends
+    pub virtualedit: VirtualEdit,
+    // what the text and background of the windows that aren't focused are
+    // multiplied by, 1 leaves them as they are
+    pub inactive_brightness: f32,
+}
+
+impl Options {
+    // every option :set knows, for completion
+    pub const NAMES: &'static [&'static str] = &["autopairs", "backup", "backupdir", "byteruler", "colorcolumn", "cursorblink", "expandtab", "fixeol", "foldmethod", "fontsize", "formatonsave", "formatprg", "inactivebrightness", "linebreak", "list", "maxfps", "physicalkeys", "profile", "scrollbar", "shiftwidth", "showtrailing", "smarthome", "tabstop", "textwidth", "titlestring", "trimonsave", "undochunk", "virtualedit", "visualbell", "vsync", "whichkey", "wildignore", "wrap", "wrapscan"];
+
+    pub fn new() -> Self {
+        Self {
+            byte_ruler: false,
+            undo_chunk: UndoChunk::Insert,
+            formatprg: String::new(),
+            format_on_save: false,
+            trim_on_save: false,
+            visual_bell: false,
+            font_size: DEFAULT_FONT_SIZE,
+            vsync: false,
+            max_fps: 0,
+            cursor_blink: 500,
+            list: false,
+            show_trailing: true,
+            tabstop: 8,
+            wrap: false,
+            linebreak: false,
+            scrollbar: true,
+            color_column: Vec::new(),
+            titlestring: String::from("%f%m%r%e - moded"),
+            fix_eol: true,
+            backup: false,
+            backup_dir: String::new(),
+            wrap_scan: true,
+            shiftwidth: 4,
+            expandtab: true,
+            textwidth: 0,
+            autopairs: false,
+            smart_home: false,
+            fold_method: FoldMethod::Manual,
+            which_key: 500,
+            wildignore: ["target/", "node_modules/", ".git/", "*.o"].map(String::from).to_vec(),
+            profile: false,
+            physical_keys: false,
+            virtualedit: VirtualEdit::default(),
+            inactive_brightness: 0.6,
+        }
+    }
+
+    // accepts "name", "noname", "name!" and "name=value" like vim
+    pub fn set(&mut self, arg: &str) -> Result<(), ()> {
+        if let Some((name, value)) = arg.split_once('=') {
+            return self.set_value(name, value)
+        }
+
+        let (name, value) = if let Some(name) = arg.strip_suffix('!') {
+            (name, None)
+        } else if let Some(name) = arg.strip_prefix("no") {
+            (name, Some(false))
+        } else {
+            (arg, Some(true))
+        };
+
+        let Some(option) = self.bool_option(name) else { return Err(()) };
+        *option = value.unwrap_or(!*option);
+
+        Ok(())
+    }
+
+    fn bool_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "autopairs" => Some(&mut self.autopairs),
+            "backup" => Some(&mut self.backup),
+            "byteruler" => Some(&mut self.byte_ruler),
+            "expandtab" => Some(&mut self.expandtab),
+            "fixeol" => Some(&mut self.fix_eol),
+            "formatonsave" => Some(&mut self.format_on_save),
+            "linebreak" => Some(&mut self.linebreak),
+            "list" => Some(&mut self.list),
+            "physicalkeys" => Some(&mut self.physical_keys),
+            "profile" => Some(&mut self.profile),
+            "visualbell" => Some(&mut self.visual_bell),
+            "scrollbar" => Some(&mut self.scrollbar),
+            "showtrailing" => Some(&mut self.show_trailing),
+            "smarthome" => Some(&mut self.smart_home),
+            "trimonsave" => Some(&mut self.trim_on_save),
+            "vsync" => Some(&mut self.vsync),
+            "wrap" => Some(&mut self.wrap),
+            "wrapscan" => Some(&mut self.wrap_scan),
+            _ => None,
+        }
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Result<(), ()> {
+        match name {
+            "undochunk" => self.undo_chunk = UndoChunk::parse(value)?,
+            "foldmethod" => self.fold_method = FoldMethod::parse(value)?,
+            "virtualedit" => self.virtualedit = VirtualEdit::parse(value)?,
+            "formatprg" => self.formatprg = value.to_owned(),
+            "backupdir" => self.backup_dir = value.to_owned(),
+            "titlestring" => self.titlestring = value.to_owned(),
+            "maxfps" => self.max_fps = value.parse().map_err(|_| ())?,
+            "textwidth" => self.textwidth = value.parse().map_err(|_| ())?,
+            // colorcolumn= clears them
+            "colorcolumn" => {
+                let columns = value.split(',').filter(|column| !column.is_empty()).map(|column| column.trim().parse::<usize>());
+                let columns = columns.collect::<Result<Vec<_>, _>>().map_err(|_| ())?;
+                if columns.contains(&0) {
+                    return Err(())
+                }
+                self.color_column = columns;
+            },
+            // a comma separated list, wildignore= clears it
+            "wildignore" => self.wildignore = value.split(',').filter(|pattern| !pattern.is_empty()).map(String::from).collect(),
+            "cursorblink" => self.cursor_blink = value.parse().map_err(|_| ())?,
+            "whichkey" => self.which_key = value.parse().map_err(|_| ())?,
+            "tabstop" => self.tabstop = parse_width(value)?,
+            "shiftwidth" => self.shiftwidth = parse_width(value)?,
+            "fontsize" => {
+                let size = value.parse::<f32>().map_err(|_| ())?;
+                if !(MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&size) {
+                    return Err(())
+                }
+                self.font_size = size;
+            },
+            "inactivebrightness" => {
+                let brightness = value.parse::<f32>().map_err(|_| ())?;
+                if !(0.0..=1.0).contains(&brightness) {
+                    return Err(())
+                }
+                self.inactive_brightness = brightness;
+            },
+            _ => return Err(()),
+        }
+
+        Ok(())
+    }
+
+    pub fn shiftwidth(&self, local: &LocalOptions) -> usize {
+        local.shiftwidth.unwrap_or(self.shiftwidth)
+    }
+
+    pub fn expandtab(&self, local: &LocalOptions) -> bool {
+        local.expandtab.unwrap_or(self.expandtab)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the options a buffer can have its own value of with :setlocal, None is the
+// editor wide one. tabstop isn't one, it's how tabs are drawn everywhere
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalOptions {
+    pub shiftwidth: Option<usize>,
+    pub expandtab: Option<bool>,
+}
+
+impl LocalOptions {
+    // takes the same forms as Options::set
+    pub fn set(&mut self, arg: &str) -> Result<(), ()> {
+        match arg.split_once('=') {
+            Some(("shiftwidth", value)) => self.shiftwidth = Some(parse_width(value)?),
+            Some(_) => return Err(()),
+            None if arg == "expandtab" => self.expandtab = Some(true),
+            None if arg == "noexpandtab" => self.expandtab = Some(false),
+            None => return Err(()),
+        }
+
+        Ok(())
+    }
+
+    // drops the buffer's value of the option :set arg changed, the editor
+    // wide one is used again
+    pub fn clear(&mut self, arg: &str) {
+        let name = arg.split_once('=').map_or(arg, |(name, _)| name).trim_end_matches('!');
+        match name.strip_prefix("no").unwrap_or(name) {
+            "shiftwidth" => self.shiftwidth = None,
+            "expandtab" => self.expandtab = None,
+            _ => {},
+        }
+    }
+}
+
+// a width in cells, at least 1
+fn parse_width(value: &str) -> Result<usize, ()> {
+    match value.parse::<usize>() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(()),
+    }
+}
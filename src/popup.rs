@@ -0,0 +1,132 @@
+use crate::width;
+
+
+// boxes of text drawn over the buffer after it, for hover text, menus and
+// the like. positions and sizes are in screen cells, row 0 at the top. a popup
+// that doesn't fit is moved in from the edges and then clipped
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Popup {
+    pub lines: Vec<String>,
+    // the top left cell, the border's if it has one
+    pub row: usize,
+    pub col: usize,
+    pub border: bool,
+    // popups with a higher z are drawn over the ones with a lower one
+    pub z: i32,
+    // a line drawn highlighted, like a menu's selected item
+    pub selected: Option<usize>,
+    pub color: (f32, f32, f32),
+}
+
+// where a popup ends up on a screen, the box with its border and the text in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+    pub text_row: usize,
+    pub text_col: usize,
+    pub text_rows: usize,
+    pub text_cols: usize,
+}
+
+impl Popup {
+    pub fn new(lines: Vec<String>) -> Self {
+        Self { lines, row: 0, col: 0, border: false, z: 0, selected: None, color: (1.0, 1.0, 1.0) }
+    }
+
+    pub fn with_border(mut self) -> Self {
+        self.border = true;
+        self
+    }
+
+    pub fn with_z(mut self, z: i32) -> Self {
+        self.z = z;
+        self
+    }
+
+    // on the rows below row starting at col, or above it if there's more
+    // room there and not enough below
+    pub fn near(mut self, row: usize, col: usize, max_rows: usize) -> Self {
+        let (rows, _) = self.size();
+        let below = max_rows.saturating_sub(row + 1);
+        self.row = if rows > below && row > below { row.saturating_sub(rows) } else { row + 1 };
+        self.col = col;
+        self
+    }
+
+    // rows and cols the box wants, border included
+    pub fn size(&self) -> (usize, usize) {
+        let border = if self.border { 2 } else { 0 };
+        let cols = self.lines.iter().map(|line| width::str_width(line)).max().unwrap_or(0).max(1);
+        (self.lines.len().max(1) + border, cols + border)
+    }
+
+    // moved left to fit on a screen max_rows by max_cols and cut off at its
+    // bottom, None if nothing of it shows
+    pub fn layout(&self, max_rows: usize, max_cols: usize) -> Option<Layout> {
+        let border = if self.border { 1 } else { 0 };
+        let (rows, cols) = self.size();
+        let cols = cols.min(max_cols);
+        let rows = rows.min(max_rows.saturating_sub(self.row));
+        if rows <= 2 * border || cols <= 2 * border {
+            return None
+        }
+
+        let col = self.col.min(max_cols - cols);
+        Some(Layout {
+            row: self.row,
+            col,
+            rows,
+            cols,
+            text_row: self.row + border,
+            text_col: col + border,
+            text_rows: rows - 2 * border,
+            text_cols: cols - 2 * border,
+        })
+    }
+}
+
+// the start of text that fits in cells, a wide char that doesn't is left out
+pub fn clip(text: &str, cells: usize) -> String {
+    let mut used = 0;
+    text.chars().take_while(|&c| {
+        used += width::char_width(c);
+        used <= cells
+    }).collect()
+}
+
+// the border around a box cols wide, top, sides and bottom
+pub fn border_lines(cols: usize) -> (String, char, String) {
+    let inside = "─".repeat(cols.saturating_sub(2));
+    (format!("┌{inside}┐"), '│', format!("└{inside}┘"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let popup = Popup::new(vec!["one".into(), "three".into()]).with_border().near(5, 30, 20);
+        assert_eq!(popup.size(), (4, 7));
+        let layout = popup.layout(20, 80).unwrap();
+        assert_eq!((layout.row, layout.col, layout.text_row, layout.text_col), (6, 30, 7, 31));
+        assert_eq!((layout.text_rows, layout.text_cols), (2, 5));
+
+        // no room below goes above, the right edge pushes it left
+        let popup = Popup::new(vec!["a long line".into(); 3]).near(18, 75, 20);
+        assert_eq!(popup.row, 15);
+        assert_eq!(popup.layout(20, 80).unwrap().col, 69);
+
+        // too big for the screen it's clipped
+        let popup = Popup::new(vec!["x".repeat(100); 30]).near(0, 0, 20);
+        let layout = popup.layout(20, 80).unwrap();
+        assert_eq!((layout.rows, layout.cols), (19, 80));
+        assert_eq!(clip("ab日本", 3), "ab");
+        assert_eq!(border_lines(4).0, "┌──┐");
+    }
+}
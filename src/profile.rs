@@ -0,0 +1,116 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+
+// how long frames take and what in them, for the overlay :set profile shows.
+// a frame is split by marks, each one ends the part since the one before it.
+// what's shown is the frame before the one being drawn
+
+// frames the average and the slowest one are taken over
+const FRAMES: usize = 60;
+
+pub struct Profiler {
+    frame_start: Instant,
+    last_mark: Instant,
+    // the parts of the frame being drawn so far, in the order they came
+    sections: Vec<(&'static str, Duration)>,
+    // of the last frame
+    last_sections: Vec<(&'static str, Duration)>,
+    // newest last
+    frames: VecDeque<Duration>,
+    draw_calls: usize,
+    glyphs: usize,
+}
+
+impl Profiler {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            frame_start: now,
+            last_mark: now,
+            sections: Vec::new(),
+            last_sections: Vec::new(),
+            frames: VecDeque::new(),
+            draw_calls: 0,
+            glyphs: 0,
+        }
+    }
+
+    // also drops the marks of a frame that wasn't drawn
+    pub fn begin_frame(&mut self, now: Instant) {
+        self.frame_start = now;
+        self.last_mark = now;
+        self.sections.clear();
+    }
+
+    // a name marked twice in a frame adds up
+    pub fn mark(&mut self, name: &'static str, now: Instant) {
+        let time = now.saturating_duration_since(self.last_mark);
+        self.last_mark = now;
+        match self.sections.iter_mut().find(|(section, _)| *section == name) {
+            Some((_, total)) => *total += time,
+            None => self.sections.push((name, time)),
+        }
+    }
+
+    pub fn end_frame(&mut self, now: Instant, draw_calls: usize, glyphs: usize) {
+        self.mark("other", now);
+        self.last_sections = std::mem::take(&mut self.sections);
+        if self.frames.len() == FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(now.saturating_duration_since(self.frame_start));
+        (self.draw_calls, self.glyphs) = (draw_calls, glyphs);
+    }
+
+    // the overlay's lines, times in ms
+    pub fn lines(&self) -> Vec<String> {
+        let ms = |time: &Duration| time.as_secs_f64() * 1000.0;
+        let Some(last) = self.frames.back() else { return vec![String::from("No frames yet")] };
+        let average = self.frames.iter().map(ms).sum::<f64>() / self.frames.len() as f64;
+        let slowest = self.frames.iter().map(ms).fold(0.0, f64::max);
+        let mut lines = vec![
+            format!("frame {:.2} ms, avg {average:.2}, max {slowest:.2}", ms(last)),
+            format!("draw calls {}, glyphs {}", self.draw_calls, self.glyphs),
+        ];
+        let width = self.last_sections.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        lines.extend(self.last_sections.iter().map(|(name, time)| format!("{name:width$} {:.2} ms", ms(time))));
+        lines
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiler() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut profiler = Profiler::new(start);
+        assert_eq!(profiler.lines(), ["No frames yet"]);
+
+        profiler.begin_frame(at(0));
+        profiler.mark("input", at(1));
+        profiler.mark("text", at(3));
+        profiler.mark("input", at(4));
+        profiler.end_frame(at(6), 12, 80);
+        // a frame that wasn't drawn leaves nothing behind
+        profiler.begin_frame(at(10));
+        profiler.mark("input", at(11));
+        profiler.begin_frame(at(20));
+        profiler.mark("text", at(22));
+        profiler.end_frame(at(22), 5, 81);
+
+        assert_eq!(profiler.lines(), [
+            "frame 2.00 ms, avg 4.00, max 6.00",
+            "draw calls 5, glyphs 81",
+            "text  2.00 ms",
+            "other 0.00 ms",
+        ]);
+        for i in 0..FRAMES as u64 {
+            profiler.begin_frame(at(100 + i));
+            profiler.end_frame(at(100 + i), 0, 0);
+        }
+        assert_eq!(profiler.lines()[0], "frame 0.00 ms, avg 0.00, max 0.00");
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+
+
+// the folder a project lives in, found by walking up from a file for what
+// marks one. the nearest .git wins over a Cargo.toml further in, so a crate in
+// a workspace gets the repository's root
+
+const MARKERS: &[&str] = &[".git", "Cargo.toml"];
+
+// the root for a file or folder at start, None if nothing up from it looks like one
+pub fn find_root(start: &Path) -> Option<PathBuf> {
+    let start = start.canonicalize().ok()?;
+    let dirs = start.ancestors().filter(|dir| dir.is_dir()).collect::<Vec<_>>();
+    MARKERS.iter().find_map(|marker| {
+        dirs.iter().find(|dir| dir.join(marker).exists()).map(|dir| dir.to_path_buf())
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn test_find_root() {
+        let dir = env::temp_dir().join(format!("moded_project_{}", std::process::id()));
+        let src = dir.join("crates/core/src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(dir.join("crates/core/Cargo.toml"), "").unwrap();
+        fs::write(src.join("lib.rs"), "").unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        assert_eq!(find_root(&src.join("lib.rs")), Some(dir.join("crates/core")));
+        fs::create_dir(dir.join(".git")).unwrap();
+        assert_eq!(find_root(&src.join("lib.rs")), Some(dir.clone()));
+        assert_eq!(find_root(&dir.join("missing")), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::{gap_buffer::LinePos, options::config_dir};
+
+
+// files that were open before, newest first, with where the cursor and the
+// view were when they were left. kept in the config folder so a file opened
+// again the next time goes back to the same place
+
+// older files are dropped when there are more
+const MAX_FILES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFile {
+    pub path: PathBuf,
+    pub pos: LinePos,
+    // the first line shown
+    pub start_line: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct RecentFiles {
+    pub files: Vec<RecentFile>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let text = config_dir().and_then(|dir| fs::read_to_string(dir.join("recent")).ok());
+        Self::parse(&text.unwrap_or_default())
+    }
+
+    pub fn save(&self) {
+        let Some(dir) = config_dir() else { return };
+        if fs::create_dir_all(&dir).is_ok() {
+            let _ = fs::write(dir.join("recent"), self.to_text());
+        }
+    }
+
+    // "line col start_line path" on each line, the path last since it can have spaces
+    pub fn parse(text: &str) -> Self {
+        let files = text.lines().filter_map(|line| {
+            let mut parts = line.splitn(4, ' ');
+            let mut number = || parts.next()?.parse::<usize>().ok();
+            let (line, col, start_line) = (number()?, number()?, number()?);
+            let path = parts.next().filter(|path| !path.is_empty())?;
+            Some(RecentFile { path: PathBuf::from(path), pos: LinePos::new(line, col), start_line })
+        });
+
+        Self { files: files.take(MAX_FILES).collect() }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.files.iter().map(|file| format!("{} {} {} {}\n", file.pos.line, file.pos.col, file.start_line, file.path.display())).collect()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&RecentFile> {
+        let path = canonical(path);
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    // puts the file first, replacing where it was before
+    pub fn remember(&mut self, path: &Path, pos: LinePos, start_line: usize) {
+        let path = canonical(path);
+        self.files.retain(|file| file.path != path);
+        self.files.insert(0, RecentFile { path, pos, start_line });
+        self.files.truncate(MAX_FILES);
+    }
+
+    // a new place for a file that keeps its spot in the list, a new one goes
+    // last. the numbers :oldfiles shows stay the same while the editor runs
+    pub fn update(&mut self, path: &Path, pos: LinePos, start_line: usize) {
+        let path = canonical(path);
+        if let Some(file) = self.files.iter_mut().find(|file| file.path == path) {
+            (file.pos, file.start_line) = (pos, start_line);
+        } else if self.files.len() < MAX_FILES {
+            self.files.push(RecentFile { path, pos, start_line });
+        }
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_files() {
+        let mut recent = RecentFiles::parse("3 4 0 /tmp/a file.rs\nnot a line\n10 0 5 /tmp/b.rs\n");
+        assert_eq!(recent.files.len(), 2);
+        assert_eq!(recent.get(Path::new("/tmp/a file.rs")).map(|file| file.pos), Some(LinePos::new(3, 4)));
+
+        recent.remember(Path::new("/tmp/b.rs"), LinePos::new(1, 2), 0);
+        assert_eq!(recent.to_text(), "1 2 0 /tmp/b.rs\n3 4 0 /tmp/a file.rs\n");
+        recent.update(Path::new("/tmp/a file.rs"), LinePos::new(5, 0), 1);
+        recent.update(Path::new("/tmp/c.rs"), LinePos::new(0, 0), 0);
+        assert_eq!(recent.to_text(), "1 2 0 /tmp/b.rs\n5 0 1 /tmp/a file.rs\n0 0 0 /tmp/c.rs\n");
+        for i in 0..MAX_FILES {
+            recent.remember(Path::new(&format!("/tmp/{i}")), LinePos::new(0, 0), 0);
+        }
+        assert_eq!(recent.files.len(), MAX_FILES);
+        assert!(recent.get(Path::new("/tmp/b.rs")).is_none());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::vim_commands::is_letter;
+
+
+// small backtracking regex engine using vim's "magic" syntax:
+// . * \+ \= \? ^ $ [abc] [^a-z] \( \) \| \< \> \s \S \d \D \w \W \t
+// \c anywhere in the pattern makes it ignore case, \C forces matching case.
+// positions are char indices into the matched text
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class { items: Vec<(char, char)>, negated: bool },
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+    Group(usize, Box<Node>),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+// start and end of the whole match in [0] and of each \( \) group after it
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+pub struct Regex {
+    node: Node,
+    groups: usize,
+    pub ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, ()> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+            ignore_case: None,
+        };
+
+        let node = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            // unmatched \)
+            return Err(())
+        }
+
+        Ok(Self { node, groups: parser.groups + 1, ignore_case: parser.ignore_case.unwrap_or(false) })
+    }
+
+    // ignore case unless the pattern says otherwise with \c or \C
+    pub fn with_default_ignore_case(pattern: &str, ignore_case: bool) -> Result<Self, ()> {
+        let mut me = Self::new(pattern)?;
+        if !pattern.contains("\\c") && !pattern.contains("\\C") {
+            me.ignore_case = ignore_case;
+        }
+
+        Ok(me)
+    }
+
+    pub fn is_match(&self, text: &[char]) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    // first match starting at or after start
+    pub fn find_at(&self, text: &[char], start: usize) -> Option<Captures> {
+        let matcher = Matcher { text, ignore_case: self.ignore_case };
+        for pos in start..(text.len() + 1) {
+            let mut caps = vec![None; self.groups];
+            let mut end = None;
+            let found = matcher.match_node(&self.node, pos, &mut caps, &mut |p, _| {
+                end = Some(p);
+                true
+            });
+            if found {
+                caps[0] = Some((pos, end.unwrap_or(pos)));
+                return Some(caps)
+            }
+        }
+
+        None
+    }
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    ignore_case: Option<bool>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_escaped(&self, wanted: char) -> bool {
+        self.peek() == Some('\\') && self.chars.get(self.pos + 1) == Some(&wanted)
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, ()> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek_escaped('|') {
+            self.pos += 2;
+            branches.push(self.parse_concat()?);
+        }
+
+        if branches.len() == 1 {
+            return Ok(branches.pop().unwrap())
+        }
+        Ok(Node::Alternation(branches))
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, ()> {
+        let mut nodes = Vec::new();
+        while let Some(char) = self.peek() {
+            if self.peek_escaped('|') || self.peek_escaped(')') {
+                break
+            }
+
+            let atom = match char {
+                '^' if nodes.is_empty() => { self.pos += 1; Node::LineStart },
+                '$' if self.at_branch_end(self.pos + 1) => { self.pos += 1; Node::LineEnd },
+                '*' if nodes.is_empty() => { self.pos += 1; Node::Char('*') },
+                '.' => { self.pos += 1; Node::Any },
+                '[' => self.parse_class()?,
+                '\\' => {
+                    let Some(atom) = self.parse_escape()? else { continue };
+                    atom
+                },
+                c => { self.pos += 1; Node::Char(c) },
+            };
+
+            nodes.push(self.parse_repeat(atom));
+        }
+
+        if nodes.len() == 1 {
+            return Ok(nodes.pop().unwrap())
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn at_branch_end(&self, pos: usize) -> bool {
+        match self.chars.get(pos) {
+            None => true,
+            Some('\\') => matches!(self.chars.get(pos + 1), Some('|') | Some(')')),
+            _ => false,
+        }
+    }
+
+    fn parse_repeat(&mut self, atom: Node) -> Node {
+        let (min, max) = if self.peek() == Some('*') {
+            self.pos += 1;
+            (0, None)
+        } else if self.peek_escaped('+') {
+            self.pos += 2;
+            (1, None)
+        } else if self.peek_escaped('=') || self.peek_escaped('?') {
+            self.pos += 2;
+            (0, Some(1))
+        } else {
+            return atom
+        };
+
+        Node::Repeat { node: Box::new(atom), min, max }
+    }
+
+    // returns None for escapes that only change settings, like \c
+    fn parse_escape(&mut self) -> Result<Option<Node>, ()> {
+        self.pos += 1;
+        let Some(char) = self.peek() else { return Ok(Some(Node::Char('\\'))) };
+        self.pos += 1;
+
+        let class = |items: &[(char, char)], negated: bool| Node::Class { items: items.to_vec(), negated };
+        const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\t')];
+        const DIGIT: &[(char, char)] = &[('0', '9')];
+        const WORD: &[(char, char)] = &[('0', '9'), ('a', 'z'), ('A', 'Z'), ('_', '_')];
+
+        let node = match char {
+            '(' => {
+                self.groups += 1;
+                let index = self.groups;
+                let inner = self.parse_alternation()?;
+                if !self.peek_escaped(')') {
+                    return Err(())
+                }
+                self.pos += 2;
+                Node::Group(index, Box::new(inner))
+            },
+            '<' => Node::WordStart,
+            '>' => Node::WordEnd,
+            's' => class(SPACE, false),
+            'S' => class(SPACE, true),
+            'd' => class(DIGIT, false),
+            'D' => class(DIGIT, true),
+            'w' => class(WORD, false),
+            'W' => class(WORD, true),
+            't' => Node::Char('\t'),
+            'c' => { self.ignore_case = Some(true); return Ok(None) },
+            'C' => { self.ignore_case = Some(false); return Ok(None) },
+            c => Node::Char(c),
+        };
+
+        Ok(Some(node))
+    }
+
+    fn parse_class(&mut self) -> Result<Node, ()> {
+        let start = self.pos;
+        self.pos += 1;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(mut char) = self.peek() else {
+                // no closing ], vim takes the [ literally
+                self.pos = start + 1;
+                return Ok(Node::Char('['))
+            };
+            self.pos += 1;
+
+            if char == ']' && !first {
+                break
+            }
+            first = false;
+
+            if char == '\\' {
+                let Some(escaped) = self.peek() else { return Err(()) };
+                self.pos += 1;
+                char = if escaped == 't' { '\t' } else { escaped };
+            }
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                let end = self.chars[self.pos + 1];
+                self.pos += 2;
+                items.push((char, end));
+            } else {
+                items.push((char, char));
+            }
+        }
+
+        Ok(Node::Class { items, negated })
+    }
+}
+
+
+struct Matcher<'a> {
+    text: &'a [char],
+    ignore_case: bool,
+}
+
+type Continuation<'k> = &'k mut dyn FnMut(usize, &mut Captures) -> bool;
+
+impl Matcher<'_> {
+    fn chars_equal(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn in_class(&self, char: char, items: &[(char, char)]) -> bool {
+        items.iter().any(|&(start, end)| {
+            if start <= char && char <= end {
+                return true
+            }
+            if !self.ignore_case {
+                return false
+            }
+            char.to_lowercase().chain(char.to_uppercase()).any(|c| start <= c && c <= end)
+        })
+    }
+
+    fn is_word_at(&self, pos: usize) -> bool {
+        self.text.get(pos).is_some_and(|c| is_letter(*c))
+    }
+
+    // calls k with every end position node can match to, stops when k returns true
+    fn match_node(&self, node: &Node, pos: usize, caps: &mut Captures, k: Continuation) -> bool {
+        match node {
+            Node::Char(c) => pos < self.text.len() && self.chars_equal(self.text[pos], *c) && k(pos + 1, caps),
+            Node::Any => pos < self.text.len() && k(pos + 1, caps),
+            Node::Class { items, negated } => {
+                pos < self.text.len() && self.in_class(self.text[pos], items) != *negated && k(pos + 1, caps)
+            },
+            Node::LineStart => pos == 0 && k(pos, caps),
+            Node::LineEnd => pos == self.text.len() && k(pos, caps),
+            Node::WordStart => {
+                self.is_word_at(pos) && (pos == 0 || !self.is_word_at(pos - 1)) && k(pos, caps)
+            },
+            Node::WordEnd => {
+                pos > 0 && self.is_word_at(pos - 1) && !self.is_word_at(pos) && k(pos, caps)
+            },
+            Node::Group(index, inner) => {
+                let index = *index;
+                self.match_node(inner, pos, caps, &mut |end, caps| {
+                    let previous = caps[index];
+                    caps[index] = Some((pos, end));
+                    if k(end, caps) {
+                        return true
+                    }
+                    caps[index] = previous;
+                    false
+                })
+            },
+            Node::Concat(nodes) => self.match_sequence(nodes, pos, caps, k),
+            Node::Alternation(branches) => {
+                branches.iter().any(|branch| self.match_node(branch, pos, caps, k))
+            },
+            Node::Repeat { node, min, max } => self.match_repeat(node, *min, *max, 0, pos, caps, k),
+        }
+    }
+
+    fn match_sequence(&self, nodes: &[Node], pos: usize, caps: &mut Captures, k: Continuation) -> bool {
+        let Some((first, rest)) = nodes.split_first() else { return k(pos, caps) };
+        self.match_node(first, pos, caps, &mut |end, caps| self.match_sequence(rest, end, caps, k))
+    }
+
+    // greedy, tries one more repetition before giving the rest to k
+    #[allow(clippy::too_many_arguments)]
+    fn match_repeat(&self, node: &Node, min: usize, max: Option<usize>, count: usize, pos: usize, caps: &mut Captures, k: Continuation) -> bool {
+        if max.is_none_or(|max| count < max) {
+            let more = self.match_node(node, pos, caps, &mut |end, caps| {
+                // an empty match can't make progress, stop repeating it
+                (end != pos || count < min) && self.match_repeat(node, min, max, count + 1, end, caps, k)
+            });
+            if more {
+                return true
+            }
+        }
+
+        count >= min && k(pos, caps)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        let text = text.chars().collect::<Vec<_>>();
+        Regex::new(pattern).unwrap().find_at(&text, 0)?[0]
+    }
+
+    #[test]
+    fn test_matching() {
+        assert_eq!(find("b.d", "abcde"), Some((1, 4)));
+        assert_eq!(find("a*b", "xaaab"), Some((1, 5)));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "abb"), Some((2, 3)));
+        assert_eq!(find("[0-9]\\+", "ab123c"), Some((2, 5)));
+        assert_eq!(find("[^a-c]", "abcd"), Some((3, 4)));
+        assert_eq!(find("foo\\|bar", "xbar"), Some((1, 4)));
+        assert_eq!(find("\\<is\\>", "this is"), Some((5, 7)));
+        assert_eq!(find("\\cHELLO", "say hello"), Some((4, 9)));
+        assert_eq!(find("colou\\=r", "color"), Some((0, 5)));
+    }
+
+    #[test]
+    fn test_groups() {
+        let text = "key = value".chars().collect::<Vec<_>>();
+        let re = Regex::new("\\(\\w\\+\\) = \\(\\w\\+\\)").unwrap();
+        let caps = re.find_at(&text, 0).unwrap();
+        assert_eq!(caps[1], Some((0, 3)));
+        assert_eq!(caps[2], Some((6, 11)));
+
+        assert!(Regex::new("\\(a").is_err());
+        assert!(Regex::new("a\\)").is_err());
+    }
+}
@@ -1,63 +1,140 @@
-use crate::{shader::{RectShader, TextShader}, CharacterCache, State};
+use std::{cell::Cell, collections::HashMap};
+
+use crate::{font::CharacterCache, popup::{self, Popup}, shader::{uniform_location, RectShader, TextShader}, width, wrap::Row, State};
 
 pub struct DrawLine<'a> {
     pub text: &'a str,
     pub linenr: usize,
+    pub start_col: usize,
     pub color: (f32, f32, f32),
 }
 
 impl<'a> DrawLine<'a> {
     pub fn new(text: &'a str, linenr: usize, color: (f32, f32, f32)) -> Self {
-        Self { text, linenr, color }
+        Self { text, linenr, start_col: 0, color }
+    }
+
+    pub fn at_col(mut self, col: usize) -> Self {
+        self.start_col = col;
+        self
+    }
+}
+
+
+// vertex data of a line drawn last frame, rebuilt only when its text or the layout changes
+struct CachedLine {
+    text: String,
+    layout: [f32; 5],
+    vao: u32,
+    vbo: u32,
+    // texture of each quad in the buffer
+    textures: Vec<u32>,
+    used: bool,
+}
+
+impl Drop for CachedLine {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
     }
 }
 
+// the viewport, screen row, column and color of a line
+type LineKey = ((i32, i32), usize, usize, [u32; 3]);
 
 pub struct TextRenderer {
     pub shader: TextShader,
     pub char_cache: CharacterCache,
-    pub vao: u32,
-    pub vbo: u32,
     pub font_height: f32,
     pub font_ascent: f32,
+    // without it text is drawn in the shader's own color
+    color_location: Option<i32>,
+    brightness_location: Option<i32>,
+    pub error: Option<String>,
+    // where the viewport is, so windows side by side don't share lines
+    pub view: (i32, i32),
+    // text drawn over other text keeps its own
+    lines: HashMap<LineKey, CachedLine>,
+    // since the last take_draw_calls, for the profile overlay
+    draw_calls: Cell<usize>,
 }
 
 impl TextRenderer {
     pub fn new(shader: TextShader, char_cache: CharacterCache, font_height: f32, font_ascent: f32) -> Self {
-        // vao / vbo for texture quads
-        let mut vertex_array_object = 0;
-        let mut vertex_buffer_object = 0;
+        let color_location = uniform_location(shader.id, c"textColor");
+        let error = color_location.is_none().then(|| String::from("Text shader has no textColor, drawing text without colors"));
+        let brightness_location = uniform_location(shader.id, c"brightness");
+
+        let renderer = Self { shader, char_cache, font_height, font_ascent, color_location, brightness_location, error, view: (0, 0), lines: HashMap::new(), draw_calls: Cell::new(0) };
+        // uniforms start at 0
+        renderer.set_brightness(1.0);
+        renderer
+    }
+
+    // what the colors of the text drawn from here on are multiplied by
+    pub fn set_brightness(&self, brightness: f32) {
+        let Some(location) = self.brightness_location else { return };
+        self.shader.use_program();
+        unsafe { gl::Uniform1f(location, brightness) };
+    }
+
+    pub fn draw_line(&mut self, state: &State, line: DrawLine) {
+        self.shader.use_program();
         unsafe {
-            gl::GenVertexArrays(1, &mut vertex_array_object);
-            gl::GenBuffers(1, &mut vertex_buffer_object);
-            gl::BindVertexArray(vertex_array_object);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_object);
-            gl::BufferData(gl::ARRAY_BUFFER, std::mem::size_of::<f32>() as isize * 6 * 4, 0 as *const _, gl::DYNAMIC_DRAW);
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, 4 * std::mem::size_of::<f32>() as i32, 0 as *const _);
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            if let Some(location) = self.color_location {
+                gl::Uniform3f(location, line.color.0, line.color.1, line.color.2);
+            }
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+
+        let layout = [state.char_width, state.height as f32, self.font_height, state.char_scale, state.options.tabstop as f32];
+        let key = (self.view, line.linenr, line.start_col, [line.color.0.to_bits(), line.color.1.to_bits(), line.color.2.to_bits()]);
+        let fresh = self.lines.get(&key).is_some_and(|cached| cached.text == line.text && cached.layout == layout);
+        if !fresh {
+            let cached = self.build_line(state, &line, layout);
+            self.lines.insert(key, cached);
+        }
+
+        let Some(cached) = self.lines.get_mut(&key) else { return };
+        cached.used = true;
+        unsafe {
+            gl::BindVertexArray(cached.vao);
+            for (i, texture) in cached.textures.iter().enumerate() {
+                gl::BindTexture(gl::TEXTURE_2D, *texture);
+                gl::DrawArrays(gl::TRIANGLES, (i * 6) as i32, 6);
+            }
             gl::BindVertexArray(0);
         }
+        self.draw_calls.set(self.draw_calls.get() + cached.textures.len());
+    }
 
-        Self { shader, char_cache, vao: vertex_array_object, vbo: vertex_buffer_object, font_height, font_ascent }
+    pub fn take_draw_calls(&self) -> usize {
+        self.draw_calls.take()
     }
 
-    pub fn draw_line(&mut self, state: &State, line: DrawLine) {
-        self.shader.use_program();
+    // lines that weren't drawn this frame are scrolled away or gone, their buffers are freed
+    pub fn end_frame(&mut self) {
+        self.lines.retain(|_, cached| std::mem::take(&mut cached.used));
+    }
+
+    fn build_line(&mut self, state: &State, line: &DrawLine, layout: [f32; 5]) -> CachedLine {
+        let mut vertices: Vec<[[f32; 4]; 6]> = Vec::new();
+        let mut textures = Vec::new();
 
-        let mut x = 0f32;
+        let mut x = line.start_col as f32 * state.char_width;
+        let mut display = 0;
         for ch in line.text.chars() {
-            // colors
-            unsafe {
-                let uniform_location = gl::GetUniformLocation(self.shader.id, c"textColor".as_ptr().cast());
-                assert!(uniform_location != -1);
-
-                gl::Uniform3f(uniform_location, line.color.0, line.color.1, line.color.2);
-                gl::ActiveTexture(gl::TEXTURE0);
-                gl::BindVertexArray(self.vao);
+            // a tab is only space up to the next tabstop
+            let cells = width::cell_width(ch, display, state.options.tabstop);
+            let cell_x = x;
+            display += cells;
+            x += cells as f32 * state.char_width;
+            if ch == '\t' {
+                continue
             }
 
-            //let (xpos, ypos) = (0f32, 100f32);
             let c = if let Some(c) = self.char_cache.get(ch) {
                 c
             } else {
@@ -65,13 +142,13 @@ impl TextRenderer {
                 let Some(c) = self.char_cache.get(ch) else {continue;};
                 c
             };
-            //let c = self.char_cache.get(ch).unwrap();
 
-            let xadvance = ((state.char_width - c.width) / 2.0).max(0.0);
+            // wide chars are centered in their two cells
+            let xadvance = ((cells as f32 * state.char_width - c.width) / 2.0).max(0.0);
             let (h, w) = (c.height, c.width);
-            let (xpos, ypos) = (x + xadvance, state.height as f32 - self.font_ascent - c.position_max_y - (self.font_height * (line.linenr - 1) as f32));
+            let (xpos, ypos) = (cell_x + xadvance, state.height as f32 - self.font_ascent - c.position_max_y - (self.font_height * (line.linenr - 1) as f32));
 
-            let vertices: [[f32; 4]; 6] = [
+            vertices.push([
                 [xpos,     ypos + h, 0.0, 0.0],
                 [xpos,     ypos,     0.0, 1.0],
                 [xpos + w, ypos,     1.0, 1.0],
@@ -79,23 +156,32 @@ impl TextRenderer {
                 [xpos,     ypos + h, 0.0, 0.0],
                 [xpos + w, ypos,     1.0, 1.0],
                 [xpos + w, ypos + h, 1.0, 0.0],
-            ];
-
-            unsafe {
-                gl::BindTexture(gl::TEXTURE_2D, c.texture_id);
-                gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-                // std::mem::size_of_val(&vertices) as isize
-                //gl::BufferSubData(gl::ARRAY_BUFFER, 0, 4 * 6 * std::mem::size_of::<f32>() as isize, vertices.as_ptr().cast());
-                gl::BufferSubData(gl::ARRAY_BUFFER, 0, std::mem::size_of_val(&vertices) as isize, vertices.as_ptr().cast());
+            ]);
+            textures.push(c.texture_id);
+        }
 
-                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-                gl::DrawArrays(gl::TRIANGLES, 0, 6);
-            }
+        // vao / vbo for texture quads
+        let mut vertex_array_object = 0;
+        let mut vertex_buffer_object = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array_object);
+            gl::GenBuffers(1, &mut vertex_buffer_object);
+            gl::BindVertexArray(vertex_array_object);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_object);
+            gl::BufferData(gl::ARRAY_BUFFER, std::mem::size_of_val(vertices.as_slice()) as isize, vertices.as_ptr().cast(), gl::STATIC_DRAW);
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, 4 * std::mem::size_of::<f32>() as i32, std::ptr::null());
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
 
-            //const CHAR_SPACE: f32 = 5.0;
-            x += state.char_width;
-            //x += c.width + char_space;
-            //x += c.advance_horizontal - c.bearing_horizontal;
+        CachedLine {
+            text: line.text.to_owned(),
+            layout,
+            vao: vertex_array_object,
+            vbo: vertex_buffer_object,
+            textures,
+            used: false,
         }
     }
 }
@@ -124,6 +210,15 @@ impl DrawRect {
         let height = (state.char_height * 2.0) / state.height as f32;
         Self { height, width, xpos, ypos, color }
     }
+
+    // x and y in pixels from the top left corner
+    pub fn from_pixels(state: &State, x: f32, y: f32, width: f32, height: f32, color: (f32, f32, f32)) -> Self {
+        let xpos = ((x * 2.0) / state.width as f32) - 1.0;
+        let ypos = (((state.height as f32 - y - height) * 2.0) / state.height as f32) - 1.0;
+        let width = (width * 2.0) / state.width as f32;
+        let height = (height * 2.0) / state.height as f32;
+        Self { height, width, xpos, ypos, color }
+    }
 }
 
 
@@ -131,6 +226,9 @@ pub struct RectRenderer {
     pub shader: RectShader,
     pub vao: u32,
     pub vbo: u32,
+    color_location: Option<i32>,
+    pub error: Option<String>,
+    draw_calls: Cell<usize>,
 }
 
 impl RectRenderer {
@@ -150,16 +248,16 @@ impl RectRenderer {
             gl::BindVertexArray(0);
         }
 
-        Self { shader, vao: vertex_array_object, vbo: vertex_buffer_object }
+        let color_location = uniform_location(shader.id, c"rectColor");
+        let error = color_location.is_none().then(|| String::from("Rect shader has no rectColor, drawing rects without colors"));
+
+        Self { shader, vao: vertex_array_object, vbo: vertex_buffer_object, color_location, error, draw_calls: Cell::new(0) }
     }
 
     pub fn draw_rect(&self, _state: &State, rect: DrawRect) {
         self.shader.use_program();
-        unsafe {
-            let uniform_location = gl::GetUniformLocation(self.shader.id, c"rectColor".as_ptr().cast());
-            assert!(uniform_location != -1);
-
-            gl::Uniform3f(uniform_location, rect.color.0, rect.color.1, rect.color.2);
+        if let Some(location) = self.color_location {
+            unsafe { gl::Uniform3f(location, rect.color.0, rect.color.1, rect.color.2) };
         }
 
         let (h, w) = (rect.height, rect.width);
@@ -185,9 +283,15 @@ impl RectRenderer {
 
             gl::BindVertexArray(0);
         }
+        self.draw_calls.set(self.draw_calls.get() + 1);
+    }
+
+    pub fn take_draw_calls(&self) -> usize {
+        self.draw_calls.take()
     }
 }
 
+// start and end are screen columns, end included
 pub fn highlight_line(state: &State, start: usize, end: usize, line: usize) -> DrawRect {
     let mut width = (end + 1 - start) as f32 * state.char_width;
     width = (width * 2.0) / state.width as f32;
@@ -203,3 +307,67 @@ pub fn highlight_line(state: &State, start: usize, end: usize, line: usize) -> D
 
     DrawRect::new(height, width, xpos, ypos, color)
 }
+
+// like highlight_line but start and end are char columns of text, wide chars cover two cells
+pub fn highlight_text(state: &State, text: &str, start: usize, end: usize, line: usize) -> DrawRect {
+    let start_cell = width::display_col(text, start, state.options.tabstop);
+    let end_cell = width::display_col(text, end + 1, state.options.tabstop).max(start_cell + 1) - 1;
+
+    highlight_line(state, start_cell, end_cell, line)
+}
+
+// like highlight_text for chars of a buffer line, one rect for each of the line's rows in
+// rows, the screen rows from wrap::screen_rows
+pub fn highlight_chars(state: &State, rows: &[Row], text: &str, line: usize, start: usize, end: usize) -> Vec<DrawRect> {
+    let mut rects = Vec::new();
+    for (i, row) in rows.iter().enumerate().filter(|(_, row)| row.line == line) {
+        // the last row of a line also covers cols past its end
+        let last = rows.get(i + 1).is_none_or(|next| next.line != line);
+        let start = start.max(row.start);
+        let end = if last { end } else { end.min(row.end.saturating_sub(1)) };
+        if start > end {
+            continue
+        }
+        rects.push(highlight_text(state, &row.text(text), start - row.start, end - row.start, state.start_line + i));
+    }
+
+    rects
+}
+
+// popups go over everything else, the ones with a higher z over the others
+pub fn draw_popups(state: &State, rect_renderer: &RectRenderer, text_renderer: &mut TextRenderer, popups: &[Popup]) {
+    let mut popups = popups.iter().collect::<Vec<_>>();
+    popups.sort_by_key(|popup| popup.z);
+    for popup in popups {
+        let Some(layout) = popup.layout(state.max_rows(), state.max_cols()) else { continue };
+        let last_col = layout.col + layout.cols - 1;
+        for row in layout.row..layout.row + layout.rows {
+            let mut rect = highlight_line(state, layout.col, last_col, state.start_line + row);
+            rect.color = (0.25, 0.25, 0.3);
+            rect_renderer.draw_rect(state, rect);
+        }
+
+        if popup.border {
+            let color = (0.6, 0.6, 0.65);
+            let (top, side, bottom) = popup::border_lines(layout.cols);
+            let side = side.to_string();
+            text_renderer.draw_line(state, DrawLine::new(&top, layout.row + 1, color).at_col(layout.col));
+            for row in layout.text_row..layout.text_row + layout.text_rows {
+                text_renderer.draw_line(state, DrawLine::new(&side, row + 1, color).at_col(layout.col));
+                text_renderer.draw_line(state, DrawLine::new(&side, row + 1, color).at_col(last_col));
+            }
+            text_renderer.draw_line(state, DrawLine::new(&bottom, layout.row + layout.rows, color).at_col(layout.col));
+        }
+
+        for (i, line) in popup.lines.iter().take(layout.text_rows).enumerate() {
+            let row = layout.text_row + i;
+            if popup.selected == Some(i) {
+                let mut rect = highlight_line(state, layout.text_col, layout.text_col + layout.text_cols - 1, state.start_line + row);
+                rect.color = (0.4, 0.4, 0.6);
+                rect_renderer.draw_rect(state, rect);
+            }
+            let text = popup::clip(line, layout.text_cols);
+            text_renderer.draw_line(state, DrawLine::new(&text, row + 1, popup.color).at_col(layout.text_col));
+        }
+    }
+}
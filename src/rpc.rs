@@ -0,0 +1,198 @@
+use std::{env, fs, io::{self, BufRead, BufReader, Write}, os::unix::{fs::PermissionsExt, net::{UnixListener, UnixStream}}, path::{Path, PathBuf}, sync::atomic::Ordering};
+
+use crate::{editor::{Editor, EditorMode}, json::Json, options::config_dir, State, SHOULD_QUIT};
+
+
+// --headless serves the editor over a unix socket without a window. every request is
+// one line of json like {"id": 1, "method": "keys", "params": {"keys": "dd"}} and gets
+// one line back, {"id": 1, "result": ...} or {"id": 1, "error": "..."}. only the user
+// can connect to the socket, and shell commands are refused all the same
+const SOCKET_NAME: &str = "moded.sock";
+
+// in the runtime folder, which only the user can get into, or the config folder
+pub fn default_socket() -> Option<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join(SOCKET_NAME)),
+        _ => Some(config_dir()?.join(SOCKET_NAME)),
+    }
+}
+
+// the screen size scrolling and the like assume, in cells
+const COLS: i32 = 80;
+const ROWS: i32 = 24;
+
+pub fn headless_state() -> State {
+    let mut state = State::new(COLS, ROWS);
+    state.char_width = 1.0;
+    state.char_height = 1.0;
+
+    state
+}
+
+// clients are served one at a time, a quit request stops the server
+pub fn serve(editor: &mut Editor, state: &mut State, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    // left behind by a server that didn't get to clean up
+    if UnixStream::connect(path).is_err() {
+        let _ = fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    editor.no_shell = true;
+    eprintln!("listening on {}", path.display());
+
+    let mut result = Ok(());
+    for stream in listener.incoming() {
+        match stream.and_then(|stream| serve_client(editor, state, stream)) {
+            Ok(true) => break,
+            Ok(false) => (),
+            Err(e) => {
+                result = Err(e);
+                break
+            },
+        }
+    }
+    let _ = fs::remove_file(path);
+
+    result
+}
+
+// true if the client asked to quit
+fn serve_client(editor: &mut Editor, state: &mut State, stream: UnixStream) -> io::Result<bool> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+
+        editor.poll_lsp();
+        editor.poll_loading();
+        editor.poll_search();
+        let (response, quit) = match Json::parse(&line) {
+            Ok(request) => {
+                let method = request.get("method").and_then(Json::as_str).unwrap_or_default();
+                let params = request.get("params").cloned().unwrap_or(Json::Null);
+                let id = request.get("id").cloned().unwrap_or(Json::Null);
+                editor.enter_dir();
+                let response = match handle(editor, state, method, &params) {
+                    Ok(result) => Json::Object(vec![("id".to_owned(), id), ("result".to_owned(), result)]),
+                    Err(e) => Json::Object(vec![("id".to_owned(), id), ("error".to_owned(), Json::String(e))]),
+                };
+                // :q from a command or keys stops the server too
+                (response, method == "quit" || SHOULD_QUIT.load(Ordering::Relaxed))
+            },
+            Err(_) => (Json::object(vec![("id", Json::Null), ("error", Json::string("Invalid json"))]), false),
+        };
+
+        writeln!(writer, "{response}")?;
+        if quit {
+            return Ok(true)
+        }
+    }
+
+    Ok(false)
+}
+
+fn handle(editor: &mut Editor, state: &mut State, method: &str, params: &Json) -> Result<Json, String> {
+    let param = |name: &str| params.get(name).and_then(Json::as_str).ok_or(format!("Missing param: {name}"));
+    match method {
+        "open" => {
+            let path = Path::new(param("path")?);
+            editor.current_buffer = editor.open_file(path).map_err(|e| format!("Can't open {}: {e}", path.display()))?;
+            Ok(buffer_info(editor))
+        },
+        "keys" => {
+            editor.feed_keys(state, param("keys")?);
+            Ok(status(editor))
+        },
+        "command" => {
+            editor.run_command(state, param("command")?);
+            Ok(status(editor))
+        },
+        "lines" => {
+            let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Err(String::from("No buffer")) };
+            let start = params.get("start").and_then(Json::as_u64).unwrap_or(0) as usize;
+            let end = params.get("end").and_then(Json::as_u64).map_or(buffer.total_lines(), |end| end as usize);
+            let lines = (start..end.min(buffer.total_lines())).map(|line| Json::String(buffer.line(line))).collect();
+            Ok(Json::Array(lines))
+        },
+        "status" => Ok(status(editor)),
+        "buffers" => {
+            let buffers = editor.buffers.iter().map(|buffer| Json::object(vec![
+                ("id", Json::Number(buffer.id as f64)),
+                ("path", buffer.file_path.as_ref().map_or(Json::Null, |path| Json::String(path.display().to_string()))),
+            ]));
+            Ok(Json::Array(buffers.collect()))
+        },
+        "quit" => Ok(Json::Null),
+        _ => Err(format!("Unknown method: {method}")),
+    }
+}
+
+fn buffer_info(editor: &Editor) -> Json {
+    let Some(buffer) = editor.buffers.get(editor.current_buffer) else { return Json::Null };
+    Json::object(vec![
+        ("id", Json::Number(buffer.id as f64)),
+        ("lines", Json::Number(buffer.total_lines() as f64)),
+    ])
+}
+
+// where the editor is after a request, lines and columns from 0
+fn status(editor: &Editor) -> Json {
+    let mode = match editor.mode {
+        EditorMode::Insert => "insert",
+        EditorMode::Normal => "normal",
+        EditorMode::Replace => "replace",
+        EditorMode::Visual => "visual",
+        EditorMode::VisualLine => "visual line",
+        EditorMode::CommandBar => "command",
+        EditorMode::Search => "search",
+    };
+    let cursor = editor.cursors.get(editor.current_buffer).map(|cursor| cursor.to_linepos());
+    let encoding = editor.buffers.get(editor.current_buffer).map(|buffer| buffer.encoding.name());
+
+    Json::object(vec![
+        ("mode", Json::string(mode)),
+        ("line", cursor.map_or(Json::Null, |pos| Json::Number(pos.line as f64))),
+        ("col", cursor.map_or(Json::Null, |pos| Json::Number(pos.col as f64))),
+        ("message", Json::string(&editor.message)),
+        ("encoding", encoding.map_or(Json::Null, Json::string)),
+    ])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command_bar::SHELL_REFUSED, editor::next_buffer_id, gap_buffer::TextBuffer};
+
+    #[test]
+    fn test_handle() {
+        let buffer = TextBuffer::from_data(next_buffer_id(), b"one\ntwo\nthree\n".to_vec());
+        let mut editor = Editor::from_buffer(buffer);
+        let mut state = headless_state();
+
+        let keys = Json::object(vec![("keys", Json::string("jdd"))]);
+        let status = handle(&mut editor, &mut state, "keys", &keys).unwrap();
+        assert_eq!(status.get("mode"), Some(&Json::string("normal")));
+        assert_eq!(status.get("line").and_then(Json::as_u64), Some(1));
+
+        let range = Json::object(vec![("start", Json::Number(0.0)), ("end", Json::Number(2.0))]);
+        let lines = handle(&mut editor, &mut state, "lines", &range).unwrap();
+        assert_eq!(lines, Json::Array(vec![Json::string("one"), Json::string("three")]));
+
+        assert!(handle(&mut editor, &mut state, "keys", &Json::Null).is_err());
+        assert!(handle(&mut editor, &mut state, "nope", &Json::Null).is_err());
+
+        // clients can't reach the shell, not even through keys
+        editor.no_shell = true;
+        let keys = Json::object(vec![("keys", Json::string(":%!sort<CR>"))]);
+        let status = handle(&mut editor, &mut state, "keys", &keys).unwrap();
+        assert_eq!(status.get("message"), Some(&Json::string(SHELL_REFUSED)));
+        let lines = handle(&mut editor, &mut state, "lines", &Json::Null).unwrap();
+        assert_eq!(lines, Json::Array(vec![Json::string("one"), Json::string("three")]));
+    }
+}
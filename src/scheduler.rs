@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+
+// times features want the screen redrawn at (the end of a flash, a blink, a debounce),
+// so the main loop can sleep until the next one instead of polling
+#[derive(Default)]
+pub struct Scheduler {
+    // sorted, soonest first
+    wakeups: Vec<Instant>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self { wakeups: Vec::new() }
+    }
+
+    pub fn redraw_at(&mut self, at: Instant) {
+        let i = self.wakeups.partition_point(|&wakeup| wakeup <= at);
+        if i > 0 && self.wakeups[i - 1] == at {
+            return
+        }
+        self.wakeups.insert(i, at);
+    }
+
+    pub fn redraw_after(&mut self, delay: Duration) {
+        self.redraw_at(Instant::now() + delay);
+    }
+
+    // drops the wakeups that are due, true if there were any
+    pub fn take_due(&mut self, now: Instant) -> bool {
+        let due = self.wakeups.partition_point(|&wakeup| wakeup <= now);
+        self.wakeups.drain(..due);
+
+        due > 0
+    }
+
+    // how long to wait for events before the next redraw, None waits until an event
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.wakeups.first().map(|&wakeup| wakeup.saturating_duration_since(now))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wakeups() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.timeout(now), None);
+
+        scheduler.redraw_at(now + Duration::from_millis(30));
+        scheduler.redraw_at(now + Duration::from_millis(10));
+        scheduler.redraw_at(now + Duration::from_millis(10));
+        assert_eq!(scheduler.timeout(now), Some(Duration::from_millis(10)));
+        assert_eq!(scheduler.timeout(now + Duration::from_millis(20)), Some(Duration::ZERO));
+
+        assert!(!scheduler.take_due(now));
+        assert!(scheduler.take_due(now + Duration::from_millis(10)));
+        assert_eq!(scheduler.timeout(now), Some(Duration::from_millis(30)));
+        assert!(scheduler.take_due(now + Duration::from_millis(40)));
+        assert_eq!(scheduler.timeout(now), None);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{cell::RefCell, fs, ops::Range, path::Path, rc::Rc};
+
+use memchr::memchr;
+use rhai::{Array, Dynamic, Engine, Scope, AST};
+
+use crate::gap_buffer::{LinePos, Snapshot, TextBuffer};
+
+
+// how many steps a script gets before it's stopped, so a plugin stuck in a
+// loop can't hang the editor
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+
+// what a script asked the editor to do, applied once the script returns.
+// lines and columns are zero indexed like LinePos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOp {
+    SetLine(usize, String),
+    InsertLine(usize, String),
+    RemoveLine(usize),
+    SetCursor(LinePos),
+    Command(String),
+    Keys(String),
+    Message(String),
+    // virtual text after a line, and clearing all of the plugins' text
+    Annotate(usize, String),
+    ClearAnnotations,
+}
+
+// a function in one of the plugins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptFn {
+    pub plugin: usize,
+    pub name: String,
+}
+
+// the current buffer as scripts see it, writes go to both the lines and ops
+// so a script reads back what it wrote
+struct Shared {
+    view: View,
+    // all the lines once a script changed one, reads go to view until then
+    lines: Option<Vec<String>>,
+    cursor: LinePos,
+    ops: Vec<ScriptOp>,
+    // registered by the running plugin, function names
+    maps: Vec<(String, String)>,
+    commands: Vec<(String, String)>,
+}
+
+impl Shared {
+    fn line_count(&self) -> usize {
+        self.lines.as_ref().map_or(self.view.line_count, Vec::len)
+    }
+
+    fn line(&mut self, n: usize) -> Option<String> {
+        match &self.lines {
+            Some(lines) => lines.get(n).cloned(),
+            None => self.view.line(n),
+        }
+    }
+
+    fn lines_mut(&mut self) -> &mut Vec<String> {
+        let view = &mut self.view;
+        self.lines.get_or_insert_with(|| (0..view.line_count).filter_map(|n| view.line(n)).collect())
+    }
+}
+
+// a snapshot of the buffer, lines are only found as far as a script reads
+struct View {
+    text: Option<Snapshot>,
+    line_count: usize,
+    // the starts of the lines up to the furthest one read, and the one after it
+    starts: Vec<usize>,
+}
+
+impl View {
+    fn new(text: Option<Snapshot>, line_count: usize) -> Self {
+        Self { text, line_count, starts: vec![0] }
+    }
+
+    fn line(&mut self, n: usize) -> Option<String> {
+        let text = self.text.as_ref()?;
+        if n >= self.line_count {
+            return None
+        }
+        let (first, second) = text.halves();
+        let len = first.len() + second.len();
+        while self.starts.len() < n + 2 {
+            let from = self.starts[self.starts.len() - 1];
+            // the last line may not end in a newline, it ends at len then
+            let newline = if from < first.len() {
+                memchr(b'\n', &first[from..]).map(|i| from + i).or_else(|| memchr(b'\n', second).map(|i| first.len() + i))
+            } else {
+                memchr(b'\n', &second[(from - first.len()).min(second.len())..]).map(|i| from + i)
+            };
+            self.starts.push(newline.unwrap_or(len) + 1);
+        }
+
+        let range = self.starts[n]..(self.starts[n + 1] - 1).min(len);
+        let bytes = bytes_in(first, second, range);
+        let line = bytes.strip_suffix(b"\r").unwrap_or(&bytes);
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+}
+
+// range of first followed by second
+fn bytes_in(first: &[u8], second: &[u8], range: Range<usize>) -> Vec<u8> {
+    let split = first.len();
+    let mut bytes = first[range.start.min(split)..range.end.min(split)].to_vec();
+    bytes.extend_from_slice(&second[range.start.max(split) - split..range.end.max(split) - split]);
+    bytes
+}
+
+pub struct Scripting {
+    engine: Engine,
+    plugins: Vec<AST>,
+    shared: Rc<RefCell<Shared>>,
+    // normal mode keys that call a function
+    pub maps: Vec<(String, ScriptFn)>,
+    // :Name args calls a function with args
+    pub commands: Vec<(String, ScriptFn)>,
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let shared = Rc::new(RefCell::new(Shared {
+            view: View::new(None, 0),
+            lines: None,
+            cursor: LinePos::new(0, 0),
+            ops: Vec::new(),
+            maps: Vec::new(),
+            commands: Vec::new(),
+        }));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let s = shared.clone();
+        engine.on_print(move |text| s.borrow_mut().ops.push(ScriptOp::Message(text.to_owned())));
+
+        let s = shared.clone();
+        engine.register_fn("line_count", move || s.borrow().line_count() as i64);
+        let s = shared.clone();
+        engine.register_fn("line", move |n: i64| s.borrow_mut().line(n.max(0) as usize).unwrap_or_default());
+        let s = shared.clone();
+        engine.register_fn("set_line", move |n: i64, text: &str| {
+            let mut s = s.borrow_mut();
+            if n < 0 || n as usize >= s.line_count() {
+                return
+            }
+            s.lines_mut()[n as usize] = text.to_owned();
+            s.ops.push(ScriptOp::SetLine(n as usize, text.to_owned()));
+        });
+        let s = shared.clone();
+        engine.register_fn("insert_line", move |n: i64, text: &str| {
+            let mut s = s.borrow_mut();
+            let n = (n.max(0) as usize).min(s.line_count());
+            s.lines_mut().insert(n, text.to_owned());
+            s.ops.push(ScriptOp::InsertLine(n, text.to_owned()));
+        });
+        let s = shared.clone();
+        engine.register_fn("remove_line", move |n: i64| {
+            let mut s = s.borrow_mut();
+            // a buffer always has a line
+            if n < 0 || n as usize >= s.line_count() || s.line_count() == 1 {
+                return
+            }
+            s.lines_mut().remove(n as usize);
+            s.ops.push(ScriptOp::RemoveLine(n as usize));
+        });
+        let s = shared.clone();
+        engine.register_fn("cursor", move || {
+            let cursor = s.borrow().cursor;
+            vec![Dynamic::from(cursor.line as i64), Dynamic::from(cursor.col as i64)] as Array
+        });
+        let s = shared.clone();
+        engine.register_fn("set_cursor", move |line: i64, col: i64| {
+            let mut s = s.borrow_mut();
+            let pos = LinePos::new(line.max(0) as usize, col.max(0) as usize);
+            s.cursor = pos;
+            s.ops.push(ScriptOp::SetCursor(pos));
+        });
+        let s = shared.clone();
+        engine.register_fn("command", move |text: &str| s.borrow_mut().ops.push(ScriptOp::Command(text.to_owned())));
+        let s = shared.clone();
+        engine.register_fn("keys", move |text: &str| s.borrow_mut().ops.push(ScriptOp::Keys(text.to_owned())));
+        let s = shared.clone();
+        engine.register_fn("message", move |text: &str| s.borrow_mut().ops.push(ScriptOp::Message(text.to_owned())));
+        let s = shared.clone();
+        engine.register_fn("annotate", move |n: i64, text: &str| s.borrow_mut().ops.push(ScriptOp::Annotate(n.max(0) as usize, text.to_owned())));
+        let s = shared.clone();
+        engine.register_fn("clear_annotations", move || s.borrow_mut().ops.push(ScriptOp::ClearAnnotations));
+        let s = shared.clone();
+        engine.register_fn("map", move |keys: &str, func: &str| s.borrow_mut().maps.push((keys.to_owned(), func.to_owned())));
+        let s = shared.clone();
+        engine.register_fn("register_command", move |name: &str, func: &str| {
+            s.borrow_mut().commands.push((name.to_owned(), func.to_owned()));
+        });
+
+        Self { engine, plugins: Vec::new(), shared, maps: Vec::new(), commands: Vec::new() }
+    }
+
+    pub fn map(&self, keys: &str) -> Option<&ScriptFn> {
+        self.maps.iter().find(|(map, _)| map == keys).map(|(_, func)| func)
+    }
+
+    // true if keys could still become a map
+    pub fn is_map_prefix(&self, keys: &str) -> bool {
+        self.maps.iter().any(|(map, _)| map.len() > keys.len() && map.starts_with(keys))
+    }
+
+    pub fn command(&self, name: &str) -> Option<&ScriptFn> {
+        self.commands.iter().find(|(command, _)| command == name).map(|(_, func)| func)
+    }
+
+    // the lines aren't copied, scripts read them from a snapshot
+    fn set_view(&self, buffer: &TextBuffer, cursor: LinePos) {
+        let mut shared = self.shared.borrow_mut();
+        shared.view = View::new(Some(buffer.snapshot()), buffer.total_lines());
+        shared.lines = None;
+        shared.cursor = cursor;
+        shared.ops.clear();
+    }
+
+    // the ops of the script that ran, maps and commands it registered belong to plugin
+    fn take_ops(&mut self, plugin: usize) -> Vec<ScriptOp> {
+        let mut shared = self.shared.borrow_mut();
+        // the snapshot would make the buffer's next edit copy its text
+        shared.view = View::new(None, 0);
+        shared.lines = None;
+
+        for (keys, name) in std::mem::take(&mut shared.maps) {
+            self.maps.retain(|(map, _)| *map != keys);
+            self.maps.push((keys, ScriptFn { plugin, name }));
+        }
+        for (command, name) in std::mem::take(&mut shared.commands) {
+            self.commands.retain(|(existing, _)| *existing != command);
+            self.commands.push((command, ScriptFn { plugin, name }));
+        }
+
+        std::mem::take(&mut shared.ops)
+    }
+
+    // compiles and runs a plugin, it registers its maps and commands when it runs
+    pub fn load(&mut self, path: &Path, buffer: &TextBuffer, cursor: LinePos) -> Result<Vec<ScriptOp>, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let ast = self.engine.compile(&text).map_err(|e| e.to_string())?;
+
+        self.set_view(buffer, cursor);
+        let result = self.engine.run_ast_with_scope(&mut Scope::new(), &ast);
+        let plugin = self.plugins.len();
+        self.plugins.push(ast);
+        let ops = self.take_ops(plugin);
+        result.map_err(|e| e.to_string())?;
+
+        Ok(ops)
+    }
+
+    pub fn call(&mut self, func: &ScriptFn, args: &str, buffer: &TextBuffer, cursor: LinePos) -> Result<Vec<ScriptOp>, String> {
+        let Some(ast) = self.plugins.get(func.plugin) else { return Err(format!("No plugin for {}", func.name)) };
+
+        self.set_view(buffer, cursor);
+        // functions that take no arguments are fine for maps and commands
+        let mut result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, &func.name, (args.to_owned(),));
+        if result.as_ref().is_err_and(|e| matches!(**e, rhai::EvalAltResult::ErrorFunctionNotFound(..))) {
+            result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, &func.name, ());
+        }
+        let ops = self.take_ops(func.plugin);
+
+        result.map(|_| ops).map_err(|e| e.to_string())
+    }
+}
+
+impl Default for Scripting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_ops() {
+        let path = std::env::temp_dir().join(format!("moded_test_plugin_{}.rhai", std::process::id()));
+        fs::write(&path, r#"
+            map("gs", "shout");
+            register_command("Shout", "shout");
+            fn shout(args) {
+                let n = cursor()[0];
+                set_line(n, line(n).to_upper() + args);
+                message(line(n));
+                annotate(n, "shouted");
+            }
+        "#).unwrap();
+
+        let buffer = TextBuffer::from_data(0, b"one\ntwo\n".to_vec());
+        let mut scripting = Scripting::new();
+        let ops = scripting.load(&path, &buffer, LinePos::new(0, 0)).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(ops.is_empty());
+        assert!(scripting.is_map_prefix("g"));
+        let func = scripting.map("gs").unwrap().clone();
+        assert_eq!(scripting.command("Shout"), Some(&func));
+
+        let ops = scripting.call(&func, "!", &buffer, LinePos::new(1, 0)).unwrap();
+        assert_eq!(ops, [ScriptOp::SetLine(1, "TWO!".into()), ScriptOp::Message("TWO!".into()), ScriptOp::Annotate(1, "shouted".into())]);
+        assert!(scripting.call(&ScriptFn { plugin: 0, name: "missing".into() }, "", &buffer, LinePos::new(0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_view() {
+        let mut buffer = TextBuffer::from_data(0, b"one\r\ntwo\r\nthree".to_vec());
+        // the gap in the middle of a line
+        buffer.insert_text(LinePos::new(1, 1), "x");
+        let mut view = View::new(Some(buffer.snapshot()), buffer.total_lines());
+        assert_eq!(view.line(1).as_deref(), Some("txwo"));
+        assert_eq!(view.starts.len(), 3);
+        assert_eq!(view.line(2).as_deref(), Some("three"));
+        assert_eq!(view.line(0).as_deref(), Some("one"));
+        assert_eq!(view.line(3), None);
+    }
+
+    #[test]
+    fn test_runaway_script() {
+        let path = std::env::temp_dir().join(format!("moded_test_loop_{}.rhai", std::process::id()));
+        fs::write(&path, "loop { }").unwrap();
+        let buffer = TextBuffer::from_data(0, b"one\n".to_vec());
+        let result = Scripting::new().load(&path, &buffer, LinePos::new(0, 0));
+        let _ = fs::remove_file(&path);
+        assert!(result.is_err());
+    }
+}
@@ -1,30 +1,212 @@
-use crate::gap_buffer::{LinePos, LineView, TextBuffer};
+use memchr::memmem::Finder;
 
+use crate::{gap_buffer::{LinePos, LineView, TextBuffer}, tasks::{Cancel, Task, Tasks}, vim_commands::is_letter};
 
+
+// buffers bigger than this are searched on another thread so typing the
+// pattern never waits for the search
+pub const ASYNC_SEARCH_THRESHOLD: usize = 4 << 20;
+
+// how many bytes are searched between looking whether the search was cancelled
+const CANCEL_CHECK: usize = 1 << 20;
+
+// literal search over the two halves of the gap buffer without joining them
 pub fn search(needle: &[u8], buf: &TextBuffer) -> Vec<LinePos> {
-    let view = buf.full_view();
-    let mut positions = Vec::new();
-    match view {
-        LineView::Contiguous(s) => {
-            for (i, window) in s.as_bytes().windows(needle.len()).enumerate() {
-                if window == needle {
-                    positions.push(buf.byte_to_linepos(i));
-                }
-            }
-        },
-        LineView::Parts(s1, s2) => {
-            for (i, window) in s1.as_bytes().windows(needle.len()).enumerate() {
-                if window == needle {
-                    positions.push(buf.byte_to_linepos(i));
-                }
-            }
-            for (i, window) in s2.as_bytes().windows(needle.len()).enumerate() {
-                if window == needle {
-                    positions.push(buf.byte_to_linepos(i + s1.len()));
-                }
-            }
-        },
+    let (first, second) = halves(buf);
+    find_pattern(needle, first, second, &Cancel::default()).into_iter().map(|byte| buf.byte_to_linepos(byte)).collect()
+}
+
+fn halves(buf: &TextBuffer) -> (&[u8], &[u8]) {
+    match buf.full_view() {
+        LineView::Contiguous(s) => (s.as_bytes(), &[][..]),
+        LineView::Parts(s1, s2) => (s1.as_bytes(), s2.as_bytes()),
+    }
+}
+
+// like in vim a "\<" at the start and a "\>" at the end of the pattern only match
+// where a word starts or ends, words are made of is_letter chars
+fn find_pattern(pattern: &[u8], first: &[u8], second: &[u8], cancel: &Cancel) -> Vec<usize> {
+    let (word_start, rest) = pattern.strip_prefix(b"\\<").map_or((false, pattern), |rest| (true, rest));
+    let (word_end, needle) = rest.strip_suffix(b"\\>").map_or((false, rest), |needle| (true, needle));
+    let mut bytes = find_bytes(needle, first, second, cancel);
+    if !word_start && !word_end {
+        return bytes
+    }
+
+    // the chars on both sides of a boundary are a letter and not a letter
+    let boundary = |before: Option<char>, after: Option<char>| before.is_some_and(is_letter) != after.is_some_and(is_letter);
+    bytes.retain(|&byte| {
+        let end = byte + needle.len();
+        (!word_start || boundary(char_before(first, second, byte), char_at(first, second, byte)))
+            && (!word_end || boundary(char_before(first, second, end), char_at(first, second, end)))
+    });
+
+    bytes
+}
+
+fn byte_at(first: &[u8], second: &[u8], byte: usize) -> Option<u8> {
+    first.get(byte).or_else(|| second.get(byte.checked_sub(first.len())?)).copied()
+}
+
+// the char starting at byte
+fn char_at(first: &[u8], second: &[u8], byte: usize) -> Option<char> {
+    let bytes = (byte..(byte + 4)).map_while(|i| byte_at(first, second, i)).collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).chars().next()
+}
+
+// the char ending right before byte
+fn char_before(first: &[u8], second: &[u8], byte: usize) -> Option<char> {
+    let start = (byte.saturating_sub(4)..byte).rev().find(|&i| byte_at(first, second, i).is_some_and(|b| b & 0xc0 != 0x80))?;
+    char_at(first, second, start)
+}
+
+// where needle starts in first followed by second. matches inside a half are
+// found with memchr's memmem, the few that cross the gap are checked by hand.
+// matches may overlap like with windows(). a cancelled search stops with what it found so far
+fn find_bytes(needle: &[u8], first: &[u8], second: &[u8], cancel: &Cancel) -> Vec<usize> {
+    if needle.is_empty() {
+        return Vec::new()
+    }
+
+    let finder = Finder::new(needle);
+    let mut bytes = find_all(&finder, first, cancel);
+    for start in first.len().saturating_sub(needle.len() - 1)..first.len() {
+        let (head, tail) = needle.split_at(first.len() - start);
+        if first[start..] == *head && second.starts_with(tail) {
+            bytes.push(start);
+        }
+    }
+    bytes.extend(find_all(&finder, second, cancel).into_iter().map(|i| i + first.len()));
+
+    bytes
+}
+
+// goes through the haystack CANCEL_CHECK bytes at a time, plus what a match
+// starting near the end of them needs
+fn find_all(finder: &Finder, haystack: &[u8], cancel: &Cancel) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while from < haystack.len() && !cancel.is_set() {
+        let end = (from + CANCEL_CHECK + finder.needle().len() - 1).min(haystack.len());
+        match finder.find(&haystack[from..end]) {
+            Some(i) => {
+                found.push(from + i);
+                from += i + 1;
+            },
+            None => from += CANCEL_CHECK,
+        }
+    }
+
+    found
+}
+
+// a search running on a task thread over a snapshot of a buffer's text
+pub struct SearchJob {
+    pub buffer_id: usize,
+    needle: Vec<u8>,
+    // the buffer's changes when the snapshot was taken
+    changes: u64,
+    task: Task<Vec<usize>>,
+}
+
+impl SearchJob {
+    pub fn spawn(needle: &[u8], buf: &TextBuffer, tasks: &Tasks) -> Self {
+        let snapshot = buf.snapshot();
+        let task_needle = needle.to_vec();
+        let task = tasks.submit(move |cancel| {
+            let (first, second) = snapshot.halves();
+            find_pattern(&task_needle, first, second, cancel)
+        });
+
+        Self { buffer_id: buf.id, needle: needle.to_vec(), changes: buf.changes, task }
+    }
+
+    // the results once the search is done. if buf was edited in the meantime
+    // they'd be stale, the search starts over on the new text instead
+    pub fn poll(&mut self, buf: &TextBuffer, tasks: &Tasks) -> Option<Vec<LinePos>> {
+        if buf.changes != self.changes {
+            *self = Self::spawn(&self.needle, buf, tasks);
+            return None
+        }
+        let bytes = self.task.poll()?.unwrap_or_default();
+        Some(bytes.into_iter().map(|byte| buf.byte_to_linepos(byte)).collect())
     }
 
-    positions
+    // blocks until the search is done, a stale one isn't waited for
+    pub fn wait(self, buf: &TextBuffer) -> Vec<LinePos> {
+        if buf.changes != self.changes {
+            return search(&self.needle, buf)
+        }
+        let bytes = self.task.wait().unwrap_or_default();
+        bytes.into_iter().map(|byte| buf.byte_to_linepos(byte)).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let mut buf = TextBuffer::from_data(0, b"aaa b\nxaab\n".to_vec());
+        let found = search(b"aa", &buf);
+        assert_eq!(found, [LinePos::new(0, 0), LinePos::new(0, 1), LinePos::new(1, 1)]);
+        assert!(search(b"", &buf).is_empty());
+
+        // the gap right inside a match
+        buf.insert_text(LinePos::new(1, 2), "a");
+        assert_eq!(search(b"aaa", &buf), [LinePos::new(0, 0), LinePos::new(1, 1)]);
+        assert_eq!(search(b"b\nx", &buf), [LinePos::new(0, 4)]);
+        assert_eq!(search(b"ab\n", &buf), [LinePos::new(1, 3)]);
+    }
+
+    #[test]
+    fn test_find_all() {
+        let mut haystack = vec![b'.'; 2 * CANCEL_CHECK + 10];
+        // across the end of the first stretch
+        haystack[(CANCEL_CHECK - 1)..(CANCEL_CHECK + 2)].copy_from_slice(b"abc");
+        haystack[2 * CANCEL_CHECK..(2 * CANCEL_CHECK + 3)].copy_from_slice(b"abc");
+        let finder = Finder::new(b"abc");
+        assert_eq!(find_all(&finder, &haystack, &Cancel::default()), [CANCEL_CHECK - 1, 2 * CANCEL_CHECK]);
+
+        let tasks = Tasks::default();
+        let cancel = tasks.submit(|cancel| cancel.clone()).wait().unwrap();
+        assert!(cancel.is_set());
+        assert!(find_all(&finder, &haystack, &cancel).is_empty());
+    }
+
+    #[test]
+    fn test_whole_word() {
+        let mut buf = TextBuffer::from_data(0, "foo foobar barfoo\nfoo_x äfoo foo.\n".as_bytes().to_vec());
+        assert_eq!(search(b"\\<foo\\>", &buf), [LinePos::new(0, 0), LinePos::new(1, 11)]);
+        assert_eq!(search(b"\\<foo", &buf).len(), 4);
+        assert_eq!(search(b"foo\\>", &buf), [LinePos::new(0, 0), LinePos::new(0, 14), LinePos::new(1, 7), LinePos::new(1, 11)]);
+
+        // the gap between a word and the char after it
+        buf.insert_text(LinePos::new(0, 3), "d");
+        assert_eq!(search(b"\\<foo\\>", &buf), [LinePos::new(1, 11)]);
+    }
+
+    #[test]
+    fn test_search_job() {
+        let mut buf = TextBuffer::from_data(0, b"one two\ntwo\n".to_vec());
+        let tasks = Tasks::default();
+        let job = SearchJob::spawn(b"two", &buf, &tasks);
+        assert_eq!(job.wait(&buf), [LinePos::new(0, 4), LinePos::new(1, 0)]);
+
+        // an edit while it runs starts it over
+        let mut job = SearchJob::spawn(b"two", &buf, &tasks);
+        buf.insert_text(LinePos::new(0, 0), "two ");
+        assert_eq!(job.poll(&buf, &tasks), None);
+        let found = loop {
+            if let Some(found) = job.poll(&buf, &tasks) {
+                break found
+            }
+        };
+        assert_eq!(found, [LinePos::new(0, 0), LinePos::new(0, 8), LinePos::new(1, 0)]);
+        let job = SearchJob::spawn(b"one", &buf, &tasks);
+        buf.insert_text(LinePos::new(0, 0), "x");
+        assert_eq!(job.wait(&buf), [LinePos::new(0, 5)]);
+    }
 }
@@ -1,7 +1,22 @@
 
+use std::{ffi::CStr, fmt};
+
+
 #[derive(Debug)]
 pub struct ShaderProgramError(String);
 
+impl fmt::Display for ShaderProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// None if the program has no such uniform, or the compiler optimized it out
+pub fn uniform_location(program: u32, name: &CStr) -> Option<i32> {
+    let location = unsafe { gl::GetUniformLocation(program, name.as_ptr()) };
+    (location != -1).then_some(location)
+}
+
 
 pub struct TextShader {
     pub id: u32
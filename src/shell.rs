@@ -0,0 +1,72 @@
+use std::{ffi::OsStr, io::Write, process::{Command, Output, Stdio}, thread};
+
+
+fn command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+fn error_message(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(line) = stderr.lines().find(|line| !line.trim().is_empty()) {
+        return line.to_owned()
+    }
+
+    match output.status.code() {
+        Some(code) => format!("shell returned {code}"),
+        None => String::from("shell command was terminated"),
+    }
+}
+
+// runs cmd and returns what it printed to stdout and stderr
+pub fn run(cmd: &str) -> Result<String, String> {
+    let output = command(cmd)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if text.is_empty() && !output.status.success() {
+        return Err(error_message(&output))
+    }
+
+    Ok(text)
+}
+
+// pipes input through cmd and returns its stdout, fails if cmd exits with an error
+pub fn filter(cmd: &str, input: &str) -> Result<String, String> {
+    filter_through(command(cmd), input)
+}
+
+// like filter without the shell, the args are passed as they are so file
+// names can't be read as shell code
+pub fn filter_program<S: AsRef<OsStr>>(program: &str, args: &[S], input: &str) -> Result<String, String> {
+    let mut command = Command::new(program);
+    command.args(args);
+    filter_through(command, input)
+}
+
+fn filter_through(mut command: Command, input: &str) -> Result<String, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // write from another thread so a child filling its stdout pipe can't deadlock us
+    let Some(mut stdin) = child.stdin.take() else { return Err(String::from("can't open stdin")) };
+    let input = input.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(error_message(&output))
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
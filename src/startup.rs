@@ -0,0 +1,107 @@
+use std::{panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}};
+
+use crate::{editor::Editor, log::{self, LogLevel}, options::config_dir, rpc};
+
+
+// what both binaries do before and around the editor: the arguments, the log, and
+// saving unsaved text when moded panics. moded-headless is the --headless part of
+// moded alone, so it builds without the window
+
+pub struct Args {
+    pub path: PathBuf,
+    pub headless: bool,
+    pub socket: Option<PathBuf>,
+}
+
+// moded [--headless] [--listen {socket}] [--log-level {level}] [file]. also starts
+// the log and the panic hook
+pub fn init(mut args: impl Iterator<Item = String>) -> Args {
+    let mut path = None;
+    let mut headless = false;
+    let mut socket = rpc::default_socket();
+    let mut log_level = LogLevel::Info;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => headless = true,
+            "--listen" => socket = args.next().map(PathBuf::from).or(socket),
+            "--log-level" => {
+                let name = args.next().unwrap_or_default();
+                match LogLevel::parse(&name) {
+                    Some(level) => log_level = level,
+                    None => eprintln!("Unknown log level {name:?}, use error, warn, info or debug"),
+                }
+            },
+            _ => path = Some(arg),
+        }
+    }
+    log::init(log_level, config_dir().map(|dir| dir.join("log")).as_deref());
+    install_panic_hook();
+    let path = path.unwrap_or_else(|| String::from("./Cargo.toml"));
+    // made absolute before moving into the project root
+    let path = std::path::absolute(&path).unwrap_or_else(|_| path.into());
+
+    Args { path, headless, socket }
+}
+
+// where buffers with unsaved changes are written when moded panics
+pub fn recovery_dir() -> PathBuf {
+    config_dir().unwrap_or_else(std::env::temp_dir).join("recovery")
+}
+
+// the panic hook only logs it, the buffers are saved once it's unwound out of
+// the main loop and the editor can be looked at again
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log::error(&info.to_string());
+        default_hook(info);
+    }));
+}
+
+pub fn recover(editor: &Editor) -> ! {
+    let saved = editor.save_recovery(&recovery_dir());
+    if !saved.is_empty() {
+        eprintln!("moded crashed, unsaved text was written to:");
+        for path in &saved {
+            eprintln!("  {}", path.display());
+            log::error(&format!("Unsaved text written to {}", path.display()));
+        }
+    }
+    std::process::exit(101)
+}
+
+// the file moded was started with, it has to open for there to be an editor
+pub fn open(path: &Path) -> Editor {
+    Editor::from_path(path).unwrap_or_else(|e| {
+        eprintln!("Can't open {}: {e}", path.display());
+        std::process::exit(1)
+    })
+}
+
+// paths typed later, like with :e or :!, are from the project root wherever
+// moded was started
+pub fn enter_project(editor: &mut Editor) {
+    let root = editor.root_folder.clone();
+    editor.change_dir(&root, false);
+    editor.enter_dir();
+}
+
+// serves path over the socket until a client quits, see rpc
+pub fn run_headless(path: &Path, socket: Option<&Path>) {
+    let mut state = rpc::headless_state();
+    let mut editor = open(path);
+    enter_project(&mut editor);
+    editor.load_config(&mut state);
+    let Some(socket) = socket else {
+        eprintln!("No folder for the socket, use --listen {{path}}");
+        std::process::exit(1);
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| rpc::serve(&mut editor, &mut state, socket))) {
+        Ok(Ok(())) => (),
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        },
+        Err(_) => recover(&editor),
+    }
+}
@@ -0,0 +1,230 @@
+use crate::regex::{Captures, Regex};
+
+
+pub struct Substitute {
+    pub pattern: String,
+    pub regex: Regex,
+    pub replacement: String,
+    pub global: bool,
+    pub preserve_case: bool,
+}
+
+impl Substitute {
+    // parses "/pattern/replacement/flags", any non-alphanumeric char works as the separator
+    pub fn parse(args: &str, preserve_case: bool) -> Result<Self, ()> {
+        let mut chars = args.chars();
+        let Some(sep) = chars.next() else { return Err(()) };
+        if sep.is_alphanumeric() || sep.is_whitespace() || sep == '\\' {
+            return Err(())
+        }
+
+        let rest = chars.as_str();
+        let (pattern, rest) = split_at_separator(rest, sep);
+        let (replacement, flags) = split_at_separator(rest.unwrap_or(""), sep);
+        if pattern.is_empty() {
+            return Err(())
+        }
+
+        let mut global = false;
+        let mut ignore_case = preserve_case;
+        for flag in flags.unwrap_or("").trim().chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                'I' => ignore_case = false,
+                _ => return Err(()),
+            }
+        }
+
+        let regex = Regex::with_default_ignore_case(&pattern, ignore_case)?;
+
+        Ok(Self { pattern, regex, replacement, global, preserve_case })
+    }
+
+    // returns None if nothing matched
+    pub fn apply(&self, line: &str) -> Option<String> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut out = String::new();
+        let mut found = false;
+        let mut i = 0;
+
+        while i <= chars.len() {
+            if found && !self.global {
+                break
+            }
+            let Some(caps) = self.regex.find_at(&chars, i) else { break };
+            let Some((start, end)) = caps[0] else { break };
+
+            out.extend(&chars[i..start]);
+            let replacement = expand_replacement(&self.replacement, &chars, &caps);
+            if self.preserve_case {
+                let matched = chars[start..end].iter().collect::<String>();
+                out.push_str(&apply_case(&matched, &replacement));
+            } else {
+                out.push_str(&replacement);
+            }
+            found = true;
+
+            // empty match, step over one char so we don't match it again
+            if end == start {
+                if let Some(char) = chars.get(end) {
+                    out.push(*char);
+                }
+                i = end + 1;
+            } else {
+                i = end;
+            }
+        }
+
+        if !found {
+            return None
+        }
+        if i < chars.len() {
+            out.extend(&chars[i..]);
+        }
+
+        Some(out)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CaseChange {
+    None,
+    Upper,
+    Lower,
+}
+
+// & and \0 are the whole match, \1..\9 groups, \U \L change case until \E,
+// \u \l change the next char and \r starts a new line
+fn expand_replacement(replacement: &str, text: &[char], caps: &Captures) -> String {
+    let mut out = String::new();
+    let mut case = CaseChange::None;
+    let mut next_char_case = CaseChange::None;
+
+    let push = |out: &mut String, char: char, case: CaseChange, next_char_case: &mut CaseChange| {
+        let case = if *next_char_case != CaseChange::None { *next_char_case } else { case };
+        *next_char_case = CaseChange::None;
+        match case {
+            CaseChange::None => out.push(char),
+            CaseChange::Upper => out.extend(char.to_uppercase()),
+            CaseChange::Lower => out.extend(char.to_lowercase()),
+        }
+    };
+
+    let mut chars = replacement.chars();
+    while let Some(char) = chars.next() {
+        let group = match char {
+            '&' => Some(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => Some(digit as usize - '0' as usize),
+                Some('U') => { case = CaseChange::Upper; continue },
+                Some('L') => { case = CaseChange::Lower; continue },
+                Some('E') | Some('e') => { case = CaseChange::None; continue },
+                Some('u') => { next_char_case = CaseChange::Upper; continue },
+                Some('l') => { next_char_case = CaseChange::Lower; continue },
+                Some('r') | Some('n') => { out.push('\n'); continue },
+                Some('t') => { push(&mut out, '\t', case, &mut next_char_case); continue },
+                Some(other) => { push(&mut out, other, case, &mut next_char_case); continue },
+                None => { push(&mut out, '\\', case, &mut next_char_case); continue },
+            },
+            _ => None,
+        };
+
+        match group {
+            Some(group) => {
+                let Some(Some((start, end))) = caps.get(group) else { continue };
+                for char in &text[*start..*end] {
+                    push(&mut out, *char, case, &mut next_char_case);
+                }
+            },
+            None => push(&mut out, char, case, &mut next_char_case),
+        }
+    }
+
+    out
+}
+
+// returns text up to the next unescaped sep and what comes after it, "\sep" becomes sep
+fn split_at_separator(input: &str, sep: char) -> (String, Option<&str>) {
+    let mut out = String::new();
+    let mut escaped = false;
+    for (i, char) in input.char_indices() {
+        if escaped {
+            if char != sep {
+                out.push('\\');
+            }
+            out.push(char);
+            escaped = false;
+        } else if char == '\\' {
+            escaped = true;
+        } else if char == sep {
+            return (out, Some(&input[(i + char.len_utf8())..]))
+        } else {
+            out.push(char);
+        }
+    }
+    if escaped {
+        out.push('\\');
+    }
+
+    (out, None)
+}
+
+// makes replacement follow the case of matched: FOO -> BAR, Foo -> Bar, foo -> bar
+pub fn apply_case(matched: &str, replacement: &str) -> String {
+    let letters = matched.chars().filter(|c| c.is_alphabetic()).collect::<Vec<_>>();
+    if letters.is_empty() {
+        return replacement.to_owned()
+    }
+
+    if letters.iter().all(|c| c.is_uppercase()) && letters.len() > 1 {
+        return replacement.to_uppercase()
+    }
+    if letters.iter().all(|c| c.is_lowercase()) {
+        return replacement.to_lowercase()
+    }
+    if letters[0].is_uppercase() && letters[1..].iter().all(|c| c.is_lowercase()) {
+        let mut chars = replacement.chars();
+        let Some(first) = chars.next() else { return String::new() };
+        return first.to_uppercase().chain(chars.as_str().to_lowercase().chars()).collect()
+    }
+
+    replacement.to_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preserve_case() {
+        let sub = Substitute::parse("/foo/bar/g", true).unwrap();
+
+        assert_eq!(sub.apply("Foo FOO foo fOo").unwrap(), "Bar BAR bar bar");
+        assert_eq!(sub.apply("nothing here"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let sub = Substitute::parse("#a\\#b#c", false).unwrap();
+        assert_eq!(sub.pattern, "a#b");
+        assert_eq!(sub.replacement, "c");
+        assert!(!sub.global);
+
+        assert!(Substitute::parse("/a/b/x", false).is_err());
+        assert!(Substitute::parse("//b/", false).is_err());
+    }
+
+    #[test]
+    fn test_captures() {
+        let sub = Substitute::parse("/\\(\\w\\+\\) = \\(\\w\\+\\)/\\2 = \\1/", false).unwrap();
+        assert_eq!(sub.apply("let a = b;").unwrap(), "let b = a;");
+
+        let sub = Substitute::parse("/\\w\\+/\\U&\\E!/g", false).unwrap();
+        assert_eq!(sub.apply("ab cd").unwrap(), "AB! CD!");
+
+        let sub = Substitute::parse("/x*/-/g", false).unwrap();
+        assert_eq!(sub.apply("abc").unwrap(), "-a-b-c-");
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fs, path::{Path, PathBuf}, time::SystemTime};
+
+
+// where a tag is in its file, ctags writes either a line number or a search pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Line(usize),
+    Pattern { text: String, line_start: bool, line_end: bool },
+}
+
+impl Address {
+    fn parse(address: &str) -> Option<Self> {
+        // the ex command ends with ;" when extension fields follow
+        let address = address.strip_suffix(";\"").unwrap_or(address);
+        if let Ok(line) = address.parse::<usize>() {
+            return Some(Address::Line(line.max(1) - 1))
+        }
+
+        let delimiter = address.chars().next().filter(|&c| c == '/' || c == '?')?;
+        let inner = address[1..].strip_suffix(delimiter).unwrap_or(&address[1..]);
+        let (line_start, inner) = match inner.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, inner),
+        };
+        let (line_end, inner) = match inner.strip_suffix('$') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, inner),
+        };
+
+        let mut text = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                text.extend(chars.next());
+            } else {
+                text.push(c);
+            }
+        }
+
+        Some(Address::Pattern { text, line_start, line_end })
+    }
+
+    pub fn matches(&self, line: &str) -> bool {
+        match self {
+            Address::Line(_) => false,
+            Address::Pattern { text, line_start: true, line_end: true } => line == text,
+            Address::Pattern { text, line_start: true, .. } => line.starts_with(text.as_str()),
+            Address::Pattern { text, line_end: true, .. } => line.ends_with(text.as_str()),
+            Address::Pattern { text, .. } => line.contains(text.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub path: PathBuf,
+    pub address: Address,
+}
+
+pub struct TagFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    tags: Vec<Tag>,
+}
+
+impl TagFile {
+    pub fn load(path: &Path) -> Result<Self, ()> {
+        let text = fs::read_to_string(path).map_err(|_| ())?;
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        Ok(Self { path: path.to_owned(), modified, tags: parse(&text, dir) })
+    }
+
+    // true if the file was regenerated since it was loaded
+    pub fn is_stale(&self) -> bool {
+        fs::metadata(&self.path).and_then(|meta| meta.modified()).ok() != self.modified
+    }
+
+    pub fn find(&self, name: &str) -> Vec<&Tag> {
+        self.tags.iter().filter(|tag| tag.name == name).collect()
+    }
+}
+
+// paths in the tags file are relative to the directory it's in
+pub fn parse(text: &str, dir: &Path) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for line in text.lines() {
+        if line.starts_with("!_TAG_") {
+            continue
+        }
+
+        let mut fields = line.splitn(3, '\t');
+        let (Some(name), Some(file), Some(rest)) = (fields.next(), fields.next(), fields.next()) else { continue };
+        // extension fields come after the address and another tab
+        let address = match rest.find(";\"\t") {
+            Some(end) => &rest[..(end + 2)],
+            None => rest,
+        };
+        let Some(address) = Address::parse(address) else { continue };
+
+        tags.push(Tag { name: name.to_owned(), path: dir.join(file), address });
+    }
+
+    tags
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+            Editor\tsrc/editor.rs\t/^pub struct Editor {$/;\"\ts\n\
+            main\tsrc/main.rs\t250;\"\tf\n\
+            path\tsrc/a.rs\t/^fn path() -> \\/usr\\/bin$/\n";
+        let tags = parse(text, Path::new("root"));
+
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].path, Path::new("root/src/editor.rs"));
+        assert!(tags[0].address.matches("pub struct Editor {"));
+        assert!(!tags[0].address.matches("    pub struct Editor {"));
+        assert_eq!(tags[1].address, Address::Line(249));
+        assert!(tags[2].address.matches("fn path() -> /usr/bin"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{cell::OnceCell, panic::{self, AssertUnwindSafe}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender, TryRecvError}, Arc, Mutex}, thread};
+
+use crate::log;
+
+
+// a few threads that run jobs off the main thread, like a search over a big
+// buffer, instead of each job starting a thread of its own. submit hands back
+// a Task whose result is polled once a frame, the way language server
+// messages and loaded chunks are. the threads start with the first job.
+// dropping the Task cancels the job, one still queued doesn't run at all
+
+// threads at most, fewer on a machine with fewer cores
+const MAX_THREADS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+pub struct Tasks {
+    jobs: OnceCell<Sender<Job>>,
+}
+
+// the result of one job
+pub struct Task<T> {
+    receiver: Receiver<T>,
+    cancel: Cancel,
+}
+
+// set once nobody wants a job's result, long jobs check it now and then and stop early
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// what a task gives instead of a result when its job panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Panicked;
+
+impl Tasks {
+    pub fn submit<T: Send + 'static>(&self, job: impl FnOnce(&Cancel) -> T + Send + 'static) -> Task<T> {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Cancel::default();
+        let job_cancel = cancel.clone();
+        let job: Job = Box::new(move || {
+            if !job_cancel.is_set() {
+                let _ = sender.send(job(&job_cancel));
+            }
+        });
+        if self.jobs.get_or_init(start_threads).send(job).is_err() {
+            log::error("Task threads are gone");
+        }
+
+        Task { receiver, cancel }
+    }
+}
+
+// the threads end when Tasks is dropped and the jobs left are done
+fn start_threads() -> Sender<Job> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_THREADS);
+    for _ in 0..threads {
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || loop {
+            let Ok(job) = receiver.lock().map_err(|_| ()).and_then(|jobs| jobs.recv().map_err(|_| ())) else { break };
+            // a job that panics drops its sender, its task sees that as a failure
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+        });
+    }
+
+    sender
+}
+
+impl<T> Task<T> {
+    // None while it runs, then the result
+    pub fn poll(&self) -> Option<Result<T, Panicked>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(Ok(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(Panicked)),
+        }
+    }
+
+    // blocks until it's done
+    pub fn wait(&self) -> Result<T, Panicked> {
+        self.receiver.recv().map_err(|_| Panicked)
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.cancel.0.store(true, Ordering::Relaxed);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tasks() {
+        let tasks = Tasks::default();
+        let slow = tasks.submit(|_| {
+            thread::sleep(Duration::from_millis(50));
+            1
+        });
+        let squares = (0..20u64).map(|i| tasks.submit(move |_| i * i)).collect::<Vec<_>>();
+        assert_eq!(slow.poll(), None);
+        assert_eq!(squares.into_iter().map(|task| task.wait().unwrap()).sum::<u64>(), 2470);
+        assert_eq!(slow.wait(), Ok(1));
+
+        // a panic fails its task and the threads go on
+        let panics = tasks.submit(|_| -> u8 { panic!("job failed") });
+        assert_eq!(panics.wait(), Err(Panicked));
+        assert_eq!(tasks.submit(|_| 2).wait(), Ok(2));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let tasks = Tasks::default();
+        // a running job sees its task dropped
+        let (sender, stopped) = mpsc::channel();
+        let (started_sender, started) = mpsc::channel();
+        let task = tasks.submit(move |cancel| {
+            started_sender.send(()).unwrap();
+            while !cancel.is_set() {
+                thread::yield_now();
+            }
+            sender.send(()).unwrap();
+        });
+        started.recv().unwrap();
+        drop(task);
+        assert_eq!(stopped.recv_timeout(Duration::from_secs(5)), Ok(()));
+
+        // one dropped while every thread is busy never starts
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let busy = (0..MAX_THREADS).map(|_| {
+            let released = Arc::clone(&released);
+            tasks.submit(move |_| { let _ = released.lock().unwrap().recv(); })
+        }).collect::<Vec<_>>();
+        let ran = Arc::new(AtomicBool::new(false));
+        let queued = {
+            let ran = Arc::clone(&ran);
+            tasks.submit(move |_| ran.store(true, Ordering::Relaxed))
+        };
+        drop(queued);
+        drop(release);
+        for task in busy {
+            task.wait().unwrap();
+        }
+        tasks.submit(|_| ()).wait().unwrap();
+        assert!(!ran.load(Ordering::Relaxed));
+    }
+}
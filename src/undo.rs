@@ -0,0 +1,122 @@
+// undo history of a buffer, recorded as byte level changes. changes between
+// two break points are undone together
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Insert { at: usize, data: Vec<u8> },
+    Remove { at: usize, data: Vec<u8> },
+}
+
+impl Change {
+    pub fn at(&self) -> usize {
+        match self {
+            Change::Insert { at, .. } | Change::Remove { at, .. } => *at,
+        }
+    }
+}
+
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    current: Vec<Change>,
+    // text removed since collect_removed, in the order it was in the buffer,
+    // and where the last of it was removed
+    removed: Option<Vec<u8>>,
+    removed_at: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self { undo: Vec::new(), redo: Vec::new(), current: Vec::new(), removed: None, removed_at: 0 }
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.redo.clear();
+
+        if let (Some(removed), Change::Remove { at, data }) = (&mut self.removed, &change) {
+            // removing backwards like backspace puts the text before what came before
+            if at + data.len() == self.removed_at && !removed.is_empty() {
+                removed.splice(0..0, data.iter().copied());
+            } else {
+                removed.extend(data);
+            }
+            self.removed_at = *at;
+        }
+
+        // typing and backspacing make lots of small changes, glue them together
+        match (self.current.last_mut(), change) {
+            (Some(Change::Insert { at, data }), Change::Insert { at: new_at, data: new_data }) if *at + data.len() == new_at => {
+                data.extend(new_data);
+            },
+            (Some(Change::Remove { at, data }), Change::Remove { at: new_at, data: mut new_data }) if new_at + new_data.len() == *at => {
+                new_data.extend(data.iter());
+                *data = new_data;
+                *at = new_at;
+            },
+            (_, change) => self.current.push(change),
+        }
+    }
+
+    pub fn break_point(&mut self) {
+        if !self.current.is_empty() {
+            self.undo.push(std::mem::take(&mut self.current));
+        }
+    }
+
+    // keeps the text removed from now on for take_removed
+    pub fn collect_removed(&mut self) {
+        self.removed = Some(Vec::new());
+    }
+
+    pub fn take_removed(&mut self) -> Vec<u8> {
+        self.removed.take().unwrap_or_default()
+    }
+
+    pub fn take_undo(&mut self) -> Option<Vec<Change>> {
+        self.break_point();
+        self.undo.pop()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Vec<Change>> {
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, changes: Vec<Change>) {
+        self.undo.push(changes);
+    }
+
+    pub fn push_redo(&mut self, changes: Vec<Change>) {
+        self.redo.push(changes);
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_merges() {
+        let mut history = History::default();
+        // typing goes on the insert before it, an insert somewhere else doesn't
+        history.record(Change::Insert { at: 0, data: b"a".to_vec() });
+        history.record(Change::Insert { at: 1, data: b"bc".to_vec() });
+        history.record(Change::Insert { at: 10, data: b"x".to_vec() });
+        assert_eq!(history.take_undo().unwrap(), [Change::Insert { at: 0, data: b"abc".to_vec() }, Change::Insert { at: 10, data: b"x".to_vec() }]);
+
+        // backspacing over "abc" goes backwards, deleting forward after it doesn't merge
+        history.collect_removed();
+        history.record(Change::Remove { at: 2, data: b"c".to_vec() });
+        history.record(Change::Remove { at: 1, data: b"b".to_vec() });
+        history.record(Change::Remove { at: 0, data: b"a".to_vec() });
+        history.record(Change::Remove { at: 0, data: b"d".to_vec() });
+        assert_eq!(history.take_removed(), b"abcd");
+        assert_eq!(history.take_undo().unwrap(), [Change::Remove { at: 0, data: b"abc".to_vec() }, Change::Remove { at: 0, data: b"d".to_vec() }]);
+        assert!(history.take_undo().is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{editor::EditorMode, gap_buffer::{LinePos, TextBuffer}, SpecialKey, State};
+use crate::{editor::EditorMode, gap_buffer::{LinePos, TextBuffer}, layout::{Axis, Direction, WindowCommand}, SpecialKey, State};
 
 
 #[derive(PartialEq, Clone, Copy)]
@@ -7,6 +7,33 @@ pub enum Action {
     Goto,
     GOTO,
     Scroll,
+    // ] and [
+    Next,
+    Previous,
+    // q and @, waiting for the register
+    Record,
+    Replay,
+    // c and y only start cs and ys, there's no change or yank yet
+    Change,
+    Yank,
+    // ds and cs, waiting for the pair and for cs what it becomes
+    DeleteSurround,
+    ChangeSurround,
+    // ctrl-w, waiting for the window command
+    Window,
+}
+
+// operators go over the lines a motion moves across, unlike actions they
+// don't change what the motion's keys mean
+#[derive(PartialEq, Clone, Copy)]
+pub enum Operator {
+    Reindent,
+    // gq
+    Format,
+    // ys, the pair to add comes after the motion
+    Surround,
+    // zf
+    Fold,
 }
 
 #[derive(Clone, Copy)]
@@ -19,6 +46,14 @@ pub enum Object {
     WORDEnd,
     Append,
     Insert,
+    // A and I
+    AppendLineEnd,
+    InsertLineStart,
+    // C, S and s, deletes that start insert mode
+    ChangeLineEnd,
+    ChangeLine,
+    ChangeChar,
+    ReplaceMode,
     NormalMode,
     VisualMode,
     VisualLineMode,
@@ -27,14 +62,33 @@ pub enum Object {
     VisualSelection,
     Up,
     Down,
+    // gk and gj, by screen rows of wrapped lines
+    RowUp,
+    RowDown,
     Left,
     Right,
     Line,
     LineStart,
     LineEnd,
+    // the pair ds and cs work on
+    Surrounding,
+    // ^, and _ which goes count - 1 lines down first
+    FirstNonBlank,
+    FirstNonBlankDown,
+    MatchingBracket,
     CharUnderCursor,
+    // ga, what the char under the cursor is
+    CharInfo,
+    // g ctrl-g, words, chars, bytes and lines of the buffer or selection
+    Counts,
     NextSearchResult,
     PreviousSearchResult,
+    // zo, zc and za, zR and zM for every fold
+    FoldOpen,
+    FoldClose,
+    FoldToggle,
+    FoldOpenAll,
+    FoldCloseAll,
     PageTop,
     PageMiddle,
     PageBot,
@@ -42,6 +96,19 @@ pub enum Object {
     HalfScreenDown,
     InsertLineUp,
     InsertLineDown,
+    Definition,
+    References,
+    JumpBack,
+    Undo,
+    Redo,
+    Hover,
+    NextHunk,
+    PreviousHunk,
+    GotoTag,
+    PopTag,
+    RecordMacro,
+    ReplayMacro,
+    Window(WindowCommand),
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -57,24 +124,126 @@ pub enum Modifier {
 
 pub struct Motion {
     pub action: Option<Action>,
+    pub operator: Option<Operator>,
     pub object: Option<Object>,
     pub modifier: Option<Modifier>,
+    // keys typed so far, for error messages
+    pub keys: String,
+    pub register: Option<char>,
+    // the pair ds and cs look for, and the one cs, ys and S put in
+    pub pair: Option<char>,
+    pub new_pair: Option<char>,
 }
 
 impl Motion {
     pub fn new() -> Self {
-        Self { action: None, object: None, modifier: None }
+        Self { action: None, operator: None, object: None, modifier: None, keys: String::new(), register: None, pair: None, new_pair: None }
+    }
+
+    // the next key is a register or a pair, not a command
+    pub fn wants_char(&self) -> bool {
+        matches!(self.action, Some(Action::Record | Action::Replay | Action::DeleteSurround | Action::ChangeSurround))
+            || (self.operator == Some(Operator::Surround) && self.object.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.action.is_none() && self.operator.is_none() && self.object.is_none() && self.modifier.is_none()
+    }
+
+    // nothing typed yet other than a count
+    fn is_empty_but_count(&self) -> bool {
+        self.action.is_none() && self.operator.is_none() && self.object.is_none() && !matches!(self.modifier, Some(Modifier::Around | Modifier::Inside))
+    }
+
+    // true if running the motion changes the text or starts insert or replace mode
+    pub fn edits(&self) -> bool {
+        matches!(self.action, Some(Action::Delete | Action::DeleteSurround | Action::ChangeSurround)) || self.operator.is_some_and(|operator| operator != Operator::Fold) || matches!(self.object, Some(Object::Append | Object::Insert | Object::AppendLineEnd | Object::InsertLineStart | Object::ReplaceMode | Object::InsertLineUp | Object::InsertLineDown | Object::CharUnderCursor | Object::Undo | Object::Redo))
     }
 
     pub fn clear(&mut self) {
         self.action = None;
+        self.operator = None;
         self.object = None;
         self.modifier = None;
+        self.keys.clear();
+        self.register = None;
+        self.pair = None;
+        self.new_pair = None;
     }
 
-    pub fn parse(&mut self, state: &State, char: char, current_mode: EditorMode) {
+    // returns false if char doesn't continue the pending motion
+    pub fn parse(&mut self, state: &State, char: char, current_mode: EditorMode) -> bool {
+        if state.io.pressed_special(SpecialKey::Control) {
+            self.keys.push('^');
+        }
+        self.keys.push(char);
+
+        if let Some(action @ (Action::Record | Action::Replay)) = self.action {
+            let valid = char.is_ascii_alphanumeric() || (action == Action::Replay && char == '@');
+            if !valid {
+                return false
+            }
+            self.register = Some(char);
+            self.object = Some(if action == Action::Record { Object::RecordMacro } else { Object::ReplayMacro });
+            return true
+        }
+        // with or without ctrl held, ctrl-w ctrl-w is ctrl-w w
+        if self.action == Some(Action::Window) {
+            let command = match char {
+                's' | 'S' => WindowCommand::Split(Axis::Rows),
+                'v' => WindowCommand::Split(Axis::Cols),
+                'c' | 'q' => WindowCommand::Close,
+                'o' => WindowCommand::Only,
+                'w' => WindowCommand::Next,
+                'h' => WindowCommand::Focus(Direction::Left),
+                'j' => WindowCommand::Focus(Direction::Down),
+                'k' => WindowCommand::Focus(Direction::Up),
+                'l' => WindowCommand::Focus(Direction::Right),
+                'z' => WindowCommand::Zoom,
+                'r' => WindowCommand::Resize,
+                _ => return false,
+            };
+            self.object = Some(Object::Window(command));
+            return true
+        }
+
+        match self.action {
+            Some(Action::DeleteSurround) => {
+                self.pair = Some(char);
+                self.object = Some(Object::Surrounding);
+                return true
+            },
+            Some(Action::ChangeSurround) if self.pair.is_none() => {
+                self.pair = Some(char);
+                return true
+            },
+            Some(Action::ChangeSurround) => {
+                self.new_pair = Some(char);
+                self.object = Some(Object::Surrounding);
+                return true
+            },
+            _ => {},
+        }
+        if self.operator == Some(Operator::Surround) && self.object.is_some() {
+            self.new_pair = Some(char);
+            return true
+        }
+        if matches!(self.action, Some(Action::Change | Action::Yank)) && char != 's' {
+            return false
+        }
+
         match char {
             '$' => self.object = Some(Object::LineEnd),
+            '%' => self.object = Some(Object::MatchingBracket),
+            '^' => self.object = Some(Object::FirstNonBlank),
+            '_' => {
+                // linewise, d_ and =_ are dd and ==
+                if self.action == Some(Action::Delete) || self.operator.is_some() {
+                    self.object = Some(Object::Line);
+                } else {
+                    self.object = Some(Object::FirstNonBlankDown);
+                }
+            },
             '1' ..= '9' => {
                 if let Some(Modifier::Count(n)) = self.modifier {
                     self.modifier = Some(Modifier::Count(n * 10 + (char as u32 - '0' as u32)));
@@ -90,7 +259,11 @@ impl Motion {
                 }
             },
             'a' => {
-                if current_mode == EditorMode::Visual {
+                if self.action == Some(Action::Scroll) {
+                    self.object = Some(Object::FoldToggle);
+                } else if self.action == Some(Action::Goto) {
+                    self.object = Some(Object::CharInfo);
+                } else if current_mode == EditorMode::Visual {
                     self.modifier = Some(Modifier::Around);
                 } else if self.action == Some(Action::Delete) {
                     self.modifier = Some(Modifier::Around);
@@ -98,6 +271,32 @@ impl Motion {
                     self.object = Some(Object::Append);
                 }
             },
+            'A' => self.object = Some(Object::AppendLineEnd),
+            // D is d$, C, S and s delete and insert. only on their own in normal mode
+            'S' if matches!(current_mode, EditorMode::Visual | EditorMode::VisualLine) => {
+                self.operator = Some(Operator::Surround);
+                self.object = Some(Object::VisualSelection);
+            },
+            's' if self.action == Some(Action::Delete) && self.object.is_none() && self.modifier.is_none() => self.action = Some(Action::DeleteSurround),
+            's' if self.action == Some(Action::Change) => self.action = Some(Action::ChangeSurround),
+            's' if self.action == Some(Action::Yank) => {
+                self.action = None;
+                self.operator = Some(Operator::Surround);
+            },
+            // yss is the line
+            's' if self.operator == Some(Operator::Surround) => self.object = Some(Object::Line),
+            'C' | 'D' | 'S' | 's' => {
+                if current_mode != EditorMode::Normal || !self.is_empty_but_count() {
+                    return false
+                }
+                self.action = Some(Action::Delete);
+                self.object = Some(match char {
+                    'C' => Object::ChangeLineEnd,
+                    'D' => Object::LineEnd,
+                    'S' => Object::ChangeLine,
+                    _ => Object::ChangeChar,
+                });
+            },
             'b' => {
                 if self.action == Some(Action::Scroll) {
                     self.object = Some(Object::PageBot);
@@ -105,9 +304,24 @@ impl Motion {
                     self.object = Some(Object::BackWord);
                 }
             },
+            'c' => {
+                if self.action == Some(Action::Scroll) {
+                    self.object = Some(Object::FoldClose);
+                } else if self.action == Some(Action::Next) {
+                    self.object = Some(Object::NextHunk);
+                } else if self.action == Some(Action::Previous) {
+                    self.object = Some(Object::PreviousHunk);
+                } else if current_mode == EditorMode::Normal && self.is_empty() {
+                    self.action = Some(Action::Change);
+                } else {
+                    return false
+                }
+            },
             'd' => {
                 if self.action == Some(Action::Delete) {
                     self.object = Some(Object::Line);
+                } else if self.action == Some(Action::Goto) {
+                    self.object = Some(Object::Definition);
                 } else if state.io.pressed_special(SpecialKey::Control) {
                     self.action = Some(Action::Scroll);
                     self.object = Some(Object::HalfScreenDown);
@@ -119,6 +333,14 @@ impl Motion {
                 }
             },
             'e' => self.object = Some(Object::WordEnd),
+            'f' if self.action == Some(Action::Scroll) && self.operator.is_none() => {
+                self.action = None;
+                self.operator = Some(Operator::Fold);
+                if matches!(current_mode, EditorMode::Visual | EditorMode::VisualLine) {
+                    self.object = Some(Object::VisualSelection);
+                }
+            },
+            'g' if self.action == Some(Action::Goto) && state.io.pressed_special(SpecialKey::Control) => self.object = Some(Object::Counts),
             'g' => {
                 if self.action == Some(Action::Goto) {
                     self.object = Some(Object::Line);
@@ -134,28 +356,86 @@ impl Motion {
             'i' => {
                 if current_mode == EditorMode::Visual {
                     self.modifier = Some(Modifier::Inside);
-                } else if self.action == Some(Action::Delete) {
+                } else if self.action == Some(Action::Delete) || self.operator.is_some() {
                     self.modifier = Some(Modifier::Inside);
                 } else {
                     self.object = Some(Object::Insert);
                 }
             },
-            'j' => self.object = Some(Object::Down),
-            'k' => self.object = Some(Object::Up),
+            'I' => self.object = Some(Object::InsertLineStart),
+            'j' => {
+                if self.action == Some(Action::Goto) {
+                    self.object = Some(Object::RowDown);
+                } else {
+                    self.object = Some(Object::Down);
+                }
+            },
+            'k' => {
+                if self.action == Some(Action::Goto) {
+                    self.object = Some(Object::RowUp);
+                } else {
+                    self.object = Some(Object::Up);
+                }
+            },
+            'K' => self.object = Some(Object::Hover),
             'l' => self.object = Some(Object::Right),
             'n' => self.object = Some(Object::NextSearchResult),
             'N' => self.object = Some(Object::PreviousSearchResult),
-            'o' => self.object = Some(Object::InsertLineDown),
+            'o' => {
+                if state.io.pressed_special(SpecialKey::Control) {
+                    self.object = Some(Object::JumpBack);
+                } else if self.action == Some(Action::Scroll) {
+                    self.object = Some(Object::FoldOpen);
+                } else {
+                    self.object = Some(Object::InsertLineDown);
+                }
+            },
             'O' => self.object = Some(Object::InsertLineUp),
+            'q' => {
+                if self.action == Some(Action::Goto) && matches!(current_mode, EditorMode::Visual | EditorMode::VisualLine) {
+                    self.action = None;
+                    self.operator = Some(Operator::Format);
+                    self.object = Some(Object::VisualSelection);
+                } else if self.action == Some(Action::Goto) && self.operator.is_none() {
+                    self.action = None;
+                    self.operator = Some(Operator::Format);
+                } else if self.operator == Some(Operator::Format) {
+                    // gqq and gqgq
+                    self.action = None;
+                    self.object = Some(Object::Line);
+                } else if self.operator.is_none() && self.action.is_none() {
+                    self.action = Some(Action::Record);
+                } else {
+                    return false
+                }
+            },
+            'r' => {
+                if state.io.pressed_special(SpecialKey::Control) {
+                    self.object = Some(Object::Redo);
+                } else if self.action == Some(Action::Goto) {
+                    self.object = Some(Object::References);
+                } else {
+                    return false
+                }
+            },
+            'R' if self.action == Some(Action::Scroll) => self.object = Some(Object::FoldOpenAll),
+            'M' if self.action == Some(Action::Scroll) => self.object = Some(Object::FoldCloseAll),
+            'R' => self.object = Some(Object::ReplaceMode),
             't' => {
-                if self.action == Some(Action::Scroll) {
+                if state.io.pressed_special(SpecialKey::Control) {
+                    self.object = Some(Object::PopTag);
+                } else if self.action == Some(Action::Scroll) {
                     self.object = Some(Object::PageTop);
+                } else {
+                    return false
                 }
             },
             'u' => {
                 if state.io.pressed_special(SpecialKey::Control) {
                     self.action = Some(Action::Scroll);
                     self.object = Some(Object::HalfScreenUp);
+                } else {
+                    self.object = Some(Object::Undo);
                 }
             },
             'v' => {
@@ -172,6 +452,7 @@ impl Motion {
                     self.object = Some(Object::VisualLineMode);
                 }
             },
+            'w' if state.io.pressed_special(SpecialKey::Control) => self.action = Some(Action::Window),
             'w' => self.object = Some(Object::Word),
             'W' => self.object = Some(Object::WORD),
             'x' => {
@@ -182,6 +463,7 @@ impl Motion {
                     self.object = Some(Object::CharUnderCursor);
                 }
             },
+            'y' if current_mode == EditorMode::Normal && self.is_empty() => self.action = Some(Action::Yank),
             'z' => {
                 if self.action == Some(Action::Scroll) {
                     self.object = Some(Object::PageMiddle);
@@ -189,10 +471,33 @@ impl Motion {
                     self.action = Some(Action::Scroll);
                 }
             },
+            ']' => {
+                if state.io.pressed_special(SpecialKey::Control) {
+                    self.object = Some(Object::GotoTag);
+                } else {
+                    self.action = Some(Action::Next);
+                }
+            },
+            '[' => self.action = Some(Action::Previous),
+            '@' => self.action = Some(Action::Replay),
+            '=' => {
+                if self.operator == Some(Operator::Reindent) {
+                    self.object = Some(Object::Line);
+                } else if current_mode == EditorMode::Visual || current_mode == EditorMode::VisualLine {
+                    self.operator = Some(Operator::Reindent);
+                    self.object = Some(Object::VisualSelection);
+                } else if self.operator.is_none() && self.action.is_none() {
+                    self.operator = Some(Operator::Reindent);
+                } else {
+                    return false
+                }
+            },
             ':' => self.object = Some(Object::CommandBarMode),
             '/' => self.object = Some(Object::SearchMode),
-            _ => {},
+            _ => return false,
         }
+
+        true
     }
 }
 
@@ -604,7 +909,246 @@ pub fn find_next_word_end(cursor: LinePos, buf: &TextBuffer) -> Option<LinePos>
 }
 
 
-fn is_letter(char: char) -> bool {
+// the col of the first char on line that isn't a blank, the last col if they all are
+pub fn first_nonblank(buf: &TextBuffer, line: usize) -> usize {
+    let text = buf.line(line);
+    let blanks = text.chars().take_while(|c| c.is_whitespace()).count();
+    blanks.min(text.chars().count().saturating_sub(1))
+}
+
+// the pairs % jumps between
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+pub fn is_bracket(char: char) -> bool {
+    BRACKETS.iter().any(|&(open, close)| char == open || char == close)
+}
+
+// the bracket matching the one at pos, looking at most max_lines lines away from it
+pub fn matching_bracket(pos: LinePos, buf: &TextBuffer, max_lines: usize) -> Option<LinePos> {
+    let chars = |line: usize| buf.line(line).chars().collect::<Vec<_>>();
+    let c = *chars(pos.line).get(pos.col)?;
+    let mut depth = 0;
+
+    if let Some(&(open, close)) = BRACKETS.iter().find(|&&(open, _)| open == c) {
+        let last = buf.total_lines().min(pos.line.saturating_add(max_lines).saturating_add(1));
+        for line in pos.line..last {
+            let start = if line == pos.line { pos.col } else { 0 };
+            for (col, &char) in chars(line).iter().enumerate().skip(start) {
+                if char == open {
+                    depth += 1;
+                } else if char == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(LinePos { line, col })
+                    }
+                }
+            }
+        }
+    } else if let Some(&(open, close)) = BRACKETS.iter().find(|&&(_, close)| close == c) {
+        for line in (pos.line.saturating_sub(max_lines)..=pos.line).rev() {
+            let chars = chars(line);
+            let end = if line == pos.line { pos.col + 1 } else { chars.len() };
+            for (col, &char) in chars[..end].iter().enumerate().rev() {
+                if char == close {
+                    depth += 1;
+                } else if char == open {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(LinePos { line, col })
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// how far ds and cs look for the pair around the cursor
+const SURROUND_LINES: usize = 1000;
+
+// the two halves of a pair around some text, both ends inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Surrounding {
+    pub open: (LinePos, LinePos),
+    pub close: (LinePos, LinePos),
+}
+
+// the brackets c stands for in ds, cs and ys, b, B, r and a like vim-surround
+fn surround_brackets(c: char) -> Option<(char, char)> {
+    match c {
+        '(' | ')' | 'b' => Some(('(', ')')),
+        '[' | ']' | 'r' => Some(('[', ']')),
+        '{' | '}' | 'B' => Some(('{', '}')),
+        '<' | '>' | 'a' => Some(('<', '>')),
+        _ => None,
+    }
+}
+
+// what ys and cs put around text for c. an opening bracket gets a space on
+// the inside, other punctuation goes on both sides as it is
+pub fn surround_text(c: char) -> Option<(String, String)> {
+    match surround_brackets(c) {
+        Some((open, close)) if matches!(c, '(' | '[' | '{') => Some((format!("{open} "), format!(" {close}"))),
+        Some((open, close)) => Some((open.to_string(), close.to_string())),
+        None if c.is_ascii_punctuation() => Some((c.to_string(), c.to_string())),
+        None => None,
+    }
+}
+
+// the pair of c around pos for ds and cs. brackets are the innermost pair
+// holding pos, quotes the nearest ones on pos's line and t the innermost tag
+pub fn surrounding(pos: LinePos, buf: &TextBuffer, c: char) -> Option<Surrounding> {
+    if c == 't' {
+        return surrounding_tag(pos, buf)
+    }
+    if let Some((open, close)) = surround_brackets(c) {
+        let open_pos = enclosing_open(pos, buf, open, close)?;
+        let close_pos = closing_of(open_pos, buf, open, close)?;
+        return Some(Surrounding { open: (open_pos, open_pos), close: (close_pos, close_pos) })
+    }
+    if !matches!(c, '"' | '\'' | '`') {
+        return None
+    }
+
+    let chars = buf.line(pos.line).chars().collect::<Vec<_>>();
+    let quotes = chars.iter().enumerate().filter(|(_, &char)| char == c).map(|(col, _)| col).collect::<Vec<_>>();
+    // a quote under the cursor opens if an even number come before it
+    let i = match quotes.iter().position(|&col| col == pos.col) {
+        Some(i) if i % 2 == 0 => i,
+        Some(i) => i - 1,
+        None => quotes.iter().rposition(|&col| col < pos.col)?,
+    };
+    let (open, close) = (*quotes.get(i)?, *quotes.get(i + 1)?);
+    let (open, close) = (LinePos { line: pos.line, col: open }, LinePos { line: pos.line, col: close });
+
+    Some(Surrounding { open: (open, open), close: (close, close) })
+}
+
+// the open bracket of the innermost pair holding pos, pos can be on either bracket
+fn enclosing_open(pos: LinePos, buf: &TextBuffer, open: char, close: char) -> Option<LinePos> {
+    let mut depth = 0;
+    for line in (pos.line.saturating_sub(SURROUND_LINES)..=pos.line).rev() {
+        let chars = buf.line(line).chars().collect::<Vec<_>>();
+        let end = if line == pos.line { (pos.col + 1).min(chars.len()) } else { chars.len() };
+        for col in (0..end).rev() {
+            let at_pos = line == pos.line && col == pos.col;
+            if chars[col] == close && !at_pos {
+                depth += 1;
+            } else if chars[col] == open {
+                if depth == 0 {
+                    return Some(LinePos { line, col })
+                }
+                depth -= 1;
+            }
+        }
+    }
+
+    None
+}
+
+// the bracket closing the one at open
+fn closing_of(open: LinePos, buf: &TextBuffer, open_char: char, close: char) -> Option<LinePos> {
+    let mut depth = 0;
+    let last = buf.total_lines().min(open.line + SURROUND_LINES + 1);
+    for line in open.line..last {
+        let start = if line == open.line { open.col } else { 0 };
+        for (col, char) in buf.line(line).chars().enumerate().skip(start) {
+            if char == open_char {
+                depth += 1;
+            } else if char == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(LinePos { line, col })
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// the innermost <name ...> and </name> with pos between them or on them
+fn surrounding_tag(pos: LinePos, buf: &TextBuffer) -> Option<Surrounding> {
+    let text = String::from_utf8_lossy(&buf.chars.get_by_range(0..buf.chars.len())).into_owned();
+    let at = buf.linepos_to_byte(pos);
+    let to_pos = |start: usize, end: usize| (buf.byte_to_linepos(start), buf.byte_to_linepos(end - 1));
+
+    let upto = at + text[at..].chars().next().map_or(0, char::len_utf8);
+    for (start, _) in text[..upto].rmatch_indices('<') {
+        let name = text[start + 1..].chars().take_while(|c| c.is_alphanumeric() || *c == '-').collect::<String>();
+        if name.is_empty() {
+            continue
+        }
+        let open_end = start + text[start..].find('>')? + 1;
+        // same name tags inside nest
+        let mut depth = 0;
+        let mut from = open_end;
+        while let Some(i) = text[from..].find(&format!("</{name}>")) {
+            let close = from + i;
+            depth += text[from..close].match_indices(&format!("<{name}")).filter(|&(i, _)| !text[from + i + name.len() + 1..].starts_with(|c: char| c.is_alphanumeric() || c == '-')).count();
+            if depth == 0 {
+                let close_end = close + name.len() + 3;
+                if close_end > at {
+                    return Some(Surrounding { open: to_pos(start, open_end), close: to_pos(close, close_end) })
+                }
+                break
+            }
+            depth -= 1;
+            from = close + name.len() + 3;
+        }
+    }
+
+    None
+}
+
+// % jumps from the first bracket at or after the cursor on its line to its match
+pub fn find_matching_bracket(cursor: LinePos, buf: &TextBuffer) -> Option<LinePos> {
+    let col = cursor.col + buf.line(cursor.line).chars().skip(cursor.col).position(is_bracket)?;
+    matching_bracket(LinePos { line: cursor.line, col }, buf, usize::MAX)
+}
+
+
+// word motions inside a single line of text, like the command bar input.
+// uses the same word rules as the buffer motions above
+pub fn previous_word_start_in_line(chars: &[char], col: usize) -> usize {
+    let mut col = col.min(chars.len());
+    while col > 0 && chars[col - 1].is_whitespace() {
+        col -= 1;
+    }
+    if col == 0 {
+        return 0
+    }
+
+    let letter = is_letter(chars[col - 1]);
+    while col > 0 && !chars[col - 1].is_whitespace() && is_letter(chars[col - 1]) == letter {
+        col -= 1;
+    }
+
+    col
+}
+
+pub fn next_word_start_in_line(chars: &[char], col: usize) -> usize {
+    let mut col = col;
+    if col >= chars.len() {
+        return chars.len()
+    }
+
+    if !chars[col].is_whitespace() {
+        let letter = is_letter(chars[col]);
+        while col < chars.len() && !chars[col].is_whitespace() && is_letter(chars[col]) == letter {
+            col += 1;
+        }
+    }
+    while col < chars.len() && chars[col].is_whitespace() {
+        col += 1;
+    }
+
+    col
+}
+
+
+pub fn is_letter(char: char) -> bool {
     char.is_alphanumeric() || char == '_'
 }
 
@@ -0,0 +1,111 @@
+// text drawn after the end of a line that isn't in the buffer, like a
+// language server's diagnostics. it isn't saved, searched or gone over by the
+// cursor, it only moves along with the lines it's on
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationKind {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl AnnotationKind {
+    pub fn color(&self) -> (f32, f32, f32) {
+        match self {
+            AnnotationKind::Error => (0.75, 0.35, 0.35),
+            AnnotationKind::Warning => (0.75, 0.65, 0.3),
+            AnnotationKind::Info => (0.45, 0.55, 0.75),
+            AnnotationKind::Hint => (0.5, 0.5, 0.55),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub line: usize,
+    pub text: String,
+    pub kind: AnnotationKind,
+    // what put it there, like "lsp", so it can replace its own
+    pub source: &'static str,
+}
+
+// sorted by line
+#[derive(Debug, Default)]
+pub struct VirtualText {
+    annotations: Vec<Annotation>,
+}
+
+impl VirtualText {
+    pub fn add(&mut self, annotation: Annotation) {
+        let i = self.annotations.partition_point(|other| other.line <= annotation.line);
+        self.annotations.insert(i, annotation);
+    }
+
+    // drops the annotations from source and puts in new ones, like when a
+    // server sends a file's diagnostics again
+    pub fn replace(&mut self, source: &'static str, annotations: Vec<Annotation>) {
+        self.annotations.retain(|annotation| annotation.source != source);
+        self.annotations.extend(annotations);
+        self.annotations.sort_by_key(|annotation| annotation.line);
+    }
+
+    pub fn on_line(&self, line: usize) -> &[Annotation] {
+        let start = self.annotations.partition_point(|annotation| annotation.line < line);
+        let end = self.annotations.partition_point(|annotation| annotation.line <= line);
+        &self.annotations[start..end]
+    }
+
+    // what's drawn after line, the annotations on it one after the other
+    pub fn line_text(&self, line: usize) -> Option<(String, AnnotationKind)> {
+        let annotations = self.on_line(line);
+        let worst = annotations.iter().map(|annotation| annotation.kind).min_by_key(|kind| *kind as u8)?;
+        let text = annotations.iter().map(|annotation| annotation.text.as_str()).collect::<Vec<_>>().join("  ");
+        Some((text, worst))
+    }
+
+    // like Folds, n lines were put in before line at
+    pub fn lines_inserted(&mut self, at: usize, n: usize) {
+        for annotation in self.annotations.iter_mut().filter(|annotation| annotation.line >= at) {
+            annotation.line += n;
+        }
+    }
+
+    // lines at..at + n were removed with their annotations
+    pub fn lines_removed(&mut self, at: usize, n: usize) {
+        self.annotations.retain(|annotation| annotation.line < at || annotation.line >= at + n);
+        for annotation in self.annotations.iter_mut().filter(|annotation| annotation.line >= at + n) {
+            annotation.line -= n;
+        }
+    }
+
+    pub fn clamp(&mut self, total_lines: usize) {
+        self.annotations.retain(|annotation| annotation.line < total_lines);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_text() {
+        let annotation = |line: usize, text: &str, kind: AnnotationKind, source: &'static str| Annotation { line, text: text.into(), kind, source };
+        let mut virtual_text = VirtualText::default();
+        virtual_text.add(annotation(3, "unused", AnnotationKind::Warning, "lsp"));
+        virtual_text.add(annotation(3, "mismatched types", AnnotationKind::Error, "lsp"));
+        virtual_text.add(annotation(7, "blame", AnnotationKind::Hint, "plugin"));
+        assert_eq!(virtual_text.line_text(3), Some(("unused  mismatched types".into(), AnnotationKind::Error)));
+        assert_eq!(virtual_text.line_text(4), None);
+
+        virtual_text.lines_inserted(2, 2);
+        virtual_text.lines_removed(9, 1);
+        assert_eq!(virtual_text.on_line(5).len(), 2);
+        assert_eq!(virtual_text.on_line(8), []);
+
+        virtual_text.replace("lsp", vec![annotation(0, "missing ;", AnnotationKind::Error, "lsp")]);
+        assert_eq!(virtual_text.annotations.iter().map(|a| a.line).collect::<Vec<_>>(), [0]);
+    }
+}
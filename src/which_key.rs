@@ -0,0 +1,88 @@
+use crate::keys::{self, KeyMap};
+
+
+// when a prefix like g or z waits for a while, what can come after it is
+// listed with what it does. the built in commands are here, user maps are
+// added from their lhs and rhs
+
+// the keys so far, what can follow them and what it does
+const CONTINUATIONS: &[(&str, &str, &str)] = &[
+    ("g", "g", "first line"),
+    ("g", "d", "go to definition"),
+    ("g", "r", "references"),
+    ("g", "a", "character info"),
+    ("g", "^G", "word and char counts"),
+    ("g", "j", "down a screen row"),
+    ("g", "k", "up a screen row"),
+    ("g", "q", "format lines"),
+    ("gq", "q", "format the line"),
+    ("z", "z", "cursor line to the middle"),
+    ("z", "t", "cursor line to the top"),
+    ("z", "b", "cursor line to the bottom"),
+    ("z", "f", "make a fold"),
+    ("z", "o", "open fold"),
+    ("z", "c", "close fold"),
+    ("z", "a", "toggle fold"),
+    ("z", "R", "open all folds"),
+    ("z", "M", "close all folds"),
+    ("[", "c", "previous change"),
+    ("]", "c", "next change"),
+    ("d", "d", "delete line"),
+    ("d", "w", "delete word"),
+    ("d", "iw", "delete inner word"),
+    ("d", "s", "delete surrounding pair"),
+    ("d", "/", "delete up to a search"),
+    ("c", "s", "change surrounding pair"),
+    ("y", "s", "add a surrounding pair"),
+    ("ys", "s", "surround the line"),
+    ("=", "=", "reindent line"),
+    ("^w", "s", "split the window"),
+    ("^w", "v", "split the window side by side"),
+    ("^w", "w", "next window"),
+    ("^w", "h", "window to the left"),
+    ("^w", "j", "window below"),
+    ("^w", "k", "window above"),
+    ("^w", "l", "window to the right"),
+    ("^w", "c", "close the window"),
+    ("^w", "o", "close the other windows"),
+    ("^w", "z", "zoom the window or put the others back"),
+    ("^w", "r", "resize with h/j/k/l until escape"),
+    ("q", "{a-z}", "record a macro"),
+    ("@", "{a-z}", "replay a macro"),
+    ("@", "@", "replay the last macro"),
+];
+
+// "keys  description" lines for what can follow pending, typed as motion keys
+// with ^ for ctrl, and for the maps starting with the keys held for them
+pub fn hints<'a>(pending: &str, maps: impl IntoIterator<Item = &'a KeyMap>, map_pending: &[keys::Key]) -> Vec<String> {
+    let mut hints = CONTINUATIONS.iter()
+        .filter(|(prefix, _, _)| *prefix == pending)
+        .map(|(_, next, description)| (next.to_string(), description.to_string()))
+        .collect::<Vec<_>>();
+    if !map_pending.is_empty() {
+        hints.extend(maps.into_iter().filter(|map| map.lhs.len() > map_pending.len() && map.lhs.starts_with(map_pending)).map(|map| {
+            (keys::to_notation(&map.lhs[map_pending.len()..]), map.rhs.clone())
+        }));
+    }
+
+    let width = hints.iter().map(|(next, _)| next.chars().count()).max().unwrap_or(0);
+    hints.into_iter().map(|(next, description)| format!("{next:width$}  {description}")).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::MapMode;
+
+    #[test]
+    fn test_hints() {
+        let hints = hints("z", &[], &[]);
+        assert_eq!(hints[0], "z  cursor line to the middle");
+        assert_eq!(hints.len(), 9);
+        assert!(super::hints("x", &[], &[]).is_empty());
+
+        let map = KeyMap { mode: MapMode::Normal, lhs: keys::parse(",ff"), rhs: ":find ".into(), noremap: true };
+        assert_eq!(super::hints("", &[map], &keys::parse(",")), ["ff  :find "]);
+    }
+}
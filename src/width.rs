@@ -0,0 +1,168 @@
+// how many cells chars take on screen. cursor columns count chars, these convert
+// them to screen columns and back
+
+
+// east asian wide and fullwidth chars and emoji, from the unicode EastAsianWidth table
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F), (0x231A, 0x231B), (0x2329, 0x232A), (0x23E9, 0x23EC), (0x23F0, 0x23F0),
+    (0x23F3, 0x23F3), (0x25FD, 0x25FE), (0x2614, 0x2615), (0x2648, 0x2653), (0x267F, 0x267F),
+    (0x2693, 0x2693), (0x26A1, 0x26A1), (0x26AA, 0x26AB), (0x26BD, 0x26BE), (0x26C4, 0x26C5),
+    (0x26CE, 0x26CE), (0x26D4, 0x26D4), (0x26EA, 0x26EA), (0x26F2, 0x26F3), (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA), (0x26FD, 0x26FD), (0x2705, 0x2705), (0x270A, 0x270B), (0x2728, 0x2728),
+    (0x274C, 0x274C), (0x274E, 0x274E), (0x2753, 0x2755), (0x2757, 0x2757), (0x2795, 0x2797),
+    (0x27B0, 0x27B0), (0x27BF, 0x27BF), (0x2B1B, 0x2B1C), (0x2B50, 0x2B50), (0x2B55, 0x2B55),
+    (0x2E80, 0x303E), (0x3041, 0x33FF), (0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xA000, 0xA4CF),
+    (0xA960, 0xA97F), (0xAC00, 0xD7A3), (0xF900, 0xFAFF), (0xFE10, 0xFE19), (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60), (0xFFE0, 0xFFE6), (0x16FE0, 0x16FE4), (0x17000, 0x18AFF), (0x1B000, 0x1B16F),
+    (0x1F004, 0x1F004), (0x1F0CF, 0x1F0CF), (0x1F18E, 0x1F18E), (0x1F191, 0x1F19A), (0x1F200, 0x1F251),
+    (0x1F300, 0x1F320), (0x1F32D, 0x1F335), (0x1F337, 0x1F37C), (0x1F37E, 0x1F393), (0x1F3A0, 0x1F3CA),
+    (0x1F3CF, 0x1F3D3), (0x1F3E0, 0x1F3F0), (0x1F3F4, 0x1F3F4), (0x1F3F8, 0x1F43E), (0x1F440, 0x1F440),
+    (0x1F442, 0x1F4FC), (0x1F4FF, 0x1F53D), (0x1F54B, 0x1F54E), (0x1F550, 0x1F567), (0x1F57A, 0x1F57A),
+    (0x1F595, 0x1F596), (0x1F5A4, 0x1F5A4), (0x1F5FB, 0x1F64F), (0x1F680, 0x1F6C5), (0x1F6CC, 0x1F6CC),
+    (0x1F6D0, 0x1F6D2), (0x1F6D5, 0x1F6D7), (0x1F6DC, 0x1F6DF), (0x1F6EB, 0x1F6EC), (0x1F6F4, 0x1F6FC),
+    (0x1F7E0, 0x1F7EB), (0x1F7F0, 0x1F7F0), (0x1F90C, 0x1F93A), (0x1F93C, 0x1F945), (0x1F947, 0x1F9FF),
+    (0x1FA70, 0x1FAFF), (0x20000, 0x2FFFD), (0x30000, 0x3FFFD),
+];
+
+// combining marks, variation selectors and zero width spaces and joiners
+const ZERO: &[(u32, u32)] = &[
+    (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x0610, 0x061A), (0x064B, 0x065F),
+    (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x200B, 0x200F), (0x20D0, 0x20FF), (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F), (0xE0100, 0xE01EF),
+];
+
+fn in_table(table: &[(u32, u32)], c: u32) -> bool {
+    table.binary_search_by(|&(start, end)| {
+        if end < c {
+            std::cmp::Ordering::Less
+        } else if start > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }).is_ok()
+}
+
+pub fn char_width(c: char) -> usize {
+    let c = c as u32;
+    if c < 0x300 {
+        1
+    } else if in_table(ZERO, c) {
+        0
+    } else if in_table(WIDE, c) {
+        2
+    } else {
+        1
+    }
+}
+
+pub fn str_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+// the cells c takes starting at screen column display, a tab reaches the next tabstop
+pub fn cell_width(c: char, display: usize, tabstop: usize) -> usize {
+    if c == '\t' {
+        let tabstop = tabstop.max(1);
+        tabstop - display % tabstop
+    } else {
+        char_width(c)
+    }
+}
+
+// the screen column char col of line starts at, cols past the end take a cell each
+pub fn display_col(line: &str, col: usize, tabstop: usize) -> usize {
+    let mut width = 0;
+    let mut chars = 0;
+    for c in line.chars().take(col) {
+        width += cell_width(c, width, tabstop);
+        chars += 1;
+    }
+
+    width + (col - chars)
+}
+
+// the char covering screen column display, the inverse of display_col
+pub fn col_at_display(line: &str, display: usize, tabstop: usize) -> usize {
+    let mut width = 0;
+    let mut col = 0;
+    for c in line.chars() {
+        let w = cell_width(c, width, tabstop);
+        if width + w > display {
+            return col
+        }
+        width += w;
+        col += 1;
+    }
+
+    col + (display - width)
+}
+
+// what :set list draws over a line, dim markers for tabs, trailing spaces and
+// non-breaking spaces laid out in the same cells, None if there's nothing to mark
+pub fn list_markers(line: &str, tabstop: usize) -> Option<String> {
+    let trailing = line.len() - line.trim_end_matches(' ').len();
+    let mut markers = String::new();
+    let mut marked = false;
+    let mut display = 0;
+    for (i, c) in line.char_indices() {
+        let cells = cell_width(c, display, tabstop);
+        display += cells;
+        match c {
+            '\t' => {
+                markers.push('»');
+                markers.push_str(&"·".repeat(cells - 1));
+            },
+            '\u{a0}' => markers.push('␣'),
+            ' ' if i >= line.len() - trailing => markers.push('·'),
+            _ => {
+                markers.push_str(&" ".repeat(cells));
+                continue
+            },
+        }
+        marked = true;
+    }
+
+    marked.then_some(markers)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('漢'), 2);
+        assert_eq!(char_width('🦀'), 2);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(str_width("a漢字b"), 6);
+
+        let line = "a漢字b";
+        assert_eq!(display_col(line, 2, 8), 3);
+        assert_eq!(display_col(line, 6, 8), 8);
+        // both cells of a wide char map to it
+        assert_eq!(col_at_display(line, 1, 8), 1);
+        assert_eq!(col_at_display(line, 2, 8), 1);
+        assert_eq!(col_at_display(line, 5, 8), 3);
+        assert_eq!(col_at_display(line, 8, 8), 6);
+
+        assert_eq!(list_markers("a b", 4), None);
+        assert_eq!(list_markers("\t漢\u{a0}b  ", 2).as_deref(), Some("»·  ␣ ··"));
+    }
+
+    #[test]
+    fn test_tabs() {
+        // tabs go to the next tabstop
+        let line = "a\tb\t\tc";
+        assert_eq!(display_col(line, 2, 4), 4);
+        assert_eq!(display_col(line, 4, 4), 8);
+        assert_eq!(display_col(line, 5, 4), 12);
+        // every cell of a tab maps to it
+        assert_eq!(col_at_display(line, 1, 4), 1);
+        assert_eq!(col_at_display(line, 3, 4), 1);
+        assert_eq!(col_at_display(line, 4, 4), 2);
+        assert_eq!(col_at_display(line, 11, 4), 4);
+    }
+}
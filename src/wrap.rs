@@ -0,0 +1,167 @@
+use crate::{fold::Fold, gap_buffer::{LinePos, TextBuffer}, width, State};
+
+
+// with :set wrap a line wider than the window goes on over the rows below it.
+// without it every line is one row
+
+// a screen row, chars start..end of a buffer line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    // the closed fold shown as this row's line, see fold.rs
+    pub fold: Option<Fold>,
+}
+
+impl Row {
+    pub fn text(&self, line: &str) -> String {
+        line.chars().skip(self.start).take(self.end - self.start).collect()
+    }
+}
+
+// the col each row of line starts at when rows are cols cells wide. tabs are
+// measured from the start of their row, with linebreak rows end after a blank
+pub fn row_starts(line: &str, cols: usize, tabstop: usize, linebreak: bool) -> Vec<usize> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut starts = vec![0];
+    let mut start = 0;
+    let mut cells = 0;
+    let mut col = 0;
+    while col < chars.len() {
+        let cell_width = width::cell_width(chars[col], cells, tabstop);
+        // a char wider than the window still gets a row
+        if cells + cell_width > cols.max(1) && col > start {
+            let blank = chars[start..col].iter().rposition(|c| c.is_whitespace()).filter(|_| linebreak);
+            start = blank.map_or(col, |blank| start + blank + 1);
+            starts.push(start);
+            col = start;
+            cells = 0;
+            continue
+        }
+        cells += cell_width;
+        col += 1;
+    }
+
+    starts
+}
+
+// a closed fold is one row at its first line and none for the others
+pub fn line_rows(buffer: &TextBuffer, state: &State, line: usize) -> Vec<Row> {
+    let len = buffer.line_len(line);
+    if let Some(fold) = buffer.folds.closed_at(line) {
+        return if fold.start == line { vec![Row { line, start: 0, end: len, fold: Some(fold) }] } else { Vec::new() }
+    }
+    if !state.options.wrap {
+        return vec![Row { line, start: 0, end: len, fold: None }]
+    }
+
+    let starts = row_starts(&buffer.line(line), state.max_cols(), state.options.tabstop, state.options.linebreak);
+    starts.iter().enumerate().map(|(i, &start)| Row { line, start, end: starts.get(i + 1).copied().unwrap_or(len), fold: None }).collect()
+}
+
+// the rows from state.start_line down, with one more for a partly shown last row
+pub fn screen_rows(buffer: &TextBuffer, state: &State) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let mut line = state.start_line;
+    while rows.len() <= state.max_rows() && line < buffer.total_lines() {
+        rows.extend(line_rows(buffer, state, line));
+        line += 1;
+    }
+    rows.truncate(state.max_rows() + 1);
+
+    rows
+}
+
+// the index of the row pos is on, cols past the end are on the line's last row
+pub fn row_of(rows: &[Row], pos: LinePos) -> Option<usize> {
+    rows.iter().rposition(|row| row.line == pos.line && row.start <= pos.col)
+}
+
+// the indices of line's rows in rows
+pub fn rows_of(rows: &[Row], line: usize) -> impl Iterator<Item = usize> + '_ {
+    rows.iter().enumerate().filter(move |(_, row)| row.line == line).map(|(i, _)| i)
+}
+
+// moves start_line down until the cursor's row fits on screen, start_line is
+// already at most a screen of lines above the cursor
+pub fn scroll_to(buffer: &TextBuffer, state: &mut State, cursor: LinePos) {
+    loop {
+        let rows = (state.start_line..cursor.line).map(|line| line_rows(buffer, state, line).len()).sum::<usize>();
+        let cursor_row = row_of(&line_rows(buffer, state, cursor.line), cursor).unwrap_or(0);
+        if state.start_line >= cursor.line || rows + cursor_row < state.max_rows() {
+            break
+        }
+        state.start_line += 1;
+    }
+}
+
+// the position one screen row below or above pos at the same screen column, for gj and gk
+pub fn row_move(buffer: &TextBuffer, state: &State, pos: LinePos, down: bool) -> Option<LinePos> {
+    let text = buffer.line(pos.line);
+    let rows = line_rows(buffer, state, pos.line);
+    let i = row_of(&rows, pos)?;
+    let cell = width::display_col(&rows[i].text(&text), pos.col - rows[i].start, state.options.tabstop);
+
+    // lines hidden in a closed fold have no rows
+    let next = buffer.folds.closed_at(pos.line).map_or(pos.line, |fold| fold.end) + 1;
+    let target = if down {
+        match rows.get(i + 1) {
+            Some(row) => *row,
+            None if next < buffer.total_lines() => line_rows(buffer, state, next)[0],
+            None => return None,
+        }
+    } else if i > 0 {
+        rows[i - 1]
+    } else if pos.line > 0 {
+        *line_rows(buffer, state, buffer.folds.shown_line(pos.line - 1)).last()?
+    } else {
+        return None
+    };
+
+    let target_text = target.text(&buffer.line(target.line));
+    let col = target.start + width::col_at_display(&target_text, cell, state.options.tabstop);
+    // stays on its row, the last col of a line is its last char
+    let last = if target.end > target.start && target.end < buffer.line_len(target.line) { target.end - 1 } else { buffer.line_len(target.line).max(1) - 1 };
+
+    Some(LinePos { line: target.line, col: col.min(last).max(target.start) })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_starts() {
+        assert_eq!(row_starts("", 4, 8, false), [0]);
+        assert_eq!(row_starts("abcdefghij", 4, 8, false), [0, 4, 8]);
+        assert_eq!(row_starts("ab cd efgh", 6, 8, false), [0, 6]);
+        // with linebreak rows end at a blank if there's one
+        assert_eq!(row_starts("ab cd efgh", 6, 8, true), [0, 6]);
+        assert_eq!(row_starts("ab cdefgh ij", 6, 8, true), [0, 3, 9]);
+        // a wide char that doesn't fit goes to the next row
+        assert_eq!(row_starts("abc漢", 4, 8, false), [0, 3]);
+    }
+
+    #[test]
+    fn test_row_move() {
+        let buffer = TextBuffer::from_data(0, b"abcdefghij\nxy\n".to_vec());
+        let mut state = State::new(4, 10);
+        state.char_width = 1.0;
+        state.char_height = 1.0;
+        state.options.wrap = true;
+
+        assert_eq!(screen_rows(&buffer, &state).len(), 4);
+        assert_eq!(row_move(&buffer, &state, LinePos::new(0, 1), true), Some(LinePos::new(0, 5)));
+        assert_eq!(row_move(&buffer, &state, LinePos::new(0, 5), true), Some(LinePos::new(0, 9)));
+        assert_eq!(row_move(&buffer, &state, LinePos::new(0, 9), true), Some(LinePos::new(1, 1)));
+        assert_eq!(row_move(&buffer, &state, LinePos::new(1, 1), false), Some(LinePos::new(0, 9)));
+        assert_eq!(row_move(&buffer, &state, LinePos::new(0, 2), false), None);
+
+        // the cursor's row is kept on screen
+        state.height = 2;
+        scroll_to(&buffer, &mut state, LinePos::new(1, 0));
+        assert_eq!(state.start_line, 1);
+    }
+}